@@ -0,0 +1,177 @@
+//! Crafted repository content served through the filesystem.
+//!
+//! The first input byte picks what the rest becomes: the raw body of a tree
+//! object (committed, so it is reachable as a snapshot), the raw body of a
+//! commit object, the content of a loose ref, or a `packed-refs` file. The
+//! repository is then mounted in-process and walked from the root: every
+//! directory listed, every file read, every symlink resolved, every extended
+//! attribute fetched, which renders the `.git-meta/` files of every snapshot
+//! on the way. Nothing the repository contains may panic the daemon.
+//!
+//! Each input gets a fresh bare repository in a temporary directory, as the
+//! filesystem only serves repositories on disk; point `TMPDIR` at a tmpfs to
+//! keep the run from waiting on the disk.
+
+#![no_main]
+
+use std::ffi::CString;
+use std::io;
+
+use fuse_backend_rs::abi::fuse_abi::ROOT_ID;
+use fuse_backend_rs::api::filesystem::{
+    Context, Entry, FileSystem, FsOptions, ListxattrReply, ZeroCopyWriter,
+};
+use fuse_backend_rs::file_traits::FileReadWriteVolatile;
+use gix::objs::Write as _;
+use gix::ObjectId;
+use libfuzzer_sys::fuzz_target;
+
+use gitsnapfs::fs::GitSnapFs;
+use gitsnapfs::repo::Repository;
+
+/// Directories below the root the walk descends into.
+const MAX_DEPTH: usize = 8;
+/// Entries the walk visits before giving up on the rest.
+const MAX_ENTRIES: usize = 2048;
+/// Bytes read from each file.
+const READ_SIZE: u32 = 64 * 1024;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&kind, content)) = data.split_first() else {
+        return;
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let repo = gix::init_bare(dir.path()).unwrap();
+    let head = match kind % 4 {
+        0 => {
+            let Ok(tree) = repo.write_buf(gix::object::Kind::Tree, content) else {
+                return;
+            };
+            commit(&repo, tree)
+        }
+        1 => match repo.write_buf(gix::object::Kind::Commit, content) {
+            Ok(id) => id,
+            Err(_) => return,
+        },
+        _ => commit(&repo, gix::ObjectId::empty_tree(repo.object_hash())),
+    };
+    std::fs::write(dir.path().join("refs/heads/main"), format!("{head}\n")).unwrap();
+    std::fs::write(dir.path().join("HEAD"), "ref: refs/heads/main\n").unwrap();
+    match kind % 4 {
+        2 => std::fs::write(dir.path().join("refs/heads/fuzz"), content).unwrap(),
+        3 => std::fs::write(dir.path().join("packed-refs"), content).unwrap(),
+        _ => {}
+    }
+
+    let Ok(repo) = Repository::open(dir.path()) else {
+        return;
+    };
+    let fs = GitSnapFs::new(repo);
+    if fs.init(FsOptions::all()).is_err() {
+        return;
+    }
+    let mut budget = MAX_ENTRIES;
+    walk(&fs, ROOT_ID, 0, &mut budget);
+});
+
+/// A commit of `tree` with no parents.
+fn commit(repo: &gix::Repository, tree: ObjectId) -> ObjectId {
+    let signature = gix::actor::Signature {
+        name: "Fuzz".into(),
+        email: "fuzz@example.com".into(),
+        time: gix::date::Time::new(1_700_000_000, 0),
+    };
+    repo.write_object(&gix::objs::Commit {
+        tree,
+        parents: Vec::new().into(),
+        author: signature.clone(),
+        committer: signature,
+        encoding: None,
+        message: "fuzz\n".into(),
+        extra_headers: Vec::new(),
+    })
+    .unwrap()
+    .detach()
+}
+
+/// Visit everything below the directory `inode`, depth first.
+fn walk(fs: &GitSnapFs, inode: u64, depth: usize, budget: &mut usize) {
+    let ctx = Context::default();
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    loop {
+        let mut listed = 0;
+        let result = fs.readdirplus(&ctx, inode, 0, 4096, offset, &mut |dirent, entry| {
+            offset = dirent.offset;
+            if dirent.name != b"." && dirent.name != b".." {
+                entries.push(entry);
+            }
+            listed += 1;
+            Ok(1)
+        });
+        if result.is_err() || listed == 0 || entries.len() >= *budget {
+            break;
+        }
+    }
+    for entry in entries {
+        if *budget == 0 {
+            return;
+        }
+        *budget -= 1;
+        visit(fs, &entry);
+        if entry.attr.st_mode & libc::S_IFMT == libc::S_IFDIR && depth < MAX_DEPTH {
+            walk(fs, entry.inode, depth + 1, budget);
+        }
+    }
+}
+
+/// Read whatever `entry` offers besides its children.
+fn visit(fs: &GitSnapFs, entry: &Entry) {
+    let ctx = Context::default();
+    let _ = fs.getattr(&ctx, entry.inode, None);
+    match entry.attr.st_mode & libc::S_IFMT {
+        libc::S_IFREG => {
+            let mut sink = Sink::default();
+            let _ = fs.read(&ctx, entry.inode, 0, &mut sink, READ_SIZE, 0, None, 0);
+        }
+        libc::S_IFLNK => {
+            let _ = fs.readlink(&ctx, entry.inode);
+        }
+        _ => {}
+    }
+    if let Ok(ListxattrReply::Names(names)) = fs.listxattr(&ctx, entry.inode, 4096) {
+        for name in names.split(|&byte| byte == 0).filter(|name| !name.is_empty()) {
+            let name = CString::new(name).unwrap();
+            let _ = fs.getxattr(&ctx, entry.inode, &name, 4096);
+        }
+    }
+}
+
+/// Collects read replies.
+#[derive(Default)]
+struct Sink(Vec<u8>);
+
+impl io::Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ZeroCopyWriter for Sink {
+    fn write_from(
+        &mut self,
+        _f: &mut dyn FileReadWriteVolatile,
+        _count: usize,
+        _off: u64,
+    ) -> io::Result<usize> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+
+    fn available_bytes(&self) -> usize {
+        usize::MAX
+    }
+}
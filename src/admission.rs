@@ -0,0 +1,95 @@
+//! Per-user admission control for shared mounts.
+//!
+//! With `--allow-other` every local user can send requests, and one busy
+//! user could otherwise keep every worker thread occupied. Each request
+//! handler takes a [`Permit`] for the calling uid; once a uid has
+//! `--max-requests-per-uid` requests in flight, further ones are refused with
+//! `EAGAIN` straight away, so the worker is free for other users again.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use tracing::debug;
+
+/// Counts in-flight requests per uid against a fixed limit.
+#[derive(Debug)]
+pub struct UidLimiter {
+    limit: usize,
+    in_flight: Mutex<HashMap<u32, usize>>,
+}
+
+/// An admitted request; releases its slot when dropped.
+#[derive(Debug)]
+pub struct Permit<'a> {
+    limiter: &'a UidLimiter,
+    uid: u32,
+}
+
+impl UidLimiter {
+    /// Allow at most `limit` concurrent requests per uid.
+    #[must_use]
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit: limit.max(1),
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Admit a request from `uid`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `EAGAIN` if `uid` already has the maximum number of requests in flight.
+    pub fn admit(&self, uid: u32) -> io::Result<Permit<'_>> {
+        let mut in_flight = self.lock();
+        let count = in_flight.entry(uid).or_default();
+        if *count >= self.limit {
+            debug!(
+                uid,
+                limit = self.limit,
+                "refusing request over per-uid limit"
+            );
+            return Err(io::Error::from_raw_os_error(libc::EAGAIN));
+        }
+        *count += 1;
+        Ok(Permit { limiter: self, uid })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<u32, usize>> {
+        self.in_flight
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.limiter.lock();
+        if let Some(count) = in_flight.get_mut(&self.uid) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&self.uid);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_each_uid_separately() {
+        let limiter = UidLimiter::new(2);
+        let first = limiter.admit(1000).unwrap();
+        let _second = limiter.admit(1000).unwrap();
+        let err = limiter.admit(1000).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EAGAIN));
+        let _other = limiter.admit(1001).unwrap();
+
+        drop(first);
+        let _third = limiter.admit(1000).unwrap();
+        assert_eq!(limiter.lock().get(&1000), Some(&2));
+    }
+}
@@ -0,0 +1,323 @@
+//! Tar archives of snapshot directories, for `gitsnapfs export`.
+//!
+//! The archive is written from the same [`Vfs`] view the mount, the HTTP
+//! gateway and SFTP serve, so `--respect-export-ignore`, `--synthetic-files`,
+//! `--max-file-size` and the other content settings shape it exactly as they
+//! shape a mounted snapshot. Entries are laid out like
+//! `git archive --format=tar`: ustar headers, PAX extended headers for names
+//! and sizes that do not fit, and a global header carrying the commit id, so
+//! `git get-tar-commit-id` works on the result. The snapshot's `.git-meta/`
+//! is left out: it describes the commit and links outside the archive.
+//!
+//! Entries are streamed as the snapshot is walked; only one file is held in
+//! memory at a time. Compressed formats pipe the stream through the `gzip`
+//! or `zstd` program, which must be on `PATH`.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use gix::bstr::ByteSlice;
+use gix::ObjectId;
+
+use crate::meta::META_DIR_NAME;
+use crate::vfs::{DirEntry, Node, NodeKind, Vfs};
+
+/// Size of a tar block; headers take one and file data is padded to whole blocks.
+const BLOCK: usize = 512;
+
+/// Longest name or link target a ustar header holds without a PAX record.
+const USTAR_NAME_LEN: usize = 100;
+
+/// Largest size the 12-byte octal field holds.
+const USTAR_MAX_SIZE: u64 = 0o777_7777_7777;
+
+/// Container and compression of an export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ArchiveFormat {
+    /// Uncompressed tar.
+    Tar,
+    /// Tar compressed with `gzip`.
+    #[value(name = "tar.gz", alias = "tgz")]
+    TarGz,
+    /// Tar compressed with `zstd`.
+    #[value(name = "tar.zst")]
+    TarZst,
+}
+
+impl ArchiveFormat {
+    /// The format an output file name implies, if its extension names one.
+    #[must_use]
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        [
+            (".tar", Self::Tar),
+            (".tar.gz", Self::TarGz),
+            (".tgz", Self::TarGz),
+            (".tar.zst", Self::TarZst),
+        ]
+        .into_iter()
+        .find(|(suffix, _)| name.ends_with(suffix))
+        .map(|(_, format)| format)
+    }
+
+    /// Program and arguments compressing stdin to stdout, if any.
+    fn compressor(self) -> Option<(&'static str, &'static [&'static str])> {
+        match self {
+            Self::Tar => None,
+            Self::TarGz => Some(("gzip", &["-c", "-n"])),
+            Self::TarZst => Some(("zstd", &["-q", "-c"])),
+        }
+    }
+}
+
+/// How entries are named and stamped.
+#[derive(Debug, Clone, Default)]
+pub struct TarOptions {
+    /// Prepended to every path, like `git archive --prefix`; with a trailing
+    /// `/` it also gets a directory entry of its own.
+    pub prefix: Vec<u8>,
+    /// Modification time of every entry, as Unix seconds.
+    pub mtime: i64,
+    /// Recorded in the global header, where `git get-tar-commit-id` finds it.
+    pub commit: Option<ObjectId>,
+}
+
+/// Write the directory `dir` of `vfs` as an archive in `format` to `output`,
+/// or to stdout. A partly written output file is removed on failure.
+///
+/// # Errors
+///
+/// Returns an error if a node cannot be read (for example a file above
+/// `--max-file-size`), the output cannot be written, or the compressor fails.
+pub fn export(
+    vfs: &Vfs,
+    dir: &Node,
+    options: &TarOptions,
+    format: ArchiveFormat,
+    output: Option<&Path>,
+) -> Result<()> {
+    let result = write_output(vfs, dir, options, format, output);
+    if let (Err(_), Some(path)) = (&result, output) {
+        let _ = std::fs::remove_file(path);
+    }
+    result
+}
+
+fn write_output(
+    vfs: &Vfs,
+    dir: &Node,
+    options: &TarOptions,
+    format: ArchiveFormat,
+    output: Option<&Path>,
+) -> Result<()> {
+    let file = output
+        .map(|path| {
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))
+        })
+        .transpose()?;
+    let Some((program, args)) = format.compressor() else {
+        return match file {
+            Some(file) => write_tar(vfs, dir, options, &mut BufWriter::new(file)),
+            None => write_tar(vfs, dir, options, &mut BufWriter::new(io::stdout().lock())),
+        };
+    };
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(file.map_or_else(Stdio::inherit, Stdio::from))
+        .spawn()
+        .with_context(|| format!("failed to run {program}"))?;
+    let stdin = child.stdin.take().context("compressor has no stdin")?;
+    // Dropping the writer closes the pipe, so the compressor can finish.
+    let written = write_tar(vfs, dir, options, &mut BufWriter::new(stdin));
+    let status = child.wait()?;
+    written?;
+    if !status.success() {
+        bail!("{program} failed with {status}");
+    }
+    Ok(())
+}
+
+/// Write the directory `dir` of `vfs` to `out` as an uncompressed tar stream.
+///
+/// # Errors
+///
+/// Returns an error if a node cannot be read or `out` cannot be written.
+pub fn write_tar(vfs: &Vfs, dir: &Node, options: &TarOptions, out: &mut impl Write) -> Result<()> {
+    let mut tar = TarWriter {
+        out,
+        mtime: u64::try_from(options.mtime).unwrap_or_default(),
+    };
+    if let Some(commit) = options.commit {
+        tar.pax(b'g', &[("comment", commit.to_string().as_bytes())])?;
+    }
+    if options.prefix.ends_with(b"/") {
+        tar.entry(&options.prefix, b'5', 0o755, b"", b"")?;
+    }
+    let entries = vfs.read_dir(dir).context("failed to list the snapshot")?;
+    let entries = entries
+        .into_iter()
+        .filter(|entry| entry.name != META_DIR_NAME);
+    add_entries(vfs, entries, &options.prefix, &mut tar)?;
+    tar.out.write_all(&[0; 2 * BLOCK])?;
+    tar.out.flush()?;
+    Ok(())
+}
+
+fn add_entries(
+    vfs: &Vfs,
+    entries: impl IntoIterator<Item = DirEntry>,
+    base: &[u8],
+    tar: &mut TarWriter<impl Write>,
+) -> Result<()> {
+    for entry in entries {
+        let mut path = base.to_vec();
+        path.extend_from_slice(&entry.name);
+        let node = entry.node;
+        match node.kind {
+            NodeKind::Directory => {
+                path.push(b'/');
+                tar.entry(&path, b'5', node.mode, b"", b"")?;
+                let children = vfs
+                    .read_dir(&node)
+                    .with_context(|| format!("failed to list {}", path.as_bstr()))?;
+                add_entries(vfs, children, &path, tar)?;
+            }
+            NodeKind::File => {
+                let data = vfs
+                    .read(&node)
+                    .with_context(|| format!("failed to read {}", path.as_bstr()))?;
+                tar.entry(&path, b'0', node.mode, b"", &data)?;
+            }
+            NodeKind::Symlink => {
+                let target = vfs
+                    .read_link(&node)
+                    .with_context(|| format!("failed to read link {}", path.as_bstr()))?;
+                tar.entry(&path, b'2', 0o777, &target, b"")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+struct TarWriter<W> {
+    out: W,
+    mtime: u64,
+}
+
+impl<W: Write> TarWriter<W> {
+    /// One entry, preceded by a PAX header for whatever ustar cannot hold.
+    fn entry(&mut self, path: &[u8], kind: u8, mode: u32, link: &[u8], data: &[u8]) -> Result<()> {
+        let size = data.len() as u64;
+        let size_text = size.to_string();
+        let mut records: Vec<(&str, &[u8])> = Vec::new();
+        if path.len() > USTAR_NAME_LEN {
+            records.push(("path", path));
+        }
+        if link.len() > USTAR_NAME_LEN {
+            records.push(("linkpath", link));
+        }
+        if size > USTAR_MAX_SIZE {
+            records.push(("size", size_text.as_bytes()));
+        }
+        if !records.is_empty() {
+            self.pax(b'x', &records)?;
+        }
+        self.out
+            .write_all(&header(path, kind, mode, size, self.mtime, link))?;
+        self.data(data)
+    }
+
+    /// A PAX header of type `kind` (`g` global, `x` for the next entry).
+    fn pax(&mut self, kind: u8, records: &[(&str, &[u8])]) -> Result<()> {
+        let mut body = Vec::new();
+        for (key, value) in records {
+            body.extend_from_slice(&pax_record(key, value));
+        }
+        let name: &[u8] = if kind == b'g' {
+            b"pax_global_header"
+        } else {
+            b"@PaxHeader"
+        };
+        self.out.write_all(&header(
+            name,
+            kind,
+            0o666,
+            body.len() as u64,
+            self.mtime,
+            b"",
+        ))?;
+        self.data(&body)
+    }
+
+    fn data(&mut self, data: &[u8]) -> Result<()> {
+        self.out.write_all(data)?;
+        let padding = (BLOCK - data.len() % BLOCK) % BLOCK;
+        self.out.write_all(&[0; BLOCK][..padding])?;
+        Ok(())
+    }
+}
+
+/// A ustar header block; fields too long for it are cut short and carried
+/// by a preceding PAX header instead.
+fn header(path: &[u8], kind: u8, mode: u32, size: u64, mtime: u64, link: &[u8]) -> [u8; BLOCK] {
+    let mut block = [0; BLOCK];
+    copy_field(&mut block[0..100], path);
+    octal(&mut block[100..108], u64::from(mode & 0o7777));
+    octal(&mut block[108..116], 0);
+    octal(&mut block[116..124], 0);
+    octal(&mut block[124..136], size.min(USTAR_MAX_SIZE));
+    octal(&mut block[136..148], mtime.min(USTAR_MAX_SIZE));
+    block[156] = kind;
+    copy_field(&mut block[157..257], link);
+    block[257..265].copy_from_slice(b"ustar\x0000");
+    copy_field(&mut block[265..297], b"root");
+    copy_field(&mut block[297..329], b"root");
+    // The checksum is computed with its own field read as spaces.
+    block[148..156].fill(b' ');
+    let sum: u64 = block.iter().map(|&byte| u64::from(byte)).sum();
+    octal(&mut block[148..155], sum);
+    block
+}
+
+fn copy_field(field: &mut [u8], value: &[u8]) {
+    let len = value.len().min(field.len());
+    field[..len].copy_from_slice(&value[..len]);
+}
+
+/// Zero-padded octal filling all but the last byte of `field`, which stays NUL.
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{value:0width$o}", width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+}
+
+/// `<len> <key>=<value>\n`, where `<len>` counts the whole record including itself.
+fn pax_record(key: &str, value: &[u8]) -> Vec<u8> {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    let mut record = format!("{len} {key}=").into_bytes();
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pax_records_count_their_own_length() {
+        assert_eq!(pax_record("path", b"abc"), b"12 path=abc\n");
+        assert_eq!(pax_record("path", &[b'a'; 90]).len(), 99);
+        let long = pax_record("path", &[b'a'; 91]);
+        assert_eq!(long.len(), 101);
+        assert!(long.starts_with(b"101 path="));
+    }
+}
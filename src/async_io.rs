@@ -0,0 +1,478 @@
+//! Serving requests as tasks on a tokio runtime (`--async-runtime`, built
+//! with the `async-io` feature).
+//!
+//! The thread-based modes give every request in progress a thread of its
+//! own, so a handful of slow blob reads occupies every worker. Here each of
+//! `--threads` workers reads requests from the FUSE device and answers each
+//! as a task of its own [`LocalSet`], through fuse-backend-rs's asynchronous
+//! server, whose request futures cannot move between threads. The handlers
+//! that may reach into the repository (`lookup`, `getattr`, `open` and
+//! `read`) run on the blocking pool of the worker's runtime, and the task
+//! waits for them without holding the worker: it keeps receiving, and
+//! answers the rest in place, while blobs are inflated or fetched from a
+//! `--shared-cache` daemon.
+//!
+//! At most [`MAX_IN_FLIGHT`] requests are held at once across the workers;
+//! a worker waits for one to finish before reading the next. The blocking
+//! pools together hold no more threads than that. Replies are
+//! always written with one `writev` and counted as such in
+//! `/.gitsnapfs/info`. There are no priority lanes, and interrupts are left
+//! to the kernel: a request it gave up on is still answered, and the reply
+//! discarded.
+
+use std::cell::RefCell;
+use std::ffi::CStr;
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, BorrowedFd, RawFd};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use fuse_backend_rs::abi::fuse_abi::{stat64, CreateIn};
+use fuse_backend_rs::api::filesystem::{
+    AsyncFileSystem, AsyncZeroCopyReader, AsyncZeroCopyWriter, Context, Entry, FileSystem,
+    OpenOptions, SetattrValid, ZeroCopyWriter,
+};
+use fuse_backend_rs::api::server::Server;
+use fuse_backend_rs::file_traits::FileReadWriteVolatile;
+use fuse_backend_rs::transport::{FuseBuf, FuseDevWriter, Reader};
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+use tokio::sync::Semaphore;
+use tokio::task::{spawn_local, LocalSet};
+use tracing::error;
+
+use crate::fs::GitSnapFs;
+use crate::splice::Writes;
+
+/// Requests received and not yet answered before the receiver waits.
+pub const MAX_IN_FLIGHT: usize = 64;
+
+/// What a worker waits for on the device; an unmounted device reports an
+/// error rather than being readable.
+const DEVICE_READINESS: Interest = Interest::READABLE.add(Interest::ERROR);
+
+thread_local! {
+    /// The filesystem the worker on this thread serves, for handlers to
+    /// hand to its blocking pool.
+    static SERVING: RefCell<Option<Arc<GitSnapFs>>> = const { RefCell::new(None) };
+}
+
+/// Run `job` against `fs` on the blocking pool of the worker's runtime, or
+/// in place when no worker on this thread serves `fs`.
+async fn offload<T: Send + 'static>(
+    fs: &GitSnapFs,
+    job: impl FnOnce(&GitSnapFs) -> io::Result<T> + Send + 'static,
+) -> io::Result<T> {
+    let serving = SERVING
+        .with(|serving| serving.borrow().clone())
+        .filter(|serving| std::ptr::eq(Arc::as_ptr(serving), fs));
+    let Some(serving) = serving else {
+        return job(fs);
+    };
+    tokio::task::spawn_blocking(move || job(&serving))
+        .await
+        .unwrap_or_else(|_| Err(io::Error::other("a FUSE handler panicked")))
+}
+
+// Handlers that may reach into the repository are offloaded; the rest run
+// the synchronous one in place.
+#[async_trait]
+impl AsyncFileSystem for GitSnapFs {
+    async fn async_lookup(&self, ctx: &Context, parent: u64, name: &CStr) -> io::Result<Entry> {
+        let ctx = *ctx;
+        let name = name.to_owned();
+        offload(self, move |fs| fs.lookup(&ctx, parent, &name)).await
+    }
+
+    async fn async_getattr(
+        &self,
+        ctx: &Context,
+        inode: u64,
+        handle: Option<u64>,
+    ) -> io::Result<(stat64, Duration)> {
+        let ctx = *ctx;
+        offload(self, move |fs| fs.getattr(&ctx, inode, handle)).await
+    }
+
+    async fn async_setattr(
+        &self,
+        ctx: &Context,
+        inode: u64,
+        attr: stat64,
+        handle: Option<u64>,
+        valid: SetattrValid,
+    ) -> io::Result<(stat64, Duration)> {
+        self.setattr(ctx, inode, attr, handle, valid)
+    }
+
+    async fn async_open(
+        &self,
+        ctx: &Context,
+        inode: u64,
+        flags: u32,
+        fuse_flags: u32,
+    ) -> io::Result<(Option<u64>, OpenOptions)> {
+        let ctx = *ctx;
+        offload(self, move |fs| {
+            let (handle, options, _) = fs.open(&ctx, inode, flags, fuse_flags)?;
+            Ok((handle, options))
+        })
+        .await
+    }
+
+    async fn async_create(
+        &self,
+        ctx: &Context,
+        parent: u64,
+        name: &CStr,
+        args: CreateIn,
+    ) -> io::Result<(Entry, Option<u64>, OpenOptions)> {
+        let (entry, handle, options, _) = self.create(ctx, parent, name, args)?;
+        Ok((entry, handle, options))
+    }
+
+    async fn async_read(
+        &self,
+        ctx: &Context,
+        inode: u64,
+        handle: u64,
+        w: &mut (dyn AsyncZeroCopyWriter + Send),
+        size: u32,
+        offset: u64,
+        lock_owner: Option<u64>,
+        flags: u32,
+    ) -> io::Result<usize> {
+        let ctx = *ctx;
+        let data = offload(self, move |fs| {
+            let mut reply = Reply::default();
+            fs.read(
+                &ctx, inode, handle, &mut reply, size, offset, lock_owner, flags,
+            )?;
+            Ok(reply.0)
+        })
+        .await?;
+        w.write_all(&data)?;
+        Ok(data.len())
+    }
+
+    async fn async_write(
+        &self,
+        ctx: &Context,
+        inode: u64,
+        handle: u64,
+        r: &mut (dyn AsyncZeroCopyReader + Send),
+        size: u32,
+        offset: u64,
+        lock_owner: Option<u64>,
+        delayed_write: bool,
+        flags: u32,
+        fuse_flags: u32,
+    ) -> io::Result<usize> {
+        self.write(
+            ctx,
+            inode,
+            handle,
+            r,
+            size,
+            offset,
+            lock_owner,
+            delayed_write,
+            flags,
+            fuse_flags,
+        )
+    }
+
+    async fn async_fsync(
+        &self,
+        ctx: &Context,
+        inode: u64,
+        datasync: bool,
+        handle: u64,
+    ) -> io::Result<()> {
+        self.fsync(ctx, inode, datasync, handle)
+    }
+
+    async fn async_fallocate(
+        &self,
+        ctx: &Context,
+        inode: u64,
+        handle: u64,
+        mode: u32,
+        offset: u64,
+        length: u64,
+    ) -> io::Result<()> {
+        self.fallocate(ctx, inode, handle, mode, offset, length)
+    }
+
+    async fn async_fsyncdir(
+        &self,
+        ctx: &Context,
+        inode: u64,
+        datasync: bool,
+        handle: u64,
+    ) -> io::Result<()> {
+        self.fsyncdir(ctx, inode, datasync, handle)
+    }
+}
+
+/// The data a read handed off to the blocking pool produced, copied to the
+/// request's writer once it is done.
+#[derive(Default)]
+struct Reply(Vec<u8>);
+
+impl io::Write for Reply {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ZeroCopyWriter for Reply {
+    fn write_from(
+        &mut self,
+        _f: &mut dyn FileReadWriteVolatile,
+        _count: usize,
+        _off: u64,
+    ) -> io::Result<usize> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+
+    fn available_bytes(&self) -> usize {
+        usize::MAX
+    }
+}
+
+/// Answer the requests to `fs` read from `device`, with replies of up to
+/// `bufsize` bytes counted in `writes`, on `workers` threads until the
+/// filesystem is unmounted.
+///
+/// # Errors
+///
+/// Returns an error if a worker or its runtime cannot be started, a worker
+/// panics, or reading the device fails other than by the filesystem being
+/// unmounted.
+pub fn serve(
+    fs: &Arc<GitSnapFs>,
+    server: Arc<Server<Arc<GitSnapFs>>>,
+    device: &File,
+    bufsize: usize,
+    workers: usize,
+    writes: Arc<Writes>,
+) -> io::Result<()> {
+    let flags = OFlag::from_bits_truncate(fcntl(device, FcntlArg::F_GETFL)?);
+    fcntl(device, FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK))?;
+    let sink = File::options().write(true).open("/dev/null")?;
+    let shared = Arc::new(Served {
+        server,
+        device: device.as_raw_fd(),
+        sink: sink.as_raw_fd(),
+        bufsize,
+        writes,
+    });
+    let in_flight = Arc::new(Semaphore::new(MAX_IN_FLIGHT));
+    let workers = workers.max(1);
+    let blocking = MAX_IN_FLIGHT.div_ceil(workers);
+    thread::scope(|scope| {
+        let workers = (0..workers)
+            .map(|_| {
+                let fs = Arc::clone(fs);
+                thread::Builder::new()
+                    .name("gitsnapfs-async".into())
+                    .spawn_scoped(scope, || work(fs, &shared, &in_flight, blocking))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut result = Ok(());
+        for worker in workers {
+            let outcome = worker
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("a FUSE worker panicked")));
+            result = result.and(outcome);
+        }
+        result
+    })
+}
+
+/// Receive and answer requests to `fs` on this thread, on a runtime of its
+/// own with up to `blocking` threads for offloaded handlers, until the
+/// filesystem is unmounted.
+fn work(
+    fs: Arc<GitSnapFs>,
+    served: &Arc<Served>,
+    in_flight: &Arc<Semaphore>,
+    blocking: usize,
+) -> io::Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .max_blocking_threads(blocking)
+        .thread_name("gitsnapfs-blocking")
+        .build()?;
+    SERVING.with(|serving| *serving.borrow_mut() = Some(fs));
+    LocalSet::new().block_on(&runtime, async {
+        let device = AsyncFd::with_interest(served.device, DEVICE_READINESS)?;
+        receive(served, &device, in_flight).await
+    })
+}
+
+/// What every request task answers with.
+struct Served {
+    server: Arc<Server<Arc<GitSnapFs>>>,
+    device: RawFd,
+    /// `/dev/null`, where the server's own writes of a reply go; see [`answer`].
+    sink: RawFd,
+    bufsize: usize,
+    writes: Arc<Writes>,
+}
+
+/// Read requests from `device` and spawn a task local to this worker
+/// answering each.
+async fn receive(
+    served: &Arc<Served>,
+    device: &AsyncFd<RawFd>,
+    in_flight: &Arc<Semaphore>,
+) -> io::Result<()> {
+    loop {
+        let Ok(permit) = Arc::clone(in_flight).acquire_owned().await else {
+            return Ok(());
+        };
+        let mut message = vec![0u8; served.bufsize];
+        let len = loop {
+            let mut ready = device.ready(DEVICE_READINESS).await?;
+            let read = ready
+                .try_io(|device| nix::unistd::read(device, &mut message).map_err(io::Error::from));
+            match read {
+                Ok(Ok(len)) => break len,
+                // ENOENT: the request was interrupted before it was read.
+                Ok(Err(err)) if matches!(err.raw_os_error(), Some(libc::EINTR | libc::ENOENT)) => {}
+                Ok(Err(err)) if err.raw_os_error() == Some(libc::ENODEV) => return Ok(()),
+                Ok(Err(err)) => return Err(err),
+                // Not readable after all, or another worker read it first;
+                // wait for the next wakeup.
+                Err(_) => {}
+            }
+        };
+        message.truncate(len);
+        let served = Arc::clone(served);
+        spawn_local(async move {
+            answer(&served, message).await;
+            drop(permit);
+        });
+    }
+}
+
+/// Handle the request `message` and send the reply to the device.
+///
+/// The asynchronous server of fuse-backend-rs 0.13 cannot be trusted to
+/// send a reply itself: with an unbuffered writer, error replies go out a
+/// second time, garbled, and with a buffered one, most other replies never
+/// go out at all. So the writer is buffered and writes to [`Served::sink`],
+/// and the reply, which a buffered writer always assembles at the start of
+/// its buffer, is sent from there once the server is done.
+async fn answer(served: &Served, mut message: Vec<u8>) {
+    let started = Instant::now();
+    let mut buffer = vec![0u8; served.bufsize];
+    let reader = match Reader::<()>::from_fuse_buffer(FuseBuf::new(&mut message)) {
+        Ok(reader) => reader,
+        Err(err) => {
+            error!(?err, "wrapping FUSE request failed");
+            return;
+        }
+    };
+    let mut writer = match FuseDevWriter::<()>::new(served.sink, &mut buffer) {
+        Ok(writer) => writer,
+        Err(err) => {
+            error!(?err, "preparing FUSE reply failed");
+            return;
+        }
+    };
+    // Splitting off the empty tail is what makes a writer buffer.
+    if let Err(err) = writer.split_at(served.bufsize) {
+        error!(?err, "preparing FUSE reply failed");
+        return;
+    }
+    let handled = {
+        // SAFETY: `message` and `buffer` are owned here and outlive the future.
+        let handling = unsafe {
+            served
+                .server
+                .async_handle_message(reader, writer.into(), None, None)
+        };
+        handling.await
+    };
+    let len = match handled {
+        Ok(0) => return,
+        Ok(len) => len,
+        Err(err) => {
+            error!(?err, "handling FUSE message failed");
+            return;
+        }
+    };
+    // SAFETY: the device stays open while requests are served.
+    let device = unsafe { BorrowedFd::borrow_raw(served.device) };
+    match nix::unistd::write(device, &buffer[..len]) {
+        Ok(_) => served.writes.record(false, len, started.elapsed()),
+        // The request was interrupted and the kernel no longer waits for it.
+        Err(Errno::ENOENT) => {}
+        Err(err) => error!(?err, "sending FUSE reply failed"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use fuse_backend_rs::abi::fuse_abi::ROOT_ID;
+    use fuse_backend_rs::api::filesystem::FsOptions;
+    use gix::objs::tree;
+
+    use crate::repo::Repository;
+
+    #[test]
+    fn handlers_answer_on_the_runtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = gix::init_bare(dir.path()).unwrap();
+        let tree = repo
+            .write_object(&gix::objs::Tree {
+                entries: vec![tree::Entry {
+                    mode: tree::EntryKind::Blob.into(),
+                    filename: "README".into(),
+                    oid: repo.write_blob(b"hello\n").unwrap().detach(),
+                }],
+            })
+            .unwrap()
+            .detach();
+        let fs = Arc::new(GitSnapFs::new(Repository::open(dir.path()).unwrap()));
+        fs.init(FsOptions::all()).unwrap();
+        SERVING.with(|serving| *serving.borrow_mut() = Some(Arc::clone(&fs)));
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .max_blocking_threads(1)
+            .build()
+            .unwrap();
+        let ctx = Context::default();
+        runtime.block_on(async {
+            let trees = fs.async_lookup(&ctx, ROOT_ID, c"trees").await.unwrap();
+            let name = std::ffi::CString::new(tree.to_string()).unwrap();
+            let snapshot = fs.async_lookup(&ctx, trees.inode, &name).await.unwrap();
+            let readme = fs
+                .async_lookup(&ctx, snapshot.inode, c"README")
+                .await
+                .unwrap();
+            let (attr, _) = fs.async_getattr(&ctx, readme.inode, None).await.unwrap();
+            assert_eq!(attr.st_size, 6);
+            assert_eq!(
+                fs.async_lookup(&ctx, snapshot.inode, c"missing")
+                    .await
+                    .unwrap_err()
+                    .raw_os_error(),
+                Some(libc::ENOENT)
+            );
+        });
+    }
+}
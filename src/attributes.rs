@@ -0,0 +1,131 @@
+//! Git attribute evaluation against snapshot trees.
+//!
+//! `git archive` omits paths carrying the `export-ignore` attribute. The
+//! attributes come from `$GIT_DIR/info/attributes` plus every `.gitattributes`
+//! file *inside the archived tree*, so the result depends only on the tree and
+//! can be computed once per snapshot.
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use gix::attrs::search::{MetadataCollection, Outcome};
+use gix::attrs::{Search, StateRef};
+use gix::bstr::{BStr, ByteSlice};
+use gix::glob::pattern::Case;
+use gix::ObjectId;
+
+use crate::limits::{Budget, TreeLimits};
+
+const ATTRIBUTES_FILE: &[u8] = b".gitattributes";
+const EXPORT_IGNORE: &str = "export-ignore";
+
+/// Collect the paths below `tree` that `git archive` would leave out.
+///
+/// Only the top-most ignored path of a subtree is reported; everything below
+/// an ignored directory is implied.
+///
+/// # Errors
+///
+/// Returns an error if a tree or `.gitattributes` blob cannot be read, or the
+/// walk exceeds `limits`.
+pub fn export_ignored_paths(
+    repo: &gix::Repository,
+    tree: ObjectId,
+    limits: TreeLimits,
+) -> Result<BTreeSet<Vec<u8>>> {
+    let mut collection = MetadataCollection::default();
+    let mut buf = Vec::new();
+    let info = repo.common_dir().join("info").join("attributes");
+    let mut search = Search::new_globals([info], &mut buf, &mut collection)?;
+    let mut walker = Walker {
+        repo,
+        collection,
+        outcome: Outcome::default(),
+        ignored: BTreeSet::new(),
+        budget: Budget::new(limits),
+    };
+    walker.walk(&mut search, tree, &[], 0)?;
+    Ok(walker.ignored)
+}
+
+struct Walker<'a> {
+    repo: &'a gix::Repository,
+    collection: MetadataCollection,
+    outcome: Outcome,
+    ignored: BTreeSet<Vec<u8>>,
+    budget: Budget,
+}
+
+impl Walker<'_> {
+    fn walk(
+        &mut self,
+        search: &mut Search,
+        tree: ObjectId,
+        prefix: &[u8],
+        depth: usize,
+    ) -> Result<()> {
+        self.budget.descend(depth)?;
+        let tree = self.repo.find_tree(tree)?;
+        let mut entries = Vec::new();
+        let mut attributes_blob = None;
+        for entry in tree.iter() {
+            let entry = entry?;
+            self.budget.visit()?;
+            let name = entry.inner.filename.as_bytes();
+            if name == ATTRIBUTES_FILE && entry.inner.mode.is_blob() {
+                attributes_blob = Some(entry.inner.oid.to_owned());
+            }
+            entries.push((name.to_vec(), entry.inner.mode, entry.inner.oid.to_owned()));
+        }
+
+        let pushed = if let Some(blob) = attributes_blob {
+            let data = self.repo.find_blob(blob)?.detach().data;
+            let mut source = PathBuf::from(prefix.to_str_lossy().as_ref());
+            source.push(".gitattributes");
+            search.add_patterns_buffer(
+                &data,
+                source,
+                Some(Path::new("")),
+                &mut self.collection,
+                true,
+            );
+            true
+        } else {
+            false
+        };
+
+        for (name, mode, oid) in entries {
+            let mut path = prefix.to_vec();
+            if !path.is_empty() {
+                path.push(b'/');
+            }
+            path.extend_from_slice(&name);
+            if self.is_export_ignored(search, path.as_bstr(), mode.is_tree()) {
+                self.ignored.insert(path);
+            } else if mode.is_tree() {
+                self.walk(search, oid, &path, depth + 1)?;
+            }
+        }
+
+        if pushed {
+            search.pop_pattern_list();
+        }
+        Ok(())
+    }
+
+    fn is_export_ignored(&mut self, search: &Search, path: &BStr, is_dir: bool) -> bool {
+        self.outcome
+            .initialize_with_selection(&self.collection, [EXPORT_IGNORE]);
+        search.pattern_matching_relative_path(
+            path,
+            Case::Sensitive,
+            Some(is_dir),
+            &mut self.outcome,
+        );
+        self.outcome
+            .iter_selected()
+            .next()
+            .is_some_and(|m| matches!(m.assignment.state, StateRef::Set))
+    }
+}
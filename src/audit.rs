@@ -0,0 +1,172 @@
+//! Record of the content served, per caller (`--audit-log`).
+//!
+//! Teams exposing proprietary source on shared machines need to know who
+//! read what. Every successful read appends one JSON line naming the time,
+//! the calling uid and pid, the path the file was looked up by, the blob id
+//! and the byte range:
+//!
+//! ```text
+//! {"time":"2024-05-01T12:00:00.123Z","uid":1000,"pid":4242,"path":"/commits/<id>/src/main.rs","oid":"<blob id>","offset":0,"bytes":4096}
+//! ```
+//!
+//! The file is only ever appended to. Once it reaches its size limit it is
+//! renamed to `<file>.1`, older generations shift up to `<file>.<KEPT>`,
+//! and a new file is started. A record that cannot be written fails the
+//! read with `EIO`, so nothing is served unrecorded.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+use gix::ObjectId;
+use serde::Serialize;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use tracing::error;
+
+/// Rotated generations kept besides the current file.
+pub const KEPT: usize = 5;
+
+/// One read, as logged.
+#[derive(Debug)]
+pub struct Record<'a> {
+    pub uid: u32,
+    /// Calling process; 0 when the kernel does not say (for example for
+    /// reads on behalf of the page cache).
+    pub pid: i32,
+    /// Path below the mount root, lossily decoded; empty if the inode was
+    /// not reached by lookup.
+    pub path: &'a str,
+    pub oid: ObjectId,
+    pub offset: u64,
+    pub bytes: usize,
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    time: String,
+    uid: u32,
+    pid: i32,
+    path: &'a str,
+    oid: String,
+    offset: u64,
+    bytes: usize,
+}
+
+/// An append-only log file rotated by size.
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    max_size: u64,
+    /// The open file and its current size.
+    file: Mutex<(File, u64)>,
+}
+
+impl AuditLog {
+    /// Append to `path`, rotating it once it holds `max_size` bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened.
+    pub fn open(path: PathBuf, max_size: u64) -> io::Result<Self> {
+        let file = open_append(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_size,
+            file: Mutex::new((file, size)),
+        })
+    }
+
+    /// Append `record`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `EIO` if the record could not be written.
+    pub fn record(&self, record: &Record<'_>) -> io::Result<()> {
+        let time = OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .unwrap_or_default();
+        let line = Line {
+            time,
+            uid: record.uid,
+            pid: record.pid,
+            path: record.path,
+            oid: record.oid.to_string(),
+            offset: record.offset,
+            bytes: record.bytes,
+        };
+        let mut line = serde_json::to_vec(&line).map_err(io::Error::other)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        let written = self
+            .rotate_if_full(&mut file)
+            .and_then(|()| file.0.write_all(&line));
+        if let Err(err) = written {
+            error!(%err, "failed to write audit record to {}", self.path.display());
+            return Err(io::Error::from_raw_os_error(libc::EIO));
+        }
+        file.1 += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate_if_full(&self, file: &mut (File, u64)) -> io::Result<()> {
+        if file.1 < self.max_size {
+            return Ok(());
+        }
+        for generation in (1..KEPT).rev() {
+            let from = generation_path(&self.path, generation);
+            if from.exists() {
+                fs::rename(&from, generation_path(&self.path, generation + 1))?;
+            }
+        }
+        fs::rename(&self.path, generation_path(&self.path, 1))?;
+        *file = (open_append(&self.path)?, 0);
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// `<path>.<generation>`.
+fn generation_path(path: &Path, generation: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{generation}"));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn appends_json_lines_and_rotates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let log = AuditLog::open(path.clone(), 200).unwrap();
+        let record = Record {
+            uid: 1000,
+            pid: 42,
+            path: "/commits/abc/README",
+            oid: ObjectId::null(gix::hash::Kind::Sha1),
+            offset: 0,
+            bytes: 6,
+        };
+        for _ in 0..3 {
+            log.record(&record).unwrap();
+        }
+
+        let current = fs::read_to_string(&path).unwrap();
+        let rotated = fs::read_to_string(generation_path(&path, 1)).unwrap();
+        assert_eq!(current.lines().count() + rotated.lines().count(), 3);
+        let line: serde_json::Value =
+            serde_json::from_str(current.lines().next().unwrap()).unwrap();
+        assert_eq!(line["uid"], 1000);
+        assert_eq!(line["path"], "/commits/abc/README");
+        assert_eq!(line["bytes"], 6);
+        assert!(line["time"].as_str().unwrap().ends_with('Z'));
+    }
+}
@@ -0,0 +1,215 @@
+//! Path-level authorization through an external hook (`--authz-cmd`,
+//! `--authz-socket`).
+//!
+//! Shared mounts sometimes need ACLs finer than the mount's permissions,
+//! kept in a policy service rather than in this program. Each lookup and
+//! read is put to the hook as the calling uid, the operation and the path
+//! below the mount root; anything but an explicit allow refuses the request
+//! with `EACCES`.
+//!
+//! A command is run with the three as arguments and allows by exiting with
+//! status 0. A socket gets one request line per question over a Unix stream
+//! connection:
+//!
+//! ```text
+//! CHECK <uid> <lookup|read> <path>\n
+//! ```
+//!
+//! answered by `ALLOW\n` or `DENY\n`. Decisions are cached for
+//! [`DECISION_TTL`], and dropped on `SIGHUP`.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use tracing::{debug, warn};
+
+/// How long a decision is reused before the hook is asked again.
+pub const DECISION_TTL: Duration = Duration::from_secs(30);
+
+/// Decisions remembered at most; the cache starts over when it is full.
+const MAX_DECISIONS: usize = 65_536;
+
+/// Where authorization decisions come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Hook {
+    /// A program run with `<uid> <op> <path>`, allowing by exiting with 0.
+    Command(PathBuf),
+    /// A Unix socket answering `CHECK` lines.
+    Socket(PathBuf),
+}
+
+/// Operation being authorized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Op {
+    /// Resolving a name in a directory.
+    Lookup,
+    /// Reading a file's content.
+    Read,
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Lookup => "lookup",
+            Self::Read => "read",
+        })
+    }
+}
+
+type Question = (u32, Op, Vec<u8>);
+
+/// Asks a [`Hook`] and caches its answers.
+#[derive(Debug)]
+pub struct Authorizer {
+    hook: Hook,
+    decisions: Mutex<HashMap<Question, (bool, Instant)>>,
+}
+
+impl Authorizer {
+    /// Authorize requests by asking `hook`.
+    #[must_use]
+    pub fn new(hook: Hook) -> Self {
+        Self {
+            hook,
+            decisions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Allow `uid` to perform `op` on `path`, a slash-separated path below
+    /// the mount root starting with `/`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `EACCES` if the hook denies the request or cannot be asked.
+    pub fn check(&self, uid: u32, op: Op, path: &[u8]) -> io::Result<()> {
+        let question = (uid, op, path.to_vec());
+        let now = Instant::now();
+        let cached = self
+            .lock()
+            .get(&question)
+            .filter(|(_, at)| now.duration_since(*at) < DECISION_TTL)
+            .map(|&(allowed, _)| allowed);
+        let allowed = if let Some(allowed) = cached {
+            allowed
+        } else {
+            let allowed = self.ask(uid, op, path).unwrap_or_else(|err| {
+                warn!(%err, uid, %op, "authorization hook failed, denying");
+                false
+            });
+            let mut decisions = self.lock();
+            if decisions.len() >= MAX_DECISIONS {
+                decisions.clear();
+            }
+            decisions.insert(question, (allowed, now));
+            allowed
+        };
+        if allowed {
+            Ok(())
+        } else {
+            debug!(uid, %op, path = %String::from_utf8_lossy(path), "denied");
+            Err(io::Error::from_raw_os_error(libc::EACCES))
+        }
+    }
+
+    /// Forget every cached decision, so the hook is asked again.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn ask(&self, uid: u32, op: Op, path: &[u8]) -> io::Result<bool> {
+        match &self.hook {
+            Hook::Command(program) => {
+                let status = Command::new(program)
+                    .arg(uid.to_string())
+                    .arg(op.to_string())
+                    .arg(OsStr::from_bytes(path))
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::null())
+                    .status()?;
+                Ok(status.success())
+            }
+            Hook::Socket(socket) => {
+                let mut conn = BufReader::new(UnixStream::connect(socket)?);
+                conn.get_mut().write_all(&encode_request(uid, op, path)?)?;
+                let mut reply = String::new();
+                conn.read_line(&mut reply)?;
+                match reply.trim_end() {
+                    "ALLOW" => Ok(true),
+                    "DENY" => Ok(false),
+                    other => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unexpected authorization reply {other:?}"),
+                    )),
+                }
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<Question, (bool, Instant)>> {
+        self.decisions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The `CHECK` line for a question; paths containing a newline cannot be sent.
+fn encode_request(uid: u32, op: Op, path: &[u8]) -> io::Result<Vec<u8>> {
+    if path.contains(&b'\n') {
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+    let mut line = format!("CHECK {uid} {op} ").into_bytes();
+    line.extend_from_slice(path);
+    line.push(b'\n');
+    Ok(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn asks_the_socket_once_per_question() {
+        use std::os::unix::net::UnixListener;
+        use std::thread;
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("authz.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let server = thread::spawn(move || {
+            let mut asked = Vec::new();
+            for stream in listener.incoming().take(2) {
+                let mut conn = BufReader::new(stream.unwrap());
+                let mut line = String::new();
+                conn.read_line(&mut line).unwrap();
+                let reply = if line.ends_with("/public\n") {
+                    "ALLOW\n"
+                } else {
+                    "DENY\n"
+                };
+                conn.get_mut().write_all(reply.as_bytes()).unwrap();
+                asked.push(line);
+            }
+            asked
+        });
+
+        let authorizer = Authorizer::new(Hook::Socket(socket));
+        authorizer.check(1000, Op::Lookup, b"/public").unwrap();
+        let denied = authorizer.check(1000, Op::Read, b"/secret").unwrap_err();
+        assert_eq!(denied.raw_os_error(), Some(libc::EACCES));
+        // Answered from the cache; the server has stopped listening.
+        authorizer.check(1000, Op::Lookup, b"/public").unwrap();
+        assert_eq!(
+            server.join().unwrap(),
+            ["CHECK 1000 lookup /public\n", "CHECK 1000 read /secret\n"]
+        );
+        assert!(encode_request(0, Op::Read, b"/a\nb").is_err());
+    }
+}
@@ -0,0 +1,322 @@
+//! Support for mounting Git bundle files directly.
+//!
+//! A bundle is a short textual header (format version, prerequisites, refs)
+//! followed by a regular packfile. We index the pack into a bare repository
+//! under the user's cache directory once, keyed by the bundle's identity, and
+//! reuse that repository for every later mount of the same file.
+//!
+//! `--pack-stdin` goes through the same indexing for a pack or bundle
+//! streamed on standard input, into a [`ScratchRepo`] that only lives as long
+//! as the mount.
+
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::time::UNIX_EPOCH;
+
+use anyhow::{bail, Context, Result};
+use gix::bstr::ByteSlice;
+use gix::ObjectId;
+
+const SIGNATURE_V2: &[u8] = b"# v2 git bundle\n";
+const SIGNATURE_V3: &[u8] = b"# v3 git bundle\n";
+
+/// Parsed bundle header.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Header {
+    /// Commits the receiving repository must already have.
+    pub prerequisites: Vec<ObjectId>,
+    /// References recorded in the bundle, as `(full ref name, target)`.
+    pub refs: Vec<(String, ObjectId)>,
+}
+
+/// Check whether `path` is a regular file starting with a bundle signature.
+#[must_use]
+pub fn is_bundle(path: &Path) -> bool {
+    if !path.is_file() {
+        return false;
+    }
+    let mut signature = [0u8; SIGNATURE_V2.len()];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut signature))
+        .is_ok()
+        && (signature == SIGNATURE_V2 || signature == SIGNATURE_V3)
+}
+
+/// Parse the bundle header from `reader`, leaving it positioned at the start of the pack.
+///
+/// # Errors
+///
+/// Returns an error if the signature is unknown or a header line is malformed.
+pub fn read_header(reader: &mut impl BufRead) -> Result<Header> {
+    let mut line = Vec::new();
+    reader.read_until(b'\n', &mut line)?;
+    if line != SIGNATURE_V2 && line != SIGNATURE_V3 {
+        bail!("not a git bundle (unknown signature)");
+    }
+    let mut header = Header::default();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            bail!("bundle header ended before the pack data");
+        }
+        let text = line.strip_suffix(b"\n").unwrap_or(&line);
+        if text.is_empty() {
+            return Ok(header);
+        }
+        if let Some(capability) = text.strip_prefix(b"@") {
+            if capability != b"object-format=sha1" && !capability.starts_with(b"filter=") {
+                bail!(
+                    "unsupported bundle capability '{}'",
+                    capability.to_str_lossy()
+                );
+            }
+            continue;
+        }
+        if let Some(prerequisite) = text.strip_prefix(b"-") {
+            let hex = prerequisite
+                .split(|b| *b == b' ')
+                .next()
+                .unwrap_or_default();
+            header.prerequisites.push(ObjectId::from_hex(hex)?);
+            continue;
+        }
+        let (hex, name) = text
+            .split_once_str(" ")
+            .with_context(|| format!("malformed bundle ref line '{}'", text.to_str_lossy()))?;
+        let name = name
+            .to_str()
+            .context("bundle ref name is not valid UTF-8")?;
+        header
+            .refs
+            .push((name.to_owned(), ObjectId::from_hex(hex)?));
+    }
+}
+
+/// Return a bare repository holding the contents of the bundle at `path`.
+///
+/// The repository lives in the cache directory and is created on first use.
+///
+/// # Errors
+///
+/// Returns an error if the bundle cannot be read, depends on prerequisite
+/// commits, or the cache repository cannot be written.
+pub fn unbundle_cached(path: &Path) -> Result<PathBuf> {
+    let target = cache_dir()?.join(format!("{:016x}.git", bundle_key(path)?));
+    if target.is_dir() {
+        return Ok(target);
+    }
+    let parent = target
+        .parent()
+        .context("bundle cache path has no parent directory")?;
+    fs::create_dir_all(parent)
+        .with_context(|| format!("failed to create bundle cache {}", parent.display()))?;
+    let staging = target.with_extension(format!("tmp-{}", std::process::id()));
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    unbundle_into(path, &staging).inspect_err(|_| {
+        let _ = fs::remove_dir_all(&staging);
+    })?;
+    if let Err(err) = fs::rename(&staging, &target) {
+        // Another process may have won the race; its result is just as good.
+        let _ = fs::remove_dir_all(&staging);
+        if !target.is_dir() {
+            return Err(err).context("failed to move unbundled repository into place");
+        }
+    }
+    Ok(target)
+}
+
+fn unbundle_into(path: &Path, repo_dir: &Path) -> Result<()> {
+    let file =
+        File::open(path).with_context(|| format!("failed to open bundle {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let header = read_header(&mut reader)?;
+    if !header.prerequisites.is_empty() {
+        bail!(
+            "bundle {} requires {} prerequisite commit(s) and cannot be mounted on its own",
+            path.display(),
+            header.prerequisites.len()
+        );
+    }
+    index_pack(&mut reader, repo_dir).context("failed to index bundle pack")?;
+    write_refs(repo_dir, &header.refs)
+}
+
+/// A bare repository in the temporary directory holding a pack received on
+/// a stream, removed again when dropped.
+#[derive(Debug)]
+pub struct ScratchRepo {
+    path: PathBuf,
+    tips: Vec<ObjectId>,
+}
+
+impl ScratchRepo {
+    /// Index the packfile (as written by `git pack-objects --stdout`) or
+    /// bundle read from `reader` into a new repository.
+    ///
+    /// Bundles bring their references along. A bare pack has none, so `HEAD`
+    /// is detached at the newest commit no other commit in the pack descends
+    /// from; every such tip is reported by [`Self::tips`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stream is neither a complete pack nor a bundle
+    /// without prerequisites, for example a thin pack.
+    pub fn from_reader(mut reader: impl BufRead) -> Result<Self> {
+        let path = std::env::temp_dir().join(format!("gitsnapfs-pack-{}", std::process::id()));
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+        let mut scratch = Self {
+            path,
+            tips: Vec::new(),
+        };
+        if reader.fill_buf()?.starts_with(b"# v") {
+            let header = read_header(&mut reader)?;
+            if !header.prerequisites.is_empty() {
+                bail!(
+                    "bundle requires {} prerequisite commit(s) and cannot be mounted on its own",
+                    header.prerequisites.len()
+                );
+            }
+            index_pack(&mut reader, &scratch.path).context("failed to index bundle pack")?;
+            write_refs(&scratch.path, &header.refs)?;
+            scratch.tips = header.refs.iter().map(|(_, id)| *id).collect();
+        } else {
+            let index = index_pack(&mut reader, &scratch.path).context("failed to index pack")?;
+            scratch.tips = pack_tips(&scratch.path, &index)?;
+            if let Some(newest) = scratch.tips.first() {
+                fs::write(scratch.path.join("HEAD"), format!("{newest}\n"))?;
+            }
+        }
+        Ok(scratch)
+    }
+
+    /// Directory of the repository.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Commits the stream was sent for, newest first for bare packs.
+    #[must_use]
+    pub fn tips(&self) -> &[ObjectId] {
+        &self.tips
+    }
+}
+
+impl Drop for ScratchRepo {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Write the pack read from `reader` into a new bare repository at
+/// `repo_dir`, returning the path of its index.
+fn index_pack(reader: &mut dyn BufRead, repo_dir: &Path) -> Result<PathBuf> {
+    gix::init_bare(repo_dir)
+        .with_context(|| format!("failed to initialise {}", repo_dir.display()))?;
+    let pack_dir = repo_dir.join("objects").join("pack");
+    let interrupt = AtomicBool::new(false);
+    let outcome = gix_pack::Bundle::write_to_directory(
+        reader,
+        Some(&pack_dir),
+        &mut gix::progress::Discard,
+        &interrupt,
+        None::<gix::objs::find::Never>,
+        gix_pack::bundle::write::Options::default(),
+    )?;
+    if let Some(keep) = outcome.keep_path {
+        fs::remove_file(keep)?;
+    }
+    outcome.index_path.context("pack was not written")
+}
+
+/// Commits in the pack at `index` that are no other packed commit's parent,
+/// newest first.
+fn pack_tips(repo_dir: &Path, index: &Path) -> Result<Vec<ObjectId>> {
+    let repo = gix::open(repo_dir)?;
+    let index = gix_pack::index::File::at(index, repo.object_hash())?;
+    let mut commits = Vec::new();
+    let mut parents = std::collections::HashSet::new();
+    for entry in index.iter() {
+        if repo.find_header(entry.oid)?.kind() != gix::object::Kind::Commit {
+            continue;
+        }
+        let commit = repo.find_commit(entry.oid)?;
+        parents.extend(commit.parent_ids().map(gix::Id::detach));
+        commits.push((commit.time()?.seconds, entry.oid));
+    }
+    commits.retain(|(_, id)| !parents.contains(id));
+    commits.sort_by(|a, b| b.cmp(a));
+    Ok(commits.into_iter().map(|(_, id)| id).collect())
+}
+
+fn write_refs(repo_dir: &Path, refs: &[(String, ObjectId)]) -> Result<()> {
+    let mut head = None;
+    for (name, id) in refs {
+        if name == "HEAD" {
+            head = Some(format!("{id}\n"));
+            continue;
+        }
+        gix::validate::reference::name(name.as_bytes().as_bstr())
+            .with_context(|| format!("bundle contains invalid ref name '{name}'"))?;
+        let ref_path = repo_dir.join(name);
+        if let Some(dir) = ref_path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&ref_path, format!("{id}\n"))?;
+        if head.is_none() && name.starts_with("refs/heads/") {
+            head = Some(format!("ref: {name}\n"));
+        }
+    }
+    if let Some(head) = head {
+        fs::write(repo_dir.join("HEAD"), head)?;
+    }
+    Ok(())
+}
+
+/// Identify a bundle by location, size and modification time.
+fn bundle_key(path: &Path) -> Result<u64> {
+    let canonical = fs::canonicalize(path)
+        .with_context(|| format!("failed to resolve bundle path {}", path.display()))?;
+    let metadata = fs::metadata(&canonical)?;
+    let mtime = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    canonical.hash(&mut hasher);
+    metadata.len().hash(&mut hasher);
+    mtime.hash(&mut hasher);
+    Ok(hasher.finish())
+}
+
+fn cache_dir() -> Result<PathBuf> {
+    Ok(crate::settings::cache_dir()
+        .context("cannot cache unbundled repositories")?
+        .join("bundles"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_v2_header() {
+        let data = b"# v2 git bundle\n\
+            -0123456789abcdef0123456789abcdef01234567 base\n\
+            89abcdef0123456789abcdef0123456789abcdef refs/heads/main\n\
+            \nPACK";
+        let mut reader = &data[..];
+        let header = read_header(&mut reader).unwrap();
+        assert_eq!(header.prerequisites.len(), 1);
+        assert_eq!(header.refs.len(), 1);
+        assert_eq!(header.refs[0].0, "refs/heads/main");
+        assert_eq!(reader, b"PACK");
+    }
+}
@@ -0,0 +1,358 @@
+//! In-memory blob cache with a fixed byte budget, and the `--preload` walk
+//! that fills it.
+//!
+//! The cache never evicts: once the budget is used up further blobs are
+//! simply read from the object database on every access. That keeps lookups
+//! lock-cheap and makes memory use predictable, which is what short-lived CI
+//! mounts want.
+//!
+//! Values derived from objects (diffs, checksums and the like) are kept in
+//! a [`Memo`] instead, which holds a fixed number of entries and drops the
+//! least recently used; generated files, which can be large, in a
+//! [`ContentMemo`], which does the same within a byte budget.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, RwLock};
+
+use clru::{CLruCache, WeightScale};
+
+use anyhow::Result;
+use gix::object::tree::EntryKind;
+use gix::object::Kind;
+use gix::ObjectId;
+use tracing::{info, warn};
+
+/// Decoded blob contents keyed by object id.
+#[derive(Debug)]
+pub struct BlobCache {
+    budget: usize,
+    state: RwLock<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    blobs: HashMap<ObjectId, Arc<[u8]>>,
+    used: usize,
+}
+
+/// Summary of a [`preload`] run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PreloadStats {
+    /// Objects visited, of any kind.
+    pub objects: usize,
+    /// Blobs that were stored in the cache.
+    pub cached_blobs: usize,
+    /// Bytes held by the cache afterwards.
+    pub cached_bytes: usize,
+}
+
+impl BlobCache {
+    /// Create an empty cache holding at most `budget` bytes of blob data.
+    #[must_use]
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            state: RwLock::new(State::default()),
+        }
+    }
+
+    /// Cached contents of `id`, if present.
+    #[must_use]
+    pub fn get(&self, id: &ObjectId) -> Option<Arc<[u8]>> {
+        self.state
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .blobs
+            .get(id)
+            .cloned()
+    }
+
+    /// Store `data` for `id` if it fits the remaining budget. Returns whether it was stored.
+    pub fn insert(&self, id: ObjectId, data: Arc<[u8]>) -> bool {
+        let mut state = self
+            .state
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if state.blobs.contains_key(&id) {
+            return true;
+        }
+        let Some(used) = state.used.checked_add(data.len()) else {
+            return false;
+        };
+        if used > self.budget {
+            return false;
+        }
+        state.used = used;
+        state.blobs.insert(id, data);
+        true
+    }
+
+    /// Drop every cached blob.
+    pub fn clear(&self) {
+        *self
+            .state
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = State::default();
+    }
+
+    /// Bytes currently held.
+    #[must_use]
+    pub fn used(&self) -> usize {
+        self.state
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .used
+    }
+}
+
+/// Values derived from immutable objects, holding at most a fixed number
+/// of entries and evicting the least recently used.
+#[derive(Debug)]
+pub struct Memo<K: Hash + Eq, V> {
+    entries: Mutex<CLruCache<K, V>>,
+}
+
+impl<K: Clone + Hash + Eq, V: Clone> Memo<K, V> {
+    /// Create an empty memo holding at most `capacity` entries (at least one).
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(CLruCache::new(
+                NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN),
+            )),
+        }
+    }
+
+    /// The value remembered for `key`, marking it recently used.
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.lock().get(key).cloned()
+    }
+
+    /// Remember `value` for `key`, evicting the least recently used entry if full.
+    pub fn insert(&self, key: K, value: V) {
+        self.lock().put(key, value);
+    }
+
+    /// Remember every entry of `entries`, as [`Memo::insert`] does.
+    pub fn extend(&self, entries: impl IntoIterator<Item = (K, V)>) {
+        let mut memo = self.lock();
+        for (key, value) in entries {
+            memo.put(key, value);
+        }
+    }
+
+    /// Forget every entry.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CLruCache<K, V>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// Generated file contents, holding at most a fixed number of bytes and
+/// evicting the least recently used.
+#[derive(Debug)]
+pub struct ContentMemo<K: Hash + Eq> {
+    entries: Mutex<CLruCache<K, Arc<[u8]>, RandomState, ByLength>>,
+}
+
+/// Weighs contents by their length.
+#[derive(Debug)]
+struct ByLength;
+
+impl<K> WeightScale<K, Arc<[u8]>> for ByLength {
+    fn weight(&self, _key: &K, value: &Arc<[u8]>) -> usize {
+        value.len()
+    }
+}
+
+impl<K: Clone + Hash + Eq> ContentMemo<K> {
+    /// Create an empty memo holding at most `budget` bytes.
+    #[must_use]
+    pub fn new(budget: usize) -> Self {
+        Self {
+            entries: Mutex::new(CLruCache::with_scale(
+                NonZeroUsize::new(budget).unwrap_or(NonZeroUsize::MIN),
+                ByLength,
+            )),
+        }
+    }
+
+    /// The content remembered for `key`, marking it recently used.
+    pub fn get(&self, key: &K) -> Option<Arc<[u8]>> {
+        self.lock().get(key).cloned()
+    }
+
+    /// Remember `content` for `key`, evicting the least recently used
+    /// entries to make room; content larger than the budget is not kept.
+    pub fn insert(&self, key: K, content: Arc<[u8]>) {
+        let _ = self.lock().put_with_weight(key, content);
+    }
+
+    /// Forget every entry.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CLruCache<K, Arc<[u8]>, RandomState, ByLength>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// Walk every object reachable from the repository's references, decoding
+/// commits and trees and storing blobs in `cache` until its budget is spent.
+/// With `verify`, blobs whose content does not match their id are not cached;
+/// blobs above `max_file_size` are not even decoded.
+///
+/// # Errors
+///
+/// Returns an error if the references cannot be listed. Unreadable objects
+/// are skipped with a warning; they will fail again, visibly, when read
+/// through the mount.
+pub fn preload(
+    repo: &gix::Repository,
+    cache: &BlobCache,
+    verify: bool,
+    max_file_size: Option<u64>,
+) -> Result<PreloadStats> {
+    let mut stats = PreloadStats::default();
+    let mut pending: Vec<(ObjectId, Option<Kind>)> = Vec::new();
+    for reference in repo.references()?.all()? {
+        let Ok(mut reference) = reference else {
+            continue;
+        };
+        if let Ok(id) = reference.follow_to_object() {
+            pending.push((id.detach(), None));
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut budget_exhausted = false;
+    while let Some((id, kind)) = pending.pop() {
+        if !seen.insert(id) {
+            continue;
+        }
+        stats.objects += 1;
+        if kind == Some(Kind::Blob)
+            && max_file_size.is_some_and(|limit| {
+                repo.find_header(id)
+                    .is_ok_and(|header| header.size() > limit)
+            })
+        {
+            continue;
+        }
+        let object = match repo.find_object(id) {
+            Ok(object) => object,
+            Err(err) => {
+                warn!(%id, ?kind, %err, "preload skipped unreadable object");
+                continue;
+            }
+        };
+        match object.kind {
+            Kind::Commit => {
+                let commit = object.into_commit();
+                if let Ok(tree) = commit.tree_id() {
+                    pending.push((tree.detach(), Some(Kind::Tree)));
+                }
+                pending.extend(
+                    commit
+                        .parent_ids()
+                        .map(|parent| (parent.detach(), Some(Kind::Commit))),
+                );
+            }
+            Kind::Tree => {
+                let tree = object.into_tree();
+                for entry in tree.iter().flatten() {
+                    let kind = match entry.inner.mode.kind() {
+                        EntryKind::Tree => Kind::Tree,
+                        EntryKind::Blob | EntryKind::BlobExecutable | EntryKind::Link => {
+                            if budget_exhausted {
+                                continue;
+                            }
+                            Kind::Blob
+                        }
+                        EntryKind::Commit => continue,
+                    };
+                    pending.push((entry.inner.oid.to_owned(), Some(kind)));
+                }
+            }
+            Kind::Blob => {
+                if verify && !crate::repo::blob_matches(repo, id, &object.data) {
+                    warn!(%id, "preload skipped blob whose content does not match its id");
+                    continue;
+                }
+                let data: Arc<[u8]> = Arc::from(object.detach().data);
+                if cache.insert(id, data) {
+                    stats.cached_blobs += 1;
+                } else {
+                    budget_exhausted = true;
+                }
+            }
+            Kind::Tag => {
+                if let Ok(target) = object.into_tag().target_id() {
+                    pending.push((target.detach(), None));
+                }
+            }
+        }
+    }
+    stats.cached_bytes = cache.used();
+    if budget_exhausted {
+        info!(
+            "preload stopped caching blobs after {} bytes; raise --max-memory to cache more",
+            stats.cached_bytes
+        );
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn respects_budget() {
+        let cache = BlobCache::new(8);
+        let id = |byte| ObjectId::from_bytes_or_panic(&[byte; 20]);
+        assert!(cache.insert(id(1), Arc::from(&b"12345"[..])));
+        assert!(!cache.insert(id(2), Arc::from(&b"6789"[..])));
+        assert!(cache.insert(id(3), Arc::from(&b"678"[..])));
+        assert_eq!(cache.used(), 8);
+        assert_eq!(cache.get(&id(1)).as_deref(), Some(&b"12345"[..]));
+        assert!(cache.get(&id(2)).is_none());
+    }
+
+    #[test]
+    fn memo_evicts_least_recently_used() {
+        let memo = Memo::new(2);
+        memo.insert(1, "one");
+        memo.insert(2, "two");
+        assert_eq!(memo.get(&1), Some("one"));
+        memo.insert(3, "three");
+        assert_eq!(memo.get(&2), None);
+        assert_eq!(memo.get(&1), Some("one"));
+        assert_eq!(memo.get(&3), Some("three"));
+    }
+
+    #[test]
+    fn content_memo_stays_within_its_budget() {
+        let memo = ContentMemo::new(12);
+        memo.insert(1, Arc::from(&b"abcd"[..]));
+        memo.insert(2, Arc::from(&b"efgh"[..]));
+        assert!(memo.get(&1).is_some());
+        memo.insert(3, Arc::from(&b"ijkl"[..]));
+        assert!(memo.get(&2).is_none());
+        assert_eq!(memo.get(&1).as_deref(), Some(&b"abcd"[..]));
+        memo.insert(4, Arc::from(&[0; 16][..]));
+        assert!(memo.get(&4).is_none());
+        assert!(memo.get(&3).is_some());
+    }
+}
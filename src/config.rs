@@ -0,0 +1,385 @@
+//! Runtime configuration for the filesystem, independent of how it was supplied.
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use gix::bstr::ByteSlice;
+use gix::glob::wildmatch;
+use gix::ObjectId;
+
+use crate::authz::Hook;
+use crate::limits::TreeLimits;
+use crate::releases::ReleasePattern;
+use crate::submodule::Checkout;
+use crate::synth::SyntheticFile;
+
+/// What the synthetic `.git` file in each commit snapshot points at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FakeDotGit {
+    /// `gitdir:` names the repository backing the mount, so `git` commands work.
+    Repo,
+    /// `gitdir:` names `/dev/null`; enough for tools that only check for presence.
+    Stub,
+}
+
+/// Order in which directory entries are returned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SortOrder {
+    /// Tree order for snapshots and reference database order for refs.
+    #[default]
+    Git,
+    /// Byte-wise by name.
+    Name,
+    /// By modification time, ties broken by name.
+    Mtime,
+}
+
+/// Size reported for snapshot directories.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum DirSize {
+    /// Always 0, like most synthetic filesystems.
+    #[default]
+    Zero,
+    /// Number of entries in the backing tree.
+    Entries,
+    /// Size of the backing tree object in bytes.
+    TreeBytes,
+}
+
+/// Globs limiting which branches and tags are served (`--branch-filter`,
+/// `--tag-filter`). An empty list lets every name of its kind through;
+/// other references are never filtered.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RefFilter {
+    /// Patterns for names below `refs/heads/`, such as `release/*`.
+    pub branches: Vec<String>,
+    /// Patterns for names below `refs/tags/`.
+    pub tags: Vec<String>,
+}
+
+impl RefFilter {
+    /// Whether the reference with full name `name` is served. Patterns
+    /// match the short name as `git branch --list` does, so `*` also
+    /// matches `/`.
+    #[must_use]
+    pub fn allows(&self, name: &[u8]) -> bool {
+        let (patterns, short) = if let Some(short) = name.strip_prefix(b"refs/heads/") {
+            (&self.branches, short)
+        } else if let Some(short) = name.strip_prefix(b"refs/tags/") {
+            (&self.tags, short)
+        } else {
+            return true;
+        };
+        patterns.is_empty()
+            || patterns.iter().any(|pattern| {
+                wildmatch(
+                    pattern.as_bytes().as_bstr(),
+                    short.as_bstr(),
+                    wildmatch::Mode::empty(),
+                )
+            })
+    }
+}
+
+/// Permission bits forced on the snapshot paths matching a glob
+/// (`--mode-override`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModeOverride {
+    /// Pattern for paths below the snapshot root, as in `.gitattributes`:
+    /// without a `/` it matches the last component at any depth, and `*`
+    /// does not match `/` while `**` does.
+    pub pattern: String,
+    /// Permission bits, such as `0o755`.
+    pub mode: u32,
+}
+
+/// Parse a `--mode-override` value of the form `<glob>=<octal mode>`.
+///
+/// # Errors
+///
+/// Returns an error if the `=` or the glob is missing, or the mode is not
+/// an octal number up to `7777`.
+pub fn parse_mode_override(input: &str) -> Result<ModeOverride> {
+    let Some((pattern, mode)) = input.rsplit_once('=') else {
+        bail!("expected <glob>=<mode>, got '{input}'");
+    };
+    if pattern.is_empty() {
+        bail!("expected <glob>=<mode>, got '{input}'");
+    }
+    Ok(ModeOverride {
+        pattern: pattern.to_owned(),
+        mode: parse_mode(mode)?,
+    })
+}
+
+/// Parse a file mode given in octal, such as `755`.
+///
+/// # Errors
+///
+/// Returns an error if `input` is not an octal number up to `7777`.
+pub fn parse_mode(input: &str) -> Result<u32> {
+    u32::from_str_radix(input, 8)
+        .ok()
+        .filter(|&mode| mode <= 0o7777)
+        .with_context(|| format!("invalid octal mode '{input}'"))
+}
+
+/// The `--mode-override` rules, in the order given; the last one matching
+/// a path wins, as in `.gitattributes`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModeOverrides {
+    pub rules: Vec<ModeOverride>,
+}
+
+impl ModeOverrides {
+    /// Whether there are no rules, so snapshots keep the stored modes.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Permission bits for the file or directory at slash-separated `path`
+    /// below the snapshot root, if a rule matches it.
+    #[must_use]
+    pub fn mode_for(&self, path: &[u8]) -> Option<u32> {
+        let basename = path.rsplit(|&b| b == b'/').next().unwrap_or(path);
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| {
+                let pattern = rule.pattern.trim_start_matches('/');
+                let subject = if rule.pattern.contains('/') {
+                    path
+                } else {
+                    basename
+                };
+                wildmatch(
+                    pattern.as_bytes().as_bstr(),
+                    subject.as_bstr(),
+                    wildmatch::Mode::NO_MATCH_SLASH_LITERAL,
+                )
+            })
+            .map(|rule| rule.mode)
+    }
+
+    /// The distinct modes of the rules, in order of first use.
+    #[must_use]
+    pub fn modes(&self) -> Vec<u32> {
+        let mut modes = Vec::new();
+        for rule in &self.rules {
+            if !modes.contains(&rule.mode) {
+                modes.push(rule.mode);
+            }
+        }
+        modes
+    }
+}
+
+/// Options that change what the mounted snapshots look like.
+#[derive(Debug, Clone, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct FsConfig {
+    /// Hide paths carrying the `export-ignore` attribute from commit snapshots,
+    /// mirroring what `git archive` would produce.
+    pub respect_export_ignore: bool,
+    /// Files synthesized at the root of every commit snapshot.
+    pub synthetic_files: Vec<SyntheticFile>,
+    /// Expose a `.git` file at the root of every commit snapshot.
+    pub fake_dotgit: Option<FakeDotGit>,
+    /// Upper bound on file data served per second, in bytes.
+    pub max_read_bandwidth: Option<u64>,
+    /// Upper bound on read requests served per second.
+    pub max_iops: Option<u32>,
+    /// Ordering applied to every directory listing.
+    pub sort: SortOrder,
+    /// Byte budget for the in-memory blob cache; `None` disables caching.
+    pub cache_budget: Option<u64>,
+    /// Answer `opendir` with real handles instead of relying on the kernel's
+    /// zero-message path, as overlayfs expects from a lower layer.
+    pub overlay_compat: bool,
+    /// Serve `branches/<name>` as the tip's snapshot directory instead of a
+    /// symlink into `commits/`.
+    pub branches_as_dirs: bool,
+    /// Count opens and bytes served per snapshot, exposed as `/.gitsnapfs/stats`.
+    pub stats: bool,
+    /// Local checkouts served in place of submodule placeholders.
+    pub submodule_checkouts: Vec<Checkout>,
+    /// Concurrent requests allowed per calling uid; `None` disables the limit.
+    pub max_requests_per_uid: Option<usize>,
+    /// Open large blobs with real handles that inflate them once, ahead of the first read.
+    pub readahead: bool,
+    /// Size reported for snapshot directories; anything but `Zero` also sets
+    /// `nlink` to 2 plus the number of subdirectories.
+    pub dir_size: DirSize,
+    /// Bounds on the whole-tree walks behind `.git-meta` files, conflict
+    /// listings and export-ignore filtering.
+    pub tree_limits: TreeLimits,
+    /// Link the README and license files of HEAD's root tree from the mount root.
+    pub root_readme: bool,
+    /// Serve `/releases`, the tags whose names this pattern finds versions
+    /// in, arranged by version; see [`crate::releases`].
+    pub releases: Option<ReleasePattern>,
+    /// Socket of a `gitsnapfs cache-daemon` to read blobs through.
+    pub shared_cache: Option<PathBuf>,
+    /// Only serve commits that are this commit or descend from it; references
+    /// to anything else are hidden and `trees/` is empty.
+    pub only_descendants_of: Option<ObjectId>,
+    /// Line rendered per commit in `/branches-meta/<branch>/CHANGELOG`;
+    /// `None` uses [`crate::meta::DEFAULT_CHANGELOG_TEMPLATE`].
+    pub changelog_template: Option<String>,
+    /// Serve `/refs-debug`, listing every reference as stored, including
+    /// those hidden from the other views.
+    pub refs_debug: bool,
+    /// Follow only first parents wherever history is walked: changelogs,
+    /// `referenced-by/` and the `reachable-from` xattr.
+    pub first_parent: bool,
+    /// Commits numbered in each `/commits-on/<branch>`, counting the tip;
+    /// `None` uses [`crate::meta::DEFAULT_HISTORY_DEPTH`].
+    pub history_depth: Option<usize>,
+    /// Check every blob decoded for serving against its object id.
+    pub verify_reads: bool,
+    /// Serve loose blobs stored uncompressed from a read-only mapping of
+    /// their files; see [`crate::loose`]. Off under `verify_reads`.
+    pub mmap_loose: bool,
+    /// Largest blob, in bytes, decoded for reading; larger files keep their
+    /// size but fail reads with `EFBIG`.
+    pub max_file_size: Option<u64>,
+    /// Serve structure only: reading the content of Git objects fails with
+    /// `EPERM`, while listings, attributes and symlinks work as usual.
+    pub metadata_only: bool,
+    /// Answer extended attribute requests with `ENOSYS`, which the kernel
+    /// remembers: it fails later ones itself without asking the daemon.
+    pub no_xattrs: bool,
+    /// Serve Git LFS pointer files as placeholders of the size they record,
+    /// reading as zeros; see [`crate::lfs`].
+    pub lfs_sparse: bool,
+    /// Show author and committer identities as recorded instead of as the
+    /// repository's `.mailmap` maps them.
+    pub ignore_mailmap: bool,
+    /// Shorten the object ids in links into `commits/` and `trees/` to unique
+    /// prefixes of at least this many hex digits; `None` keeps full ids.
+    pub abbrev: Option<usize>,
+    /// Hook deciding which uids may look up and read which paths; `None`
+    /// allows everything the mount's permissions allow.
+    pub authz: Option<Hook>,
+    /// Branches and tags served; the others are hidden everywhere, including
+    /// from the reachability xattrs and `referenced-by/`.
+    pub ref_filter: RefFilter,
+    /// Permission bits replacing the stored ones for matching paths of
+    /// commit snapshots.
+    pub mode_overrides: ModeOverrides,
+    /// Git config keys whose values are served as files in
+    /// `.gitsnapfs/config/`, named by the key as given.
+    pub git_config_keys: Vec<String>,
+}
+
+/// Parse an `--expose-config` key such as `remote.origin.url`.
+///
+/// # Errors
+///
+/// Returns an error unless `input` has a section and a name, and can be a
+/// file name: subsections holding `/` are not supported.
+pub fn parse_git_config_key(input: &str) -> Result<String> {
+    let Some((section, name)) = input.split_once('.') else {
+        bail!("expected a key of the form section.name, got '{input}'");
+    };
+    let name = name.rsplit_once('.').map_or(name, |(_, name)| name);
+    if section.is_empty() || name.is_empty() {
+        bail!("expected a key of the form section.name, got '{input}'");
+    }
+    if input.contains(['/', '\0']) {
+        bail!("config key '{input}' cannot be a file name");
+    }
+    Ok(input.to_owned())
+}
+
+/// Parse a byte count such as `4096`, `512K`, `20MiB` or `1G` (binary multiples).
+///
+/// # Errors
+///
+/// Returns an error for empty, zero, malformed or overflowing values.
+pub fn parse_byte_size(input: &str) -> Result<u64> {
+    let trimmed = input
+        .trim()
+        .trim_end_matches(['B', 'b'])
+        .trim_end_matches('i');
+    let (digits, multiplier) = match trimmed.char_indices().last() {
+        Some((index, suffix)) if suffix.is_ascii_alphabetic() => {
+            let multiplier: u64 = match suffix.to_ascii_uppercase() {
+                'K' => 1 << 10,
+                'M' => 1 << 20,
+                'G' => 1 << 30,
+                _ => bail!("unknown unit in byte size '{input}'"),
+            };
+            (&trimmed[..index], multiplier)
+        }
+        _ => (trimmed, 1),
+    };
+    let value: u64 = digits
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid byte size '{input}'"))?;
+    let size = value
+        .checked_mul(multiplier)
+        .with_context(|| format!("byte size '{input}' is too large"))?;
+    if size == 0 {
+        bail!("byte size must be positive");
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ref_filter_matches_short_names_of_its_kind() {
+        let filter = RefFilter {
+            branches: vec!["release/*".to_owned(), "main".to_owned()],
+            tags: Vec::new(),
+        };
+        assert!(filter.allows(b"refs/heads/main"));
+        assert!(filter.allows(b"refs/heads/release/1.0"));
+        assert!(filter.allows(b"refs/heads/release/1.x/hotfix"));
+        assert!(!filter.allows(b"refs/heads/feature/x"));
+        assert!(!filter.allows(b"refs/heads/mainline"));
+        assert!(filter.allows(b"refs/tags/v1.0"));
+        assert!(filter.allows(b"refs/remotes/origin/feature/x"));
+    }
+
+    #[test]
+    fn last_matching_mode_override_wins() {
+        let overrides = ModeOverrides {
+            rules: ["bin/**=755", "*.key=0600", "bin/README=644"]
+                .into_iter()
+                .map(|rule| parse_mode_override(rule).unwrap())
+                .collect(),
+        };
+        assert_eq!(overrides.mode_for(b"bin/tool"), Some(0o755));
+        assert_eq!(overrides.mode_for(b"bin/sub/tool"), Some(0o755));
+        assert_eq!(overrides.mode_for(b"bin/README"), Some(0o644));
+        assert_eq!(overrides.mode_for(b"etc/ssh/host.key"), Some(0o600));
+        assert_eq!(overrides.mode_for(b"bin"), None);
+        assert_eq!(overrides.mode_for(b"src/bin/tool"), None);
+        assert_eq!(overrides.modes(), [0o755, 0o600, 0o644]);
+        assert!(parse_mode_override("bin/**=0855").is_err());
+        assert!(parse_mode_override("=0755").is_err());
+    }
+
+    #[test]
+    fn config_keys_need_a_section_and_a_name() {
+        for key in ["remote.origin.url", "core.bare", "branch.release.1.x.merge"] {
+            assert_eq!(parse_git_config_key(key).unwrap(), key);
+        }
+        for key in [
+            "core",
+            ".bare",
+            "core.",
+            "remote.origin.",
+            "url.https://x/.insteadof",
+        ] {
+            assert!(parse_git_config_key(key).is_err(), "{key}");
+        }
+    }
+}
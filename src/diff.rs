@@ -0,0 +1,236 @@
+//! Minimal recursive tree diff used by the synthetic metadata views and
+//! `gitsnapfs diff-mounts`.
+//!
+//! We only need path-level granularity (no rename detection, no content
+//! diffs), so walking both trees side by side is simpler and cheaper than
+//! configuring the full `gix` diff machinery.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{Context, Result};
+use gix::bstr::ByteSlice;
+use gix::object::tree::EntryMode;
+use gix::ObjectId;
+
+use crate::limits::{Budget, TreeLimits};
+use crate::meta::quote_path;
+
+/// One side of a changed path: the tree entry mode and object id.
+pub type Side = Option<(EntryMode, ObjectId)>;
+
+/// A single path that differs between two trees.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    /// Slash-separated path relative to the tree roots.
+    pub path: Vec<u8>,
+    /// Entry in the old tree, if the path existed there.
+    pub old: Side,
+    /// Entry in the new tree, if the path exists there.
+    pub new: Side,
+}
+
+/// Compute the non-tree paths that differ between `old` and `new`.
+///
+/// Passing `None` for either side treats it as the empty tree. Changes are
+/// reported in byte order of their paths.
+///
+/// # Errors
+///
+/// Returns an error if one of the trees cannot be read from the object
+/// database, or the walk exceeds `limits`.
+pub fn diff_trees(
+    repo: &gix::Repository,
+    old: Option<ObjectId>,
+    new: Option<ObjectId>,
+    limits: TreeLimits,
+) -> Result<Vec<Change>> {
+    let mut out = Vec::new();
+    diff_into(repo, old, new, &[], 0, &mut Budget::new(limits), &mut out)?;
+    Ok(out)
+}
+
+impl Change {
+    /// Single-letter status as in `git diff --name-status`: `A`dded,
+    /// `D`eleted, `M`odified, or `T` when the entry changes type (file,
+    /// symlink, submodule).
+    #[must_use]
+    pub fn status(&self) -> char {
+        match (self.old, self.new) {
+            (None, _) => 'A',
+            (_, None) => 'D',
+            (Some((old, _)), Some((new, _)))
+                if old.kind() != new.kind() && !both_blobs(old, new) =>
+            {
+                'T'
+            }
+            _ => 'M',
+        }
+    }
+}
+
+fn both_blobs(old: EntryMode, new: EntryMode) -> bool {
+    let blob = |mode: EntryMode| mode.is_blob() && !mode.is_link();
+    blob(old) && blob(new)
+}
+
+/// Tree that the revision `spec` (a commit, tag or tree) points at.
+///
+/// # Errors
+///
+/// Returns an error if `spec` does not resolve to a commit or tree.
+pub fn resolve_tree(repo: &gix::Repository, spec: &str) -> Result<ObjectId> {
+    let tree = repo
+        .rev_parse_single(spec)
+        .with_context(|| format!("cannot resolve revision '{spec}'"))?
+        .object()?
+        .peel_to_tree()
+        .with_context(|| format!("'{spec}' does not name a commit or tree"))?;
+    Ok(tree.id)
+}
+
+/// Render `changes` one per line as `<status>\t<old size>\t<new size>\t<path>`,
+/// with `-` for a missing side or a submodule and paths quoted like git does.
+///
+/// # Errors
+///
+/// Returns an error if a blob's size cannot be read.
+pub fn render_changes(repo: &gix::Repository, changes: &[Change]) -> Result<Vec<u8>> {
+    let size = |side: Side| -> Result<String> {
+        Ok(match side {
+            Some((mode, id)) if !mode.is_commit() => repo.find_header(id)?.size().to_string(),
+            _ => "-".to_owned(),
+        })
+    };
+    let mut out = Vec::new();
+    for change in changes {
+        let line = format!(
+            "{}\t{}\t{}\t",
+            change.status(),
+            size(change.old)?,
+            size(change.new)?
+        );
+        out.extend_from_slice(line.as_bytes());
+        quote_path(&change.path, &mut out);
+        out.push(b'\n');
+    }
+    Ok(out)
+}
+
+/// Paths of a merge result tree that differ from *every* parent tree.
+///
+/// This mirrors what `git diff --cc` highlights: hunks taken verbatim from one
+/// side of the merge are not interesting, only paths where the merge author
+/// produced something no parent had (conflict resolutions or "evil" edits).
+///
+/// # Errors
+///
+/// Returns an error if any of the trees cannot be read from the object
+/// database, or a diff against one parent exceeds `limits`.
+pub fn combined_changes(
+    repo: &gix::Repository,
+    parent_trees: &[ObjectId],
+    result_tree: ObjectId,
+    limits: TreeLimits,
+) -> Result<BTreeSet<Vec<u8>>> {
+    let mut combined: Option<BTreeSet<Vec<u8>>> = None;
+    for parent in parent_trees {
+        let paths: BTreeSet<Vec<u8>> = diff_trees(repo, Some(*parent), Some(result_tree), limits)?
+            .into_iter()
+            .map(|change| change.path)
+            .collect();
+        combined = Some(match combined {
+            None => paths,
+            Some(previous) => previous.intersection(&paths).cloned().collect(),
+        });
+    }
+    Ok(combined.unwrap_or_default())
+}
+
+fn diff_into(
+    repo: &gix::Repository,
+    old: Option<ObjectId>,
+    new: Option<ObjectId>,
+    prefix: &[u8],
+    depth: usize,
+    budget: &mut Budget,
+    out: &mut Vec<Change>,
+) -> Result<()> {
+    if old == new {
+        return Ok(());
+    }
+    let old_entries = tree_entries(repo, old)?;
+    let new_entries = tree_entries(repo, new)?;
+    let names: BTreeSet<&Vec<u8>> = old_entries.keys().chain(new_entries.keys()).collect();
+    for name in names {
+        let before = old_entries.get(name).copied();
+        let after = new_entries.get(name).copied();
+        if before == after {
+            continue;
+        }
+        budget.visit()?;
+        let mut path = prefix.to_vec();
+        if !path.is_empty() {
+            path.push(b'/');
+        }
+        path.extend_from_slice(name);
+
+        let subtree = |side: Side| side.filter(|(mode, _)| mode.is_tree()).map(|(_, id)| id);
+        let (old_tree, new_tree) = (subtree(before), subtree(after));
+        if old_tree.is_some() || new_tree.is_some() {
+            budget.descend(depth + 1)?;
+            diff_into(repo, old_tree, new_tree, &path, depth + 1, budget, out)?;
+        }
+        let leaf = |side: Side| side.filter(|(mode, _)| !mode.is_tree());
+        let (old_leaf, new_leaf) = (leaf(before), leaf(after));
+        if old_leaf.is_some() || new_leaf.is_some() {
+            out.push(Change {
+                path,
+                old: old_leaf,
+                new: new_leaf,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn tree_entries(
+    repo: &gix::Repository,
+    id: Option<ObjectId>,
+) -> Result<BTreeMap<Vec<u8>, (EntryMode, ObjectId)>> {
+    let Some(id) = id else {
+        return Ok(BTreeMap::new());
+    };
+    let tree = repo.find_tree(id)?;
+    let mut entries = BTreeMap::new();
+    for entry in tree.iter() {
+        let entry = entry?;
+        entries.insert(
+            entry.inner.filename.as_bytes().to_vec(),
+            (entry.inner.mode, entry.inner.oid.to_owned()),
+        );
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gix::objs::tree::EntryKind;
+
+    #[test]
+    fn status_letters() {
+        let id = ObjectId::null(gix::hash::Kind::Sha1);
+        let side = |kind: EntryKind| Some((EntryMode::from(kind), id));
+        let change = |old, new| Change {
+            path: b"a".to_vec(),
+            old,
+            new,
+        };
+        assert_eq!(change(None, side(EntryKind::Blob)).status(), 'A');
+        assert_eq!(change(side(EntryKind::Blob), None).status(), 'D');
+        let chmod = change(side(EntryKind::Blob), side(EntryKind::BlobExecutable));
+        assert_eq!(chmod.status(), 'M');
+        let retype = change(side(EntryKind::Blob), side(EntryKind::Link));
+        assert_eq!(retype.status(), 'T');
+    }
+}
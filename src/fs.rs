@@ -1,15 +1,21 @@
 //! FUSE filesystem implementation for `GitSnapFS`.
 
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::ffi::CStr;
+use std::hash::{Hash, Hasher};
 use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
 use std::str;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use fuse_backend_rs::abi::fuse_abi::{stat64, Attr, CreateIn, ROOT_ID};
 use fuse_backend_rs::api::filesystem::{
-    Context, DirEntry, Entry, FileSystem, FsOptions, OpenOptions, SetattrValid, ZeroCopyReader,
-    ZeroCopyWriter,
+    Context, DirEntry, Entry, FileSystem, FsOptions, GetxattrReply, IoctlData, ListxattrReply,
+    OpenOptions, SetattrValid, ZeroCopyReader, ZeroCopyWriter,
 };
 use gix::bstr::ByteSlice;
 use gix::object::tree::{EntryKind, EntryMode};
@@ -17,69 +23,742 @@ use gix::object::Kind;
 use gix::ObjectId;
 use libc::{S_IFDIR, S_IFLNK, S_IFREG};
 
-use crate::inode::inode_from_oid;
-use crate::repo::Repository;
+use crate::admission::{Permit, UidLimiter};
+use crate::attributes::export_ignored_paths;
+use crate::audit::{self, AuditLog};
+use crate::authz::{Authorizer, Op};
+use crate::cache::{self, BlobCache, ContentMemo, Memo, PreloadStats};
+use crate::config::{DirSize, FakeDotGit, FsConfig, SortOrder};
+use crate::health::Health;
+use crate::inode::{
+    mode_index, mode_inode, narrow_hex_prefix, object_view, synthetic_inode, view_inode,
+    ObjectView, Space, OBJECT_PREFIX_BITS,
+};
+use crate::interrupt::{self, Interrupted};
+use crate::journal::ErrorJournal;
+use crate::lfs::{self, LFS_XATTR};
+use crate::limits::LimitExceeded;
+use crate::lookups::LookupCounts;
+use crate::loose::MappedBlob;
+use crate::meta::{
+    self, MetaKind, MetaNode, CHANGELOG_NAME, COMMIT_JSON_NAME, DISK_USAGE_NAME, DOTGIT_NAME,
+    MANIFEST_NAME, META_DIR_NAME, SHALLOW_NAME,
+};
+use crate::names::{escape_name, unescape_name};
+use crate::readahead::{self, Readahead};
+use crate::ref_inodes::RefInodes;
+use crate::refs_debug;
+use crate::releases::{ReleaseNode, Releases};
+use crate::repo::{self, Repository, UnbornHead};
+use crate::reverse::{self, ReverseIndex};
+use crate::scrub::Scrub;
+use crate::sha256;
+use crate::shared_cache::SharedCache;
+use crate::sniff::{self, ContentType};
+use crate::splice::Writes;
+use crate::stats::SnapshotStats;
+use crate::submodule::{self, Gitlink};
+use crate::throttle::ReadThrottle;
 
 const ROOT_ATTR_MODE: u32 = S_IFDIR | 0o755;
 const DIRECTORY_ATTR_MODE: u32 = S_IFDIR | 0o755;
 const SYMLINK_ATTR_MODE: u32 = S_IFLNK | 0o777;
 
-const INODE_COMMITS: u64 = 2;
-const INODE_TREES: u64 = 3;
-const INODE_BRANCHES: u64 = 4;
-const INODE_TAGS: u64 = 5;
-const INODE_HEAD: u64 = 6;
+/// Inode of `commits/`.
+pub const INODE_COMMITS: u64 = 2;
+/// Inode of `trees/`.
+pub const INODE_TREES: u64 = 3;
+/// Inode of `branches/`.
+pub const INODE_BRANCHES: u64 = 4;
+/// Inode of `tags/`.
+pub const INODE_TAGS: u64 = 5;
+/// Inode of `HEAD`.
+pub const INODE_HEAD: u64 = 6;
+/// Inode of `.gitsnapfs/`.
+pub const INODE_CONTROL: u64 = 7;
+/// Inode of `.gitsnapfs/stats`.
+pub const INODE_STATS: u64 = 8;
+/// Inode of `latest/`.
+pub const INODE_LATEST: u64 = 9;
+/// Inode of `refs/`.
+pub const INODE_REFS: u64 = 10;
+/// Inode of `branches-meta/`.
+pub const INODE_BRANCHES_META: u64 = 11;
+/// Inode of `objects/`.
+pub const INODE_OBJECTS: u64 = 12;
+/// Inode of `.gitsnapfs/health`.
+pub const INODE_HEALTH: u64 = 13;
+/// Inode of `at/`.
+pub const INODE_AT: u64 = 14;
+/// Inode of `worktrees/`.
+pub const INODE_WORKTREES: u64 = 15;
+/// Inode of `.gitsnapfs/errors`.
+pub const INODE_ERRORS: u64 = 16;
+/// Inode of `.gitsnapfs/info`.
+pub const INODE_INFO: u64 = 17;
+/// Inode of `.gitsnapfs/current`.
+pub const INODE_CURRENT: u64 = 18;
+/// Inode of `.gitsnapfs/scrub`.
+pub const INODE_SCRUB: u64 = 19;
+/// Inode of `releases/`.
+pub const INODE_RELEASES: u64 = 20;
+/// Inode of `commits-on/`.
+pub const INODE_COMMITS_ON: u64 = 21;
+/// Inode of `.gitsnapfs/config/`.
+pub const INODE_GIT_CONFIG: u64 = 22;
+/// Inode of `refs-debug/`.
+pub const INODE_REFS_DEBUG: u64 = 23;
+/// Inode of `refs-debug/refs`.
+pub const INODE_REFS_TABLE: u64 = 24;
+/// Inode of `refs-debug/packed-refs`.
+pub const INODE_PACKED_REFS: u64 = 25;
+
+/// Longest revision the [`SWITCH_CURRENT`] ioctl takes, NUL-padded.
+pub const SWITCH_CURRENT_LEN: usize = 256;
+
+/// `ioctl` on `.gitsnapfs/current` pointing `/current` at another commit;
+/// the argument is the revision naming it, NUL-padded to
+/// [`SWITCH_CURRENT_LEN`] bytes.
+#[allow(clippy::cast_possible_truncation)]
+pub const SWITCH_CURRENT: u32 = nix::request_code_write!(b'G', 1, SWITCH_CURRENT_LEN) as u32;
+
+/// Directory at the mount root holding runtime information (`--stats`,
+/// `--health-interval`, `--error-journal`, `--splice`, `--current`,
+/// `--verify-sample`, `--expose-config`).
+const CONTROL_DIR_NAME: &[u8] = b".gitsnapfs";
+
+/// Stems of the files in HEAD's root tree linked from the mount root
+/// (`--root-readme`), compared case-insensitively.
+const ROOT_LINK_STEMS: [&[u8]; 4] = [b"readme", b"license", b"licence", b"copying"];
+
+/// Namespace of every extended attribute served. Other names, such as the
+/// `security.capability` the kernel asks about on each exec, are answered
+/// with `ENODATA` before the inode is even resolved.
+const XATTR_NAMESPACE: &[u8] = b"user.gitsnapfs.";
+/// Extended attribute of commit snapshots listing the refs they are reachable from.
+const REACHABLE_XATTR: &[u8] = b"user.gitsnapfs.reachable-from";
+/// Prefix of the per-query xattr `<prefix><rev>` on commit snapshots, `1` if
+/// the commit is an ancestor of (or equal to) `<rev>` and `0` if not.
+const IS_ANCESTOR_XATTR_PREFIX: &[u8] = b"user.gitsnapfs.is-ancestor-of.";
+/// Extended attribute of commit snapshots holding their generation number.
+const GENERATION_XATTR: &[u8] = b"user.gitsnapfs.generation";
+/// Extended attribute of blobs holding the hex SHA-256 of their content.
+const SHA256_XATTR: &[u8] = b"user.gitsnapfs.sha256";
+/// Extended attribute of blobs naming their sniffed MIME type.
+const MIME_XATTR: &[u8] = b"user.gitsnapfs.mime";
+/// Extended attribute of blobs, `1` if they look binary and `0` if text.
+const BINARY_XATTR: &[u8] = b"user.gitsnapfs.binary";
+/// Extended attribute of snapshot directories holding the hex id of their tree.
+const TREE_OID_XATTR: &[u8] = b"user.gitsnapfs.tree-oid";
+/// Extended attribute of the mount root holding [`Repository::volume_uuid`].
+const UUID_XATTR: &[u8] = b"user.gitsnapfs.uuid";
+
+/// Loose objects kept mapped under `--mmap-loose`.
+const MAX_MAPPED_BLOBS: usize = 1024;
+
+/// Trees whose recursive size (`.git-meta/DU`) is kept.
+const MAX_TREE_USAGE: usize = 1 << 18;
+
+/// Snapshot root trees whose `export-ignore` paths are kept.
+const HIDDEN_PATHS_MEMO_ENTRIES: usize = 1024;
+
+/// Snapshot root trees whose `.gitmodules` declarations are kept.
+const DECLARED_SUBMODULES_MEMO_ENTRIES: usize = 1024;
+
+/// Rendered `--synthetic-files` kept, each for one commit.
+const INJECTED_MEMO_ENTRIES: usize = 1024;
+
+/// Merge commits whose `.git-meta/conflicts/` listing is kept.
+const CONFLICT_MEMO_ENTRIES: usize = 256;
+
+/// Bytes kept by each cache of rendered files (`MANIFEST`, `CHANGELOG`)
+/// when no blob cache budget is set.
+const DEFAULT_RENDERED_BUDGET: usize = 64 << 20;
+
+/// Commits and trees whose shape (`--dir-size`) is kept.
+const TREE_SHAPE_MEMO_ENTRIES: usize = 1 << 16;
+
+/// Commits whose descent from `--only-descendants-of` is kept.
+const DESCENT_MEMO_ENTRIES: usize = 1 << 18;
 
-const NAMESPACE_BRANCH: u8 = 1;
-const NAMESPACE_TAG: u8 = 2;
+/// Blobs whose SHA-256 (`user.gitsnapfs.sha256`) is kept.
+const CHECKSUM_MEMO_ENTRIES: usize = 1 << 16;
+
+/// Blobs whose sniffed type (`user.gitsnapfs.mime`) is kept.
+const CONTENT_TYPE_MEMO_ENTRIES: usize = 1 << 16;
+
+/// Small blobs whose parse as an LFS pointer (`--lfs-sparse`) is kept.
+const LFS_POINTER_MEMO_ENTRIES: usize = 1 << 16;
+
+/// Commits whose generation number (`user.gitsnapfs.generation`) is kept.
+const GENERATION_MEMO_ENTRIES: usize = 1 << 18;
 
 const ENTRY_TTL: Duration = Duration::from_secs(1);
 const ATTR_TTL: Duration = Duration::from_secs(1);
+/// Entry timeout under `latest/` and `branches-meta/`, which must follow
+/// branch tips closely. It only bounds staleness if nobody invalidates moved
+/// entries (see [`GitSnapFs::take_moved_tip_entries`]).
+const LATEST_TTL: Duration = Duration::from_millis(500);
+
+pub(crate) struct DirRecord {
+    pub(crate) name: Vec<u8>,
+    pub(crate) ino: u64,
+    pub(crate) dtype: u32,
+    pub(crate) entry: Option<Entry>,
+}
+
+/// A directory inside a commit snapshot whose listing depends on its path
+/// (because some of its descendants are hidden or submodules), so it cannot
+/// share the object-derived inode of its tree.
+#[derive(Clone, Debug)]
+struct ScopedDir {
+    /// Root tree of the snapshot the directory belongs to.
+    root: ObjectId,
+    /// Slash-separated path of the directory below `root`.
+    path: Vec<u8>,
+    /// Tree object backing the directory.
+    tree: ObjectId,
+}
+
+/// Path context of a snapshot directory while path-dependent filtering applies.
+struct Scope {
+    root: ObjectId,
+    path: Vec<u8>,
+    hidden: Arc<BTreeSet<Vec<u8>>>,
+    submodules: Arc<submodule::Declarations>,
+}
+
+impl Scope {
+    /// Whether `path` needs a scoped inode: some descendant is hidden or a declared submodule.
+    fn has_scoped_descendants(&self, path: &[u8]) -> bool {
+        let mut prefix = path.to_vec();
+        prefix.push(b'/');
+        let below = |first: Option<&Vec<u8>>| first.is_some_and(|p| p.starts_with(&prefix));
+        below(self.hidden.range(prefix.clone()..).next())
+            || below(
+                self.submodules
+                    .range(prefix.clone()..)
+                    .next()
+                    .map(|(p, _)| p),
+            )
+    }
+}
+
+/// What a tree holds, as far as directory attributes are concerned.
+#[derive(Clone, Copy, Debug)]
+struct TreeShape {
+    entries: u64,
+    /// Size of the tree object.
+    bytes: u64,
+    /// Subtrees and gitlinks, both served as directories.
+    subdirs: u64,
+}
 
-struct DirRecord {
-    name: Vec<u8>,
-    ino: u64,
-    dtype: u32,
-    entry: Option<Entry>,
+/// Everything needed to list or look up entries of a snapshot directory.
+struct DirSource {
+    tree: ObjectId,
+    /// Commit whose root directory this is, if any.
+    commit_root: Option<ObjectId>,
+    scope: Option<Scope>,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 enum RefNamespace {
     Branches,
     Tags,
+    /// Branches again, always as directories of their tip commit.
+    Latest,
 }
 
 impl RefNamespace {
-    fn marker(self) -> u8 {
+    fn space(self) -> Space {
+        match self {
+            RefNamespace::Branches | RefNamespace::Latest => Space::Branch,
+            RefNamespace::Tags => Space::Tag,
+        }
+    }
+
+    /// Prefix of the full names of the references listed.
+    fn prefix(self) -> &'static [u8] {
         match self {
-            RefNamespace::Branches => NAMESPACE_BRANCH,
-            RefNamespace::Tags => NAMESPACE_TAG,
+            RefNamespace::Branches | RefNamespace::Latest => b"refs/heads/",
+            RefNamespace::Tags => b"refs/tags/",
         }
     }
 
     fn list(self, repo: &Repository) -> io::Result<Vec<(String, ObjectId)>> {
         match self {
-            RefNamespace::Branches => repo.list_branches(),
+            RefNamespace::Branches | RefNamespace::Latest => repo.list_branches(),
             RefNamespace::Tags => repo.list_tags(),
         }
         .map_err(io::Error::other)
     }
 }
 
+/// What an inode stands for. Every operation classifies its inode once with
+/// [`GitSnapFs::node_kind`] and then matches on the result, so a new
+/// namespace adds a variant rather than another check to each operation.
+#[derive(Debug)]
+enum NodeKind {
+    Root,
+    /// One of the directories at the mount root.
+    TopDir(TopDir),
+    /// The `HEAD` symlink at the mount root.
+    Head,
+    /// `.gitsnapfs/stats`.
+    Stats,
+    /// `.gitsnapfs/health`.
+    Health,
+    /// `.gitsnapfs/errors`.
+    Errors,
+    /// `.gitsnapfs/info`.
+    Info,
+    /// `.gitsnapfs/current`.
+    Current,
+    /// `.gitsnapfs/scrub`.
+    Scrub,
+    /// A file in `.gitsnapfs/config/`, with its key.
+    GitConfig(String),
+    /// `refs-debug/refs`.
+    RefsTable,
+    /// `refs-debug/packed-refs`.
+    PackedRefs,
+    /// A `--root-readme` symlink, with its name.
+    RootLink(Vec<u8>),
+    /// A symlink under `branches/` or `tags/`.
+    RefLink(RefNamespace),
+    /// A symlink under `at/`, with its name.
+    AtLink(Vec<u8>),
+    /// A symlink under `worktrees/`, with its name.
+    WorktreeLink(Vec<u8>),
+    /// A directory or symlink below `/refs`, with its path there.
+    MirroredRef(Vec<u8>),
+    /// A directory or symlink below `/releases`, with its path there.
+    Release(Vec<u8>),
+    /// A `.git-meta` node, injected file or other synthetic node.
+    Synthetic(MetaNode),
+    /// A snapshot directory whose listing depends on its path.
+    Scoped(ScopedDir),
+    /// A Git object: a commit or tree snapshot, a blob, or a tag.
+    Object(ObjectId),
+}
+
+/// Directories at the mount root.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TopDir {
+    Commits,
+    Trees,
+    /// `objects/`, holding a directory per blob.
+    Objects,
+    /// `branches/`, `tags/` or `latest/`.
+    Refs(RefNamespace),
+    /// `refs/`.
+    RefMirror,
+    BranchesMeta,
+    /// `commits-on/`, numbering each branch's first-parent history.
+    CommitsOn,
+    /// `at/`, holding a symlink per point in time looked up.
+    At,
+    /// `worktrees/`, holding a symlink per worktree `HEAD`.
+    Worktrees,
+    /// `releases/`, tags arranged by version (`--releases`).
+    Releases,
+    /// `refs-debug/`, every reference as stored (`--refs-debug`).
+    RefsDebug,
+    /// `.gitsnapfs/` (`--stats`).
+    Control,
+    /// `.gitsnapfs/config/` (`--expose-config`).
+    GitConfig,
+}
+
 pub struct GitSnapFs {
     repo: Repository,
+    /// Current settings; swapped wholesale by [`Self::reconfigure`].
+    config: RwLock<Arc<FsConfig>>,
     // Pre-calculated time parts to avoid repeated time_to_unix_parts calls
     mount_time: (i64, i64), // (seconds, nanoseconds)
+    // `.git-meta` nodes have no backing object, so remember what each handed-out inode means.
+    meta_nodes: RwLock<HashMap<u64, MetaNode>>,
+    scoped_dirs: RwLock<HashMap<u64, ScopedDir>>,
+    // Hidden paths per snapshot root tree; trees are immutable so entries never go stale.
+    hidden_paths: Memo<ObjectId, Arc<BTreeSet<Vec<u8>>>>,
+    // Submodules declared in `.gitmodules` per snapshot root tree, keyed by path.
+    declared_submodules: Memo<ObjectId, Arc<submodule::Declarations>>,
+    // Recursive blob sizes per tree for `.git-meta/DU`, filled on first read.
+    // Trees are immutable, so subtrees shared between snapshots are measured once.
+    tree_usage: Memo<ObjectId, meta::Usage>,
+    // Rendered `--synthetic-files`, by commit and index of the declaration.
+    injected: Memo<(ObjectId, usize), Arc<[u8]>>,
+    // Paths listed under `.git-meta/conflicts/`, by merge commit.
+    conflicts: Memo<ObjectId, Arc<BTreeSet<Vec<u8>>>>,
+    // Rendered `.git-meta/MANIFEST` files; stat and every read need the whole
+    // listing. Bounded by the blob cache budget.
+    manifests: ContentMemo<ObjectId>,
+    // Rendered `branches-meta/<branch>/CHANGELOG` files, by branch tip and
+    // fingerprint of the tagged commits. Bounded by the blob cache budget.
+    changelogs: ContentMemo<(ObjectId, u64)>,
+    read_throttle: ReadThrottle,
+    blob_cache: Option<BlobCache>,
+    stats: Option<SnapshotStats>,
+    uid_limiter: Option<UidLimiter>,
+    authorizer: Option<Authorizer>,
+    /// Path below the mount root each inode was last looked up by, for the
+    /// audit log and the error journal.
+    lookup_paths: RwLock<HashMap<u64, Vec<u8>>>,
+    /// Path each uid last looked each inode up by, for the authorization
+    /// hook. An object's inode is shared by every path to the object, so a
+    /// request is decided on the path its caller walked.
+    walked_paths: RwLock<HashMap<u64, HashMap<u32, Vec<u8>>>>,
+    readahead: Option<Readahead>,
+    shared_cache: Option<SharedCache>,
+    // Branch and tag entries handed to the kernel since the last cache flush.
+    served_refs: Mutex<BTreeSet<(u64, Vec<u8>)>>,
+    // Tip each `latest/` and `branches-meta/` entry resolved to when handed
+    // to the kernel, by `(parent inode, name)`.
+    branch_tips: Mutex<HashMap<(u64, Vec<u8>), ObjectId>>,
+    // Names of the `--root-readme` links handed out, by inode.
+    root_links: RwLock<HashMap<u64, Vec<u8>>>,
+    /// Names of the `at/` links handed out, by inode.
+    at_links: RwLock<HashMap<u64, Vec<u8>>>,
+    /// Names of the `worktrees/` links handed out, by inode.
+    worktree_links: RwLock<HashMap<u64, Vec<u8>>>,
+    // Paths below `/refs` of the mirrored references and their prefixes, by inode.
+    ref_paths: RwLock<HashMap<u64, Vec<u8>>>,
+    // Paths below `/releases` handed out, by inode.
+    release_paths: RwLock<HashMap<u64, Vec<u8>>>,
+    // Shapes of the trees behind snapshot directories (`--dir-size`), by commit or tree id.
+    tree_shapes: Memo<ObjectId, TreeShape>,
+    // Whether each commit examined descends from `--only-descendants-of`.
+    descent: Memo<ObjectId, bool>,
+    // SHA-256 of blob contents, by blob id (`user.gitsnapfs.sha256`).
+    checksums: Memo<ObjectId, String>,
+    // UUID of the repository at `HEAD` when mounted (`user.gitsnapfs.uuid`).
+    volume_uuid: String,
+    // Sniffed type of blob contents, by blob id (`user.gitsnapfs.mime`).
+    content_types: Memo<ObjectId, ContentType>,
+    // The repository's `.mailmap`, read on first use.
+    mailmap: Mutex<Option<Arc<gix::mailmap::Snapshot>>>,
+    // Generation numbers of the commits asked about and their ancestors.
+    generations: Memo<ObjectId, u32>,
+    // What each small blob parsed as under `--lfs-sparse`.
+    lfs_pointers: Memo<ObjectId, Option<lfs::Pointer>>,
+    // Loose blobs mapped under `--mmap-loose`, and those that cannot be.
+    mapped_blobs: Memo<ObjectId, Option<Arc<MappedBlob>>>,
+    // Blob reverse index for `objects/<blob>/referenced-by/`, with the tips it was built at.
+    reverse_index: Mutex<Option<(Vec<ObjectId>, Arc<ReverseIndex>)>>,
+    // References the kernel holds to each inode; registry entries of inodes
+    // it has forgotten are freed.
+    lookups: LookupCounts,
+    // Inodes numbered to branch and tag names (`--state-file`).
+    ref_inodes: RefInodes,
+    // Outcome of the self-checks (`--health-interval`).
+    health: Option<Health>,
+    // Sampled re-verification of served blobs (`--verify-sample`).
+    scrub: Option<Scrub>,
+    // Record of the reads served (`--audit-log`).
+    audit_log: Option<AuditLog>,
+    // Recent failures, served as `.gitsnapfs/errors` (`--error-journal`).
+    error_journal: Option<ErrorJournal>,
+    // How replies are written, served as `.gitsnapfs/info` (`--splice`).
+    writes: Option<Arc<Writes>>,
+    // Commit `/current` shows (`--current`), and whether it was switched
+    // since the kernel was last told.
+    current: RwLock<Option<ObjectId>>,
+    current_switched: AtomicBool,
 }
 
 impl GitSnapFs {
+    /// Directories whose listings depend on references, including every
+    /// directory of the `/refs` mirror and `/releases` handed out so far.
+    #[must_use]
+    pub fn reference_dirs(&self) -> Vec<u64> {
+        let mut dirs = vec![
+            INODE_BRANCHES,
+            INODE_TAGS,
+            INODE_LATEST,
+            INODE_REFS,
+            INODE_BRANCHES_META,
+            INODE_COMMITS_ON,
+            INODE_WORKTREES,
+            INODE_RELEASES,
+        ];
+        dirs.extend(
+            self.ref_paths
+                .read()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .keys(),
+        );
+        dirs.extend(
+            self.release_paths
+                .read()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .keys(),
+        );
+        dirs
+    }
+
     pub fn new(repo: Repository) -> Self {
+        Self::with_config(repo, FsConfig::default())
+    }
+
+    pub fn with_config(repo: Repository, config: FsConfig) -> Self {
+        let shared_cache = config
+            .shared_cache
+            .clone()
+            .map(|socket| SharedCache::new(socket, &repo));
+        let volume_uuid = repo.volume_uuid();
+        let rendered_budget = config
+            .cache_budget
+            .map_or(DEFAULT_RENDERED_BUDGET, |budget| {
+                usize::try_from(budget).unwrap_or(usize::MAX)
+            });
         Self {
             repo,
+            read_throttle: ReadThrottle::new(config.max_read_bandwidth, config.max_iops),
+            blob_cache: config
+                .cache_budget
+                .map(|budget| BlobCache::new(usize::try_from(budget).unwrap_or(usize::MAX))),
+            stats: config.stats.then(SnapshotStats::default),
+            uid_limiter: config.max_requests_per_uid.map(UidLimiter::new),
+            authorizer: config.authz.clone().map(Authorizer::new),
+            lookup_paths: RwLock::new(HashMap::new()),
+            walked_paths: RwLock::new(HashMap::new()),
+            readahead: config.readahead.then(Readahead::default),
+            shared_cache,
+            config: RwLock::new(Arc::new(config)),
             mount_time: time_to_unix_parts(SystemTime::now()),
+            meta_nodes: RwLock::new(HashMap::new()),
+            scoped_dirs: RwLock::new(HashMap::new()),
+            hidden_paths: Memo::new(HIDDEN_PATHS_MEMO_ENTRIES),
+            declared_submodules: Memo::new(DECLARED_SUBMODULES_MEMO_ENTRIES),
+            tree_usage: Memo::new(MAX_TREE_USAGE),
+            injected: Memo::new(INJECTED_MEMO_ENTRIES),
+            conflicts: Memo::new(CONFLICT_MEMO_ENTRIES),
+            manifests: ContentMemo::new(rendered_budget),
+            changelogs: ContentMemo::new(rendered_budget),
+            served_refs: Mutex::new(BTreeSet::new()),
+            branch_tips: Mutex::new(HashMap::new()),
+            root_links: RwLock::new(HashMap::new()),
+            at_links: RwLock::new(HashMap::new()),
+            worktree_links: RwLock::new(HashMap::new()),
+            ref_paths: RwLock::new(HashMap::new()),
+            release_paths: RwLock::new(HashMap::new()),
+            tree_shapes: Memo::new(TREE_SHAPE_MEMO_ENTRIES),
+            descent: Memo::new(DESCENT_MEMO_ENTRIES),
+            checksums: Memo::new(CHECKSUM_MEMO_ENTRIES),
+            volume_uuid,
+            content_types: Memo::new(CONTENT_TYPE_MEMO_ENTRIES),
+            mailmap: Mutex::new(None),
+            generations: Memo::new(GENERATION_MEMO_ENTRIES),
+            lfs_pointers: Memo::new(LFS_POINTER_MEMO_ENTRIES),
+            mapped_blobs: Memo::new(MAX_MAPPED_BLOBS),
+            reverse_index: Mutex::new(None),
+            lookups: LookupCounts::default(),
+            ref_inodes: RefInodes::default(),
+            health: None,
+            scrub: None,
+            audit_log: None,
+            error_journal: None,
+            writes: None,
+            current: RwLock::new(None),
+            current_switched: AtomicBool::new(false),
+        }
+    }
+
+    /// Number branch and tag inodes with `ref_inodes`, for example one
+    /// persisted in a state file, instead of an in-memory registry.
+    #[must_use]
+    pub fn with_ref_inodes(mut self, ref_inodes: RefInodes) -> Self {
+        self.ref_inodes = ref_inodes;
+        self
+    }
+
+    /// Inode of `oid` presented as `view`.
+    fn object_inode(&self, oid: &ObjectId, view: ObjectView) -> io::Result<u64> {
+        Ok(view_inode(self.ref_inodes.object_inode(oid)?, view))
+    }
+
+    /// Object the object inode `inode` presents: the one it was handed out
+    /// for, or else the one its id prefix names.
+    fn resolve_object(&self, inode: u64) -> anyhow::Result<ObjectId> {
+        if let Some(oid) = self.ref_inodes.object(inode) {
+            return Ok(oid);
+        }
+        let bits = self.ref_inodes.prefix_bits();
+        if bits == OBJECT_PREFIX_BITS {
+            return self.repo.resolve_inode(inode);
+        }
+        let Some(hex) = narrow_hex_prefix(inode, bits) else {
+            anyhow::bail!("inode {inode:#x} was not handed out by this mount");
+        };
+        Ok(self
+            .repo
+            .thread_local()
+            .rev_parse_single(hex.as_bytes().as_bstr())?
+            .detach())
+    }
+
+    /// Record self-checks in `health` and serve it as `.gitsnapfs/health`;
+    /// [`crate::health::spawn`] runs the checks.
+    #[must_use]
+    pub fn with_health(mut self, health: Health) -> Self {
+        self.health = Some(health);
+        self
+    }
+
+    /// Offer the blobs served to `scrub` and serve its counts as
+    /// `.gitsnapfs/scrub`; [`crate::scrub::spawn`] verifies the samples.
+    #[must_use]
+    pub fn with_scrub(mut self, scrub: Scrub) -> Self {
+        self.scrub = Some(scrub);
+        self
+    }
+
+    /// Record every read in `audit_log` (`--audit-log`).
+    #[must_use]
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    /// Keep recent failures in `error_journal` and serve them as
+    /// `.gitsnapfs/errors` (`--error-journal`).
+    #[must_use]
+    pub fn with_error_journal(mut self, error_journal: ErrorJournal) -> Self {
+        self.error_journal = Some(error_journal);
+        self
+    }
+
+    /// Serve how replies are written, as counted in `writes`, as
+    /// `.gitsnapfs/info` (`--splice`, `--no-splice`).
+    #[must_use]
+    pub fn with_writes(mut self, writes: Arc<Writes>) -> Self {
+        self.writes = Some(writes);
+        self
+    }
+
+    /// Serve `/current` showing `commit` until it is switched through
+    /// `.gitsnapfs/current` (`--current`).
+    #[must_use]
+    pub fn with_current(self, commit: ObjectId) -> Self {
+        *self
+            .current
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = Some(commit);
+        self
+    }
+
+    /// Commit `/current` shows, if it exists.
+    fn current(&self) -> Option<ObjectId> {
+        *self
+            .current
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Point `/current` at the commit `rev` names.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ENOTTY` if there is no `/current`, `ENOENT` if `rev` names
+    /// no commit that may be served.
+    pub fn switch_current(&self, rev: &str) -> io::Result<ObjectId> {
+        let commit = self
+            .repo
+            .resolve_full_commit_id(rev)
+            .ok()
+            .filter(|&id| self.is_visible(id))
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?;
+        let mut current = self
+            .current
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let Some(shown) = current.as_mut() else {
+            return Err(io::Error::from_raw_os_error(libc::ENOTTY));
+        };
+        if *shown != commit {
+            *shown = commit;
+            self.current_switched.store(true, Ordering::Relaxed);
+            tracing::info!("/current switched to {commit} ({rev})");
+        }
+        Ok(commit)
+    }
+
+    /// The root entry of `/current` if it was switched since the last
+    /// call, for the kernel to forget. The directory it named stays valid,
+    /// so files open below it keep reading the commit they were opened in.
+    pub fn take_current_switch(&self) -> Option<(u64, Vec<u8>)> {
+        self.current_switched
+            .swap(false, Ordering::Relaxed)
+            .then(|| (ROOT_ID, b"current".to_vec()))
+    }
+
+    fn current_entry(&self) -> io::Result<Entry> {
+        let commit = self
+            .current()
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?;
+        let inode = self.object_inode(&commit, ObjectView::Plain)?;
+        if let Some(stats) = &self.stats {
+            stats.root(inode, commit);
+        }
+        let mut entry = Self::make_entry(inode, self.snapshot_dir_attr(inode, commit));
+        // Bounds staleness should the kernel miss the invalidation.
+        entry.entry_timeout = LATEST_TTL;
+        Ok(entry)
+    }
+
+    /// Outcome of the self-checks, if they are enabled.
+    #[must_use]
+    pub fn health(&self) -> Option<&Health> {
+        self.health.as_ref()
+    }
+
+    /// Sampled re-verification of served blobs, if it is enabled.
+    #[must_use]
+    pub fn scrub(&self) -> Option<&Scrub> {
+        self.scrub.as_ref()
+    }
+
+    /// Offer the blob behind `inode` to the scrub sample when a read starts
+    /// at its beginning.
+    pub(crate) fn sample_read(&self, inode: u64, offset: u64) {
+        let Some(scrub) = &self.scrub else {
+            return;
+        };
+        if offset == 0 {
+            if let Ok(oid) = self.resolve_object(inode) {
+                scrub.offer(oid);
+            }
         }
     }
 
+    /// Whether `.gitsnapfs/` exists.
+    fn has_control_dir(&self) -> bool {
+        self.stats.is_some()
+            || self.health.is_some()
+            || self.scrub.is_some()
+            || self.error_journal.is_some()
+            || self.writes.is_some()
+            || self.current().is_some()
+            || !self.config().git_config_keys.is_empty()
+    }
+
+    /// Repository being served.
+    #[must_use]
+    pub fn repository(&self) -> &Repository {
+        &self.repo
+    }
+
+    /// Settings in effect for the request being served.
+    fn config(&self) -> Arc<FsConfig> {
+        Arc::clone(
+            &self
+                .config
+                .read()
+                .unwrap_or_else(std::sync::PoisonError::into_inner),
+        )
+    }
+
     fn root_attr(&self) -> stat64 {
         build_attr(ROOT_ID, ROOT_ATTR_MODE, 0, self.mount_time)
     }
@@ -102,21 +781,130 @@ impl GitSnapFs {
         )
     }
 
+    /// Attributes of a snapshot directory backed by the commit or tree `oid`,
+    /// with the size and link count `--dir-size` asks for.
+    fn snapshot_dir_attr(&self, inode: u64, oid: ObjectId) -> stat64 {
+        let mut attr = build_attr(inode, DIRECTORY_ATTR_MODE, 0, self.mount_time);
+        if self.config().dir_size == DirSize::Zero {
+            return attr;
+        }
+        // Unreadable trees keep the plain attributes; listing them reports the error.
+        if let Some(shape) = self.tree_shape(oid) {
+            let size = match self.config().dir_size {
+                DirSize::Zero => 0,
+                DirSize::Entries => shape.entries,
+                DirSize::TreeBytes => shape.bytes,
+            };
+            attr.st_size = i64::try_from(size).unwrap_or(i64::MAX);
+            attr.st_nlink = 2 + shape.subdirs;
+        }
+        attr
+    }
+
+    /// Entry and subdirectory counts of the tree behind `oid`, a commit or tree.
+    fn tree_shape(&self, oid: ObjectId) -> Option<TreeShape> {
+        if let Some(shape) = self.tree_shapes.get(&oid) {
+            return Some(shape);
+        }
+        let repo = self.repo.thread_local();
+        let object = repo.find_object(oid).ok()?;
+        let tree = match object.kind {
+            Kind::Commit => object.into_commit().tree().ok()?,
+            Kind::Tree => object.into_tree(),
+            _ => return None,
+        };
+        let mut shape = TreeShape {
+            entries: 0,
+            bytes: tree.data.len() as u64,
+            subdirs: 0,
+        };
+        for entry in tree.iter() {
+            let entry = entry.ok()?;
+            shape.entries += 1;
+            if entry.inner.mode.is_tree() || entry.inner.mode.is_commit() {
+                shape.subdirs += 1;
+            }
+        }
+        self.tree_shapes.insert(oid, shape);
+        Some(shape)
+    }
+
     fn lookup_commit(&self, name: &[u8]) -> io::Result<Entry> {
         let name_str =
             str::from_utf8(name).map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
         let commit_id = self
             .repo
             .resolve_full_commit_id(name_str)
-            .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
-        let inode = inode_from_oid(&commit_id);
+            .ok()
+            .filter(|&id| self.is_visible(id))
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?;
+        let inode = self.object_inode(&commit_id, ObjectView::Plain)?;
+        if let Some(stats) = &self.stats {
+            stats.root(inode, commit_id);
+        }
         Ok(Self::make_entry(
             inode,
-            build_attr(inode, DIRECTORY_ATTR_MODE, 0, self.mount_time),
+            self.snapshot_dir_attr(inode, commit_id),
         ))
     }
 
+    /// `objects/<blob>`, for the full id of a blob. With
+    /// `--only-descendants-of` the blob must occur in a permitted commit.
+    fn lookup_object_dir(&self, name: &[u8]) -> io::Result<Entry> {
+        let blob = str::from_utf8(name)
+            .ok()
+            .filter(|hex| hex.len() == self.repo.thread_local().object_hash().len_in_hex())
+            .and_then(|hex| ObjectId::from_hex(hex.as_bytes()).ok())
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?;
+        let is_blob = self
+            .repo
+            .thread_local()
+            .find_header(blob)
+            .is_ok_and(|header| header.kind() == gix::object::Kind::Blob);
+        if !is_blob
+            || (self.config().only_descendants_of.is_some() && self.referrers(blob)?.is_empty())
+        {
+            return Err(io::Error::from_raw_os_error(libc::ENOENT));
+        }
+        self.meta_entry(MetaNode::ObjectDir(blob))
+    }
+
+    /// Visible commits reachable from the references whose snapshots
+    /// contain `blob`. The reverse index is rebuilt (or loaded from the
+    /// cache directory) whenever a reference has moved.
+    fn referrers(&self, blob: ObjectId) -> io::Result<Vec<ObjectId>> {
+        let config = self.config();
+        let tips = reverse::tips(&self.repo, |name| config.ref_filter.allows(name))
+            .map_err(io::Error::other)?;
+        let index = {
+            let mut slot = self
+                .reverse_index
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            match &*slot {
+                Some((built_at, index)) if *built_at == tips => Arc::clone(index),
+                _ => {
+                    let index = Arc::new(
+                        ReverseIndex::load_or_build(&self.repo, &tips, config.first_parent)
+                            .map_err(io::Error::other)?,
+                    );
+                    *slot = Some((tips, Arc::clone(&index)));
+                    index
+                }
+            }
+        };
+        Ok(index
+            .commits_containing(blob)
+            .into_iter()
+            .filter(|&id| self.is_visible(id))
+            .collect())
+    }
+
     fn lookup_tree(&self, name: &[u8]) -> io::Result<Entry> {
+        // Which trees belong to the permitted history is too costly to know.
+        if self.config().only_descendants_of.is_some() {
+            return Err(io::Error::from_raw_os_error(libc::ENOENT));
+        }
         let name_str =
             str::from_utf8(name).map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
         let repo = self.repo.thread_local();
@@ -126,23 +914,21 @@ impl GitSnapFs {
             .detach();
         repo.find_tree(id)
             .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
-        let inode = inode_from_oid(&id);
-        Ok(Self::make_entry(
-            inode,
-            build_attr(inode, DIRECTORY_ATTR_MODE, 0, self.mount_time),
-        ))
+        let inode = self.object_inode(&id, ObjectView::Plain)?;
+        Ok(Self::make_entry(inode, self.snapshot_dir_attr(inode, id)))
     }
 
     fn lookup_reference(&self, name: &[u8], ns: RefNamespace) -> io::Result<Entry> {
         let name_str =
             str::from_utf8(name).map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
-        let refs = ns.list(&self.repo)?;
+        let refs = self.refs_in(ns)?;
         let object_id = refs
             .into_iter()
             .find(|(ref_name, _)| ref_name == name_str)
             .map(|(_, id)| id)
             .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?;
         let (_, _, entry) = self.reference_entry_details(ns, name, object_id)?;
+        self.note_served_refs(ns, [name.to_vec()]);
         Ok(entry)
     }
 
@@ -150,24 +936,220 @@ impl GitSnapFs {
         let target = self.head_target()?;
         Ok(Self::make_entry(
             INODE_HEAD,
-            build_attr(
-                INODE_HEAD,
-                SYMLINK_ATTR_MODE,
-                target.len() as u64,
-                self.mount_time,
-            ),
+            self.symlink_attr(INODE_HEAD, &target),
         ))
     }
 
     fn head_target(&self) -> io::Result<Vec<u8>> {
-        let commit_id = self.repo.resolve_head().map_err(io::Error::other)?;
-        Ok(format!("commits/{commit_id}").into_bytes())
+        let commit_id = self.head_commit()?;
+        Ok(format!("commits/{}", self.link_name(commit_id)).into_bytes())
     }
 
-    fn tree_root_id(&self, inode: u64) -> io::Result<ObjectId> {
-        let oid = self
+    fn head_commit(&self) -> io::Result<ObjectId> {
+        let commit_id = self.repo.resolve_head().map_err(|err| {
+            if err.is::<UnbornHead>() {
+                io::Error::from_raw_os_error(libc::ENOENT)
+            } else {
+                io::Error::other(err)
+            }
+        })?;
+        if !self.is_visible(commit_id) {
+            return Err(io::Error::from_raw_os_error(libc::ENOENT));
+        }
+        Ok(commit_id)
+    }
+
+    /// Whether `id` may be served: always, unless `--only-descendants-of`
+    /// restricts the mount to commits descending from a root.
+    fn is_visible(&self, id: ObjectId) -> bool {
+        let Some(root) = self.config().only_descendants_of else {
+            return true;
+        };
+        // Unreadable or non-commit objects are not part of the permitted history.
+        let Ok((visible, walked)) = self
+            .repo
+            .descends_from(id, root, &|id| self.descent.get(id))
+        else {
+            return false;
+        };
+        self.descent.extend(walked);
+        visible
+    }
+
+    /// References of `ns` passing the filters whose targets may be served.
+    fn refs_in(&self, ns: RefNamespace) -> io::Result<Vec<(String, ObjectId)>> {
+        let config = self.config();
+        let mut refs = ns.list(&self.repo)?;
+        refs.retain(|(name, id)| {
+            let allowed = unescape_name(name.as_bytes()).is_some_and(|short| {
+                let mut full = ns.prefix().to_vec();
+                full.extend_from_slice(&short);
+                config.ref_filter.allows(&full)
+            });
+            allowed && self.is_visible(*id)
+        });
+        Ok(refs)
+    }
+
+    /// Names of the README and license files in HEAD's root tree.
+    fn head_root_files(&self) -> io::Result<Vec<Vec<u8>>> {
+        let commit_id = match self.head_commit() {
+            Ok(id) => id,
+            Err(err) if err.raw_os_error() == Some(libc::ENOENT) => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+        let repo = self.repo.thread_local();
+        let tree = repo
+            .find_commit(commit_id)
+            .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?
+            .tree()
+            .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
+        let mut names = Vec::new();
+        for entry in tree.iter() {
+            let entry = entry.map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
+            let name = entry.inner.filename.as_bytes();
+            if entry.inner.mode.is_blob_or_symlink() && is_root_link_name(name) {
+                names.push(name.to_vec());
+            }
+        }
+        Ok(names)
+    }
+
+    /// Symlink at the mount root pointing at `HEAD/<name>` (`--root-readme`).
+    fn root_link_entry(&self, name: &[u8]) -> Entry {
+        let mut key = b"root-link/".to_vec();
+        key.extend_from_slice(name);
+        let inode = synthetic_inode(Space::Meta, &key);
+        self.root_links
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .insert(inode, name.to_vec());
+        Self::make_entry(inode, self.symlink_attr(inode, &root_link_target(name)))
+    }
+
+    fn lookup_root_link(&self, name: &[u8]) -> io::Result<Entry> {
+        if !self.head_root_files()?.iter().any(|file| file == name) {
+            return Err(io::Error::from_raw_os_error(libc::ENOENT));
+        }
+        Ok(self.root_link_entry(name))
+    }
+
+    fn root_link_name(&self, inode: u64) -> Option<Vec<u8>> {
+        self.root_links
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(&inode)
+            .cloned()
+    }
+
+    /// `at/<time>`: a symlink to the commit `HEAD` stood at then.
+    fn lookup_at(&self, name: &[u8]) -> io::Result<Entry> {
+        let target = self.at_target(name)?;
+        let mut key = b"at/".to_vec();
+        key.extend_from_slice(name);
+        let inode = synthetic_inode(Space::Meta, &key);
+        self.at_links
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .insert(inode, name.to_vec());
+        Ok(Self::make_entry(inode, self.symlink_attr(inode, &target)))
+    }
+
+    fn at_link_name(&self, inode: u64) -> Option<Vec<u8>> {
+        self.at_links
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(&inode)
+            .cloned()
+    }
+
+    /// Resolved again on every call, so relative times and reflog updates
+    /// are followed.
+    fn at_target(&self, name: &[u8]) -> io::Result<Vec<u8>> {
+        let time =
+            parse_point_in_time(name).ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?;
+        let commit_id = self
             .repo
-            .resolve_inode(inode)
+            .commit_at(time)
+            .map_err(io::Error::other)?
+            .filter(|&id| self.is_visible(id))
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?;
+        Ok(format!("../commits/{}", self.link_name(commit_id)).into_bytes())
+    }
+
+    /// Worktree `HEAD`s whose commits may be served, by link name.
+    fn worktree_heads(&self) -> io::Result<Vec<(String, ObjectId)>> {
+        let mut heads = self.repo.worktree_heads().map_err(io::Error::other)?;
+        heads.retain(|&(_, id)| self.is_visible(id));
+        Ok(heads)
+    }
+
+    fn worktree_link_entry(&self, name: &[u8], id: ObjectId) -> Entry {
+        let mut key = b"worktrees/".to_vec();
+        key.extend_from_slice(name);
+        let inode = synthetic_inode(Space::Meta, &key);
+        self.worktree_links
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .insert(inode, name.to_vec());
+        let target = format!("../commits/{}", self.link_name(id)).into_bytes();
+        Self::make_entry(inode, self.symlink_attr(inode, &target))
+    }
+
+    /// `worktrees/<name>`: a symlink to the commit that worktree's `HEAD` is at.
+    fn lookup_worktree(&self, name: &[u8]) -> io::Result<Entry> {
+        Ok(self.worktree_link_entry(name, self.worktree_head(name)?))
+    }
+
+    fn list_worktrees(&self) -> io::Result<Vec<DirRecord>> {
+        Ok(self
+            .worktree_heads()?
+            .into_iter()
+            .map(|(name, id)| {
+                let entry = self.worktree_link_entry(name.as_bytes(), id);
+                DirRecord {
+                    name: name.into_bytes(),
+                    ino: entry.inode,
+                    dtype: u32::from(libc::DT_LNK),
+                    entry: Some(entry),
+                }
+            })
+            .collect())
+    }
+
+    fn worktree_link_name(&self, inode: u64) -> Option<Vec<u8>> {
+        self.worktree_links
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(&inode)
+            .cloned()
+    }
+
+    /// Read again on every call, so checkouts in the worktree are followed.
+    fn worktree_head(&self, name: &[u8]) -> io::Result<ObjectId> {
+        self.worktree_heads()?
+            .into_iter()
+            .find(|(worktree, _)| worktree.as_bytes() == name)
+            .map(|(_, id)| id)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))
+    }
+
+    fn worktree_target(&self, name: &[u8]) -> io::Result<Vec<u8>> {
+        let id = self.worktree_head(name)?;
+        Ok(format!("../commits/{}", self.link_name(id)).into_bytes())
+    }
+
+    /// Resolve a directory inode to the tree backing it and its snapshot context.
+    fn directory_source(&self, inode: u64) -> io::Result<DirSource> {
+        if let Some(dir) = self.scoped_dir(inode) {
+            return Ok(DirSource {
+                tree: dir.tree,
+                commit_root: None,
+                scope: Some(self.scope(dir.root, dir.path)?),
+            });
+        }
+        let oid = self
+            .resolve_object(inode)
             .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
         let repo = self.repo.thread_local();
         let object = repo
@@ -182,49 +1164,137 @@ impl GitSnapFs {
                     .tree_id()
                     .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?
                     .detach();
-                Ok(tree_id)
+                let scope = self.scope(tree_id, Vec::new())?;
+                Ok(DirSource {
+                    tree: tree_id,
+                    commit_root: Some(oid),
+                    scope: (!scope.hidden.is_empty()
+                        || !scope.submodules.is_empty()
+                        || !self.config().mode_overrides.is_empty())
+                    .then_some(scope),
+                })
             }
-            gix::object::Kind::Tree => Ok(oid),
+            gix::object::Kind::Tree => Ok(DirSource {
+                tree: oid,
+                commit_root: None,
+                scope: None,
+            }),
             _ => Err(io::Error::from_raw_os_error(libc::ENOTDIR)),
         }
     }
 
+    /// Size of blob `oid`, read from its header without decoding it unless
+    /// it may be an LFS pointer to show as the file it stands for.
+    fn blob_size(&self, oid: ObjectId) -> io::Result<u64> {
+        let size = self
+            .repo
+            .thread_local()
+            .find_header(oid)
+            .ok()
+            .filter(|header| header.kind() == Kind::Blob)
+            .map(|header| header.size())
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?;
+        Ok(self
+            .lfs_pointer(oid, size)
+            .map_or(size, |pointer| pointer.size))
+    }
+
+    /// The `.mailmap` identities are mapped through, unless `--no-mailmap`.
+    fn mailmap(&self) -> Option<Arc<gix::mailmap::Snapshot>> {
+        if self.config().ignore_mailmap {
+            return None;
+        }
+        let mut mailmap = self
+            .mailmap
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        Some(Arc::clone(mailmap.get_or_insert_with(|| {
+            Arc::new(self.repo.thread_local().open_mailmap())
+        })))
+    }
+
+    /// The LFS pointer blob `oid` of `size` bytes is, if `--lfs-sparse`
+    /// shows it as a placeholder.
+    fn lfs_pointer(&self, oid: ObjectId, size: u64) -> Option<lfs::Pointer> {
+        if !self.config().lfs_sparse || size > lfs::MAX_POINTER_SIZE {
+            return None;
+        }
+        if let Some(pointer) = self.lfs_pointers.get(&oid) {
+            return pointer;
+        }
+        let pointer = lfs::parse(&self.repo.read_blob(oid).ok()?);
+        self.lfs_pointers.insert(oid, pointer.clone());
+        pointer
+    }
+
+    /// The LFS pointer the file `inode` is a placeholder for, if any.
+    fn lfs_placeholder(&self, inode: u64) -> Option<lfs::Pointer> {
+        if !self.config().lfs_sparse || object_view(inode) == ObjectView::Link {
+            return None;
+        }
+        let oid = self.resolve_object(inode).ok()?;
+        let header = self.repo.thread_local().find_header(oid).ok()?;
+        if header.kind() != Kind::Blob {
+            return None;
+        }
+        self.lfs_pointer(oid, header.size())
+    }
+
+    /// The mapping of the loose blob behind `inode` under `--mmap-loose`, if
+    /// it is stored uncompressed, after the checks a read makes first.
+    fn mapped_blob(&self, inode: u64) -> io::Result<Option<Arc<MappedBlob>>> {
+        let config = self.config();
+        if !config.mmap_loose || config.verify_reads {
+            return Ok(None);
+        }
+        let Ok(oid) = self.resolve_object(inode) else {
+            return Ok(None);
+        };
+        let blob = self.mapped_blobs.get(&oid).unwrap_or_else(|| {
+            let objects = self.repo.thread_local().common_dir().join("objects");
+            let blob = MappedBlob::open(&objects, oid).map(Arc::new);
+            self.mapped_blobs.insert(oid, blob.clone());
+            blob
+        });
+        let Some(blob) = blob else {
+            return Ok(None);
+        };
+        self.check_content_allowed()?;
+        if config
+            .max_file_size
+            .is_some_and(|limit| blob.size() > limit)
+        {
+            return Err(io::Error::from_raw_os_error(libc::EFBIG));
+        }
+        Ok(Some(blob))
+    }
+
     fn entry_for_tree_child(&self, mode: EntryMode, oid: ObjectId) -> io::Result<(Entry, u32)> {
-        let inode = inode_from_oid(&oid);
         let kind = mode.kind();
+        let inode = self.object_inode(
+            &oid,
+            match kind {
+                EntryKind::BlobExecutable => ObjectView::Executable,
+                EntryKind::Link => ObjectView::Link,
+                _ => ObjectView::Plain,
+            },
+        )?;
         let entry = match kind {
-            EntryKind::Tree | EntryKind::Commit => Self::make_entry(
-                inode,
-                build_attr(inode, DIRECTORY_ATTR_MODE, 0, self.mount_time),
-            ),
+            EntryKind::Tree | EntryKind::Commit => {
+                Self::make_entry(inode, self.snapshot_dir_attr(inode, oid))
+            }
             EntryKind::Blob => {
-                let repo = self.repo.thread_local();
-                let blob = repo
-                    .find_blob(oid)
-                    .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
+                let size = self.blob_size(oid)?;
                 Self::make_entry(
                     inode,
-                    build_attr(
-                        inode,
-                        S_IFREG | 0o444,
-                        blob.data.len() as u64,
-                        self.mount_time,
-                    ),
+                    build_attr(inode, S_IFREG | 0o444, size, self.mount_time),
                 )
             }
             EntryKind::BlobExecutable => {
-                let repo = self.repo.thread_local();
-                let blob = repo
-                    .find_blob(oid)
-                    .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
+                let size = self.blob_size(oid)?;
                 Self::make_entry(
                     inode,
-                    build_attr(
-                        inode,
-                        S_IFREG | 0o555,
-                        blob.data.len() as u64,
-                        self.mount_time,
-                    ),
+                    build_attr(inode, S_IFREG | 0o555, size, self.mount_time),
                 )
             }
             EntryKind::Link => {
@@ -232,15 +1302,7 @@ impl GitSnapFs {
                 let blob = repo
                     .find_blob(oid)
                     .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
-                Self::make_entry(
-                    inode,
-                    build_attr(
-                        inode,
-                        SYMLINK_ATTR_MODE,
-                        blob.data.len() as u64,
-                        self.mount_time,
-                    ),
-                )
+                Self::make_entry(inode, self.symlink_attr(inode, &blob.data))
             }
         };
         let dtype = match kind {
@@ -252,114 +1314,1287 @@ impl GitSnapFs {
     }
 
     fn list_root(&self) -> io::Result<Vec<DirRecord>> {
-        let head_entry = self.head_entry()?;
-        Ok(vec![
-            DirRecord {
-                name: b"commits".to_vec(),
-                ino: INODE_COMMITS,
-                dtype: u32::from(libc::DT_DIR),
-                entry: Some(self.synthetic_dir_entry(INODE_COMMITS)),
-            },
-            DirRecord {
-                name: b"trees".to_vec(),
-                ino: INODE_TREES,
-                dtype: u32::from(libc::DT_DIR),
-                entry: Some(self.synthetic_dir_entry(INODE_TREES)),
-            },
-            DirRecord {
-                name: b"branches".to_vec(),
-                ino: INODE_BRANCHES,
-                dtype: u32::from(libc::DT_DIR),
-                entry: Some(self.synthetic_dir_entry(INODE_BRANCHES)),
-            },
-            DirRecord {
-                name: b"tags".to_vec(),
-                ino: INODE_TAGS,
+        // An unborn or hidden HEAD leaves the rest of the mount usable.
+        let head_entry = match self.head_entry() {
+            Ok(entry) => Some(entry),
+            Err(err) if err.raw_os_error() == Some(libc::ENOENT) => None,
+            Err(err) => return Err(err),
+        };
+        let config = self.config();
+        // The synthetic top-level directories, and whether each is shown.
+        let dirs: [(&[u8], u64, bool); 14] = [
+            (b"commits", INODE_COMMITS, true),
+            (b"trees", INODE_TREES, true),
+            (b"objects", INODE_OBJECTS, true),
+            (b"branches", INODE_BRANCHES, true),
+            (b"tags", INODE_TAGS, true),
+            (b"latest", INODE_LATEST, true),
+            (b"refs", INODE_REFS, true),
+            (b"branches-meta", INODE_BRANCHES_META, true),
+            (b"commits-on", INODE_COMMITS_ON, true),
+            (b"at", INODE_AT, true),
+            (b"worktrees", INODE_WORKTREES, true),
+            (b"releases", INODE_RELEASES, config.releases.is_some()),
+            (b"refs-debug", INODE_REFS_DEBUG, config.refs_debug),
+            (CONTROL_DIR_NAME, INODE_CONTROL, self.has_control_dir()),
+        ];
+        let mut records: Vec<DirRecord> = dirs
+            .into_iter()
+            .filter(|&(_, _, shown)| shown)
+            .map(|(name, ino, _)| DirRecord {
+                name: name.to_vec(),
+                ino,
                 dtype: u32::from(libc::DT_DIR),
-                entry: Some(self.synthetic_dir_entry(INODE_TAGS)),
-            },
-            DirRecord {
+                entry: Some(self.synthetic_dir_entry(ino)),
+            })
+            .collect();
+        if let Some(entry) = head_entry {
+            records.push(DirRecord {
                 name: b"HEAD".to_vec(),
                 ino: INODE_HEAD,
                 dtype: u32::from(libc::DT_LNK),
-                entry: Some(head_entry),
-            },
-        ])
+                entry: Some(entry),
+            });
+        }
+        if let Ok(entry) = self.current_entry() {
+            records.push(DirRecord {
+                name: b"current".to_vec(),
+                ino: entry.inode,
+                dtype: u32::from(libc::DT_DIR),
+                entry: Some(entry),
+            });
+        }
+        if config.root_readme {
+            for name in self.head_root_files()? {
+                let entry = self.root_link_entry(&name);
+                records.push(DirRecord {
+                    name,
+                    ino: entry.inode,
+                    dtype: u32::from(libc::DT_LNK),
+                    entry: Some(entry),
+                });
+            }
+        }
+        Ok(records)
     }
 
-    fn list_refs_dir(&self, ns: RefNamespace) -> io::Result<Vec<DirRecord>> {
-        let refs = ns.list(&self.repo)?;
-        refs.into_iter()
-            .map(|(name, object_id)| {
-                let (inode, dtype, entry) =
-                    self.reference_entry_details(ns, name.as_bytes(), object_id)?;
-                Ok(DirRecord {
-                    name: name.into_bytes(),
-                    ino: inode,
-                    dtype,
-                    entry: Some(entry),
-                })
-            })
+    fn list_control_dir(&self) -> io::Result<Vec<DirRecord>> {
+        let mut names: Vec<&[u8]> = Vec::new();
+        if self.stats.is_some() {
+            names.push(b"stats");
+        }
+        if self.health.is_some() {
+            names.push(b"health");
+        }
+        if self.scrub.is_some() {
+            names.push(b"scrub");
+        }
+        if self.error_journal.is_some() {
+            names.push(b"errors");
+        }
+        if self.writes.is_some() {
+            names.push(b"info");
+        }
+        if self.current().is_some() {
+            names.push(b"current");
+        }
+        let mut records = names
+            .into_iter()
+            .map(|name| {
+                let entry = self.lookup_name(INODE_CONTROL, name)?;
+                Ok(DirRecord {
+                    name: name.to_vec(),
+                    ino: entry.inode,
+                    dtype: u32::from(libc::DT_REG),
+                    entry: Some(entry),
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        if !self.config().git_config_keys.is_empty() {
+            records.push(DirRecord {
+                name: b"config".to_vec(),
+                ino: INODE_GIT_CONFIG,
+                dtype: u32::from(libc::DT_DIR),
+                entry: Some(self.synthetic_dir_entry(INODE_GIT_CONFIG)),
+            });
+        }
+        Ok(records)
+    }
+
+    /// Inode of `.gitsnapfs/config/<key>`.
+    fn git_config_inode(key: &str) -> u64 {
+        let mut name = b"config/".to_vec();
+        name.extend_from_slice(key.as_bytes());
+        synthetic_inode(Space::Meta, &name)
+    }
+
+    /// The exposed config key `inode` stands for.
+    fn git_config_key(&self, inode: u64) -> Option<String> {
+        self.config()
+            .git_config_keys
+            .iter()
+            .find(|key| Self::git_config_inode(key) == inode)
+            .cloned()
+    }
+
+    /// Contents of `.gitsnapfs/config/<key>`: each value on a line of its
+    /// own; `ENOENT` if the repository does not set `key`.
+    fn git_config_file(&self, key: &str) -> io::Result<Vec<u8>> {
+        let values = self.repo.config_values(key);
+        if values.is_empty() {
+            return Err(io::Error::from_raw_os_error(libc::ENOENT));
+        }
+        let mut content = Vec::new();
+        for value in values {
+            content.extend_from_slice(&value);
+            content.push(b'\n');
+        }
+        Ok(content)
+    }
+
+    fn git_config_entry(&self, key: &str) -> io::Result<Entry> {
+        let inode = Self::git_config_inode(key);
+        let size = self.git_config_file(key)?.len() as u64;
+        Ok(Self::make_entry(
+            inode,
+            build_attr(inode, S_IFREG | 0o444, size, self.mount_time),
+        ))
+    }
+
+    fn lookup_git_config(&self, name: &[u8]) -> io::Result<Entry> {
+        let config = self.config();
+        let key = config
+            .git_config_keys
+            .iter()
+            .find(|key| key.as_bytes() == name)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?;
+        self.git_config_entry(key)
+    }
+
+    fn list_git_config(&self) -> Vec<DirRecord> {
+        self.config()
+            .git_config_keys
+            .iter()
+            .filter_map(|key| {
+                let entry = self.git_config_entry(key).ok()?;
+                Some(DirRecord {
+                    name: key.clone().into_bytes(),
+                    ino: entry.inode,
+                    dtype: u32::from(libc::DT_REG),
+                    entry: Some(entry),
+                })
+            })
             .collect()
     }
 
+    /// Contents of `refs-debug/refs` or `refs-debug/packed-refs`, read
+    /// from the reference store on every call.
+    fn refs_debug_file(&self, inode: u64) -> io::Result<Vec<u8>> {
+        let repo = self.repo.thread_local();
+        if inode == INODE_PACKED_REFS {
+            return std::fs::read(repo.refs.packed_refs_path());
+        }
+        let records = refs_debug::read(&repo).map_err(io::Error::other)?;
+        let config = self.config();
+        Ok(refs_debug::render(&records, |record| {
+            match (&record.target, record.peeled) {
+                (None, _) => "unparsable",
+                (Some(_), None) => "dangling",
+                (Some(_), Some(_))
+                    if record.name != b"HEAD" && !config.ref_filter.allows(&record.name) =>
+                {
+                    "filtered"
+                }
+                (Some(_), Some(id)) if !self.is_visible(id) => "hidden",
+                (Some(_), Some(_)) => "served",
+            }
+        }))
+    }
+
+    fn refs_debug_entry(&self, inode: u64) -> io::Result<Entry> {
+        let size = self.refs_debug_file(inode)?.len() as u64;
+        Ok(Self::make_entry(
+            inode,
+            build_attr(inode, S_IFREG | 0o444, size, self.mount_time),
+        ))
+    }
+
+    fn list_refs_debug(&self) -> io::Result<Vec<DirRecord>> {
+        let mut records = Vec::new();
+        for (name, inode) in [
+            (&b"refs"[..], INODE_REFS_TABLE),
+            (b"packed-refs", INODE_PACKED_REFS),
+        ] {
+            let entry = match self.refs_debug_entry(inode) {
+                Ok(entry) => entry,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            records.push(DirRecord {
+                name: name.to_vec(),
+                ino: inode,
+                dtype: u32::from(libc::DT_REG),
+                entry: Some(entry),
+            });
+        }
+        Ok(records)
+    }
+
+    fn list_refs_dir(&self, ns: RefNamespace) -> io::Result<Vec<DirRecord>> {
+        let refs = self.refs_in(ns)?;
+        self.note_served_refs(ns, refs.iter().map(|(name, _)| name.clone().into_bytes()));
+        let mut records = Vec::with_capacity(refs.len());
+        for (name, object_id) in refs {
+            let (inode, dtype, entry) =
+                match self.reference_entry_details(ns, name.as_bytes(), object_id) {
+                    Ok(details) => details,
+                    // `latest/` only holds branches that point at commits.
+                    Err(err)
+                        if ns == RefNamespace::Latest
+                            && err.raw_os_error() == Some(libc::ENOENT) =>
+                    {
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                };
+            records.push(DirRecord {
+                name: name.into_bytes(),
+                ino: inode,
+                dtype,
+                entry: Some(entry),
+            });
+        }
+        Ok(records)
+    }
+
+    /// Every reference to a commit or tree as `(path below /refs, peeled
+    /// id)`, each component of the name escaped like other names derived
+    /// from references. Leaving out the others here keeps directories
+    /// holding only references to blobs out of the listing.
+    fn mirrored_refs(&self) -> io::Result<Vec<(Vec<u8>, ObjectId)>> {
+        let refs = self.repo.list_refs().map_err(io::Error::other)?;
+        let config = self.config();
+        let repo = self.repo.thread_local();
+        Ok(refs
+            .into_iter()
+            .filter(|(name, id)| config.ref_filter.allows(name) && self.is_visible(*id))
+            .filter(|(_, id)| {
+                repo.find_header(*id)
+                    .is_ok_and(|header| matches!(header.kind(), Kind::Commit | Kind::Tree))
+            })
+            .map(|(name, id)| {
+                let path = name[b"refs/".len()..]
+                    .split(|&b| b == b'/')
+                    .map(escape_name)
+                    .collect::<Vec<_>>()
+                    .join(&b'/');
+                (path, id)
+            })
+            .collect())
+    }
+
+    fn mirrored_ref_path(&self, inode: u64) -> Option<Vec<u8>> {
+        self.ref_paths
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(&inode)
+            .cloned()
+    }
+
+    /// Entry for `/refs/<path>`: a symlink for a reference, a directory for a
+    /// prefix of longer ones.
+    fn mirrored_ref_entry(
+        &self,
+        path: &[u8],
+        refs: &[(Vec<u8>, ObjectId)],
+    ) -> io::Result<(Entry, u32)> {
+        let inode = synthetic_inode(Space::Refs, path);
+        let details = if let Some((_, id)) = refs.iter().find(|(name, _)| name == path) {
+            let target = self.mirrored_ref_target(path, *id)?;
+            let attr = self.symlink_attr(inode, &target);
+            (Self::make_entry(inode, attr), u32::from(libc::DT_LNK))
+        } else if refs
+            .iter()
+            .any(|(name, _)| path_below(name, path).is_some())
+        {
+            (self.synthetic_dir_entry(inode), u32::from(libc::DT_DIR))
+        } else {
+            return Err(io::Error::from_raw_os_error(libc::ENOENT));
+        };
+        self.ref_paths
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .insert(inode, path.to_vec());
+        Ok(details)
+    }
+
+    /// Link from `/refs/<path>` to the snapshot of `id`; references to
+    /// anything but commits and trees do not appear.
+    fn mirrored_ref_target(&self, path: &[u8], id: ObjectId) -> io::Result<Vec<u8>> {
+        let kind = self
+            .repo
+            .thread_local()
+            .find_header(id)
+            .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?
+            .kind();
+        let dir = match kind {
+            Kind::Commit => "commits",
+            Kind::Tree => "trees",
+            _ => return Err(io::Error::from_raw_os_error(libc::ENOENT)),
+        };
+        let up = "../".repeat(path.split(|&b| b == b'/').count());
+        Ok(format!("{up}{dir}/{}", self.link_name(id)).into_bytes())
+    }
+
+    /// How links name `id` below `commits/` or `trees/`: in full, or
+    /// abbreviated with `--abbrev`. Lookups resolve both.
+    fn link_name(&self, id: ObjectId) -> String {
+        match self.config().abbrev {
+            Some(len) => repo::abbreviate(&self.repo.thread_local(), id, len),
+            None => id.to_string(),
+        }
+    }
+
+    /// `branches-meta/<name>` or `commits-on/<name>`, in the directory
+    /// `parent`, keyed by the branch tip so that a moved branch resolves to
+    /// a new directory, like under `latest/`.
+    fn branch_dir_entry(&self, parent: u64, name: &[u8], tip: ObjectId) -> io::Result<Entry> {
+        let kind = self
+            .repo
+            .thread_local()
+            .find_header(tip)
+            .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?
+            .kind();
+        if kind != Kind::Commit {
+            return Err(io::Error::from_raw_os_error(libc::ENOENT));
+        }
+        let node = if parent == INODE_COMMITS_ON {
+            MetaNode::History(tip)
+        } else {
+            MetaNode::BranchMeta(tip)
+        };
+        let mut entry = self.meta_entry(node)?;
+        entry.entry_timeout = LATEST_TTL;
+        self.note_tip(parent, name, tip);
+        Ok(entry)
+    }
+
+    fn lookup_branch_dir(&self, parent: u64, name: &[u8]) -> io::Result<Entry> {
+        let tip = self
+            .refs_in(RefNamespace::Branches)?
+            .into_iter()
+            .find(|(branch, _)| branch.as_bytes() == name)
+            .map(|(_, tip)| tip)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?;
+        let entry = self.branch_dir_entry(parent, name, tip)?;
+        self.note_served(parent, [name.to_vec()]);
+        Ok(entry)
+    }
+
+    fn list_branch_dirs(&self, parent: u64) -> io::Result<Vec<DirRecord>> {
+        let branches = self.refs_in(RefNamespace::Branches)?;
+        self.note_served(
+            parent,
+            branches.iter().map(|(name, _)| name.clone().into_bytes()),
+        );
+        let mut records = Vec::with_capacity(branches.len());
+        for (name, tip) in branches {
+            let entry = match self.branch_dir_entry(parent, name.as_bytes(), tip) {
+                Ok(entry) => entry,
+                // Only branches pointing at commits have a changelog or history.
+                Err(err) if err.raw_os_error() == Some(libc::ENOENT) => continue,
+                Err(err) => return Err(err),
+            };
+            records.push(DirRecord {
+                name: name.into_bytes(),
+                ino: entry.inode,
+                dtype: u32::from(libc::DT_DIR),
+                entry: Some(entry),
+            });
+        }
+        Ok(records)
+    }
+
+    /// Look up `name` in the `/refs` mirror directory `parent`, which is
+    /// `/refs` itself or the directory at `prefix` below it.
+    fn lookup_mirrored_ref(
+        &self,
+        parent: u64,
+        prefix: Option<&[u8]>,
+        name: &[u8],
+    ) -> io::Result<Entry> {
+        let path = match prefix {
+            None => name.to_vec(),
+            Some(prefix) => [prefix, name].join(&b'/'),
+        };
+        let (entry, _) = self.mirrored_ref_entry(&path, &self.mirrored_refs()?)?;
+        self.note_served(parent, [name.to_vec()]);
+        Ok(entry)
+    }
+
+    fn list_mirrored_refs(&self, inode: u64, prefix: Option<&[u8]>) -> io::Result<Vec<DirRecord>> {
+        let refs = self.mirrored_refs()?;
+        let mut children: Vec<&[u8]> = Vec::new();
+        for (name, _) in &refs {
+            let rest = match prefix {
+                None => name.as_slice(),
+                Some(prefix) => match path_below(name, prefix) {
+                    Some(rest) => rest,
+                    None => continue,
+                },
+            };
+            let child = rest.split(|&b| b == b'/').next().unwrap_or(rest);
+            if !children.contains(&child) {
+                children.push(child);
+            }
+        }
+        self.note_served(inode, children.iter().map(|child| child.to_vec()));
+        let mut records = Vec::with_capacity(children.len());
+        for child in children {
+            let path = match prefix {
+                None => child.to_vec(),
+                Some(prefix) => [prefix, child].join(&b'/'),
+            };
+            let (entry, dtype) = match self.mirrored_ref_entry(&path, &refs) {
+                Ok(details) => details,
+                Err(err) if err.raw_os_error() == Some(libc::ENOENT) => continue,
+                Err(err) => return Err(err),
+            };
+            records.push(DirRecord {
+                name: child.to_vec(),
+                ino: entry.inode,
+                dtype,
+                entry: Some(entry),
+            });
+        }
+        Ok(records)
+    }
+
+    /// The tags naming commits, arranged by the versions `--release-pattern`
+    /// finds in their names.
+    fn releases(&self) -> io::Result<Releases> {
+        let config = self.config();
+        let Some(pattern) = &config.releases else {
+            return Ok(Releases::default());
+        };
+        let repo = self.repo.thread_local();
+        let tags = self.refs_in(RefNamespace::Tags)?;
+        let commits = tags.iter().filter(|(name, id)| {
+            pattern.version(name).is_some()
+                && repo
+                    .find_header(*id)
+                    .is_ok_and(|header| header.kind() == Kind::Commit)
+        });
+        Ok(Releases::new(
+            pattern,
+            commits.map(|(name, id)| (name.as_str(), *id)),
+        ))
+    }
+
+    fn release_path(&self, inode: u64) -> Option<Vec<u8>> {
+        self.release_paths
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(&inode)
+            .cloned()
+    }
+
+    /// Entry for `/releases/<path>`: a directory for a major or minor
+    /// version, a symlink for a release or a `latest` link.
+    fn release_entry(&self, path: &[u8], releases: &Releases) -> io::Result<(Entry, u32)> {
+        let mut key = b"releases/".to_vec();
+        key.extend_from_slice(path);
+        let inode = synthetic_inode(Space::Meta, &key);
+        let details = match releases.node(path) {
+            Some(ReleaseNode::Dir) => (self.synthetic_dir_entry(inode), u32::from(libc::DT_DIR)),
+            Some(node) => {
+                let attr = self.symlink_attr(inode, &self.release_target(path, node));
+                (Self::make_entry(inode, attr), u32::from(libc::DT_LNK))
+            }
+            None => return Err(io::Error::from_raw_os_error(libc::ENOENT)),
+        };
+        self.release_paths
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .insert(inode, path.to_vec());
+        Ok(details)
+    }
+
+    /// Where the link `/releases/<path>` standing for `node` points.
+    fn release_target(&self, path: &[u8], node: ReleaseNode) -> Vec<u8> {
+        match node {
+            ReleaseNode::Release(id) => {
+                let up = "../".repeat(path.split(|&b| b == b'/').count());
+                format!("{up}commits/{}", self.link_name(id)).into_bytes()
+            }
+            ReleaseNode::Latest(target) => target,
+            ReleaseNode::Dir => Vec::new(),
+        }
+    }
+
+    fn lookup_release(&self, parent: u64, prefix: Option<&[u8]>, name: &[u8]) -> io::Result<Entry> {
+        let path = match prefix {
+            None => name.to_vec(),
+            Some(prefix) => [prefix, name].join(&b'/'),
+        };
+        let (entry, _) = self.release_entry(&path, &self.releases()?)?;
+        self.note_served(parent, [name.to_vec()]);
+        Ok(entry)
+    }
+
+    fn list_releases(&self, inode: u64, prefix: Option<&[u8]>) -> io::Result<Vec<DirRecord>> {
+        let releases = self.releases()?;
+        let children = releases
+            .children(prefix.unwrap_or_default())
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?;
+        self.note_served(inode, children.iter().cloned());
+        let mut records = Vec::with_capacity(children.len());
+        for child in children {
+            let path = match prefix {
+                None => child.clone(),
+                Some(prefix) => [prefix, &child].join(&b'/'),
+            };
+            let (entry, dtype) = self.release_entry(&path, &releases)?;
+            records.push(DirRecord {
+                name: child,
+                ino: entry.inode,
+                dtype,
+                entry: Some(entry),
+            });
+        }
+        Ok(records)
+    }
+
     fn list_tree_dir(&self, inode: u64) -> io::Result<Vec<DirRecord>> {
-        let tree_id = self.tree_root_id(inode)?;
+        let source = self.directory_source(inode)?;
         let repo = self.repo.thread_local();
         let tree = repo
-            .find_tree(tree_id)
+            .find_tree(source.tree)
             .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
-        let records = tree
-            .iter()
-            .map(|entry| {
-                let entry = entry.map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
-                let oid = entry.inner.oid.to_owned();
-                let (child_entry, dtype) = self.entry_for_tree_child(entry.inner.mode, oid)?;
-                Ok(DirRecord {
-                    name: entry.inner.filename.as_bstr().to_vec(),
-                    ino: child_entry.inode,
-                    dtype,
-                    entry: Some(child_entry),
-                })
-            })
-            .collect::<io::Result<Vec<_>>>()?;
+        let mut records = Vec::new();
+        for entry in tree.iter() {
+            let entry = entry.map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
+            let name = entry.inner.filename.as_bytes();
+            // A tracked `.git-meta` in a snapshot root is shadowed by ours, as on lookup.
+            if source.commit_root.is_some() && name == META_DIR_NAME {
+                continue;
+            }
+            let oid = entry.inner.oid.to_owned();
+            let Some((child_entry, dtype)) =
+                self.scoped_tree_child(&source, name, entry.inner.mode, oid)?
+            else {
+                continue;
+            };
+            records.push(DirRecord {
+                name: name.to_vec(),
+                ino: child_entry.inode,
+                dtype,
+                entry: Some(child_entry),
+            });
+        }
+        if let Some(commit) = source.commit_root {
+            let entry = self.meta_entry(MetaNode::Root(commit))?;
+            records.push(DirRecord {
+                name: META_DIR_NAME.to_vec(),
+                ino: entry.inode,
+                dtype: u32::from(libc::DT_DIR),
+                entry: Some(entry),
+            });
+            let injected =
+                self.injected_files(commit, |name| records.iter().any(|r| r.name == name));
+            for (name, node) in injected {
+                let entry = self.meta_entry(node)?;
+                records.push(DirRecord {
+                    name,
+                    ino: entry.inode,
+                    dtype: u32::from(libc::DT_REG),
+                    entry: Some(entry),
+                });
+            }
+        }
         Ok(records)
     }
 
-    fn list_directory(&self, inode: u64) -> io::Result<Vec<DirRecord>> {
-        match inode {
-            ROOT_ID => self.list_root(),
-            INODE_COMMITS => Err(io::Error::new(
+    pub(crate) fn list_directory(&self, inode: u64) -> io::Result<Vec<DirRecord>> {
+        let mut records = self.list_directory_unsorted(inode)?;
+        match self.config().sort {
+            SortOrder::Git => {}
+            SortOrder::Name => records.sort_by(|a, b| a.name.cmp(&b.name)),
+            SortOrder::Mtime => records.sort_by_cached_key(|record| {
+                let mtime = record
+                    .entry
+                    .as_ref()
+                    .map_or(self.mount_time.0, |entry| entry.attr.st_mtime);
+                (mtime, record.name.clone())
+            }),
+        }
+        Ok(records)
+    }
+
+    fn list_directory_unsorted(&self, inode: u64) -> io::Result<Vec<DirRecord>> {
+        match self.node_kind(inode)? {
+            NodeKind::Root => self.list_root(),
+            NodeKind::TopDir(TopDir::Commits) => Err(io::Error::new(
                 io::ErrorKind::Unsupported,
                 "enumerating the commits directory is not supported",
             )),
-            INODE_TREES => Err(io::Error::new(
+            NodeKind::TopDir(TopDir::Trees) => Err(io::Error::new(
                 io::ErrorKind::Unsupported,
                 "enumerating the trees directory is not supported",
             )),
-            INODE_BRANCHES => self.list_refs_dir(RefNamespace::Branches),
-            INODE_TAGS => self.list_refs_dir(RefNamespace::Tags),
-            _ => self.list_tree_dir(inode),
+            NodeKind::TopDir(TopDir::Objects) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "enumerating the objects directory is not supported",
+            )),
+            NodeKind::TopDir(TopDir::At) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "enumerating the at directory is not supported",
+            )),
+            NodeKind::TopDir(TopDir::Worktrees) => self.list_worktrees(),
+            NodeKind::TopDir(TopDir::Releases) => self.list_releases(inode, None),
+            NodeKind::TopDir(TopDir::RefsDebug) => self.list_refs_debug(),
+            NodeKind::Release(path) => self.list_releases(inode, Some(&path)),
+            NodeKind::TopDir(TopDir::Refs(ns)) => self.list_refs_dir(ns),
+            NodeKind::TopDir(TopDir::RefMirror) => self.list_mirrored_refs(inode, None),
+            NodeKind::TopDir(TopDir::BranchesMeta) => self.list_branch_dirs(INODE_BRANCHES_META),
+            NodeKind::TopDir(TopDir::CommitsOn) => self.list_branch_dirs(INODE_COMMITS_ON),
+            NodeKind::TopDir(TopDir::Control) => self.list_control_dir(),
+            NodeKind::TopDir(TopDir::GitConfig) => Ok(self.list_git_config()),
+            NodeKind::MirroredRef(path) => self.list_mirrored_refs(inode, Some(&path)),
+            NodeKind::Synthetic(node) => self.list_meta_dir(&node),
+            NodeKind::Scoped(_) | NodeKind::Object(_) => self.list_tree_dir(inode),
+            NodeKind::Head
+            | NodeKind::Stats
+            | NodeKind::Health
+            | NodeKind::Errors
+            | NodeKind::Info
+            | NodeKind::Current
+            | NodeKind::Scrub
+            | NodeKind::GitConfig(_)
+            | NodeKind::RefsTable
+            | NodeKind::PackedRefs
+            | NodeKind::RootLink(_)
+            | NodeKind::RefLink(_)
+            | NodeKind::AtLink(_)
+            | NodeKind::WorktreeLink(_) => Err(io::Error::from_raw_os_error(libc::ENOTDIR)),
         }
     }
 
     fn lookup_child(&self, parent: u64, name: &[u8]) -> io::Result<Entry> {
-        let tree_id = self.tree_root_id(parent)?;
+        let source = self.directory_source(parent)?;
+        if let (Some(commit), META_DIR_NAME) = (source.commit_root, name) {
+            return self.meta_entry(MetaNode::Root(commit));
+        }
         let repo = self.repo.thread_local();
         let tree = repo
-            .find_tree(tree_id)
+            .find_tree(source.tree)
             .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
         for entry in tree.iter() {
             let entry = entry.map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
             if entry.inner.filename.as_bytes() == name {
                 let oid = entry.inner.oid.to_owned();
-                let (child_entry, _) = self.entry_for_tree_child(entry.inner.mode, oid)?;
-                return Ok(child_entry);
+                return self
+                    .scoped_tree_child(&source, name, entry.inner.mode, oid)?
+                    .map(|(child_entry, _)| child_entry)
+                    .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT));
+            }
+        }
+        if let Some(commit) = source.commit_root {
+            if let Some((_, node)) = self
+                .injected_files(commit, |_| false)
+                .into_iter()
+                .find(|(injected, _)| injected == name)
+            {
+                return self.meta_entry(node);
             }
         }
         Err(io::Error::from_raw_os_error(libc::ENOENT))
     }
 
+    /// Build the entry for a tree child, applying the directory's path scope.
+    ///
+    /// Returns `None` for hidden children. Subdirectories that still contain
+    /// hidden descendants or submodules get a scoped inode so their listing can
+    /// be filtered too, and gitlinks become submodule placeholders.
+    fn scoped_tree_child(
+        &self,
+        source: &DirSource,
+        name: &[u8],
+        mode: EntryMode,
+        oid: ObjectId,
+    ) -> io::Result<Option<(Entry, u32)>> {
+        let Some(scope) = &source.scope else {
+            if mode.is_commit() {
+                return self.gitlink_entry(Gitlink {
+                    commit: oid,
+                    root: None,
+                    path: name.to_vec(),
+                });
+            }
+            return self.entry_for_tree_child(mode, oid).map(Some);
+        };
+        let mut path = scope.path.clone();
+        if !path.is_empty() {
+            path.push(b'/');
+        }
+        path.extend_from_slice(name);
+        if scope.hidden.contains(&path) {
+            return Ok(None);
+        }
+        if mode.is_commit() {
+            return self.gitlink_entry(Gitlink {
+                commit: oid,
+                root: Some(scope.root),
+                path,
+            });
+        }
+        let overrides = &self.config().mode_overrides;
+        if let (Some(file_mode), EntryKind::Blob | EntryKind::BlobExecutable) =
+            (overrides.mode_for(&path), mode.kind())
+        {
+            return self.moded_file_entry(oid, file_mode).map(Some);
+        }
+        // Any directory may hold paths the overrides match.
+        if !mode.is_tree() || (overrides.is_empty() && !scope.has_scoped_descendants(&path)) {
+            return self.entry_for_tree_child(mode, oid).map(Some);
+        }
+        let mut key = scope.root.as_bytes().to_vec();
+        key.extend_from_slice(&path);
+        let inode = synthetic_inode(Space::Scoped, &key);
+        let dir = ScopedDir {
+            root: scope.root,
+            path,
+            tree: oid,
+        };
+        let attr = self.scoped_dir_attr(inode, &dir);
+        self.scoped_dirs
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .insert(inode, dir);
+        Ok(Some((
+            Self::make_entry(inode, attr),
+            u32::from(libc::DT_DIR),
+        )))
+    }
+
+    /// Blob `oid` served with the permission bits of a `--mode-override`.
+    fn moded_file_entry(&self, oid: ObjectId, mode: u32) -> io::Result<(Entry, u32)> {
+        let index = self
+            .config()
+            .mode_overrides
+            .modes()
+            .iter()
+            .position(|&known| known == mode)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::EIO))?;
+        let inode = mode_inode(self.object_inode(&oid, ObjectView::Plain)?, index);
+        let size = self.blob_size(oid)?;
+        Ok((
+            Self::make_entry(
+                inode,
+                build_attr(inode, S_IFREG | mode, size, self.mount_time),
+            ),
+            u32::from(libc::DT_REG),
+        ))
+    }
+
+    /// Attributes of a scoped directory, with the mode of a `--mode-override`
+    /// matching its path.
+    fn scoped_dir_attr(&self, inode: u64, dir: &ScopedDir) -> stat64 {
+        let mut attr = self.snapshot_dir_attr(inode, dir.tree);
+        if let Some(mode) = self.config().mode_overrides.mode_for(&dir.path) {
+            attr.st_mode = S_IFDIR | mode;
+        }
+        attr
+    }
+
+    fn scoped_dir(&self, inode: u64) -> Option<ScopedDir> {
+        self.scoped_dirs
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(&inode)
+            .cloned()
+    }
+
+    /// Placeholder directory, or checkout symlink, for a gitlink.
+    fn gitlink_entry(&self, gitlink: Gitlink) -> io::Result<Option<(Entry, u32)>> {
+        let (node, dtype) = if self.submodule_checkout(&gitlink)?.is_some() {
+            (MetaNode::SubmoduleCheckout(gitlink), libc::DT_LNK)
+        } else {
+            (MetaNode::Submodule(gitlink), libc::DT_DIR)
+        };
+        Ok(Some((self.meta_entry(node)?, u32::from(dtype))))
+    }
+
+    /// What `.gitmodules` of the gitlink's snapshot says about it.
+    fn declared_submodule(&self, gitlink: &Gitlink) -> io::Result<Option<submodule::Declared>> {
+        let Some(root) = gitlink.root else {
+            return Ok(None);
+        };
+        Ok(self.declared_submodules(root)?.get(&gitlink.path).cloned())
+    }
+
+    /// Checkout attached to the gitlink with `--submodule-path`, if any.
+    fn submodule_checkout(&self, gitlink: &Gitlink) -> io::Result<Option<submodule::Checkout>> {
+        let config = self.config();
+        if config.submodule_checkouts.is_empty() {
+            return Ok(None);
+        }
+        let declared = self.declared_submodule(gitlink)?;
+        Ok(submodule::checkout_for(
+            &config.submodule_checkouts,
+            &gitlink.path,
+            declared.as_ref().map(|declared| declared.name.as_slice()),
+        )
+        .cloned())
+    }
+
+    /// Path scope of the directory at `path` in the snapshot rooted at `root_tree`.
+    fn scope(&self, root_tree: ObjectId, path: Vec<u8>) -> io::Result<Scope> {
+        Ok(Scope {
+            root: root_tree,
+            path,
+            hidden: self.hidden_paths(root_tree)?,
+            submodules: self.declared_submodules(root_tree)?,
+        })
+    }
+
+    /// Submodules declared by the `.gitmodules` file of the snapshot rooted at `root_tree`.
+    fn declared_submodules(&self, root_tree: ObjectId) -> io::Result<Arc<submodule::Declarations>> {
+        if let Some(declared) = self.declared_submodules.get(&root_tree) {
+            return Ok(declared);
+        }
+        let declared = Arc::new(
+            submodule::declared(&self.repo.thread_local(), root_tree).map_err(io::Error::other)?,
+        );
+        self.declared_submodules
+            .insert(root_tree, Arc::clone(&declared));
+        Ok(declared)
+    }
+
+    /// Paths hidden from the snapshot rooted at `root_tree` under the current configuration.
+    fn hidden_paths(&self, root_tree: ObjectId) -> io::Result<Arc<BTreeSet<Vec<u8>>>> {
+        if !self.config().respect_export_ignore {
+            return Ok(Arc::default());
+        }
+        if let Some(hidden) = self.hidden_paths.get(&root_tree) {
+            return Ok(hidden);
+        }
+        let hidden = Arc::new(
+            export_ignored_paths(
+                &self.repo.thread_local(),
+                root_tree,
+                self.config().tree_limits,
+            )
+            .map_err(walk_error)?,
+        );
+        self.hidden_paths.insert(root_tree, Arc::clone(&hidden));
+        Ok(hidden)
+    }
+
+    /// Free what the registries hold for `inodes`, which the kernel has
+    /// forgotten. A later lookup registers them again.
+    fn release_inodes(&self, inodes: &[u64]) {
+        for &inode in inodes {
+            match Space::of(inode) {
+                Some(Space::Meta) => {
+                    self.meta_nodes
+                        .write()
+                        .unwrap_or_else(std::sync::PoisonError::into_inner)
+                        .remove(&inode);
+                    self.root_links
+                        .write()
+                        .unwrap_or_else(std::sync::PoisonError::into_inner)
+                        .remove(&inode);
+                    self.at_links
+                        .write()
+                        .unwrap_or_else(std::sync::PoisonError::into_inner)
+                        .remove(&inode);
+                    self.worktree_links
+                        .write()
+                        .unwrap_or_else(std::sync::PoisonError::into_inner)
+                        .remove(&inode);
+                    self.release_paths
+                        .write()
+                        .unwrap_or_else(std::sync::PoisonError::into_inner)
+                        .remove(&inode);
+                }
+                Some(Space::Scoped) => {
+                    self.scoped_dirs
+                        .write()
+                        .unwrap_or_else(std::sync::PoisonError::into_inner)
+                        .remove(&inode);
+                }
+                Some(Space::Refs) => {
+                    self.ref_paths
+                        .write()
+                        .unwrap_or_else(std::sync::PoisonError::into_inner)
+                        .remove(&inode);
+                }
+                _ => {}
+            }
+            if let Some(stats) = &self.stats {
+                stats.forget(inode);
+            }
+        }
+        if self.tracks_paths() {
+            let mut paths = self
+                .lookup_paths
+                .write()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            for inode in inodes {
+                paths.remove(inode);
+            }
+        }
+        if self.authorizer.is_some() {
+            let mut paths = self
+                .walked_paths
+                .write()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            for inode in inodes {
+                paths.remove(inode);
+            }
+        }
+    }
+
+    fn meta_node(&self, inode: u64) -> Option<MetaNode> {
+        self.meta_nodes
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(&inode)
+            .cloned()
+    }
+
+    /// Register `node` under its synthetic inode and build the matching entry.
+    fn meta_entry(&self, node: MetaNode) -> io::Result<Entry> {
+        let inode = synthetic_inode(Space::Meta, &node.key());
+        let attr = match node.kind() {
+            MetaKind::Dir => build_attr(inode, DIRECTORY_ATTR_MODE, 0, self.mount_time),
+            MetaKind::Link => {
+                let target = self
+                    .meta_link_target(&node)?
+                    .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?;
+                self.symlink_attr(inode, &target)
+            }
+            MetaKind::File => {
+                let content = self.meta_file_content(&node)?;
+                build_attr(
+                    inode,
+                    S_IFREG | 0o444,
+                    content.len() as u64,
+                    self.mount_time,
+                )
+            }
+        };
+        self.meta_nodes
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .insert(inode, node);
+        Ok(Self::make_entry(inode, attr))
+    }
+
+    /// Symlink target for `node`, including attached submodule checkouts.
+    fn meta_link_target(&self, node: &MetaNode) -> io::Result<Option<Vec<u8>>> {
+        let MetaNode::SubmoduleCheckout(gitlink) = node else {
+            return Ok(meta::link_target(
+                &self.repo.thread_local(),
+                node,
+                self.config().abbrev,
+            ));
+        };
+        Ok(self.submodule_checkout(gitlink)?.map(|checkout| {
+            std::fs::canonicalize(&checkout.path)
+                .unwrap_or_else(|_| checkout.path.clone())
+                .as_os_str()
+                .as_bytes()
+                .to_vec()
+        }))
+    }
+
+    fn meta_children(&self, node: &MetaNode) -> io::Result<Vec<(Vec<u8>, MetaNode)>> {
+        let repo = self.repo.thread_local();
+        let commit = node.commit();
+        let children = match node {
+            MetaNode::Root(_) => {
+                let parents = meta::parent_ids(&repo, commit).map_err(io::Error::other)?;
+                let mut children = vec![
+                    (DISK_USAGE_NAME.to_vec(), MetaNode::DiskUsage(commit)),
+                    (MANIFEST_NAME.to_vec(), MetaNode::Manifest(commit)),
+                    (COMMIT_JSON_NAME.to_vec(), MetaNode::CommitJson(commit)),
+                    (b"parents".to_vec(), MetaNode::ParentsDir(commit)),
+                ];
+                if parents.len() > 1 {
+                    children.push((b"conflicts".to_vec(), MetaNode::ConflictsDir(commit)));
+                }
+                if repo::is_shallow_boundary(&repo, commit) {
+                    children.push((SHALLOW_NAME.to_vec(), MetaNode::Shallow(commit)));
+                }
+                children
+            }
+            MetaNode::ParentsDir(_) => {
+                let parents = meta::parent_ids(&repo, commit).map_err(io::Error::other)?;
+                (1..=parents.len())
+                    .map(|index| {
+                        (
+                            index.to_string().into_bytes(),
+                            MetaNode::Parent(commit, index),
+                        )
+                    })
+                    .collect()
+            }
+            MetaNode::ConflictsDir(_) => self
+                .conflict_paths(commit)?
+                .iter()
+                .map(|path| (escape_name(path), MetaNode::Conflict(commit, path.clone())))
+                .collect(),
+            MetaNode::Submodule(gitlink) => vec![(
+                submodule::INFO_NAME.to_vec(),
+                MetaNode::SubmoduleInfo(gitlink.clone()),
+            )],
+            MetaNode::BranchMeta(_) => {
+                vec![(CHANGELOG_NAME.to_vec(), MetaNode::Changelog(commit))]
+            }
+            MetaNode::History(_) => {
+                let depth = self
+                    .config()
+                    .history_depth
+                    .unwrap_or(meta::DEFAULT_HISTORY_DEPTH);
+                let ancestors =
+                    meta::first_parents(&repo, commit, depth).map_err(io::Error::other)?;
+                // The chain leaves `--only-descendants-of` history for good.
+                let visible = ancestors
+                    .iter()
+                    .take_while(|&&ancestor| self.is_visible(ancestor))
+                    .count();
+                (0..visible)
+                    .map(|n| (n.to_string().into_bytes(), MetaNode::Ancestor(commit, n)))
+                    .collect()
+            }
+            MetaNode::ObjectDir(blob) => {
+                vec![(b"referenced-by".to_vec(), MetaNode::ReferencedBy(*blob))]
+            }
+            MetaNode::ReferencedBy(blob) => self
+                .referrers(*blob)?
+                .into_iter()
+                .map(|id| (id.to_string().into_bytes(), MetaNode::Referrer(*blob, id)))
+                .collect(),
+            MetaNode::Parent(..)
+            | MetaNode::Ancestor(..)
+            | MetaNode::Conflict(..)
+            | MetaNode::Injected(..)
+            | MetaNode::DotGit(_)
+            | MetaNode::DiskUsage(_)
+            | MetaNode::Manifest(_)
+            | MetaNode::CommitJson(_)
+            | MetaNode::Shallow(_)
+            | MetaNode::SubmoduleInfo(_)
+            | MetaNode::SubmoduleCheckout(_)
+            | MetaNode::Changelog(_)
+            | MetaNode::Referrer(..) => return Err(io::Error::from_raw_os_error(libc::ENOTDIR)),
+        };
+        Ok(children)
+    }
+
+    /// Paths of the merge `commit` that differ from every parent, diffed
+    /// once per commit rather than on every lookup below `conflicts/`.
+    fn conflict_paths(&self, commit: ObjectId) -> io::Result<Arc<BTreeSet<Vec<u8>>>> {
+        if let Some(paths) = self.conflicts.get(&commit) {
+            return Ok(paths);
+        }
+        let paths = Arc::new(
+            meta::conflict_paths(&self.repo.thread_local(), commit, self.config().tree_limits)
+                .map_err(walk_error)?,
+        );
+        self.conflicts.insert(commit, Arc::clone(&paths));
+        Ok(paths)
+    }
+
+    fn list_meta_dir(&self, node: &MetaNode) -> io::Result<Vec<DirRecord>> {
+        self.meta_children(node)?
+            .into_iter()
+            .map(|(name, child)| {
+                let dtype = match child.kind() {
+                    MetaKind::Dir => libc::DT_DIR,
+                    MetaKind::Link => libc::DT_LNK,
+                    MetaKind::File => libc::DT_REG,
+                };
+                let entry = self.meta_entry(child)?;
+                Ok(DirRecord {
+                    name,
+                    ino: entry.inode,
+                    dtype: u32::from(dtype),
+                    entry: Some(entry),
+                })
+            })
+            .collect()
+    }
+
+    /// Targets of the tags passing `--tag-filter`, for changelogs.
+    fn tagged_commits(&self) -> io::Result<HashSet<ObjectId>> {
+        let config = self.config();
+        Ok(self
+            .repo
+            .list_refs()
+            .map_err(io::Error::other)?
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(b"refs/tags/") && config.ref_filter.allows(name))
+            .map(|(_, id)| id)
+            .collect())
+    }
+
+    fn meta_file_content(&self, node: &MetaNode) -> io::Result<Arc<[u8]>> {
+        match node {
+            MetaNode::Injected(commit, index) => self.injected_content(*commit, *index),
+            MetaNode::DotGit(_) => self.dotgit_content().map(Arc::from),
+            MetaNode::DiskUsage(commit) => self.disk_usage_content(*commit).map(Arc::from),
+            MetaNode::Manifest(commit) => self.manifest_content(*commit),
+            MetaNode::CommitJson(commit) => meta::commit_json(
+                &self.repo.thread_local(),
+                *commit,
+                self.mailmap().as_deref(),
+            )
+            .map(Arc::from)
+            .map_err(io::Error::other),
+            MetaNode::Shallow(commit) => meta::shallow_marker(&self.repo.thread_local(), *commit)
+                .map(Arc::from)
+                .map_err(io::Error::other),
+            MetaNode::Changelog(tip) => self.changelog_content(*tip),
+            MetaNode::SubmoduleInfo(gitlink) => {
+                let declared = self.declared_submodule(gitlink)?;
+                Ok(Arc::from(submodule::info_file(
+                    &gitlink.path,
+                    gitlink.commit,
+                    declared.as_ref(),
+                )))
+            }
+            _ => Err(io::Error::from_raw_os_error(libc::EISDIR)),
+        }
+    }
+
+    /// The synthetic file configured at `index`, rendered for `commit`.
+    fn injected_content(&self, commit: ObjectId, index: usize) -> io::Result<Arc<[u8]>> {
+        if let Some(content) = self.injected.get(&(commit, index)) {
+            return Ok(content);
+        }
+        let config = self.config();
+        let file = config
+            .synthetic_files
+            .get(index)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?;
+        let content: Arc<[u8]> = file
+            .template
+            .render(&self.repo.thread_local(), commit, self.mailmap().as_deref())
+            .map_err(io::Error::other)?
+            .into();
+        self.injected.insert((commit, index), Arc::clone(&content));
+        Ok(content)
+    }
+
+    /// The `gitdir:` line of a fake `.git` file.
+    fn dotgit_content(&self) -> io::Result<Vec<u8>> {
+        let target = match self.config().fake_dotgit {
+            Some(FakeDotGit::Repo) => {
+                let git_dir = self.repo.thread_local().git_dir().to_path_buf();
+                std::fs::canonicalize(&git_dir).unwrap_or(git_dir)
+            }
+            Some(FakeDotGit::Stub) => PathBuf::from("/dev/null"),
+            None => return Err(io::Error::from_raw_os_error(libc::ENOENT)),
+        };
+        let mut content = b"gitdir: ".to_vec();
+        content.extend_from_slice(target.as_os_str().as_bytes());
+        content.push(b'\n');
+        Ok(content)
+    }
+
+    /// The `DU` file of `commit`.
+    fn disk_usage_content(&self, commit: ObjectId) -> io::Result<Vec<u8>> {
+        // Measure without holding the memo, so other snapshots' `DU`
+        // reads are not stuck behind a walk of a large tree.
+        let (content, measured) = meta::disk_usage(
+            &self.repo.thread_local(),
+            commit,
+            &|tree| self.tree_usage.get(tree),
+            self.config().tree_limits,
+        )
+        .map_err(walk_error)?;
+        self.tree_usage.extend(measured);
+        Ok(content)
+    }
+
+    /// The `MANIFEST` file of `commit`.
+    fn manifest_content(&self, commit: ObjectId) -> io::Result<Arc<[u8]>> {
+        if let Some(manifest) = self.manifests.get(&commit) {
+            return Ok(manifest);
+        }
+        let manifest: Arc<[u8]> =
+            meta::manifest(&self.repo.thread_local(), commit, self.config().tree_limits)
+                .map_err(walk_error)?
+                .into();
+        self.manifests.insert(commit, Arc::clone(&manifest));
+        Ok(manifest)
+    }
+
+    /// The `CHANGELOG` of the branch at `tip`.
+    fn changelog_content(&self, tip: ObjectId) -> io::Result<Arc<[u8]>> {
+        // A tag added to the history since changes where the listing stops.
+        let tagged = self.tagged_commits()?;
+        let key = (tip, fingerprint(&tagged));
+        if let Some(changelog) = self.changelogs.get(&key) {
+            return Ok(changelog);
+        }
+        let config = self.config();
+        let template = config
+            .changelog_template
+            .as_deref()
+            .unwrap_or(meta::DEFAULT_CHANGELOG_TEMPLATE);
+        let changelog: Arc<[u8]> = meta::changelog(
+            &self.repo.thread_local(),
+            tip,
+            &tagged,
+            template,
+            self.mailmap().as_deref(),
+            config.first_parent,
+        )
+        .map_err(io::Error::other)?
+        .into();
+        self.changelogs.insert(key, Arc::clone(&changelog));
+        Ok(changelog)
+    }
+
+    /// Configured synthetic files for the root of `commit`, minus those shadowed by real entries.
+    fn injected_files(
+        &self,
+        commit: ObjectId,
+        taken: impl Fn(&[u8]) -> bool,
+    ) -> Vec<(Vec<u8>, MetaNode)> {
+        let dotgit = self
+            .config()
+            .fake_dotgit
+            .map(|_| (DOTGIT_NAME.to_vec(), MetaNode::DotGit(commit)));
+        self.config()
+            .synthetic_files
+            .iter()
+            .enumerate()
+            .map(|(index, file)| {
+                (
+                    file.name.clone().into_bytes(),
+                    MetaNode::Injected(commit, index),
+                )
+            })
+            .chain(dotgit)
+            .filter(|(name, _)| !taken(name))
+            .collect()
+    }
+
+    fn lookup_meta(&self, node: &MetaNode, name: &[u8]) -> io::Result<Entry> {
+        let child = self
+            .meta_children(node)?
+            .into_iter()
+            .find(|(child_name, _)| child_name == name)
+            .map(|(_, child)| child)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?;
+        self.meta_entry(child)
+    }
+
     fn reference_entry_details(
         &self,
         ns: RefNamespace,
@@ -371,47 +2606,45 @@ impl GitSnapFs {
             .find_object(object_id)
             .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
         match object.kind {
+            // The branch directory *is* the tip's snapshot: sharing the commit
+            // inode means a moved branch resolves to a different inode on the
+            // next lookup, so the kernel drops the old subtree instead of
+            // mixing cached entries from two tips, and open files keep
+            // reading the commit they were opened from.
+            Kind::Commit
+                if ns == RefNamespace::Latest
+                    || (ns == RefNamespace::Branches && self.config().branches_as_dirs) =>
+            {
+                let inode = self.object_inode(&object_id, ObjectView::Plain)?;
+                if let Some(stats) = &self.stats {
+                    stats.root(inode, object_id);
+                }
+                let mut entry = Self::make_entry(inode, self.snapshot_dir_attr(inode, object_id));
+                if ns == RefNamespace::Latest {
+                    entry.entry_timeout = LATEST_TTL;
+                    self.note_tip(INODE_LATEST, name, object_id);
+                }
+                Ok((inode, u32::from(libc::DT_DIR), entry))
+            }
+            _ if ns == RefNamespace::Latest => Err(io::Error::from_raw_os_error(libc::ENOENT)),
             Kind::Commit => {
-                let inode = synthetic_inode(ns.marker(), name);
-                let target = format!("../commits/{object_id}");
-                let entry = Self::make_entry(
-                    inode,
-                    build_attr(
-                        inode,
-                        SYMLINK_ATTR_MODE,
-                        target.len() as u64,
-                        self.mount_time,
-                    ),
-                );
+                let inode = self.ref_inodes.inode(ns.space(), name)?;
+                let target = format!("../commits/{}", self.link_name(object_id));
+                let entry = Self::make_entry(inode, self.symlink_attr(inode, target.as_bytes()));
                 Ok((inode, u32::from(libc::DT_LNK), entry))
             }
             Kind::Tree => {
-                let inode = synthetic_inode(ns.marker(), name);
-                let target = format!("../trees/{object_id}");
-                let entry = Self::make_entry(
-                    inode,
-                    build_attr(
-                        inode,
-                        SYMLINK_ATTR_MODE,
-                        target.len() as u64,
-                        self.mount_time,
-                    ),
-                );
+                let inode = self.ref_inodes.inode(ns.space(), name)?;
+                let target = format!("../trees/{}", self.link_name(object_id));
+                let entry = Self::make_entry(inode, self.symlink_attr(inode, target.as_bytes()));
                 Ok((inode, u32::from(libc::DT_LNK), entry))
             }
             Kind::Blob => {
-                let inode = inode_from_oid(&object_id);
-                let blob = repo
-                    .find_blob(object_id)
-                    .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
+                let inode = self.object_inode(&object_id, ObjectView::Plain)?;
+                let size = self.blob_size(object_id)?;
                 let entry = Self::make_entry(
                     inode,
-                    build_attr(
-                        inode,
-                        S_IFREG | 0o444,
-                        blob.data.len() as u64,
-                        self.mount_time,
-                    ),
+                    build_attr(inode, S_IFREG | 0o444, size, self.mount_time),
                 );
                 Ok((inode, u32::from(libc::DT_REG), entry))
             }
@@ -421,95 +2654,1055 @@ impl GitSnapFs {
         }
     }
 
-    fn reference_target(&self, inode: u64, ns: RefNamespace) -> io::Result<Vec<u8>> {
-        let refs = ns.list(&self.repo)?;
-        for (name, object_id) in refs {
-            let candidate = synthetic_inode(ns.marker(), name.as_bytes());
-            if candidate == inode {
-                let repo = self.repo.thread_local();
-                let object = repo
-                    .find_object(object_id)
-                    .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
-                let target = match object.kind {
-                    Kind::Commit => format!("../commits/{object_id}"),
-                    Kind::Tree => format!("../trees/{object_id}"),
-                    _ => {
-                        return Err(io::Error::from_raw_os_error(libc::EINVAL));
-                    }
-                };
-                return Ok(target.into_bytes());
-            }
+    fn reference_target(&self, inode: u64, ns: RefNamespace) -> io::Result<Vec<u8>> {
+        let name = self
+            .ref_inodes
+            .name(inode)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?;
+        let refs = self.refs_in(ns)?;
+        for (candidate, object_id) in refs {
+            if candidate.as_bytes() == name {
+                let repo = self.repo.thread_local();
+                let object = repo
+                    .find_object(object_id)
+                    .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
+                let target = match object.kind {
+                    Kind::Commit => format!("../commits/{}", self.link_name(object_id)),
+                    Kind::Tree => format!("../trees/{}", self.link_name(object_id)),
+                    // Such references are never handed out.
+                    _ => return Err(io::Error::from_raw_os_error(libc::ENOENT)),
+                };
+                return Ok(target.into_bytes());
+            }
+        }
+        Err(io::Error::from_raw_os_error(libc::ENOENT))
+    }
+
+    /// Classify `inode` by its [`Space`] and the nodes handed out so far;
+    /// `ENOENT` for inodes this mount never produced.
+    fn node_kind(&self, inode: u64) -> io::Result<NodeKind> {
+        let kind = match Space::of(inode) {
+            Some(Space::Fixed) => match inode {
+                ROOT_ID => Some(NodeKind::Root),
+                INODE_COMMITS => Some(NodeKind::TopDir(TopDir::Commits)),
+                INODE_TREES => Some(NodeKind::TopDir(TopDir::Trees)),
+                INODE_OBJECTS => Some(NodeKind::TopDir(TopDir::Objects)),
+                INODE_BRANCHES => Some(NodeKind::TopDir(TopDir::Refs(RefNamespace::Branches))),
+                INODE_TAGS => Some(NodeKind::TopDir(TopDir::Refs(RefNamespace::Tags))),
+                INODE_LATEST => Some(NodeKind::TopDir(TopDir::Refs(RefNamespace::Latest))),
+                INODE_REFS => Some(NodeKind::TopDir(TopDir::RefMirror)),
+                INODE_BRANCHES_META => Some(NodeKind::TopDir(TopDir::BranchesMeta)),
+                INODE_COMMITS_ON => Some(NodeKind::TopDir(TopDir::CommitsOn)),
+                INODE_AT => Some(NodeKind::TopDir(TopDir::At)),
+                INODE_WORKTREES => Some(NodeKind::TopDir(TopDir::Worktrees)),
+                INODE_RELEASES if self.config().releases.is_some() => {
+                    Some(NodeKind::TopDir(TopDir::Releases))
+                }
+                INODE_REFS_DEBUG if self.config().refs_debug => {
+                    Some(NodeKind::TopDir(TopDir::RefsDebug))
+                }
+                INODE_REFS_TABLE if self.config().refs_debug => Some(NodeKind::RefsTable),
+                INODE_PACKED_REFS if self.config().refs_debug => Some(NodeKind::PackedRefs),
+                INODE_HEAD => Some(NodeKind::Head),
+                INODE_CONTROL if self.has_control_dir() => Some(NodeKind::TopDir(TopDir::Control)),
+                INODE_STATS if self.stats.is_some() => Some(NodeKind::Stats),
+                INODE_HEALTH if self.health.is_some() => Some(NodeKind::Health),
+                INODE_ERRORS if self.error_journal.is_some() => Some(NodeKind::Errors),
+                INODE_INFO if self.writes.is_some() => Some(NodeKind::Info),
+                INODE_CURRENT if self.current().is_some() => Some(NodeKind::Current),
+                INODE_SCRUB if self.scrub.is_some() => Some(NodeKind::Scrub),
+                INODE_GIT_CONFIG if !self.config().git_config_keys.is_empty() => {
+                    Some(NodeKind::TopDir(TopDir::GitConfig))
+                }
+                _ => None,
+            },
+            Some(Space::Object) => self.resolve_object(inode).ok().map(NodeKind::Object),
+            Some(Space::Branch) => self
+                .ref_inodes
+                .name(inode)
+                .map(|_| NodeKind::RefLink(RefNamespace::Branches)),
+            Some(Space::Tag) => self
+                .ref_inodes
+                .name(inode)
+                .map(|_| NodeKind::RefLink(RefNamespace::Tags)),
+            Some(Space::Meta) => self
+                .meta_node(inode)
+                .map(NodeKind::Synthetic)
+                .or_else(|| self.root_link_name(inode).map(NodeKind::RootLink))
+                .or_else(|| self.at_link_name(inode).map(NodeKind::AtLink))
+                .or_else(|| self.worktree_link_name(inode).map(NodeKind::WorktreeLink))
+                .or_else(|| self.release_path(inode).map(NodeKind::Release))
+                .or_else(|| self.git_config_key(inode).map(NodeKind::GitConfig)),
+            Some(Space::Scoped) => self.scoped_dir(inode).map(NodeKind::Scoped),
+            Some(Space::Refs) => self.mirrored_ref_path(inode).map(NodeKind::MirroredRef),
+            None => None,
+        };
+        kind.ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))
+    }
+
+    fn symlink_attr(&self, inode: u64, target: &[u8]) -> stat64 {
+        build_attr(
+            inode,
+            SYMLINK_ATTR_MODE,
+            target.len() as u64,
+            self.mount_time,
+        )
+    }
+
+    pub(crate) fn attr_for_inode(&self, inode: u64) -> io::Result<stat64> {
+        let oid = match self.node_kind(inode)? {
+            NodeKind::Root => return Ok(self.root_attr()),
+            NodeKind::TopDir(_) => {
+                return Ok(build_attr(inode, DIRECTORY_ATTR_MODE, 0, self.mount_time))
+            }
+            NodeKind::Head => return Ok(self.symlink_attr(inode, &self.head_target()?)),
+            NodeKind::Stats
+            | NodeKind::Health
+            | NodeKind::Errors
+            | NodeKind::Info
+            | NodeKind::Current
+            | NodeKind::Scrub => {
+                let size = self.control_file(inode).len();
+                return Ok(build_attr(
+                    inode,
+                    S_IFREG | 0o444,
+                    size as u64,
+                    self.mount_time,
+                ));
+            }
+            NodeKind::GitConfig(key) => return self.git_config_entry(&key).map(|entry| entry.attr),
+            NodeKind::RefsTable | NodeKind::PackedRefs => {
+                return self.refs_debug_entry(inode).map(|entry| entry.attr)
+            }
+            NodeKind::RootLink(name) => {
+                return Ok(self.symlink_attr(inode, &root_link_target(&name)))
+            }
+            NodeKind::RefLink(ns) => {
+                let target = self.reference_target(inode, ns)?;
+                return Ok(self.symlink_attr(inode, &target));
+            }
+            NodeKind::AtLink(name) => return Ok(self.symlink_attr(inode, &self.at_target(&name)?)),
+            NodeKind::WorktreeLink(name) => {
+                return Ok(self.symlink_attr(inode, &self.worktree_target(&name)?))
+            }
+            NodeKind::MirroredRef(path) => {
+                return self
+                    .mirrored_ref_entry(&path, &self.mirrored_refs()?)
+                    .map(|(entry, _)| entry.attr)
+            }
+            NodeKind::Release(path) => {
+                return self
+                    .release_entry(&path, &self.releases()?)
+                    .map(|(entry, _)| entry.attr)
+            }
+            NodeKind::Synthetic(node) => return self.meta_entry(node).map(|entry| entry.attr),
+            NodeKind::Scoped(dir) => return Ok(self.scoped_dir_attr(inode, &dir)),
+            NodeKind::Object(oid) => oid,
+        };
+        // The header carries the size, so sizing a large blob does not
+        // decode it; only reads do, and those can be interrupted.
+        let header = self
+            .repo
+            .thread_local()
+            .find_header(oid)
+            .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
+        match header.kind() {
+            Kind::Commit | Kind::Tree => Ok(self.snapshot_dir_attr(inode, oid)),
+            Kind::Blob => {
+                let overridden = mode_index(inode)
+                    .and_then(|index| self.config().mode_overrides.modes().get(index).copied());
+                let mode = match (overridden, object_view(inode)) {
+                    (Some(mode), _) => S_IFREG | mode,
+                    (None, ObjectView::Plain) => S_IFREG | 0o444,
+                    (None, ObjectView::Executable) => S_IFREG | 0o555,
+                    (None, ObjectView::Link) => SYMLINK_ATTR_MODE,
+                };
+                let size = match object_view(inode) {
+                    ObjectView::Link => header.size(),
+                    _ => self
+                        .lfs_pointer(oid, header.size())
+                        .map_or(header.size(), |pointer| pointer.size),
+                };
+                Ok(build_attr(inode, mode, size, self.mount_time))
+            }
+            Kind::Tag => Ok(build_attr(
+                inode,
+                S_IFREG | 0o444,
+                header.size(),
+                self.mount_time,
+            )),
+        }
+    }
+
+    pub(crate) fn lookup_name(&self, parent: u64, name: &[u8]) -> io::Result<Entry> {
+        let entry = self.resolve_name(parent, name)?;
+        if let Some(stats) = &self.stats {
+            stats.inherit(parent, entry.inode);
+        }
+        Ok(entry)
+    }
+
+    fn resolve_name(&self, parent: u64, name: &[u8]) -> io::Result<Entry> {
+        // With `EXPORT_SUPPORT` the kernel revalidates NFS file handles by
+        // looking up "." in the node itself. Parents are not unique for
+        // object-derived inodes, so ".." is only answered at the root.
+        if name == b"." || (name == b".." && parent == ROOT_ID) {
+            return self
+                .attr_for_inode(parent)
+                .map(|attr| Self::make_entry(parent, attr));
+        }
+        match self.node_kind(parent)? {
+            NodeKind::Root => match name {
+                b"commits" => Ok(self.synthetic_dir_entry(INODE_COMMITS)),
+                b"trees" => Ok(self.synthetic_dir_entry(INODE_TREES)),
+                b"objects" => Ok(self.synthetic_dir_entry(INODE_OBJECTS)),
+                b"branches" => Ok(self.synthetic_dir_entry(INODE_BRANCHES)),
+                b"tags" => Ok(self.synthetic_dir_entry(INODE_TAGS)),
+                b"latest" => Ok(self.synthetic_dir_entry(INODE_LATEST)),
+                b"refs" => Ok(self.synthetic_dir_entry(INODE_REFS)),
+                b"branches-meta" => Ok(self.synthetic_dir_entry(INODE_BRANCHES_META)),
+                b"commits-on" => Ok(self.synthetic_dir_entry(INODE_COMMITS_ON)),
+                b"at" => Ok(self.synthetic_dir_entry(INODE_AT)),
+                b"worktrees" => Ok(self.synthetic_dir_entry(INODE_WORKTREES)),
+                b"releases" if self.config().releases.is_some() => {
+                    Ok(self.synthetic_dir_entry(INODE_RELEASES))
+                }
+                b"refs-debug" if self.config().refs_debug => {
+                    Ok(self.synthetic_dir_entry(INODE_REFS_DEBUG))
+                }
+                b"HEAD" => self.head_entry(),
+                b"current" => self.current_entry(),
+                CONTROL_DIR_NAME if self.has_control_dir() => {
+                    Ok(self.synthetic_dir_entry(INODE_CONTROL))
+                }
+                name if self.config().root_readme && is_root_link_name(name) => {
+                    self.lookup_root_link(name)
+                }
+                _ => Err(io::Error::from_raw_os_error(libc::ENOENT)),
+            },
+            NodeKind::TopDir(dir) => match dir {
+                TopDir::Commits => self.lookup_commit(name),
+                TopDir::Trees => self.lookup_tree(name),
+                TopDir::Objects => self.lookup_object_dir(name),
+                TopDir::Refs(ns) => self.lookup_reference(name, ns),
+                TopDir::RefMirror => self.lookup_mirrored_ref(parent, None, name),
+                TopDir::BranchesMeta | TopDir::CommitsOn => self.lookup_branch_dir(parent, name),
+                TopDir::At => self.lookup_at(name),
+                TopDir::Worktrees => self.lookup_worktree(name),
+                TopDir::Releases => self.lookup_release(parent, None, name),
+                TopDir::RefsDebug => match name {
+                    b"refs" => self.refs_debug_entry(INODE_REFS_TABLE),
+                    b"packed-refs" => self.refs_debug_entry(INODE_PACKED_REFS),
+                    _ => Err(io::Error::from_raw_os_error(libc::ENOENT)),
+                },
+                TopDir::Control => match name {
+                    b"stats" => self
+                        .attr_for_inode(INODE_STATS)
+                        .map(|attr| Self::make_entry(INODE_STATS, attr)),
+                    b"health" => self
+                        .attr_for_inode(INODE_HEALTH)
+                        .map(|attr| Self::make_entry(INODE_HEALTH, attr)),
+                    b"errors" => self
+                        .attr_for_inode(INODE_ERRORS)
+                        .map(|attr| Self::make_entry(INODE_ERRORS, attr)),
+                    b"info" => self
+                        .attr_for_inode(INODE_INFO)
+                        .map(|attr| Self::make_entry(INODE_INFO, attr)),
+                    b"current" => self
+                        .attr_for_inode(INODE_CURRENT)
+                        .map(|attr| Self::make_entry(INODE_CURRENT, attr)),
+                    b"scrub" => self
+                        .attr_for_inode(INODE_SCRUB)
+                        .map(|attr| Self::make_entry(INODE_SCRUB, attr)),
+                    b"config" if !self.config().git_config_keys.is_empty() => {
+                        Ok(self.synthetic_dir_entry(INODE_GIT_CONFIG))
+                    }
+                    _ => Err(io::Error::from_raw_os_error(libc::ENOENT)),
+                },
+                TopDir::GitConfig => self.lookup_git_config(name),
+            },
+            NodeKind::MirroredRef(path) => self.lookup_mirrored_ref(parent, Some(&path), name),
+            NodeKind::Release(path) => self.lookup_release(parent, Some(&path), name),
+            NodeKind::Synthetic(node) => self.lookup_meta(&node, name),
+            NodeKind::Scoped(_) | NodeKind::Object(_) => self.lookup_child(parent, name),
+            NodeKind::Head
+            | NodeKind::Stats
+            | NodeKind::Health
+            | NodeKind::Errors
+            | NodeKind::Info
+            | NodeKind::Current
+            | NodeKind::Scrub
+            | NodeKind::GitConfig(_)
+            | NodeKind::RefsTable
+            | NodeKind::PackedRefs
+            | NodeKind::RootLink(_)
+            | NodeKind::RefLink(_)
+            | NodeKind::AtLink(_)
+            | NodeKind::WorktreeLink(_) => Err(io::Error::from_raw_os_error(libc::ENOENT)),
+        }
+    }
+
+    pub(crate) fn link_target(&self, inode: u64) -> io::Result<Vec<u8>> {
+        let oid = match self.node_kind(inode)? {
+            NodeKind::Head => return self.head_target(),
+            NodeKind::RootLink(name) => return Ok(root_link_target(&name)),
+            NodeKind::RefLink(ns) => return self.reference_target(inode, ns),
+            NodeKind::AtLink(name) => return self.at_target(&name),
+            NodeKind::WorktreeLink(name) => return self.worktree_target(&name),
+            NodeKind::MirroredRef(path) => {
+                let refs = self.mirrored_refs()?;
+                let (_, id) = refs
+                    .iter()
+                    .find(|(name, _)| *name == path)
+                    .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?;
+                return self.mirrored_ref_target(&path, *id);
+            }
+            NodeKind::Release(path) => {
+                return match self.releases()?.node(&path) {
+                    Some(ReleaseNode::Dir) | None => {
+                        Err(io::Error::from_raw_os_error(libc::EINVAL))
+                    }
+                    Some(node) => Ok(self.release_target(&path, node)),
+                }
+            }
+            NodeKind::Synthetic(node) => {
+                return self
+                    .meta_link_target(&node)?
+                    .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))
+            }
+            NodeKind::Object(oid) => oid,
+            NodeKind::Root
+            | NodeKind::TopDir(_)
+            | NodeKind::Stats
+            | NodeKind::Health
+            | NodeKind::Errors
+            | NodeKind::Info
+            | NodeKind::Current
+            | NodeKind::Scrub
+            | NodeKind::GitConfig(_)
+            | NodeKind::RefsTable
+            | NodeKind::PackedRefs
+            | NodeKind::Scoped(_) => return Err(io::Error::from_raw_os_error(libc::EINVAL)),
+        };
+        let repo = self.repo.thread_local();
+        let blob = repo
+            .find_blob(oid)
+            .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
+        Ok(blob.data.as_slice().to_vec())
+    }
+
+    /// Admit a request from the caller of `ctx` under `--max-requests-per-uid`.
+    /// Requests interrupted while they were queued are not started.
+    fn admit(&self, ctx: &Context) -> io::Result<Option<Permit<'_>>> {
+        interrupt::check()?;
+        self.uid_limiter
+            .as_ref()
+            .map(|limiter| limiter.admit(ctx.uid))
+            .transpose()
+    }
+
+    /// Whether the path of each inode looked up is remembered.
+    fn tracks_paths(&self) -> bool {
+        self.authorizer.is_some() || self.audit_log.is_some() || self.error_journal.is_some()
+    }
+
+    /// Serve `request`, a request for `op` on `inode` (or on `name` in it),
+    /// recording its failure in the error journal if there is one.
+    fn journaled<T>(
+        &self,
+        op: &'static str,
+        inode: u64,
+        name: Option<&[u8]>,
+        request: impl FnOnce() -> io::Result<T>,
+    ) -> io::Result<T> {
+        let result = request();
+        if let (Some(journal), Err(err)) = (&self.error_journal, &result) {
+            journal.record(op, self.lookup_path(inode, name).as_deref(), err);
+        }
+        result
+    }
+
+    /// The path `parent` was looked up by, extended by `name`; `None` for
+    /// parents whose path is unknown.
+    fn lookup_path(&self, parent: u64, name: Option<&[u8]>) -> Option<Vec<u8>> {
+        let path = if parent == ROOT_ID {
+            Vec::new()
+        } else {
+            self.lookup_paths
+                .read()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .get(&parent)?
+                .clone()
+        };
+        Some(child_path(path, name))
+    }
+
+    /// The path `uid` looked `parent` up by, extended by `name`; `None` for
+    /// parents the uid has not looked up.
+    fn walked_path(&self, uid: u32, parent: u64, name: Option<&[u8]>) -> Option<Vec<u8>> {
+        let path = if parent == ROOT_ID {
+            Vec::new()
+        } else {
+            self.walked_paths
+                .read()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .get(&parent)?
+                .get(&uid)?
+                .clone()
+        };
+        Some(child_path(path, name))
+    }
+
+    /// Remember that `uid` looked `inode` up as `name` in `parent`, if paths
+    /// are tracked.
+    fn note_path(&self, uid: u32, parent: u64, name: &[u8], inode: u64) {
+        if !self.tracks_paths() {
+            return;
+        }
+        if let Some(path) = self.lookup_path(parent, Some(name)) {
+            self.lookup_paths
+                .write()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .insert(inode, path);
+        }
+        if self.authorizer.is_none() {
+            return;
+        }
+        if let Some(path) = self.walked_path(uid, parent, Some(name)) {
+            self.walked_paths
+                .write()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .entry(inode)
+                .or_default()
+                .insert(uid, path);
+        }
+    }
+
+    /// Put `op` by the caller on `inode` (or on `name` in it) to the
+    /// authorization hook, if there is one, with the path the caller looked
+    /// it up by. Inodes the caller reached other than by lookup, such as
+    /// through an NFS file handle, are refused.
+    fn authorize(&self, ctx: &Context, op: Op, inode: u64, name: Option<&[u8]>) -> io::Result<()> {
+        let Some(authorizer) = &self.authorizer else {
+            return Ok(());
+        };
+        let path = self
+            .walked_path(ctx.uid, inode, name)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::EACCES))?;
+        authorizer.check(ctx.uid, op, &path)
+    }
+
+    /// Record a read of `bytes` at `offset` in `inode` by the caller in the
+    /// audit log, if there is one.
+    fn audit_read(&self, ctx: &Context, inode: u64, offset: u64, bytes: usize) -> io::Result<()> {
+        let Some(audit_log) = &self.audit_log else {
+            return Ok(());
+        };
+        let Some(oid) = self.blob_id(inode) else {
+            return Ok(());
+        };
+        let path = self
+            .lookup_paths
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .get(&inode)
+            .map(|path| String::from_utf8_lossy(path).into_owned())
+            .unwrap_or_default();
+        audit_log.record(&audit::Record {
+            uid: ctx.uid,
+            pid: ctx.pid,
+            path: &path,
+            oid,
+            offset,
+            bytes,
+        })
+    }
+
+    /// Apply the configured read limits to a read of up to `size` bytes at
+    /// `offset` of the blob `inode`, sleeping if necessary, before anything
+    /// decodes it. The read is charged for the part of the blob it covers,
+    /// as the blob's size tells. Returns `false`, charging nothing, if
+    /// `inode` is not a blob.
+    pub(crate) fn charge_blob_read(&self, inode: u64, offset: u64, size: usize) -> bool {
+        let Some(blob_size) = self
+            .resolve_object(inode)
+            .ok()
+            .and_then(|oid| self.blob_size(oid).ok())
+        else {
+            return false;
+        };
+        let span = blob_size.saturating_sub(offset).min(size as u64);
+        self.read_throttle.acquire(span);
+        true
+    }
+
+    /// Count a read of `bytes` from `inode` towards the snapshot's
+    /// statistics, first applying the configured read limits unless
+    /// [`Self::charge_blob_read`] already did.
+    pub(crate) fn account_read(&self, inode: u64, bytes: usize, charged: bool) {
+        if !charged {
+            self.read_throttle.acquire(bytes as u64);
+        }
+        if let Some(stats) = &self.stats {
+            stats.served(inode, bytes as u64);
+        }
+    }
+
+    /// Current contents of `.gitsnapfs/stats`, `.gitsnapfs/health` or
+    /// `.gitsnapfs/errors`.
+    fn control_file(&self, inode: u64) -> Vec<u8> {
+        match inode {
+            INODE_STATS => self.stats.as_ref().map(SnapshotStats::render),
+            INODE_HEALTH => self
+                .health
+                .as_ref()
+                .map(|health| health.render().into_bytes()),
+            INODE_ERRORS => self.error_journal.as_ref().map(ErrorJournal::render),
+            INODE_INFO => self.writes.as_ref().map(|writes| writes.render()),
+            INODE_CURRENT => self
+                .current()
+                .map(|commit| format!("{commit}\n").into_bytes()),
+            INODE_SCRUB => self.scrub.as_ref().map(|scrub| scrub.render().into_bytes()),
+            _ => None,
+        }
+        .unwrap_or_default()
+    }
+
+    pub(crate) fn file_content(&self, inode: u64) -> io::Result<Arc<[u8]>> {
+        let oid = match self.node_kind(inode)? {
+            NodeKind::Stats
+            | NodeKind::Health
+            | NodeKind::Errors
+            | NodeKind::Info
+            | NodeKind::Current
+            | NodeKind::Scrub => {
+                return Ok(Arc::from(self.control_file(inode)));
+            }
+            NodeKind::GitConfig(key) => return self.git_config_file(&key).map(Arc::from),
+            NodeKind::RefsTable | NodeKind::PackedRefs => {
+                return self.refs_debug_file(inode).map(Arc::from)
+            }
+            NodeKind::Synthetic(node) => return self.meta_file_content(&node),
+            NodeKind::Object(oid) => oid,
+            NodeKind::Root
+            | NodeKind::TopDir(_)
+            | NodeKind::Scoped(_)
+            | NodeKind::MirroredRef(_)
+            | NodeKind::Release(_) => return Err(io::Error::from_raw_os_error(libc::EISDIR)),
+            NodeKind::Head
+            | NodeKind::RootLink(_)
+            | NodeKind::RefLink(_)
+            | NodeKind::AtLink(_)
+            | NodeKind::WorktreeLink(_) => return Err(io::Error::from_raw_os_error(libc::EINVAL)),
+        };
+        self.check_content_allowed()?;
+        if let Some(pointer) = self.lfs_placeholder(inode) {
+            let size = usize::try_from(pointer.size)
+                .map_err(|_| io::Error::from_raw_os_error(libc::EFBIG))?;
+            return Ok(Arc::from(vec![0; size]));
+        }
+        if let Some(data) = self.blob_cache.as_ref().and_then(|cache| cache.get(&oid)) {
+            return Ok(data);
+        }
+        self.check_file_size(oid)?;
+        let data = self.repo.read_blob(oid)?;
+        if self.config().verify_reads && !repo::blob_matches(&self.repo.thread_local(), oid, &data)
+        {
+            return Err(io::Error::from_raw_os_error(libc::EIO));
+        }
+        if let Some(cache) = &self.blob_cache {
+            cache.insert(oid, Arc::clone(&data));
+        }
+        Ok(data)
+    }
+
+    /// Fail with `EPERM` under `--metadata-only`, before any content is read.
+    fn check_content_allowed(&self) -> io::Result<()> {
+        if self.config().metadata_only {
+            return Err(io::Error::from_raw_os_error(libc::EPERM));
+        }
+        Ok(())
+    }
+
+    /// Fail with `EFBIG` if `oid` is a blob above `--max-file-size`, before
+    /// anything decodes it.
+    fn check_file_size(&self, oid: ObjectId) -> io::Result<()> {
+        let Some(limit) = self.config().max_file_size else {
+            return Ok(());
+        };
+        let oversized = self
+            .repo
+            .thread_local()
+            .find_header(oid)
+            .is_ok_and(|header| header.kind() == Kind::Blob && header.size() > limit);
+        if oversized {
+            return Err(io::Error::from_raw_os_error(libc::EFBIG));
+        }
+        Ok(())
+    }
+
+    /// Up to `len` bytes of the file `inode` from `offset`, without read
+    /// accounting.
+    pub(crate) fn read_range(&self, inode: u64, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        if let Some(pointer) = self.lfs_placeholder(inode) {
+            self.check_content_allowed()?;
+            return Ok(vec![0; placeholder_span(&pointer, offset, len)]);
+        }
+        let size = u32::try_from(len).unwrap_or(u32::MAX);
+        if let Some(data) = self.read_shared(inode, offset, size)? {
+            return Ok(data);
+        }
+        if let Some(blob) = self.mapped_blob(inode)? {
+            return Ok(blob.slices(offset, len).concat());
+        }
+        let content = self.file_content(inode)?;
+        let start = usize::try_from(offset)
+            .unwrap_or(usize::MAX)
+            .min(content.len());
+        let end = start.saturating_add(len).min(content.len());
+        Ok(content[start..end].to_vec())
+    }
+
+    /// Read a blob range through the shared cache daemon, if one is
+    /// configured, reachable and `inode` is an object.
+    fn read_shared(&self, inode: u64, offset: u64, size: u32) -> io::Result<Option<Vec<u8>>> {
+        let Some(shared) = &self.shared_cache else {
+            return Ok(None);
+        };
+        let Ok(oid) = self.resolve_object(inode) else {
+            return Ok(None);
+        };
+        self.check_content_allowed()?;
+        self.check_file_size(oid)?;
+        let len = usize::try_from(size).map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+        match shared.read(oid, offset, len) {
+            Ok(Ok(data)) => Ok(Some(data)),
+            Ok(Err(errno)) => Err(io::Error::from_raw_os_error(errno)),
+            Err(err) => {
+                shared.unavailable(&err);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Commit whose snapshot directory `inode` is.
+    fn snapshot_commit(&self, inode: u64) -> Option<ObjectId> {
+        if object_view(inode) != ObjectView::Plain {
+            return None;
         }
-        Err(io::Error::from_raw_os_error(libc::ENOENT))
+        let oid = self.resolve_object(inode).ok()?;
+        let header = self.repo.thread_local().find_header(oid).ok()?;
+        (header.kind() == Kind::Commit).then_some(oid)
     }
 
-    fn attr_for_inode(&self, inode: u64) -> io::Result<stat64> {
-        if inode == ROOT_ID {
-            return Ok(self.root_attr());
+    /// Tree backing `inode`, if it is a snapshot directory.
+    fn directory_tree(&self, inode: u64) -> Option<ObjectId> {
+        if object_view(inode) != ObjectView::Plain {
+            return None;
         }
-        if inode == INODE_COMMITS
-            || inode == INODE_TREES
-            || inode == INODE_BRANCHES
-            || inode == INODE_TAGS
-        {
-            return Ok(build_attr(inode, DIRECTORY_ATTR_MODE, 0, self.mount_time));
-        }
-        if inode == INODE_HEAD {
-            let target = self.head_target()?;
-            return Ok(build_attr(
-                INODE_HEAD,
-                SYMLINK_ATTR_MODE,
-                target.len() as u64,
-                self.mount_time,
-            ));
+        self.directory_source(inode).ok().map(|source| source.tree)
+    }
+
+    /// Blob served by `inode`, if it is a blob object.
+    fn blob_id(&self, inode: u64) -> Option<ObjectId> {
+        let oid = self.resolve_object(inode).ok()?;
+        let header = self.repo.thread_local().find_header(oid).ok()?;
+        (header.kind() == Kind::Blob).then_some(oid)
+    }
+
+    /// Hex SHA-256 of the content of blob inode `inode`, computed on first use.
+    fn blob_sha256(&self, inode: u64, oid: ObjectId) -> io::Result<String> {
+        // The pointer names the content the placeholder stands for.
+        if let Some(pointer) = self.lfs_placeholder(inode) {
+            return Ok(pointer.sha256);
         }
-        if let Ok(target) = self.reference_target(inode, RefNamespace::Branches) {
-            return Ok(build_attr(
-                inode,
-                SYMLINK_ATTR_MODE,
-                target.len() as u64,
-                self.mount_time,
-            ));
+        if let Some(hex) = self.checksums.get(&oid) {
+            return Ok(hex);
         }
-        if let Ok(target) = self.reference_target(inode, RefNamespace::Tags) {
-            return Ok(build_attr(
-                inode,
-                SYMLINK_ATTR_MODE,
-                target.len() as u64,
-                self.mount_time,
-            ));
+        let hex = sha256::hex_digest(&self.file_content(inode)?);
+        self.checksums.insert(oid, hex.clone());
+        Ok(hex)
+    }
+
+    /// What the first bytes of the blob behind `inode` say about it.
+    fn blob_content_type(&self, inode: u64, oid: ObjectId) -> io::Result<ContentType> {
+        if let Some(content_type) = self.content_types.get(&oid) {
+            return Ok(content_type);
+        }
+        let content_type = sniff::sniff(&self.read_range(inode, 0, sniff::SNIFF_LEN)?);
+        self.content_types.insert(oid, content_type);
+        Ok(content_type)
+    }
+
+    /// Value of the gitsnapfs attribute `name` of `inode`.
+    fn xattr_value(&self, inode: u64, name: &[u8]) -> io::Result<Vec<u8>> {
+        let no_data = || io::Error::from_raw_os_error(libc::ENODATA);
+        if [SHA256_XATTR, MIME_XATTR, BINARY_XATTR].contains(&name) {
+            // These would take reading the content `--metadata-only` withholds.
+            if !self.config().metadata_only {
+                return self.blob_xattr(inode, name);
+            }
+        } else if name == LFS_XATTR {
+            let pointer = self.lfs_placeholder(inode).ok_or_else(no_data)?;
+            return Ok(pointer.oid().into_bytes());
+        } else if name == UUID_XATTR && inode == ROOT_ID {
+            return Ok(self.volume_uuid.clone().into_bytes());
+        } else if name == TREE_OID_XATTR {
+            let tree = self.directory_tree(inode).ok_or_else(no_data)?;
+            return Ok(tree.to_string().into_bytes());
         }
+        let commit = self.snapshot_commit(inode).ok_or_else(no_data)?;
+        self.commit_xattr(commit, name)
+    }
 
+    /// Value of the content attribute `name` of blob inode `inode`.
+    fn blob_xattr(&self, inode: u64, name: &[u8]) -> io::Result<Vec<u8>> {
         let oid = self
-            .repo
-            .resolve_inode(inode)
-            .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
-        let repo = self.repo.thread_local();
-        let object = repo
-            .find_object(oid)
-            .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
-        match object.kind {
-            Kind::Commit | Kind::Tree => {
-                Ok(build_attr(inode, DIRECTORY_ATTR_MODE, 0, self.mount_time))
+            .blob_id(inode)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENODATA))?;
+        if name == SHA256_XATTR {
+            return Ok(self.blob_sha256(inode, oid)?.into_bytes());
+        }
+        let content_type = self.blob_content_type(inode, oid)?;
+        Ok(if name == MIME_XATTR {
+            content_type.mime.as_bytes().to_vec()
+        } else {
+            vec![if content_type.binary { b'1' } else { b'0' }]
+        })
+    }
+
+    /// Value of the history attribute `name` of the snapshot of `commit`.
+    fn commit_xattr(&self, commit: ObjectId, name: &[u8]) -> io::Result<Vec<u8>> {
+        let mut value = Vec::new();
+        if name == REACHABLE_XATTR {
+            let config = self.config();
+            for name in self
+                .repo
+                .reachable_from(commit, config.first_parent)
+                .map_err(walk_error)?
+            {
+                if !config.ref_filter.allows(name.as_bytes()) {
+                    continue;
+                }
+                value.extend_from_slice(name.as_bytes());
+                value.push(b'\n');
             }
-            Kind::Blob => {
+        } else if name == GENERATION_XATTR {
+            let (generation, walked) = self
+                .repo
+                .generation(commit, &|id| self.generations.get(id))
+                .map_err(walk_error)?;
+            self.generations.extend(walked);
+            value.extend_from_slice(generation.to_string().as_bytes());
+        } else if let Some(rev) = name.strip_prefix(IS_ANCESTOR_XATTR_PREFIX) {
+            let descendant = str::from_utf8(rev)
+                .ok()
+                .and_then(|rev| self.repo.resolve_full_commit_id(rev).ok())
+                .filter(|&id| self.is_visible(id))
+                .ok_or_else(|| io::Error::from_raw_os_error(libc::ENODATA))?;
+            let (is_ancestor, _) = self
+                .repo
+                .descends_from(descendant, commit, &|_| None)
+                .map_err(walk_error)?;
+            value.push(if is_ancestor { b'1' } else { b'0' });
+        } else {
+            return Err(io::Error::from_raw_os_error(libc::ENODATA));
+        }
+        Ok(value)
+    }
+
+    /// Start inflating the blob behind `inode` for a new handle if it spans
+    /// several reads and is not cached; the handle, if one was started.
+    fn start_readahead(&self, inode: u64) -> io::Result<Option<u64>> {
+        let Some(readahead) = &self.readahead else {
+            return Ok(None);
+        };
+        if self.meta_node(inode).is_some() {
+            return Ok(None);
+        }
+        let Ok(oid) = self.resolve_object(inode) else {
+            return Ok(None);
+        };
+        if self.mapped_blob(inode).is_ok_and(|blob| blob.is_some()) {
+            return Ok(None);
+        }
+        if self
+            .blob_cache
+            .as_ref()
+            .is_some_and(|cache| cache.get(&oid).is_some())
+        {
+            return Ok(None);
+        }
+        let repo = self.repo.thread_local();
+        let large = repo.find_header(oid).is_ok_and(|header| {
+            header.kind() == Kind::Blob
+                && header.size() > readahead::MIN_BLOB_SIZE
+                && self
+                    .config()
+                    .max_file_size
+                    .is_none_or(|limit| header.size() <= limit)
+        });
+        if !large {
+            return Ok(None);
+        }
+        let verify = self.config().verify_reads;
+        readahead
+            .start(inode, move || {
                 let blob = repo
                     .find_blob(oid)
                     .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
-                Ok(build_attr(
-                    inode,
-                    S_IFREG | 0o444,
-                    blob.data.len() as u64,
-                    self.mount_time,
-                ))
+                if verify && !repo::blob_matches(&repo, oid, &blob.data) {
+                    return Err(io::Error::from_raw_os_error(libc::EIO));
+                }
+                Ok(Arc::from(blob.detach().data))
+            })
+            .map(Some)
+    }
+
+    /// Fill the blob cache with every ref-reachable blob that fits its budget.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if caching is disabled or the references cannot be listed.
+    pub fn preload(&self) -> anyhow::Result<PreloadStats> {
+        let cache = self
+            .blob_cache
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("preloading requires a cache budget"))?;
+        cache::preload(
+            &self.repo.thread_local(),
+            cache,
+            self.config().verify_reads,
+            self.config().max_file_size,
+        )
+    }
+
+    /// Reopen the repository and drop the blob cache and per-tree caches, so
+    /// the next requests see the repository exactly as it is on disk.
+    ///
+    /// Registries of handed-out synthetic inodes are kept: the kernel may
+    /// still refer to them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the repository cannot be reopened.
+    pub fn flush_caches(&self) -> anyhow::Result<()> {
+        self.repo.reload()?;
+        *self
+            .reverse_index
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = None;
+        if let Some(cache) = &self.blob_cache {
+            cache.clear();
+        }
+        if let Some(authorizer) = &self.authorizer {
+            authorizer.clear();
+        }
+        self.hidden_paths.clear();
+        self.declared_submodules.clear();
+        self.tree_usage.clear();
+        self.injected.clear();
+        self.conflicts.clear();
+        self.manifests.clear();
+        // The root may have moved, and replacement refs rewrite history.
+        self.descent.clear();
+        self.changelogs.clear();
+        // Replacement refs may have changed what a blob id serves.
+        self.checksums.clear();
+        self.content_types.clear();
+        self.lfs_pointers.clear();
+        self.mapped_blobs.clear();
+        self.generations.clear();
+        *self
+            .mailmap
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = None;
+        Ok(())
+    }
+
+    /// Serve subsequent requests with `config` and drop every cache that
+    /// depends on the old settings. Settings only read when the mount starts
+    /// keep their current values; the flags naming those that differ are
+    /// returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the repository cannot be reopened.
+    pub fn reconfigure(&self, mut config: FsConfig) -> anyhow::Result<Vec<&'static str>> {
+        fn keep<T: PartialEq + Clone>(
+            flag: &'static str,
+            new: &mut T,
+            current: &T,
+            fixed: &mut Vec<&'static str>,
+        ) {
+            if new != current {
+                fixed.push(flag);
+                new.clone_from(current);
             }
-            Kind::Tag => Ok(build_attr(
-                inode,
-                S_IFREG | 0o444,
-                object.data.len() as u64,
-                self.mount_time,
-            )),
         }
+
+        let current = self.config();
+        let mut fixed = Vec::new();
+        keep(
+            "max-memory",
+            &mut config.cache_budget,
+            &current.cache_budget,
+            &mut fixed,
+        );
+        keep(
+            "max-read-bandwidth",
+            &mut config.max_read_bandwidth,
+            &current.max_read_bandwidth,
+            &mut fixed,
+        );
+        keep(
+            "max-iops",
+            &mut config.max_iops,
+            &current.max_iops,
+            &mut fixed,
+        );
+        keep(
+            "max-requests-per-uid",
+            &mut config.max_requests_per_uid,
+            &current.max_requests_per_uid,
+            &mut fixed,
+        );
+        keep("stats", &mut config.stats, &current.stats, &mut fixed);
+        keep(
+            "readahead",
+            &mut config.readahead,
+            &current.readahead,
+            &mut fixed,
+        );
+        keep(
+            "overlay-compat",
+            &mut config.overlay_compat,
+            &current.overlay_compat,
+            &mut fixed,
+        );
+        keep(
+            "shared-cache",
+            &mut config.shared_cache,
+            &current.shared_cache,
+            &mut fixed,
+        );
+        keep("authz-cmd", &mut config.authz, &current.authz, &mut fixed);
+        // The kernel stops asking after the first ENOSYS, for good.
+        keep(
+            "no-xattrs",
+            &mut config.no_xattrs,
+            &current.no_xattrs,
+            &mut fixed,
+        );
+        *self
+            .config
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner) = Arc::new(config);
+        self.flush_caches()?;
+        Ok(fixed)
+    }
+
+    fn note_served_refs(&self, ns: RefNamespace, names: impl IntoIterator<Item = Vec<u8>>) {
+        let parent = match ns {
+            RefNamespace::Branches => INODE_BRANCHES,
+            RefNamespace::Tags => INODE_TAGS,
+            RefNamespace::Latest => INODE_LATEST,
+        };
+        self.note_served(parent, names);
+    }
+
+    fn note_tip(&self, parent: u64, name: &[u8], tip: ObjectId) {
+        self.branch_tips
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .insert((parent, name.to_vec()), tip);
+    }
+
+    fn note_served(&self, parent: u64, names: impl IntoIterator<Item = Vec<u8>>) {
+        self.served_refs
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .extend(names.into_iter().map(|name| (parent, name)));
+    }
+
+    /// Directory entries whose meaning depends on references, as
+    /// `(parent inode, name)`: `HEAD`, the `--root-readme`, `at/` and
+    /// `worktrees/` links, the `--expose-config` files, and every branch and
+    /// tag served since the previous call, including ones that no longer
+    /// exist.
+    #[must_use]
+    pub fn take_reference_entries(&self) -> Vec<(u64, Vec<u8>)> {
+        let served = std::mem::take(
+            &mut *self
+                .served_refs
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner),
+        );
+        let root_links: Vec<(u64, Vec<u8>)> = self
+            .root_links
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .values()
+            .map(|name| (ROOT_ID, name.clone()))
+            .collect();
+        let at_links: Vec<(u64, Vec<u8>)> = self
+            .at_links
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .values()
+            .map(|name| (INODE_AT, name.clone()))
+            .collect();
+        let worktree_links: Vec<(u64, Vec<u8>)> = self
+            .worktree_links
+            .read()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .values()
+            .map(|name| (INODE_WORKTREES, name.clone()))
+            .collect();
+        let config_files: Vec<(u64, Vec<u8>)> = self
+            .config()
+            .git_config_keys
+            .iter()
+            .map(|key| (INODE_GIT_CONFIG, key.clone().into_bytes()))
+            .collect();
+        std::iter::once((ROOT_ID, b"HEAD".to_vec()))
+            .chain(root_links)
+            .chain(at_links)
+            .chain(worktree_links)
+            .chain(config_files)
+            .chain(served)
+            .collect()
+    }
+
+    /// `latest/` and `branches-meta/` entries, as `(parent inode, name)`,
+    /// whose branch has moved or been deleted since they were handed to the
+    /// kernel. They are forgotten here, so each move is reported once.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the branches cannot be listed.
+    pub fn take_moved_tip_entries(&self) -> io::Result<Vec<(u64, Vec<u8>)>> {
+        let mut tips = self
+            .branch_tips
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if tips.is_empty() {
+            return Ok(Vec::new());
+        }
+        let current: HashMap<Vec<u8>, ObjectId> = self
+            .refs_in(RefNamespace::Latest)?
+            .into_iter()
+            .map(|(name, tip)| (name.into_bytes(), tip))
+            .collect();
+        let mut moved = Vec::new();
+        tips.retain(|entry, tip| {
+            let unchanged = current.get(&entry.1) == Some(tip);
+            if !unchanged {
+                moved.push(entry.clone());
+            }
+            unchanged
+        });
+        Ok(moved)
     }
 }
 
@@ -518,51 +3711,51 @@ impl FileSystem for GitSnapFs {
     type Handle = u64;
 
     fn init(&self, capable: FsOptions) -> io::Result<FsOptions> {
-        let required = FsOptions::EXPORT_SUPPORT
-            | FsOptions::ZERO_MESSAGE_OPEN
-            | FsOptions::ZERO_MESSAGE_OPENDIR;
-        let optional = FsOptions::ASYNC_READ
-            | FsOptions::DO_READDIRPLUS
-            | FsOptions::READDIRPLUS_AUTO
-            | FsOptions::PARALLEL_DIROPS
-            | FsOptions::CACHE_SYMLINKS;
-        let wanted = required | optional;
-        let supported = capable & wanted;
-        if !supported.contains(required) {
-            return Err(io::Error::other(
-                "kernel does not advertise required export support or zero-message open capabilities"
-            ));
-        }
-        Ok(supported)
+        fuse_options(&self.config(), capable)
     }
 
-    fn lookup(&self, _ctx: &Context, parent: Self::Inode, name: &CStr) -> io::Result<Entry> {
+    fn lookup(&self, ctx: &Context, parent: Self::Inode, name: &CStr) -> io::Result<Entry> {
         let name = name.to_bytes();
-        match parent {
-            inode if inode == ROOT_ID => match name {
-                b"commits" => Ok(self.synthetic_dir_entry(INODE_COMMITS)),
-                b"trees" => Ok(self.synthetic_dir_entry(INODE_TREES)),
-                b"branches" => Ok(self.synthetic_dir_entry(INODE_BRANCHES)),
-                b"tags" => Ok(self.synthetic_dir_entry(INODE_TAGS)),
-                b"HEAD" => self.head_entry(),
-                _ => Err(io::Error::from_raw_os_error(libc::ENOENT)),
-            },
-            inode if inode == INODE_COMMITS => self.lookup_commit(name),
-            inode if inode == INODE_TREES => self.lookup_tree(name),
-            inode if inode == INODE_BRANCHES => self.lookup_reference(name, RefNamespace::Branches),
-            inode if inode == INODE_TAGS => self.lookup_reference(name, RefNamespace::Tags),
-            other => self.lookup_child(other, name),
-        }
+        self.journaled("lookup", parent, Some(name), || {
+            let _permit = self.admit(ctx)?;
+            self.authorize(ctx, Op::Lookup, parent, Some(name))?;
+            let mut entry = self.lookup_name(parent, name)?;
+            if self.authorizer.is_some() {
+                // The kernel shares cached entries between users, so each path
+                // walk has to come back here to be authorized for its caller.
+                entry.entry_timeout = Duration::ZERO;
+            }
+            self.note_path(ctx.uid, parent, name, entry.inode);
+            self.lookups.remember(entry.inode);
+            Ok(entry)
+        })
+    }
+
+    fn forget(&self, _ctx: &Context, inode: Self::Inode, count: u64) {
+        let released = self.lookups.forget(inode, count, Instant::now());
+        self.release_inodes(&released);
+    }
+
+    fn batch_forget(&self, _ctx: &Context, requests: Vec<(Self::Inode, u64)>) {
+        let now = Instant::now();
+        let released: Vec<u64> = requests
+            .into_iter()
+            .flat_map(|(inode, count)| self.lookups.forget(inode, count, now))
+            .collect();
+        self.release_inodes(&released);
     }
 
     fn getattr(
         &self,
-        _ctx: &Context,
+        ctx: &Context,
         inode: Self::Inode,
         _handle: Option<Self::Handle>,
     ) -> io::Result<(stat64, Duration)> {
-        let attr = self.attr_for_inode(inode)?;
-        Ok((attr, ATTR_TTL))
+        self.journaled("getattr", inode, None, || {
+            let _permit = self.admit(ctx)?;
+            let attr = self.attr_for_inode(inode)?;
+            Ok((attr, ATTR_TTL))
+        })
     }
 
     fn setattr(
@@ -576,26 +3769,11 @@ impl FileSystem for GitSnapFs {
         Err(io::Error::from_raw_os_error(libc::EROFS))
     }
 
-    fn readlink(&self, _ctx: &Context, inode: Self::Inode) -> io::Result<Vec<u8>> {
-        if inode == INODE_HEAD {
-            return self.head_target();
-        }
-        if let Ok(target) = self.reference_target(inode, RefNamespace::Branches) {
-            return Ok(target);
-        }
-        if let Ok(target) = self.reference_target(inode, RefNamespace::Tags) {
-            return Ok(target);
-        }
-
-        let oid = self
-            .repo
-            .resolve_inode(inode)
-            .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
-        let repo = self.repo.thread_local();
-        let blob = repo
-            .find_blob(oid)
-            .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
-        Ok(blob.data.as_slice().to_vec())
+    fn readlink(&self, ctx: &Context, inode: Self::Inode) -> io::Result<Vec<u8>> {
+        self.journaled("readlink", inode, None, || {
+            let _permit = self.admit(ctx)?;
+            self.link_target(inode)
+        })
     }
 
     fn symlink(
@@ -673,109 +3851,245 @@ impl FileSystem for GitSnapFs {
 
     fn readdir(
         &self,
-        _ctx: &Context,
+        ctx: &Context,
         inode: Self::Inode,
         _handle: Self::Handle,
         _size: u32,
         offset: u64,
         add_entry: &mut dyn FnMut(DirEntry) -> io::Result<usize>,
     ) -> io::Result<()> {
-        let records = self.list_directory(inode)?;
-        let start =
-            usize::try_from(offset).map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
-        for (index, record) in records.into_iter().enumerate().skip(start) {
-            let entry_offset = index as u64;
-            let dirent = DirEntry {
-                ino: record.ino,
-                offset: entry_offset + 1,
-                type_: record.dtype,
-                name: &record.name,
-            };
-            if add_entry(dirent)? == 0 {
-                break;
+        self.journaled("readdir", inode, None, || {
+            let _permit = self.admit(ctx)?;
+            let records = self.list_directory(inode)?;
+            let start = resume_index(&records, offset);
+            for (index, record) in records.into_iter().enumerate().skip(start) {
+                let dirent = DirEntry {
+                    ino: record.ino,
+                    offset: dir_cookie(index, &record.name),
+                    type_: record.dtype,
+                    name: &record.name,
+                };
+                if add_entry(dirent)? == 0 {
+                    break;
+                }
             }
-        }
-        Ok(())
+            Ok(())
+        })
     }
 
     fn readdirplus(
         &self,
-        _ctx: &Context,
+        ctx: &Context,
         inode: Self::Inode,
         _handle: Self::Handle,
         _size: u32,
         offset: u64,
         add_entry: &mut dyn FnMut(DirEntry, Entry) -> io::Result<usize>,
     ) -> io::Result<()> {
-        let records = self.list_directory(inode)?;
-        let start =
-            usize::try_from(offset).map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
-        for (index, record) in records.into_iter().enumerate().skip(start) {
-            let entry_offset = index as u64;
-            if let Some(entry) = record.entry {
-                let dirent = DirEntry {
-                    ino: record.ino,
-                    offset: entry_offset + 1,
-                    type_: record.dtype,
-                    name: &record.name,
-                };
-                if add_entry(dirent, entry)? == 0 {
-                    break;
+        self.journaled("readdirplus", inode, None, || {
+            let _permit = self.admit(ctx)?;
+            let records = self.list_directory(inode)?;
+            let start = resume_index(&records, offset);
+            for (index, record) in records.into_iter().enumerate().skip(start) {
+                if let Some(entry) = record.entry {
+                    let dirent = DirEntry {
+                        ino: record.ino,
+                        offset: dir_cookie(index, &record.name),
+                        type_: record.dtype,
+                        name: &record.name,
+                    };
+                    let child = entry.inode;
+                    if add_entry(dirent, entry)? == 0 {
+                        break;
+                    }
+                    self.note_path(ctx.uid, inode, &record.name, child);
+                    self.lookups.remember(child);
+                    // Attributed only where the kernel holds the entry, so
+                    // the attribution goes when it is forgotten.
+                    if let Some(stats) = &self.stats {
+                        stats.inherit(inode, child);
+                    }
                 }
             }
-        }
-        Ok(())
+            Ok(())
+        })
     }
 
     fn opendir(
         &self,
-        _ctx: &Context,
-        _inode: Self::Inode,
+        ctx: &Context,
+        inode: Self::Inode,
         _flags: u32,
     ) -> io::Result<(Option<Self::Handle>, OpenOptions)> {
-        Err(io::Error::from_raw_os_error(libc::ENOSYS))
+        self.journaled("opendir", inode, None, || {
+            let _permit = self.admit(ctx)?;
+            if !self.config().overlay_compat {
+                return Err(io::Error::from_raw_os_error(libc::ENOSYS));
+            }
+            let attr = self.attr_for_inode(inode)?;
+            if attr.st_mode & libc::S_IFMT != libc::S_IFDIR {
+                return Err(io::Error::from_raw_os_error(libc::ENOTDIR));
+            }
+            // Handles stay stateless: the inode is all a directory stream needs.
+            Ok((
+                Some(inode),
+                OpenOptions::CACHE_DIR | OpenOptions::KEEP_CACHE,
+            ))
+        })
     }
 
-    fn open(
+    fn releasedir(
         &self,
         _ctx: &Context,
         _inode: Self::Inode,
         _flags: u32,
+        _handle: Self::Handle,
+    ) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn open(
+        &self,
+        ctx: &Context,
+        inode: Self::Inode,
+        _flags: u32,
         _fuse_flags: u32,
     ) -> io::Result<(Option<Self::Handle>, OpenOptions, Option<u32>)> {
-        Err(io::Error::from_raw_os_error(libc::ENOSYS))
+        self.journaled("open", inode, None, || {
+            let _permit = self.admit(ctx)?;
+            // Opens are only worth a round trip when they are being counted,
+            // read ahead or authorized, or reads have to bypass the page cache.
+            if self.stats.is_none()
+                && self.readahead.is_none()
+                && self.authorizer.is_none()
+                && self.health.is_none()
+                && self.scrub.is_none()
+                && self.audit_log.is_none()
+                && self.error_journal.is_none()
+                && self.writes.is_none()
+                && self.current().is_none()
+                && !self.config().refs_debug
+            {
+                return Err(io::Error::from_raw_os_error(libc::ENOSYS));
+            }
+            self.authorize(ctx, Op::Read, inode, None)?;
+            // The control files change between reads, so keep them out of the
+            // page cache; so are audited and authorized reads, which it would
+            // answer unseen, and for any caller.
+            let options = if matches!(
+                inode,
+                INODE_STATS
+                    | INODE_HEALTH
+                    | INODE_ERRORS
+                    | INODE_INFO
+                    | INODE_CURRENT
+                    | INODE_SCRUB
+                    | INODE_REFS_TABLE
+                    | INODE_PACKED_REFS
+            ) || self.audit_log.is_some()
+                || self.authorizer.is_some()
+            {
+                OpenOptions::DIRECT_IO
+            } else {
+                OpenOptions::KEEP_CACHE
+            };
+            if let Some(stats) = &self.stats {
+                stats.opened(inode);
+            }
+            let handle = self.start_readahead(inode)?.unwrap_or(inode);
+            Ok((Some(handle), options, None))
+        })
     }
 
     #[allow(clippy::too_many_arguments)]
-    fn read(
+    fn release(
         &self,
         _ctx: &Context,
         inode: Self::Inode,
-        _handle: Self::Handle,
+        _flags: u32,
+        handle: Self::Handle,
+        _flush: bool,
+        _flock_release: bool,
+        _lock_owner: Option<u64>,
+    ) -> io::Result<()> {
+        if let Some(stats) = &self.stats {
+            stats.released(inode);
+        }
+        if let Some(readahead) = &self.readahead {
+            readahead.release(handle);
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn read(
+        &self,
+        ctx: &Context,
+        inode: Self::Inode,
+        handle: Self::Handle,
         w: &mut dyn ZeroCopyWriter,
         size: u32,
         offset: u64,
         _lock_owner: Option<u64>,
         _flags: u32,
     ) -> io::Result<usize> {
-        let oid = self
-            .repo
-            .resolve_inode(inode)
-            .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
-        let repo = self.repo.thread_local();
-        let blob = repo
-            .find_blob(oid)
-            .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
-        let data = blob.data.as_slice();
-        let start =
-            usize::try_from(offset).map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
-        if start >= data.len() {
-            return Ok(0);
-        }
-        let span = usize::try_from(size).map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
-        let end = start.saturating_add(span).min(data.len());
-        w.write_all(&data[start..end])?;
-        Ok(end - start)
+        self.journaled("read", inode, None, || {
+            let _permit = self.admit(ctx)?;
+            self.authorize(ctx, Op::Read, inode, None)?;
+            self.sample_read(inode, offset);
+            let charged = self.charge_blob_read(inode, offset, size as usize);
+            if let Some(pointer) = self.lfs_placeholder(inode) {
+                self.check_content_allowed()?;
+                let len = placeholder_span(&pointer, offset, size as usize);
+                self.audit_read(ctx, inode, offset, len)?;
+                self.account_read(inode, len, charged);
+                w.write_all(&vec![0; len])?;
+                return Ok(len);
+            }
+            if let Some(data) = self.read_shared(inode, offset, size)? {
+                self.audit_read(ctx, inode, offset, data.len())?;
+                self.account_read(inode, data.len(), charged);
+                w.write_all(&data)?;
+                return Ok(data.len());
+            }
+            if let Some(blob) = self.mapped_blob(inode)? {
+                let slices = blob.slices(offset, size as usize);
+                let len = slices.iter().map(|slice| slice.len()).sum();
+                self.audit_read(ctx, inode, offset, len)?;
+                self.account_read(inode, len, charged);
+                interrupt::check()?;
+                for slice in slices {
+                    w.write_all(slice)?;
+                }
+                return Ok(len);
+            }
+            let content = match self
+                .readahead
+                .as_ref()
+                .and_then(|readahead| readahead.content(handle, inode))
+            {
+                // A reload may have turned on `--metadata-only` since the open.
+                Some(content) => {
+                    self.check_content_allowed()?;
+                    content?
+                }
+                None => self.file_content(inode)?,
+            };
+            let data = &content[..];
+            let start =
+                usize::try_from(offset).map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+            if start >= data.len() {
+                return Ok(0);
+            }
+            let span =
+                usize::try_from(size).map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+            let end = start.saturating_add(span).min(data.len());
+            self.audit_read(ctx, inode, offset, end - start)?;
+            self.account_read(inode, end - start, charged);
+            interrupt::check()?;
+            w.write_all(&data[start..end])?;
+            Ok(end - start)
+        })
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -807,7 +4121,99 @@ impl FileSystem for GitSnapFs {
         Err(io::Error::from_raw_os_error(libc::EROFS))
     }
 
-    fn access(&self, _ctx: &Context, _inode: Self::Inode, mask: u32) -> io::Result<()> {
+    fn ioctl(
+        &self,
+        ctx: &Context,
+        inode: u64,
+        _handle: u64,
+        _flags: u32,
+        cmd: u32,
+        data: IoctlData,
+        _out_size: u32,
+    ) -> io::Result<IoctlData<'_>> {
+        self.journaled("ioctl", inode, None, || {
+            if inode != INODE_CURRENT || cmd != SWITCH_CURRENT || self.current().is_none() {
+                return Err(io::Error::from_raw_os_error(libc::ENOTTY));
+            }
+            // Only the user the mount belongs to may change what others see.
+            // SAFETY: geteuid has no preconditions and cannot fail.
+            if ctx.uid != 0 && ctx.uid != unsafe { libc::geteuid() } {
+                return Err(io::Error::from_raw_os_error(libc::EPERM));
+            }
+            let arg = data.data.unwrap_or_default();
+            let rev = arg.split(|&byte| byte == 0).next().unwrap_or_default();
+            let rev = str::from_utf8(rev)
+                .ok()
+                .filter(|rev| !rev.is_empty())
+                .ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?;
+            self.switch_current(rev)?;
+            Ok(IoctlData::default())
+        })
+    }
+
+    fn getxattr(
+        &self,
+        ctx: &Context,
+        inode: Self::Inode,
+        name: &CStr,
+        size: u32,
+    ) -> io::Result<GetxattrReply> {
+        if self.config().no_xattrs {
+            return Err(io::Error::from_raw_os_error(libc::ENOSYS));
+        }
+        if !name.to_bytes().starts_with(XATTR_NAMESPACE) {
+            return Err(io::Error::from_raw_os_error(libc::ENODATA));
+        }
+        self.journaled("getxattr", inode, None, || {
+            let _permit = self.admit(ctx)?;
+            xattr_reply(self.xattr_value(inode, name.to_bytes())?, size)
+        })
+    }
+
+    fn listxattr(
+        &self,
+        ctx: &Context,
+        inode: Self::Inode,
+        size: u32,
+    ) -> io::Result<ListxattrReply> {
+        if self.config().no_xattrs {
+            return Err(io::Error::from_raw_os_error(libc::ENOSYS));
+        }
+        self.journaled("listxattr", inode, None, || {
+            let _permit = self.admit(ctx)?;
+            let mut names = Vec::new();
+            if self.snapshot_commit(inode).is_some() {
+                for name in [REACHABLE_XATTR, GENERATION_XATTR] {
+                    names.extend_from_slice(name);
+                    names.push(0);
+                }
+            } else if !self.config().metadata_only && self.blob_id(inode).is_some() {
+                for name in [SHA256_XATTR, MIME_XATTR, BINARY_XATTR] {
+                    names.extend_from_slice(name);
+                    names.push(0);
+                }
+            }
+            if self.lfs_placeholder(inode).is_some() {
+                names.extend_from_slice(LFS_XATTR);
+                names.push(0);
+            }
+            if self.directory_tree(inode).is_some() {
+                names.extend_from_slice(TREE_OID_XATTR);
+                names.push(0);
+            }
+            if inode == ROOT_ID {
+                names.extend_from_slice(UUID_XATTR);
+                names.push(0);
+            }
+            Ok(match xattr_reply(names, size)? {
+                GetxattrReply::Value(names) => ListxattrReply::Names(names),
+                GetxattrReply::Count(count) => ListxattrReply::Count(count),
+            })
+        })
+    }
+
+    fn access(&self, ctx: &Context, _inode: Self::Inode, mask: u32) -> io::Result<()> {
+        let _permit = self.admit(ctx)?;
         let mask_bits =
             i32::try_from(mask).map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
         if (mask_bits & libc::W_OK) != 0 {
@@ -817,13 +4223,197 @@ impl FileSystem for GitSnapFs {
     }
 }
 
-fn synthetic_inode(namespace: u8, name: &[u8]) -> u64 {
-    use std::hash::{Hash, Hasher};
+/// Path below the mount root of a fixed inode (the root, top-level
+/// directories and control files), `""` for the root itself.
+#[must_use]
+pub fn fixed_inode_path(inode: u64) -> Option<&'static str> {
+    Some(match inode {
+        ROOT_ID => "",
+        INODE_COMMITS => "commits",
+        INODE_TREES => "trees",
+        INODE_BRANCHES => "branches",
+        INODE_TAGS => "tags",
+        INODE_HEAD => "HEAD",
+        INODE_CONTROL => ".gitsnapfs",
+        INODE_STATS => ".gitsnapfs/stats",
+        INODE_LATEST => "latest",
+        INODE_REFS => "refs",
+        INODE_BRANCHES_META => "branches-meta",
+        INODE_COMMITS_ON => "commits-on",
+        INODE_OBJECTS => "objects",
+        INODE_HEALTH => ".gitsnapfs/health",
+        INODE_ERRORS => ".gitsnapfs/errors",
+        INODE_INFO => ".gitsnapfs/info",
+        INODE_CURRENT => ".gitsnapfs/current",
+        INODE_SCRUB => ".gitsnapfs/scrub",
+        INODE_GIT_CONFIG => ".gitsnapfs/config",
+        INODE_AT => "at",
+        INODE_WORKTREES => "worktrees",
+        INODE_RELEASES => "releases",
+        INODE_REFS_DEBUG => "refs-debug",
+        INODE_REFS_TABLE => "refs-debug/refs",
+        INODE_PACKED_REFS => "refs-debug/packed-refs",
+        _ => return None,
+    })
+}
+
+/// FUSE options to negotiate for a filesystem with `config`, out of those
+/// the kernel offers in `capable`.
+///
+/// # Errors
+///
+/// Returns an error if the kernel lacks a capability the filesystem relies on.
+pub fn fuse_options(config: &FsConfig, capable: FsOptions) -> io::Result<FsOptions> {
+    let mut required = FsOptions::EXPORT_SUPPORT;
+    if !config.stats && !config.readahead {
+        required |= FsOptions::ZERO_MESSAGE_OPEN;
+    }
+    if !config.overlay_compat {
+        required |= FsOptions::ZERO_MESSAGE_OPENDIR;
+    }
+    // Nothing is ever written, so there are no privileges to drop; taking
+    // that over spares the kernel a `security.capability` query per write.
+    let mut optional = FsOptions::ASYNC_READ
+        | FsOptions::PARALLEL_DIROPS
+        | FsOptions::CACHE_SYMLINKS
+        | FsOptions::HANDLE_KILLPRIV;
+    // Entries handed out by readdirplus would skip the authorizing lookup.
+    if config.authz.is_none() {
+        optional |= FsOptions::DO_READDIRPLUS | FsOptions::READDIRPLUS_AUTO;
+    }
+    let wanted = required | optional;
+    let supported = capable & wanted;
+    if !supported.contains(required) {
+        return Err(io::Error::other(
+            "kernel does not advertise required export support or zero-message open capabilities",
+        ));
+    }
+    Ok(supported)
+}
+
+/// `path`, below the mount root, extended by `name`; the root itself is `/`.
+fn child_path(mut path: Vec<u8>, name: Option<&[u8]>) -> Vec<u8> {
+    if let Some(name) = name {
+        path.push(b'/');
+        path.extend_from_slice(name);
+    } else if path.is_empty() {
+        path.push(b'/');
+    }
+    path
+}
+
+/// Report a failed tree walk, as `ELOOP` or `EFBIG` if it hit the configured limits.
+fn walk_error(err: anyhow::Error) -> io::Error {
+    if let Some(exceeded) = err.downcast_ref::<LimitExceeded>() {
+        return io::Error::from_raw_os_error(exceeded.errno());
+    }
+    if let Some(&interrupted) = err.downcast_ref::<Interrupted>() {
+        return interrupted.into();
+    }
+    io::Error::other(err)
+}
+
+/// The part of `path` below the directory `dir`, if it is inside it.
+fn path_below<'a>(path: &'a [u8], dir: &[u8]) -> Option<&'a [u8]> {
+    path.strip_prefix(dir)?.strip_prefix(b"/")
+}
+
+/// Whether `name` is a README or license file (`README.md`, `LICENSE-MIT`, ...).
+fn is_root_link_name(name: &[u8]) -> bool {
+    let stem = name
+        .split(|&b| b == b'.' || b == b'-')
+        .next()
+        .unwrap_or(name);
+    ROOT_LINK_STEMS
+        .iter()
+        .any(|candidate| stem.eq_ignore_ascii_case(candidate))
+}
+
+fn root_link_target(name: &[u8]) -> Vec<u8> {
+    [&b"HEAD/"[..], name].concat()
+}
+
+/// Seconds since the epoch named by an `at/` entry: any date Git accepts,
+/// such as `2024-06-01T12:00:00+02:00`, `2024-06-01`, `1717243200` or
+/// `2 days ago`, and RFC 3339 times in UTC ending in `Z`.
+fn parse_point_in_time(name: &[u8]) -> Option<i64> {
+    let name = str::from_utf8(name).ok()?;
+    let name = match name.strip_suffix(['Z', 'z']) {
+        Some(utc) => format!("{utc}+00:00"),
+        None => name.to_owned(),
+    };
+    gix::date::parse(&name, Some(SystemTime::now()))
+        .ok()
+        .map(|time| time.seconds)
+}
+
+/// Bits of a readdir offset holding the hash of the entry's name.
+const COOKIE_HASH_BITS: u32 = 31;
+const COOKIE_HASH_MASK: u64 = (1 << COOKIE_HASH_BITS) - 1;
+
+/// The readdir offset (`d_off`) of the entry at `index` of a listing: the
+/// position after it in the high bits and a hash of its name in the low
+/// ones. Listings are rebuilt on every call and can change in between (a
+/// branch is created, a reload changes `--sort` or hides paths), so a
+/// resumed listing finds the entry again by name rather than trusting the
+/// position. The top bit stays clear, as `d_off` is signed in userspace.
+fn dir_cookie(index: usize, name: &[u8]) -> u64 {
+    ((index as u64 + 1) << COOKIE_HASH_BITS | cookie_hash(name)) & (u64::MAX >> 1)
+}
+
+fn cookie_hash(name: &[u8]) -> u64 {
     let mut hasher = std::collections::hash_map::DefaultHasher::new();
-    namespace.hash(&mut hasher);
     name.hash(&mut hasher);
-    let hash = hasher.finish();
-    (u64::from(namespace) << 56) | (hash & 0x00FF_FFFF_FFFF_FFFF)
+    hasher.finish() & COOKIE_HASH_MASK
+}
+
+/// A hash of `ids` independent of their order.
+fn fingerprint(ids: &HashSet<ObjectId>) -> u64 {
+    let mut sorted: Vec<&ObjectId> = ids.iter().collect();
+    sorted.sort_unstable();
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    sorted.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Where in `records` a listing resumes after the entry whose cookie is
+/// `offset`: just after that entry, looked for at its old position first
+/// and then anywhere. If it is gone, the position in the cookie is the best
+/// guess left.
+fn resume_index(records: &[DirRecord], offset: u64) -> usize {
+    if offset == 0 {
+        return 0;
+    }
+    let position = usize::try_from(offset >> COOKIE_HASH_BITS).unwrap_or(usize::MAX);
+    let hash = offset & COOKIE_HASH_MASK;
+    let is_last_listed = |index: usize| cookie_hash(&records[index].name) == hash;
+    if (1..=records.len()).contains(&position) && is_last_listed(position - 1) {
+        return position;
+    }
+    (0..records.len())
+        .find(|&index| is_last_listed(index))
+        .map_or(position, |index| index + 1)
+}
+
+/// Bytes of the placeholder for `pointer` a read of `len` bytes at
+/// `offset` returns.
+fn placeholder_span(pointer: &lfs::Pointer, offset: u64, len: usize) -> usize {
+    usize::try_from(pointer.size.saturating_sub(offset))
+        .unwrap_or(usize::MAX)
+        .min(len)
+}
+
+/// Apply the xattr size protocol: `size` 0 asks for the length, anything
+/// else must fit the value.
+fn xattr_reply(value: Vec<u8>, size: u32) -> io::Result<GetxattrReply> {
+    let len = u32::try_from(value.len()).map_err(|_| io::Error::from_raw_os_error(libc::E2BIG))?;
+    if size == 0 {
+        Ok(GetxattrReply::Count(len))
+    } else if len > size {
+        Err(io::Error::from_raw_os_error(libc::ERANGE))
+    } else {
+        Ok(GetxattrReply::Value(value))
+    }
 }
 
 fn build_attr(inode: u64, mode: u32, size: u64, time_parts: (i64, i64)) -> stat64 {
@@ -0,0 +1,169 @@
+//! Periodic self-check of a mount (`--health-interval`).
+//!
+//! A background thread resolves `HEAD` and a known path (`--health-path`)
+//! through the same handlers that serve requests, and records how that went
+//! in [`Health`], which is readable as `/.gitsnapfs/health`. A check that
+//! hangs, for example on a lock a wedged request never releases, shows up
+//! as `stuck` once it has run for longer than the interval. Orchestrators
+//! can poll the file (or time out reading it) to decide when to restart
+//! the mount.
+//!
+//! The file holds one `key value` pair per line:
+//!
+//! ```text
+//! status ok
+//! checks 42
+//! consecutive_failures 0
+//! last_check 1760601600
+//! last_duration_ms 3
+//! ```
+//!
+//! `status` is `pending` before the first check, then `ok`, `failing` or
+//! `stuck`; an `error` line follows while the last check failed. A `HEAD`
+//! that is unborn, as in a freshly initialized repository, is not resolved;
+//! the mount serves no `HEAD` then.
+
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use tracing::{error, info};
+
+use crate::fs::GitSnapFs;
+use crate::repo::UnbornHead;
+
+/// Outcome of the self-checks so far.
+#[derive(Debug)]
+pub struct Health {
+    /// Path inside the mount resolved by each check.
+    path: Vec<u8>,
+    interval: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    checks: u64,
+    consecutive_failures: u64,
+    /// When the check in progress started, if one is.
+    running_since: Option<Instant>,
+    /// When the last check finished, how long it took and its error, if any.
+    last: Option<(SystemTime, Duration, Option<String>)>,
+}
+
+impl Health {
+    /// Checks resolving `path` every `interval`.
+    #[must_use]
+    pub fn new(path: Vec<u8>, interval: Duration) -> Self {
+        Self {
+            path,
+            interval,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// The contents of `/.gitsnapfs/health`.
+    #[must_use]
+    pub fn render(&self) -> String {
+        let status = self.status(Instant::now());
+        let state = self.lock();
+        let mut text = format!(
+            "status {status}\nchecks {}\nconsecutive_failures {}\n",
+            state.checks, state.consecutive_failures
+        );
+        if let Some((finished, took, failure)) = &state.last {
+            let secs = finished
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs());
+            let _ = writeln!(text, "last_check {secs}");
+            let _ = writeln!(text, "last_duration_ms {}", took.as_millis());
+            if let Some(failure) = failure {
+                let _ = writeln!(text, "error {failure}");
+            }
+        }
+        text
+    }
+
+    fn status(&self, now: Instant) -> &'static str {
+        let state = self.lock();
+        match (&state.running_since, &state.last) {
+            (Some(since), _) if now.duration_since(*since) > self.interval => "stuck",
+            (_, None) => "pending",
+            (_, Some((_, _, Some(_)))) => "failing",
+            (_, Some((_, _, None))) => "ok",
+        }
+    }
+
+    /// Run one check against `fs` and record it.
+    fn check(&self, fs: &GitSnapFs) {
+        let started = Instant::now();
+        self.lock().running_since = Some(started);
+        let unborn = matches!(
+            fs.repository().resolve_head(),
+            Err(err) if err.is::<UnbornHead>()
+        );
+        let failure = [&b"HEAD"[..], &self.path]
+            .into_iter()
+            .filter(|path| !(unborn && *path == b"HEAD"))
+            .find_map(|path| {
+                fs.resolve_path(path)
+                    .err()
+                    .map(|err| format!("{}: {err}", String::from_utf8_lossy(path)))
+            });
+        let took = started.elapsed();
+        let mut state = self.lock();
+        state.running_since = None;
+        state.checks += 1;
+        match &failure {
+            Some(failure) if state.consecutive_failures == 0 => {
+                error!("health check failed: {failure}");
+            }
+            None if state.consecutive_failures > 0 => {
+                info!(
+                    "health check passed again after {} failures",
+                    state.consecutive_failures
+                );
+            }
+            _ => {}
+        }
+        state.consecutive_failures = match failure {
+            Some(_) => state.consecutive_failures + 1,
+            None => 0,
+        };
+        state.last = Some((SystemTime::now(), took, failure));
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// Start the thread checking `fs` at the interval of its [`Health`]; does
+/// nothing if it has none. The thread ends once `fs` is dropped.
+///
+/// # Errors
+///
+/// Returns an error if the thread cannot be started.
+pub fn spawn(fs: &Arc<GitSnapFs>) -> std::io::Result<()> {
+    if fs.health().is_none() {
+        return Ok(());
+    }
+    let fs = Arc::downgrade(fs);
+    thread::Builder::new()
+        .name("gitsnapfs-health".into())
+        .spawn(move || {
+            while let Some(fs) = fs.upgrade() {
+                let Some(health) = fs.health() else {
+                    break;
+                };
+                health.check(&fs);
+                let interval = health.interval;
+                drop(fs);
+                thread::sleep(interval);
+            }
+        })?;
+    Ok(())
+}
@@ -0,0 +1,315 @@
+//! Read-only HTTP gateway serving the snapshot hierarchy as static files.
+//!
+//! Meant for users who cannot mount FUSE. Each connection is handled on its
+//! own thread and closed after a single `GET` or `HEAD` request; at most
+//! [`MAX_CONNECTIONS`] are served at once, further ones get `503`, and a
+//! client that stalls for [`IO_TIMEOUT`] is dropped. Files are
+//! served as `application/octet-stream`, directories as minimal HTML
+//! listings, and symlinks are followed on the server side, so
+//! `/HEAD/README.md` works just like it does on a mount.
+
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use gix::bstr::ByteSlice;
+use tracing::{info, warn};
+
+use crate::vfs::{NodeKind, Vfs};
+
+/// Longest request line or header line we accept.
+const MAX_LINE: usize = 8192;
+
+/// Connections served concurrently, each on its own thread.
+pub const MAX_CONNECTIONS: usize = 64;
+
+/// How long a read from or write to a client may stall.
+pub const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Accept connections on `addr` until the process exits.
+///
+/// # Errors
+///
+/// Returns an error if the listener cannot be bound.
+pub fn serve(vfs: Vfs, addr: SocketAddr) -> Result<()> {
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("failed to listen on {addr}"))?;
+    info!(
+        "GitSnapFS HTTP gateway listening on http://{}",
+        listener.local_addr()?
+    );
+    let vfs = Arc::new(vfs);
+    let active = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!(?err, "accepting HTTP connection failed");
+                continue;
+            }
+        };
+        if let Err(err) = stream
+            .set_read_timeout(Some(IO_TIMEOUT))
+            .and_then(|()| stream.set_write_timeout(Some(IO_TIMEOUT)))
+        {
+            warn!(?err, "setting HTTP connection timeouts failed");
+            continue;
+        }
+        let Some(slot) = Slot::take(&active) else {
+            warn!("refusing HTTP connection over the limit of {MAX_CONNECTIONS}");
+            let _ = write_response(stream, &Response::error("503 Service Unavailable"), false);
+            continue;
+        };
+        let vfs = Arc::clone(&vfs);
+        let spawned = thread::Builder::new()
+            .name("gitsnapfs-http".into())
+            .spawn(move || {
+                let _slot = slot;
+                if let Err(err) = handle(&vfs, stream) {
+                    warn!(?err, "serving HTTP request failed");
+                }
+            });
+        if let Err(err) = spawned {
+            warn!(?err, "starting HTTP connection thread failed");
+        }
+    }
+    Ok(())
+}
+
+/// One of the [`MAX_CONNECTIONS`] connection slots, freed when dropped.
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn take(active: &Arc<AtomicUsize>) -> Option<Self> {
+        active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                (count < MAX_CONNECTIONS).then_some(count + 1)
+            })
+            .ok()
+            .map(|_| Self(Arc::clone(active)))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    location: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn error(status: &'static str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            location: None,
+            body: format!("{status}\n").into_bytes(),
+        }
+    }
+}
+
+fn handle(vfs: &Vfs, stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = Vec::new();
+    read_line(&mut reader, &mut request_line)?;
+    // Drain the headers; none of them change the response.
+    let mut header = Vec::new();
+    loop {
+        header.clear();
+        if read_line(&mut reader, &mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let mut parts = request_line.trim().splitn_str(3, " ");
+    let method = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or_default();
+    let response = match method {
+        b"GET" | b"HEAD" => respond(vfs, target),
+        _ => Response::error("405 Method Not Allowed"),
+    };
+    write_response(stream, &response, method == b"HEAD")
+}
+
+fn read_line(reader: &mut impl BufRead, line: &mut Vec<u8>) -> io::Result<usize> {
+    let read = reader
+        .by_ref()
+        .take(MAX_LINE as u64)
+        .read_until(b'\n', line)?;
+    if read == MAX_LINE && !line.ends_with(b"\n") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "request line too long",
+        ));
+    }
+    Ok(read)
+}
+
+fn respond(vfs: &Vfs, target: &[u8]) -> Response {
+    let raw_path = target.split(|b| *b == b'?').next().unwrap_or_default();
+    let Some(path) = percent_decode(raw_path) else {
+        return Response::error("400 Bad Request");
+    };
+    let node = match vfs.resolve(&path, true) {
+        Ok(node) => node,
+        Err(err) => return error_response(&err),
+    };
+    match node.kind {
+        NodeKind::Directory if !raw_path.ends_with(b"/") => {
+            let mut location = raw_path.to_str_lossy().into_owned();
+            location.push('/');
+            Response {
+                location: Some(location),
+                ..Response::error("301 Moved Permanently")
+            }
+        }
+        NodeKind::Directory => match vfs.read_dir(&node) {
+            Ok(entries) => Response {
+                status: "200 OK",
+                content_type: "text/html; charset=utf-8",
+                location: None,
+                body: render_listing(&path, &entries).into_bytes(),
+            },
+            Err(err) => error_response(&err),
+        },
+        _ => match vfs.read(&node) {
+            Ok(body) => Response {
+                status: "200 OK",
+                content_type: "application/octet-stream",
+                location: None,
+                body,
+            },
+            Err(err) => error_response(&err),
+        },
+    }
+}
+
+fn error_response(err: &io::Error) -> Response {
+    match err.raw_os_error() {
+        Some(libc::ENOENT | libc::ENOTDIR) => Response::error("404 Not Found"),
+        Some(libc::ELOOP) => Response::error("508 Loop Detected"),
+        _ if err.kind() == io::ErrorKind::Unsupported => Response::error("403 Forbidden"),
+        _ => Response::error("500 Internal Server Error"),
+    }
+}
+
+fn render_listing(path: &[u8], entries: &[crate::vfs::DirEntry]) -> String {
+    let path = path.trim_start_with(|ch| ch == '/');
+    let title = html_escape(&String::from_utf8_lossy(path));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Index of /{title}</title></head>\n<body><h1>Index of /{title}</h1>\n<ul>\n"
+    );
+    if !path.is_empty() {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for entry in entries {
+        let suffix = match entry.node.kind {
+            NodeKind::Directory => "/",
+            NodeKind::Symlink => "@",
+            NodeKind::File => "",
+        };
+        let href = percent_encode(&entry.name);
+        let slash = if entry.node.kind == NodeKind::Directory {
+            "/"
+        } else {
+            ""
+        };
+        let name = html_escape(&String::from_utf8_lossy(&entry.name));
+        let _ = writeln!(
+            html,
+            "<li><a href=\"{href}{slash}\">{name}{suffix}</a></li>"
+        );
+    }
+    html.push_str("</ul></body></html>\n");
+    html
+}
+
+fn write_response(mut stream: TcpStream, response: &Response, head_only: bool) -> io::Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    if let Some(location) = &response.location {
+        let _ = write!(head, "Location: {location}\r\n");
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    if !head_only {
+        stream.write_all(&response.body)?;
+    }
+    stream.flush()
+}
+
+/// Decode `%XX` escapes. Returns `None` for malformed escapes.
+fn percent_decode(input: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(input.len());
+    let mut bytes = input.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte != b'%' {
+            out.push(byte);
+            continue;
+        }
+        let high = char::from(*bytes.next()?).to_digit(16)?;
+        let low = char::from(*bytes.next()?).to_digit(16)?;
+        out.push(u8::try_from(high * 16 + low).ok()?);
+    }
+    Some(out)
+}
+
+/// Escape everything outside the RFC 3986 unreserved set.
+fn percent_encode(input: &[u8]) -> String {
+    let mut out = String::with_capacity(input.len());
+    for &byte in input {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            out.push(char::from(byte));
+        } else {
+            let _ = write!(out, "%{byte:02X}");
+        }
+    }
+    out
+}
+
+fn html_escape(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for ch in input.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_coding_roundtrip() {
+        let name = b"a b/%\xff.txt";
+        let encoded = percent_encode(name);
+        assert_eq!(encoded, "a%20b%2F%25%FF.txt");
+        assert_eq!(
+            percent_decode(encoded.as_bytes()).as_deref(),
+            Some(&name[..])
+        );
+        assert_eq!(percent_decode(b"%zz"), None);
+    }
+}
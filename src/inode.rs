@@ -1,27 +1,254 @@
-//! Conversion utilities between Git object ids and 64-bit inode numbers.
+//! Partition of the 64-bit inode space.
 //!
-//! The inode space is derived directly from the low 64 bits of the object id.
-//! We intentionally avoid tracking collisions here – higher layers will consult
-//! the Git object database with the derived prefix and surface an error if the
-//! prefix is ambiguous.
+//! The top four bits of every inode tag the [`Space`] it belongs to, and the
+//! remaining 60 bits are the payload:
+//!
+//! ```text
+//! 0x0...  fixed     root and the top-level directories, small constants
+//! 0x1...  object    the first 56 bits of a Git object id, then the view
+//! 0x2...  branch    number allocated to a branch name
+//! 0x3...  tag       number allocated to a tag name
+//! 0x4...  meta      hash of a `.git-meta` node or other synthetic file
+//! 0x5...  scoped    hash of a path-scoped snapshot directory
+//! 0x6...  refs      hash of a path below the `/refs` mirror
+//! ```
+//!
+//! Inodes from different spaces therefore never coincide, whatever the
+//! object ids or names involved. The low four bits of an object inode hold
+//! its [`ObjectView`], so a blob served both as a regular file and as a
+//! symlink gets one inode per file type and `getattr` can tell them apart.
+//! The values no view uses stand for the modes of `--mode-override` (see
+//! [`mode_inode`]).
+//!
+//! Within the object space we intentionally avoid tracking collisions –
+//! higher layers will consult the Git object database with the derived
+//! prefix and surface an error if the prefix is ambiguous.
+//!
+//! With `--inode-prefix-bits` fewer bits of the id are kept and the bits
+//! freed below them hold a slot number, see [`narrow_object_inode`]. Which
+//! object got which slot is then tracked in [`crate::ref_inodes`].
+
+use std::hash::{Hash, Hasher};
 
 use gix::ObjectId;
 
-/// Convert a Git object id into a 64-bit inode by taking the low 64 bits.
+/// Bit position of the space tag.
+const SPACE_SHIFT: u32 = 60;
+
+/// Mask selecting the payload below the space tag.
+const PAYLOAD_MASK: u64 = (1 << SPACE_SHIFT) - 1;
+
+/// Bits of an object inode's payload holding the [`ObjectView`].
+const VIEW_BITS: u32 = 4;
+
+/// First view value standing for a `--mode-override` mode.
+const FIRST_MODE_VIEW: usize = 3;
+
+/// Number of distinct `--mode-override` modes object inodes can carry.
+pub const MODE_VIEWS: usize = (1 << VIEW_BITS) - FIRST_MODE_VIEW;
+
+/// Number of bits of the object id kept in an object inode by default.
+pub const OBJECT_PREFIX_BITS: u32 = SPACE_SHIFT - VIEW_BITS;
+
+/// Fewest bits of the object id `--inode-prefix-bits` may keep.
+pub const MIN_OBJECT_PREFIX_BITS: u32 = 16;
+
+/// Number of hex digits of the object id kept in an object inode.
+const OBJECT_PREFIX_DIGITS: usize = OBJECT_PREFIX_BITS as usize / 4;
+
+/// The disjoint regions of the inode space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Space {
+    /// Inodes with fixed, small numbers.
+    Fixed = 0,
+    /// Git objects, addressed by id prefix.
+    Object = 1,
+    /// Branch entries.
+    Branch = 2,
+    /// Tag entries.
+    Tag = 3,
+    /// `.git-meta` nodes and other synthetic files.
+    Meta = 4,
+    /// Snapshot directories whose contents depend on their path.
+    Scoped = 5,
+    /// Directories and links of the full reference namespace under `/refs`.
+    Refs = 6,
+}
+
+impl Space {
+    const ALL: [Space; 7] = [
+        Space::Fixed,
+        Space::Object,
+        Space::Branch,
+        Space::Tag,
+        Space::Meta,
+        Space::Scoped,
+        Space::Refs,
+    ];
+
+    /// Space `ino` belongs to, or `None` for an unassigned tag.
+    #[must_use]
+    pub fn of(ino: u64) -> Option<Space> {
+        let tag = ino >> SPACE_SHIFT;
+        Self::ALL.into_iter().find(|space| *space as u64 == tag)
+    }
+
+    fn tag(self, payload: u64) -> u64 {
+        ((self as u64) << SPACE_SHIFT) | (payload & PAYLOAD_MASK)
+    }
+}
+
+/// How an object inode presents its object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ObjectView {
+    /// Directories for trees and commits, read-only files for blobs.
+    Plain = 0,
+    /// Blobs with the executable bit.
+    Executable = 1,
+    /// Blobs holding a symlink target.
+    Link = 2,
+}
+
+/// Convert a Git object id into its plain inode in [`Space::Object`].
+#[must_use]
+pub fn inode_from_oid(oid: &ObjectId) -> u64 {
+    object_inode(oid, ObjectView::Plain)
+}
+
+/// Inode of `oid` presented as `view`, keeping the first 56 bits of the id.
 ///
 /// # Panics
 ///
 /// Panics if the object id is shorter than eight bytes, which cannot occur for valid Git object ids.
 #[must_use]
-pub fn inode_from_oid(oid: &ObjectId) -> u64 {
-    u64::from_be_bytes(oid.as_bytes()[..8].try_into().unwrap())
+pub fn object_inode(oid: &ObjectId, view: ObjectView) -> u64 {
+    view_inode(
+        narrow_object_inode(oid, OBJECT_PREFIX_BITS, 0).unwrap(),
+        view,
+    )
 }
 
-/// Render the inode as a hexadecimal prefix string suitable for prefix
-/// resolution in the Git object database.
+/// Plain inode of `oid` keeping only the first `prefix_bits` bits of the
+/// id, followed by `slot` in the bits freed up. Returns `None` if `slot`
+/// does not fit into them.
+///
+/// # Panics
+///
+/// Panics if `prefix_bits` is not between [`MIN_OBJECT_PREFIX_BITS`] and
+/// [`OBJECT_PREFIX_BITS`].
+#[must_use]
+pub fn narrow_object_inode(oid: &ObjectId, prefix_bits: u32, slot: u64) -> Option<u64> {
+    assert!(
+        (MIN_OBJECT_PREFIX_BITS..=OBJECT_PREFIX_BITS).contains(&prefix_bits),
+        "{prefix_bits} prefix bits out of range"
+    );
+    let slot_bits = OBJECT_PREFIX_BITS - prefix_bits;
+    if slot >> slot_bits != 0 {
+        return None;
+    }
+    let high = u64::from_be_bytes(oid.as_bytes()[..8].try_into().unwrap());
+    let prefix = high >> (64 - prefix_bits);
+    Some(
+        Space::Object.tag((((prefix << slot_bits) | slot) << VIEW_BITS) | ObjectView::Plain as u64),
+    )
+}
+
+/// Object inode `plain` presented as `view`.
 #[must_use]
-pub fn inode_to_hex_prefix(ino: u64) -> String {
-    format!("{ino:016x}")
+pub fn view_inode(plain: u64, view: ObjectView) -> u64 {
+    plain_inode(plain) | view as u64
+}
+
+/// The plain inode of the object object inode `ino` presents.
+#[must_use]
+pub fn plain_inode(ino: u64) -> u64 {
+    ino & !((1 << VIEW_BITS) - 1)
+}
+
+/// View encoded in an object inode; [`ObjectView::Plain`] for anything else.
+#[must_use]
+pub fn object_view(ino: u64) -> ObjectView {
+    if Space::of(ino) != Some(Space::Object) {
+        return ObjectView::Plain;
+    }
+    match ino & ((1 << VIEW_BITS) - 1) {
+        1 => ObjectView::Executable,
+        2 => ObjectView::Link,
+        _ => ObjectView::Plain,
+    }
+}
+
+/// Inode of the blob with plain inode `plain` served with the `index`th of
+/// the distinct `--mode-override` modes, below [`MODE_VIEWS`].
+///
+/// # Panics
+///
+/// Panics if `index` is not below [`MODE_VIEWS`].
+#[must_use]
+pub fn mode_inode(plain: u64, index: usize) -> u64 {
+    assert!(index < MODE_VIEWS, "mode index {index} out of range");
+    plain_inode(plain) | (FIRST_MODE_VIEW + index) as u64
+}
+
+/// Index of the `--mode-override` mode object inode `ino` is served with.
+#[must_use]
+pub fn mode_index(ino: u64) -> Option<usize> {
+    if Space::of(ino) != Some(Space::Object) {
+        return None;
+    }
+    let view = ino & ((1 << VIEW_BITS) - 1);
+    usize::try_from(view).ok()?.checked_sub(FIRST_MODE_VIEW)
+}
+
+/// Inode number `number` of `space`, for spaces whose inodes are allocated
+/// in sequence rather than derived (see [`crate::ref_inodes`]).
+#[must_use]
+pub fn numbered_inode(space: Space, number: u64) -> u64 {
+    space.tag(number)
+}
+
+/// Derive a stable inode in `space` from `name`.
+#[must_use]
+pub fn synthetic_inode(space: Space, name: &[u8]) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    (space as u8).hash(&mut hasher);
+    name.hash(&mut hasher);
+    space.tag(hasher.finish())
+}
+
+/// Render an object inode as a hexadecimal prefix string suitable for prefix
+/// resolution in the Git object database. Returns `None` for inodes outside
+/// [`Space::Object`].
+#[must_use]
+pub fn inode_to_hex_prefix(ino: u64) -> Option<String> {
+    (Space::of(ino) == Some(Space::Object)).then(|| {
+        format!(
+            "{:0width$x}",
+            (ino & PAYLOAD_MASK) >> VIEW_BITS,
+            width = OBJECT_PREFIX_DIGITS
+        )
+    })
+}
+
+/// The first `prefix_bits` bits of object inode `ino` as a hexadecimal
+/// prefix, if its slot (see [`narrow_object_inode`]) is the first one; the
+/// objects in other slots can only be found through the slot table.
+/// `prefix_bits` is rounded down to whole digits.
+#[must_use]
+pub fn narrow_hex_prefix(ino: u64, prefix_bits: u32) -> Option<String> {
+    let slot_bits = OBJECT_PREFIX_BITS - prefix_bits;
+    let payload = (ino & PAYLOAD_MASK) >> VIEW_BITS;
+    if Space::of(ino) != Some(Space::Object) || payload & ((1 << slot_bits) - 1) != 0 {
+        return None;
+    }
+    let digits = prefix_bits / 4;
+    Some(format!(
+        "{:0width$x}",
+        payload >> (OBJECT_PREFIX_BITS - digits * 4),
+        width = digits as usize
+    ))
 }
 
 #[cfg(test)]
@@ -34,10 +261,77 @@ mod tests {
     }
 
     #[test]
-    fn inode_roundtrip_low_bits() {
+    fn inode_roundtrip_prefix() {
         let object = oid("0123456789abcdef0123456789abcdef01234567");
         let ino = inode_from_oid(&object);
-        assert_eq!(ino, 0x0123_4567_89ab_cdef);
-        assert_eq!(inode_to_hex_prefix(ino), "0123456789abcdef");
+        assert_eq!(ino, 0x1012_3456_789a_bcd0);
+        assert_eq!(inode_to_hex_prefix(ino).as_deref(), Some("0123456789abcd"));
+        assert_eq!(inode_to_hex_prefix(2), None);
+
+        let link = object_inode(&object, ObjectView::Link);
+        assert_ne!(link, ino);
+        assert_eq!(object_view(link), ObjectView::Link);
+        assert_eq!(inode_to_hex_prefix(link), inode_to_hex_prefix(ino));
+
+        let moded = mode_inode(link, MODE_VIEWS - 1);
+        assert_eq!(mode_index(moded), Some(MODE_VIEWS - 1));
+        assert_eq!(mode_index(link), None);
+        assert_eq!(object_view(moded), ObjectView::Plain);
+        assert_eq!(inode_to_hex_prefix(moded), inode_to_hex_prefix(ino));
+    }
+
+    #[test]
+    fn spaces_never_overlap() {
+        let mut rng = fastrand::Rng::with_seed(0x5eed);
+        let synthetic = [
+            Space::Branch,
+            Space::Tag,
+            Space::Meta,
+            Space::Scoped,
+            Space::Refs,
+        ];
+        for _ in 0..10_000 {
+            let bytes: [u8; 20] = std::array::from_fn(|_| rng.u8(..));
+            let ino = inode_from_oid(&ObjectId::from_bytes_or_panic(&bytes));
+            assert_eq!(Space::of(ino), Some(Space::Object));
+            let prefix = inode_to_hex_prefix(ino).unwrap();
+            assert!(ObjectId::from_bytes_or_panic(&bytes)
+                .to_string()
+                .starts_with(&prefix));
+
+            let name: Vec<u8> = (0..rng.usize(..32)).map(|_| rng.u8(..)).collect();
+            for space in synthetic {
+                let ino = synthetic_inode(space, &name);
+                assert_eq!(Space::of(ino), Some(space));
+                assert_eq!(inode_to_hex_prefix(ino), None);
+            }
+        }
+        for fixed in 1..=64 {
+            assert_eq!(Space::of(fixed), Some(Space::Fixed));
+            assert_eq!(inode_to_hex_prefix(fixed), None);
+        }
+    }
+
+    #[test]
+    fn narrow_inodes_keep_prefix_and_slot() {
+        let object = oid("0123456789abcdef0123456789abcdef01234567");
+        assert_eq!(
+            narrow_object_inode(&object, OBJECT_PREFIX_BITS, 0),
+            Some(inode_from_oid(&object))
+        );
+        assert_eq!(narrow_object_inode(&object, OBJECT_PREFIX_BITS, 1), None);
+
+        let first = narrow_object_inode(&object, 16, 0).unwrap();
+        let second = narrow_object_inode(&object, 16, 5).unwrap();
+        assert_eq!(first, 0x1012_3000_0000_0000);
+        assert_eq!(second, 0x1012_3000_0000_0050);
+        assert_eq!(narrow_hex_prefix(first, 16).as_deref(), Some("0123"));
+        assert_eq!(
+            narrow_hex_prefix(view_inode(first, ObjectView::Link), 16).as_deref(),
+            Some("0123")
+        );
+        assert_eq!(narrow_hex_prefix(second, 16), None);
+        assert_eq!(narrow_object_inode(&object, 16, 1 << 40), None);
+        assert_eq!(plain_inode(mode_inode(second, 2)), second);
     }
 }
@@ -0,0 +1,940 @@
+//! Offline inspection of a repository, run without mounting it.
+//!
+//! The checks deliberately go through the same `gix` decoding calls that the
+//! filesystem uses when serving requests (`find_commit`, `find_tree`,
+//! `find_blob`, ...), so an object that passes here will not fail later when a
+//! user touches it through the mount.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context as _, Result};
+use fuse_backend_rs::api::filesystem::{Context, FileSystem};
+use gix::bstr::{BString, ByteSlice};
+use gix::object::tree::EntryKind;
+use gix::object::Kind;
+use gix::ObjectId;
+use serde::Serialize;
+
+use crate::fs::{self, GitSnapFs};
+use crate::inode::{mode_index, object_view, ObjectView, Space};
+use crate::limits::{Budget, TreeLimits};
+use crate::ref_inodes::RefInodes;
+use crate::repo::Repository;
+use crate::reverse::{self, ReverseIndex};
+use crate::vfs::{NodeKind, Vfs};
+
+/// Outcome of [`fsck`].
+#[derive(Debug, Default, Serialize)]
+pub struct FsckReport {
+    /// Number of references examined.
+    pub refs_checked: usize,
+    /// Number of distinct objects decoded.
+    pub objects_checked: usize,
+    /// Every object or reference that could not be read.
+    pub problems: Vec<FsckProblem>,
+}
+
+/// A single unreadable object or reference.
+#[derive(Debug, Serialize)]
+pub struct FsckProblem {
+    /// Object id, if the problem concerns an object.
+    pub oid: Option<String>,
+    /// Object kind we expected to find.
+    pub kind: Option<&'static str>,
+    /// Where the object was referenced from (ref name or `<parent>:<path>`).
+    pub referenced_from: String,
+    /// Error reported by the decoder.
+    pub error: String,
+}
+
+impl FsckReport {
+    /// Whether no problems were found.
+    #[must_use]
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Walk every object reachable from the repository's references and record
+/// the ones that fail to decode.
+///
+/// # Errors
+///
+/// Returns an error only if the reference database itself cannot be listed;
+/// per-object failures end up in the report.
+pub fn fsck(repo: &Repository) -> Result<FsckReport> {
+    let repo = repo.thread_local();
+    let mut report = FsckReport::default();
+    let mut pending: Vec<(ObjectId, Option<Kind>, String)> = Vec::new();
+
+    let platform = repo.references()?;
+    for reference in platform.all()? {
+        report.refs_checked += 1;
+        let mut reference = match reference {
+            Ok(reference) => reference,
+            Err(err) => {
+                report.problems.push(FsckProblem {
+                    oid: None,
+                    kind: None,
+                    referenced_from: format!("reference #{}", report.refs_checked),
+                    error: err.to_string(),
+                });
+                continue;
+            }
+        };
+        let name = reference.name().as_bstr().to_string();
+        match reference.follow_to_object() {
+            Ok(id) => pending.push((id.detach(), None, name)),
+            Err(err) => report.problems.push(FsckProblem {
+                oid: None,
+                kind: None,
+                referenced_from: name,
+                error: err.to_string(),
+            }),
+        }
+    }
+
+    let mut seen = HashSet::new();
+    while let Some((oid, expected, from)) = pending.pop() {
+        if !seen.insert(oid) {
+            continue;
+        }
+        report.objects_checked += 1;
+        if let Err(err) = check_object(&repo, oid, expected, &mut pending) {
+            report.problems.push(FsckProblem {
+                oid: Some(oid.to_string()),
+                kind: expected.map(kind_name),
+                referenced_from: from,
+                error: format!("{err:#}"),
+            });
+        }
+    }
+    Ok(report)
+}
+
+fn check_object(
+    repo: &gix::Repository,
+    oid: ObjectId,
+    expected: Option<Kind>,
+    pending: &mut Vec<(ObjectId, Option<Kind>, String)>,
+) -> Result<()> {
+    let kind = match expected {
+        Some(kind) => kind,
+        None => repo.find_object(oid)?.kind,
+    };
+    match kind {
+        Kind::Commit => {
+            let commit = repo.find_commit(oid)?;
+            pending.push((
+                commit.tree_id()?.detach(),
+                Some(Kind::Tree),
+                format!("{oid}^{{tree}}"),
+            ));
+            for parent in commit.parent_ids() {
+                pending.push((parent.detach(), Some(Kind::Commit), format!("{oid}^")));
+            }
+        }
+        Kind::Tree => {
+            let tree = repo.find_tree(oid)?;
+            for entry in tree.iter() {
+                let entry = entry?;
+                let child_kind = match entry.inner.mode.kind() {
+                    EntryKind::Tree => Kind::Tree,
+                    EntryKind::Blob | EntryKind::BlobExecutable | EntryKind::Link => Kind::Blob,
+                    // Submodule commits live in another repository.
+                    EntryKind::Commit => continue,
+                };
+                pending.push((
+                    entry.inner.oid.to_owned(),
+                    Some(child_kind),
+                    format!("{oid}:{}", entry.inner.filename.to_str_lossy()),
+                ));
+            }
+        }
+        Kind::Blob => {
+            repo.find_blob(oid)?;
+        }
+        Kind::Tag => {
+            let tag = repo.find_tag(oid)?;
+            pending.push((tag.target_id()?.detach(), None, format!("{oid}^{{}}")));
+        }
+    }
+    Ok(())
+}
+
+fn kind_name(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Commit => "commit",
+        Kind::Tree => "tree",
+        Kind::Blob => "blob",
+        Kind::Tag => "tag",
+    }
+}
+
+/// Outcome of [`overlay_check`].
+#[derive(Debug, Default, Serialize)]
+pub struct OverlayReport {
+    /// Path inside the mount that was checked, as it would be passed to `lowerdir=`.
+    pub lowerdir: String,
+    /// Number of directories opened and listed.
+    pub directories_checked: usize,
+    /// Number of directory entries compared against their lookups.
+    pub entries_checked: usize,
+    /// Every requirement violation found.
+    pub problems: Vec<OverlayProblem>,
+}
+
+/// A single violated overlayfs requirement.
+#[derive(Debug, Serialize)]
+pub struct OverlayProblem {
+    /// Path relative to the lower directory.
+    pub path: String,
+    /// What went wrong.
+    pub error: String,
+}
+
+impl OverlayReport {
+    /// Whether no problems were found.
+    #[must_use]
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    fn problem(&mut self, path: &[u8], error: impl Into<String>) {
+        self.problems.push(OverlayProblem {
+            path: path.to_str_lossy().into_owned(),
+            error: error.into(),
+        });
+    }
+}
+
+/// Walk the directory at `lowerdir` (a path inside the mount, symlinks
+/// followed) through the filesystem's request handlers and verify what
+/// overlayfs expects from a lower layer: `opendir` succeeds on every
+/// directory, the `d_type` reported by readdir matches the entry's mode, and
+/// inodes are stable across readdir, repeated lookups and `.` lookups (the
+/// path NFS export takes).
+///
+/// `fs` should be built with `overlay_compat` set, as it would be for the mount.
+///
+/// # Errors
+///
+/// Returns an error if `lowerdir` does not resolve to a directory; per-entry
+/// failures end up in the report.
+pub fn overlay_check(fs: &Arc<GitSnapFs>, lowerdir: &[u8]) -> Result<OverlayReport> {
+    let start = Vfs::new(Arc::clone(fs)).resolve(lowerdir, true)?;
+    if start.kind != NodeKind::Directory {
+        bail!("{} is not a directory", lowerdir.to_str_lossy());
+    }
+    let mut report = OverlayReport {
+        lowerdir: lowerdir.to_str_lossy().into_owned(),
+        ..OverlayReport::default()
+    };
+    let ctx = Context::default();
+    let mut seen = HashSet::new();
+    let mut pending: Vec<(u64, BString)> = vec![(start.inode, BString::from("."))];
+    while let Some((dir, path)) = pending.pop() {
+        if !seen.insert(dir) {
+            continue;
+        }
+        report.directories_checked += 1;
+        if let Err(err) = fs.opendir(&ctx, dir, libc::O_RDONLY as u32) {
+            report.problem(&path, format!("opendir failed: {err}"));
+        }
+        match fs.lookup_name(dir, b".") {
+            Ok(entry) if entry.inode == dir => {}
+            Ok(entry) => report.problem(&path, format!("\".\" resolves to inode {}", entry.inode)),
+            Err(err) => report.problem(&path, format!("lookup of \".\" failed: {err}")),
+        }
+        let records = match fs.list_directory(dir) {
+            Ok(records) => records,
+            Err(err) => {
+                report.problem(&path, format!("readdir failed: {err}"));
+                continue;
+            }
+        };
+        for record in records {
+            report.entries_checked += 1;
+            let mut child = path.clone();
+            child.extend_from_slice(b"/");
+            child.extend_from_slice(&record.name);
+            let first = fs.lookup_name(dir, &record.name);
+            let second = fs.lookup_name(dir, &record.name);
+            let (first, second) = match (first, second) {
+                (Ok(first), Ok(second)) => (first, second),
+                (Err(err), _) | (_, Err(err)) => {
+                    report.problem(&child, format!("lookup failed: {err}"));
+                    continue;
+                }
+            };
+            if first.inode != record.ino {
+                report.problem(
+                    &child,
+                    format!(
+                        "readdir inode {} but lookup inode {}",
+                        record.ino, first.inode
+                    ),
+                );
+            }
+            if first.inode != second.inode {
+                report.problem(
+                    &child,
+                    format!("unstable inode: {} then {}", first.inode, second.inode),
+                );
+            }
+            let format = first.attr.st_mode & libc::S_IFMT;
+            if record.dtype != format >> 12 {
+                report.problem(
+                    &child,
+                    format!(
+                        "d_type {} does not match mode {:o}",
+                        record.dtype, first.attr.st_mode
+                    ),
+                );
+            }
+            if format == libc::S_IFDIR {
+                pending.push((first.inode, child));
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Outcome of [`pack_stats`].
+#[derive(Debug, Default, Serialize)]
+pub struct PackStatsReport {
+    /// Every pack in the object directory, largest first.
+    pub packs: Vec<PackStats>,
+    /// Number of loose objects.
+    pub loose_objects: usize,
+    /// Number of packed objects across all packs.
+    pub packed_objects: usize,
+    /// Packed objects per delta-chain depth bucket; depth 0 is a full object.
+    pub depth_histogram: Vec<DepthBucket>,
+    /// Deepest delta chain found.
+    pub max_depth: usize,
+    /// Mean number of pack entries inflated to read one packed object.
+    pub mean_chain_length: f64,
+    /// Compressed bytes inflated to read every packed object once, relative
+    /// to the bytes of the entries themselves.
+    pub read_amplification: f64,
+    /// Suggested maintenance, if any.
+    pub advice: Vec<String>,
+}
+
+/// Statistics for a single pack.
+#[derive(Debug, Serialize)]
+pub struct PackStats {
+    /// File name of the pack index.
+    pub name: String,
+    /// Number of objects in the pack.
+    pub objects: usize,
+    /// Size of the pack data in bytes.
+    pub bytes: u64,
+    /// Number of objects stored as deltas.
+    pub deltas: usize,
+    /// Deepest delta chain in the pack.
+    pub max_depth: usize,
+}
+
+/// One bucket of [`PackStatsReport::depth_histogram`].
+#[derive(Debug, Serialize)]
+pub struct DepthBucket {
+    /// Range of chain depths counted, such as `2-4`.
+    pub depth: &'static str,
+    /// Number of packed objects in that range.
+    pub objects: usize,
+}
+
+/// Upper bounds of the depth histogram buckets, with their labels.
+const DEPTH_BUCKETS: [(usize, &str); 7] = [
+    (0, "0"),
+    (1, "1"),
+    (4, "2-4"),
+    (9, "5-9"),
+    (19, "10-19"),
+    (49, "20-49"),
+    (usize::MAX, "50+"),
+];
+
+/// More packs than this make every object lookup probe many indices.
+const MANY_PACKS: usize = 10;
+/// More loose objects than this are worth packing.
+const MANY_LOOSE_OBJECTS: usize = 1000;
+/// Mean chain length above which reads spend most of their time resolving deltas.
+const LONG_CHAINS: f64 = 10.0;
+
+fn depth_bucket(depth: usize) -> usize {
+    DEPTH_BUCKETS
+        .iter()
+        .position(|(max, _)| depth <= *max)
+        .unwrap_or(DEPTH_BUCKETS.len() - 1)
+}
+
+/// Where the base of a pack entry lives.
+#[derive(Clone, Copy)]
+enum Base {
+    /// A full object.
+    None,
+    /// Another entry of the same pack.
+    InPack(u64),
+    /// An object outside this pack (thin pack or loose base).
+    External,
+}
+
+/// Summarize the packs and loose objects backing `repo`: object counts,
+/// delta-chain depths and how many compressed bytes must be inflated to
+/// serve a read, with advice on when to repack. Only the repository's own
+/// object directory is examined, not its alternates.
+///
+/// # Errors
+///
+/// Returns an error if the object directory cannot be listed or a pack
+/// cannot be parsed.
+#[allow(clippy::cast_precision_loss)]
+pub fn pack_stats(repo: &Repository) -> Result<PackStatsReport> {
+    let repo = repo.thread_local();
+    let objects_dir = repo.objects.store_ref().path().to_path_buf();
+    let mut report = PackStatsReport {
+        depth_histogram: DEPTH_BUCKETS
+            .iter()
+            .map(|&(_, depth)| DepthBucket { depth, objects: 0 })
+            .collect(),
+        ..PackStatsReport::default()
+    };
+
+    let mut depth_sum = 0usize;
+    let (mut inflated_bytes, mut entry_bytes) = (0u64, 0u64);
+    let loose = std::fs::read_dir(&objects_dir)
+        .with_context(|| format!("failed to read {}", objects_dir.display()))?;
+    for entry in loose {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.len() == 2 && name.bytes().all(|b| b.is_ascii_hexdigit()) {
+            report.loose_objects += std::fs::read_dir(entry.path())?.count();
+        }
+    }
+
+    let pack_dir = objects_dir.join("pack");
+    let mut indices: Vec<PathBuf> = match std::fs::read_dir(&pack_dir) {
+        Ok(entries) => entries
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<_>>()?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to read {}", pack_dir.display()))
+        }
+    };
+    indices.retain(|path| path.extension().is_some_and(|ext| ext == "idx"));
+
+    for idx in indices {
+        let (stats, chains) = measure_pack(&idx, repo.object_hash())?;
+        for chain in chains {
+            report.depth_histogram[depth_bucket(chain.depth)].objects += 1;
+            depth_sum += chain.depth;
+            inflated_bytes += chain.inflated;
+            entry_bytes += chain.size;
+        }
+        report.packed_objects += stats.objects;
+        report.max_depth = report.max_depth.max(stats.max_depth);
+        report.packs.push(stats);
+    }
+    report
+        .packs
+        .sort_by_key(|pack| std::cmp::Reverse(pack.bytes));
+
+    if report.packed_objects > 0 {
+        report.mean_chain_length =
+            (depth_sum + report.packed_objects) as f64 / report.packed_objects as f64;
+    }
+    if entry_bytes > 0 {
+        report.read_amplification = inflated_bytes as f64 / entry_bytes as f64;
+    }
+    report.advice = pack_advice(&report);
+    Ok(report)
+}
+
+/// Delta chain of one pack entry.
+struct Chain {
+    /// Number of deltas applied on top of the full object.
+    depth: usize,
+    /// Compressed bytes of every entry in the chain.
+    inflated: u64,
+    /// Compressed bytes of the entry itself.
+    size: u64,
+}
+
+/// Measure the delta chain of every entry in the pack indexed by `idx`.
+fn measure_pack(idx: &Path, object_hash: gix::hash::Kind) -> Result<(PackStats, Vec<Chain>)> {
+    let bundle = gix_pack::Bundle::at(idx, object_hash)
+        .with_context(|| format!("failed to open pack {}", idx.display()))?;
+    let mut offsets: Vec<u64> = bundle.index.iter().map(|e| e.pack_offset).collect();
+    offsets.sort_unstable();
+    let pack_end = bundle.pack.pack_end() as u64;
+
+    let mut entries = HashMap::with_capacity(offsets.len());
+    for (i, &offset) in offsets.iter().enumerate() {
+        let size = offsets.get(i + 1).copied().unwrap_or(pack_end) - offset;
+        let entry = bundle.pack.entry(offset)?;
+        let base = match entry.header {
+            gix_pack::data::entry::Header::OfsDelta { base_distance } => {
+                Base::InPack(entry.base_pack_offset(base_distance))
+            }
+            gix_pack::data::entry::Header::RefDelta { base_id } => {
+                match bundle.index.lookup(base_id) {
+                    Some(index) => Base::InPack(bundle.index.pack_offset_at_index(index)),
+                    None => Base::External,
+                }
+            }
+            _ => Base::None,
+        };
+        entries.insert(offset, (base, size));
+    }
+
+    // Depth and inflated bytes of each chain, memoized by offset.
+    let mut known: HashMap<u64, (usize, u64)> = HashMap::with_capacity(offsets.len());
+    let mut chains = Vec::with_capacity(offsets.len());
+    for &offset in &offsets {
+        // Walk down to a full object, an external base or a chain already
+        // measured, then fill in the entries on the way back.
+        let mut walk = Vec::new();
+        let mut cursor = offset;
+        let mut base = loop {
+            if let Some(&chain) = known.get(&cursor) {
+                break Some(chain);
+            }
+            if walk.len() > offsets.len() {
+                bail!("delta cycle in {} at offset {offset}", idx.display());
+            }
+            let Some(&(base, size)) = entries.get(&cursor) else {
+                bail!(
+                    "delta base at offset {cursor} missing from {}",
+                    idx.display()
+                );
+            };
+            walk.push((cursor, size));
+            match base {
+                Base::InPack(base) => cursor = base,
+                Base::None => break None,
+                Base::External => break Some((0, 0)),
+            }
+        };
+        while let Some((at, size)) = walk.pop() {
+            let chain = base.map_or((0, size), |(depth, bytes)| (depth + 1, bytes + size));
+            known.insert(at, chain);
+            base = Some(chain);
+        }
+        let (depth, inflated) = known[&offset];
+        chains.push(Chain {
+            depth,
+            inflated,
+            size: entries[&offset].1,
+        });
+    }
+
+    let stats = PackStats {
+        name: idx
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        objects: chains.len(),
+        bytes: bundle.pack.data_len() as u64,
+        deltas: chains.iter().filter(|chain| chain.depth > 0).count(),
+        max_depth: chains.iter().map(|chain| chain.depth).max().unwrap_or(0),
+    };
+    Ok((stats, chains))
+}
+
+fn pack_advice(report: &PackStatsReport) -> Vec<String> {
+    let mut advice = Vec::new();
+    if report.packs.len() > MANY_PACKS {
+        advice.push(format!(
+            "{} packs: every lookup may probe each index; consolidate with `git repack -ad`",
+            report.packs.len()
+        ));
+    }
+    if report.loose_objects > MANY_LOOSE_OBJECTS {
+        advice.push(format!(
+            "{} loose objects: pack them with `git gc`",
+            report.loose_objects
+        ));
+    }
+    if report.mean_chain_length > LONG_CHAINS {
+        advice.push(format!(
+            "reads inflate {:.1} entries on average (max depth {}): \
+             `git repack -adf --depth=20 --window=250` trades disk space for faster reads",
+            report.mean_chain_length, report.max_depth
+        ));
+    }
+    advice
+}
+
+/// Outcome of [`reachability`].
+#[derive(Debug, Serialize)]
+pub struct ReachabilityReport {
+    /// Full id of the commit.
+    pub commit: String,
+    /// Where the snapshot is served, whatever its reachability.
+    pub path: String,
+    /// Full names of the references whose history contains the commit.
+    pub reachable_from: Vec<String>,
+}
+
+/// Explain why the commit named by `spec` (anything `git rev-parse`
+/// accepts) is visible: every reference, plus `HEAD`, whose history
+/// contains it. The same list is served as the
+/// `user.gitsnapfs.reachable-from` extended attribute of its snapshot.
+///
+/// # Errors
+///
+/// Returns an error if `spec` does not name a commit or the references
+/// cannot be enumerated.
+pub fn reachability(repo: &Repository, spec: &str) -> Result<ReachabilityReport> {
+    let commit = repo.resolve_full_commit_id(spec)?;
+    Ok(ReachabilityReport {
+        commit: commit.to_string(),
+        path: format!("commits/{commit}"),
+        reachable_from: repo.reachable_from(commit, false)?,
+    })
+}
+
+/// Outcome of [`find_blob`].
+#[derive(Debug, Serialize)]
+pub struct FindBlobReport {
+    /// Full id of the blob.
+    pub blob: String,
+    /// Reference-reachable commits whose snapshots contain the blob, sorted by id.
+    pub commits: Vec<String>,
+}
+
+/// Find the commits reachable from the references whose snapshots contain
+/// the blob named by `spec` (anything `git rev-parse` accepts), through the
+/// saved [`ReverseIndex`] when the references have not moved since it was
+/// built. The mount serves the same list as `objects/<blob>/referenced-by/`.
+///
+/// # Errors
+///
+/// Returns an error if `spec` does not name a blob or the history cannot be
+/// walked.
+pub fn find_blob(repo: &Repository, spec: &str) -> Result<FindBlobReport> {
+    let blob = {
+        let repo = repo.thread_local();
+        let id = repo
+            .rev_parse_single(spec)
+            .with_context(|| format!("{spec} does not name an object"))?
+            .detach();
+        if repo.find_header(id)?.kind() != Kind::Blob {
+            bail!("{spec} does not name a blob");
+        }
+        id
+    };
+    let index = ReverseIndex::load_or_build(repo, &reverse::tips(repo, |_| true)?, false)?;
+    Ok(FindBlobReport {
+        blob: blob.to_string(),
+        commits: index
+            .commits_containing(blob)
+            .iter()
+            .map(ToString::to_string)
+            .collect(),
+    })
+}
+
+/// How many example paths [`resolve_inode`] lists for an object.
+const EXAMPLE_PATHS: usize = 5;
+
+/// Outcome of [`resolve_inode`].
+#[derive(Debug, Default, Serialize)]
+pub struct InodeReport {
+    /// The inode, in hex.
+    pub inode: String,
+    /// Region of the inode space it belongs to (see [`crate::inode`]).
+    pub space: Option<&'static str>,
+    /// Full id of the object an object inode names.
+    pub object: Option<String>,
+    /// Kind of that object: `commit`, `tree`, `blob` or `tag`.
+    pub kind: Option<String>,
+    /// How the object is presented: `plain`, `executable` or `link`.
+    pub view: Option<&'static str>,
+    /// Paths below the mount root the inode is served at; for objects a few
+    /// snapshots reachable from the references that contain it.
+    pub paths: Vec<String>,
+    /// Why nothing more could be found, if that is the case.
+    pub note: Option<String>,
+}
+
+/// Explain an inode number reported by the kernel (in `stat`, `ls -i` or an
+/// error report): its space and, where it can be worked out without the
+/// mount, the object it names and example paths. Branch and tag inodes are
+/// numbered by the mount, so their names need its `--state-file`.
+///
+/// # Errors
+///
+/// Returns an error if the references, the state file or a tree on the way
+/// to an object cannot be read.
+pub fn resolve_inode(
+    repo: &Repository,
+    inode: u64,
+    state_file: Option<&Path>,
+) -> Result<InodeReport> {
+    let mut report = InodeReport {
+        inode: format!("{inode:#x}"),
+        space: Space::of(inode).map(space_name),
+        ..InodeReport::default()
+    };
+    match Space::of(inode) {
+        Some(Space::Fixed) => match fs::fixed_inode_path(inode) {
+            Some(path) => report.paths.push(path.to_owned()),
+            None => report.note = Some("no node has this fixed inode".to_owned()),
+        },
+        Some(Space::Object) => resolve_object_inode(repo, inode, state_file, &mut report)?,
+        Some(space @ (Space::Branch | Space::Tag)) => {
+            let dir = if space == Space::Branch {
+                "branches"
+            } else {
+                "tags"
+            };
+            match state_file {
+                Some(path) => match RefInodes::read(path)?.name(inode) {
+                    Some(name) => report.paths.push(format!("{dir}/{}", name.to_str_lossy())),
+                    None => {
+                        report.note = Some(format!("not allocated in {}", path.display()));
+                    }
+                },
+                None => {
+                    report.note = Some(
+                        "numbered by the mount as names are first listed; \
+                         pass its --state-file to find the name"
+                            .to_owned(),
+                    );
+                }
+            }
+        }
+        Some(Space::Meta | Space::Scoped | Space::Refs) => {
+            report.note = Some(
+                "derived from a hash of the path; only the mount that served it can map it back"
+                    .to_owned(),
+            );
+        }
+        None => report.note = Some("not a gitsnapfs inode".to_owned()),
+    }
+    Ok(report)
+}
+
+fn resolve_object_inode(
+    repo: &Repository,
+    inode: u64,
+    state_file: Option<&Path>,
+    report: &mut InodeReport,
+) -> Result<()> {
+    report.view = Some(match object_view(inode) {
+        ObjectView::Plain if mode_index(inode).is_some() => "mode-override",
+        ObjectView::Plain => "plain",
+        ObjectView::Executable => "executable",
+        ObjectView::Link => "link",
+    });
+    // With --inode-prefix-bits the state file records the object of every
+    // inode handed out.
+    let recorded = match state_file {
+        Some(path) => RefInodes::read(path)?.object(inode),
+        None => None,
+    };
+    let object = match recorded.map_or_else(|| repo.resolve_inode(inode), Ok) {
+        Ok(object) => object,
+        Err(err) => {
+            // Typical of ESTALE: the object was pruned, or new objects made
+            // the prefix ambiguous.
+            report.note = Some(format!("{err:#}"));
+            return Ok(());
+        }
+    };
+    report.object = Some(object.to_string());
+    let kind = repo.thread_local().find_header(object)?.kind();
+    report.kind = Some(kind.to_string());
+    match kind {
+        Kind::Commit => report.paths.push(format!("commits/{object}")),
+        Kind::Tree | Kind::Blob => {
+            if kind == Kind::Tree {
+                report.paths.push(format!("trees/{object}"));
+            }
+            let index = ReverseIndex::load_or_build(repo, &reverse::tips(repo, |_| true)?, false)?;
+            for (commit, path) in index.paths_to(&repo.thread_local(), object, EXAMPLE_PATHS)? {
+                let mut full = format!("commits/{commit}");
+                if !path.is_empty() {
+                    full.push('/');
+                    full.push_str(&path.to_str_lossy());
+                }
+                report.paths.push(full);
+            }
+            if report.paths.is_empty() {
+                report.note = Some("not in any snapshot reachable from the references".to_owned());
+            }
+        }
+        Kind::Tag => {}
+    }
+    Ok(())
+}
+
+fn space_name(space: Space) -> &'static str {
+    match space {
+        Space::Fixed => "fixed",
+        Space::Object => "object",
+        Space::Branch => "branch",
+        Space::Tag => "tag",
+        Space::Meta => "meta",
+        Space::Scoped => "scoped",
+        Space::Refs => "refs",
+    }
+}
+
+/// Parse an inode number given in decimal or, with a `0x` prefix, in hex.
+///
+/// # Errors
+///
+/// Returns an error if `input` is not a number.
+pub fn parse_inode(input: &str) -> Result<u64> {
+    let parsed = match input
+        .strip_prefix("0x")
+        .or_else(|| input.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => input.parse(),
+    };
+    parsed.with_context(|| format!("invalid inode number '{input}'"))
+}
+
+/// Outcome of [`oversized_files`].
+#[derive(Debug, Serialize)]
+pub struct OversizedReport {
+    /// Size limit the files exceed, in bytes.
+    pub max_file_size: u64,
+    /// Every file above the limit, once per snapshot it appears in.
+    pub files: Vec<OversizedFile>,
+}
+
+/// A file whose reads fail with `EFBIG` under `--max-file-size`.
+#[derive(Debug, Serialize)]
+pub struct OversizedFile {
+    /// Path of the file in the mount, below `HEAD` or the `refs/` mirror.
+    pub path: String,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// Id of the blob.
+    pub blob: String,
+}
+
+/// List the files larger than `max_file_size` in the snapshots of `HEAD`
+/// and every reference naming a commit or tree. Sizes come from object
+/// headers, so nothing is decompressed.
+///
+/// # Errors
+///
+/// Returns an error if the references cannot be listed, an object cannot be
+/// read, or a snapshot exceeds `limits`.
+pub fn oversized_files(
+    repo: &Repository,
+    max_file_size: u64,
+    limits: TreeLimits,
+) -> Result<OversizedReport> {
+    let mut roots: Vec<(String, ObjectId)> = repo
+        .resolve_head()
+        .ok()
+        .map(|head| ("HEAD".to_owned(), head))
+        .into_iter()
+        .collect();
+    for (name, id) in repo.list_refs()? {
+        roots.push((name.to_str_lossy().into_owned(), id));
+    }
+    let repo = repo.thread_local();
+    let mut report = OversizedReport {
+        max_file_size,
+        files: Vec::new(),
+    };
+    for (name, id) in roots {
+        let tree = match repo.find_object(id)?.kind {
+            Kind::Commit => repo.find_commit(id)?.tree_id()?.detach(),
+            Kind::Tree => id,
+            Kind::Blob | Kind::Tag => continue,
+        };
+        let mut prefix = name.into_bytes();
+        prefix.push(b'/');
+        let mut budget = Budget::new(limits);
+        oversized_in_tree(
+            &repo,
+            tree,
+            max_file_size,
+            &mut prefix,
+            0,
+            &mut budget,
+            &mut report.files,
+        )?;
+    }
+    Ok(report)
+}
+
+fn oversized_in_tree(
+    repo: &gix::Repository,
+    tree: ObjectId,
+    max_file_size: u64,
+    prefix: &mut Vec<u8>,
+    depth: usize,
+    budget: &mut Budget,
+    out: &mut Vec<OversizedFile>,
+) -> Result<()> {
+    budget.descend(depth)?;
+    for entry in repo.find_tree(tree)?.iter() {
+        let entry = entry?;
+        budget.visit()?;
+        let oid = entry.inner.oid.to_owned();
+        let prefix_len = prefix.len();
+        prefix.extend_from_slice(entry.inner.filename);
+        match entry.inner.mode.kind() {
+            EntryKind::Tree => {
+                prefix.push(b'/');
+                oversized_in_tree(repo, oid, max_file_size, prefix, depth + 1, budget, out)?;
+            }
+            EntryKind::Blob | EntryKind::BlobExecutable => {
+                let size = repo.find_header(oid)?.size();
+                if size > max_file_size {
+                    out.push(OversizedFile {
+                        path: prefix.to_str_lossy().into_owned(),
+                        size,
+                        blob: oid.to_string(),
+                    });
+                }
+            }
+            EntryKind::Link | EntryKind::Commit => {}
+        }
+        prefix.truncate(prefix_len);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_depths_and_advises_on_long_chains() {
+        let labels: Vec<_> = [0, 1, 2, 4, 5, 49, 50, 5000]
+            .map(|depth| DEPTH_BUCKETS[depth_bucket(depth)].1)
+            .into();
+        assert_eq!(
+            labels,
+            ["0", "1", "2-4", "2-4", "5-9", "20-49", "50+", "50+"]
+        );
+
+        let mut report = PackStatsReport {
+            mean_chain_length: 1.5,
+            ..PackStatsReport::default()
+        };
+        assert!(pack_advice(&report).is_empty());
+        report.mean_chain_length = 24.0;
+        report.loose_objects = 5000;
+        let advice = pack_advice(&report);
+        assert_eq!(advice.len(), 2);
+        assert!(advice[1].contains("--depth="));
+    }
+}
@@ -0,0 +1,181 @@
+//! Abandoning requests the kernel interrupted (`FUSE_INTERRUPT`).
+//!
+//! When a process waiting on the mount gets a signal (Ctrl-C on a `cat` of
+//! a huge blob), the kernel sends `FUSE_INTERRUPT` naming the request's
+//! `unique` id. The multi-threaded server sees it on the receiving thread
+//! and sets the cancellation [`Token`] it handed out with that request;
+//! the worker serving the request had entered the token, so
+//! [`check`] fails with `EINTR` at the next cancellation point and the
+//! worker moves on to other requests. Decodes of large blobs run on a
+//! helper thread the worker stops waiting for (see
+//! [`crate::repo::Repository::read_blob`]); commit walks check between
+//! commits.
+//!
+//! With a single serving thread, requests are handled as they are read, so
+//! interrupts only arrive once the request is done and are ignored.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// FUSE opcode of `FUSE_INTERRUPT`.
+const FUSE_INTERRUPT: u32 = 36;
+
+/// Size of `fuse_in_header`, after which the request body starts.
+const IN_HEADER_LEN: usize = 40;
+
+thread_local! {
+    static CURRENT: RefCell<Option<Token>> = const { RefCell::new(None) };
+}
+
+/// Error of work abandoned because its request was interrupted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("request interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+impl From<Interrupted> for io::Error {
+    fn from(_: Interrupted) -> Self {
+        io::Error::from_raw_os_error(libc::EINTR)
+    }
+}
+
+/// Cancellation flag of one request.
+#[derive(Debug, Clone, Default)]
+pub struct Token(Arc<AtomicBool>);
+
+impl Token {
+    #[must_use]
+    pub fn is_set(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Make this the token [`check`] consults on the calling thread until
+    /// the returned guard is dropped.
+    #[must_use]
+    pub fn enter(&self) -> Entered {
+        CURRENT.with(|current| *current.borrow_mut() = Some(self.clone()));
+        Entered(())
+    }
+}
+
+/// Guard of [`Token::enter`].
+pub struct Entered(());
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = None);
+    }
+}
+
+/// Fail if the request served on this thread has been interrupted.
+///
+/// # Errors
+///
+/// Returns [`Interrupted`] once the kernel interrupted the request.
+pub fn check() -> Result<(), Interrupted> {
+    if current().is_some_and(|token| token.is_set()) {
+        Err(Interrupted)
+    } else {
+        Ok(())
+    }
+}
+
+/// The token of the request served on this thread, if it can be interrupted.
+#[must_use]
+pub fn current() -> Option<Token> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Tokens of the requests received and not yet answered, by `unique` id.
+#[derive(Debug, Default)]
+pub struct Interrupts {
+    pending: Mutex<HashMap<u64, Token>>,
+}
+
+impl Interrupts {
+    /// Note a request read from the device. Returns `None` for an interrupt,
+    /// which is applied here and needs no further handling, and the token
+    /// to enter while serving anything else.
+    pub fn received(&self, message: &[u8]) -> Option<Token> {
+        let opcode = message
+            .get(4..8)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u32::from_ne_bytes);
+        let mut pending = self.lock();
+        if opcode == Some(FUSE_INTERRUPT) {
+            // Requests already answered are no longer known and need nothing.
+            let target = u64_at(message, IN_HEADER_LEN);
+            if let Some((target, token)) = target.and_then(|target| pending.get_key_value(&target))
+            {
+                token.0.store(true, Ordering::Relaxed);
+                tracing::debug!("request {target} interrupted");
+            }
+            return None;
+        }
+        let token = Token::default();
+        pending.insert(u64_at(message, 8).unwrap_or_default(), token.clone());
+        Some(token)
+    }
+
+    /// Forget the request `message` once it has been answered.
+    pub fn answered(&self, message: &[u8]) {
+        self.lock().remove(&u64_at(message, 8).unwrap_or_default());
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Token>> {
+        self.pending
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// The native-endian `u64` at byte `at` of `message`, which is where the
+/// `unique` id sits in the header and in an interrupt's body.
+fn u64_at(message: &[u8], at: usize) -> Option<u64> {
+    message
+        .get(at..at + 8)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u64::from_ne_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(opcode: u32, unique: u64, body: &[u8]) -> Vec<u8> {
+        let mut message = vec![0; IN_HEADER_LEN];
+        message[4..8].copy_from_slice(&opcode.to_ne_bytes());
+        message[8..16].copy_from_slice(&unique.to_ne_bytes());
+        message.extend_from_slice(body);
+        message
+    }
+
+    #[test]
+    fn interrupt_sets_the_token_of_its_request() {
+        let interrupts = Interrupts::default();
+        let read = request(15, 7, &[]);
+        let token = interrupts.received(&read).unwrap();
+        let other = interrupts.received(&request(15, 8, &[])).unwrap();
+        let entered = token.enter();
+        assert_eq!(check(), Ok(()));
+        assert!(interrupts
+            .received(&request(FUSE_INTERRUPT, 9, &7u64.to_ne_bytes()))
+            .is_none());
+        assert_eq!(check(), Err(Interrupted));
+        assert!(!other.is_set());
+        drop(entered);
+        assert_eq!(check(), Ok(()));
+        interrupts.answered(&read);
+        assert!(interrupts.lock().get(&7).is_none());
+    }
+}
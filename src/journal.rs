@@ -0,0 +1,155 @@
+//! Recent failures of requests, readable as `/.gitsnapfs/errors`
+//! (`--error-journal`).
+//!
+//! A `cat` failing with `EIO` says nothing about why, and the daemon's log
+//! is often out of reach of the user who hit it. The journal keeps the last
+//! failures in memory, oldest first, one JSON line each:
+//!
+//! ```text
+//! {"time":"2024-05-01T12:00:00.123Z","op":"read","path":"/HEAD/big.bin","errno":5,"error":"EIO","cause":"object 1f2e... could not be decoded: ..."}
+//! ```
+//!
+//! `path` is the path the inode was looked up by, and is missing if it
+//! was reached otherwise. `cause` is the error the request failed with,
+//! including what it was caused by, such as the gix error behind an `EIO`.
+//! Routine answers such as `ENOENT` for a missing name or `ENODATA` for
+//! an absent extended attribute are not failures and are not recorded.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Mutex, PoisonError};
+
+use nix::errno::Errno;
+use serde::Serialize;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+/// Errnos answered in the normal course of serving a read-only tree.
+const ROUTINE_ERRNOS: &[i32] = &[
+    libc::ENOENT,
+    libc::ENODATA,
+    libc::ERANGE,
+    libc::ENOSYS,
+    libc::EINTR,
+];
+
+#[derive(Debug, Serialize)]
+struct Line {
+    time: String,
+    op: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    errno: i32,
+    error: String,
+    cause: String,
+}
+
+/// The last failures, up to a fixed number.
+#[derive(Debug)]
+pub struct ErrorJournal {
+    capacity: usize,
+    lines: Mutex<VecDeque<Vec<u8>>>,
+}
+
+impl ErrorJournal {
+    /// A journal keeping the last `capacity` failures.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            lines: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Record that `op` on `path` failed with `err`, unless the failure is
+    /// routine. Errors without an errno reach the kernel as `EIO`.
+    pub fn record(&self, op: &'static str, path: Option<&[u8]>, err: &io::Error) {
+        let errno = err.raw_os_error().unwrap_or(libc::EIO);
+        if self.capacity == 0 || ROUTINE_ERRNOS.contains(&errno) {
+            return;
+        }
+        let mut cause = err.to_string();
+        let mut source = err.get_ref().and_then(|inner| inner.source());
+        while let Some(inner) = source {
+            cause.push_str(": ");
+            cause.push_str(&inner.to_string());
+            source = inner.source();
+        }
+        let line = Line {
+            time: OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+            op,
+            path: path.map(|path| String::from_utf8_lossy(path).into_owned()),
+            errno,
+            error: format!("{:?}", Errno::from_raw(errno)),
+            cause,
+        };
+        let Ok(mut line) = serde_json::to_vec(&line) else {
+            return;
+        };
+        line.push(b'\n');
+        let mut lines = self.lines.lock().unwrap_or_else(PoisonError::into_inner);
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    /// The contents of `/.gitsnapfs/errors`.
+    #[must_use]
+    pub fn render(&self) -> Vec<u8> {
+        self.lines
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .flatten()
+            .copied()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_last_failures_with_their_causes() {
+        let journal = ErrorJournal::new(2);
+        let decode = anyhow::anyhow!("zlib stream is corrupt").context("cannot decode object");
+        journal.record("read", Some(b"/HEAD/a"), &io::Error::other(decode));
+        journal.record(
+            "lookup",
+            Some(b"/HEAD/missing"),
+            &io::Error::from_raw_os_error(libc::ENOENT),
+        );
+        journal.record("getattr", None, &io::Error::from_raw_os_error(libc::ELOOP));
+        journal.record(
+            "read",
+            Some(b"/HEAD/b"),
+            &io::Error::from_raw_os_error(libc::EFBIG),
+        );
+
+        let text = String::from_utf8(journal.render()).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["op"], "getattr");
+        assert_eq!(lines[0]["error"], "ELOOP");
+        assert!(lines[0].get("path").is_none());
+        assert_eq!(lines[1]["path"], "/HEAD/b");
+        assert_eq!(lines[1]["errno"], libc::EFBIG);
+
+        let journal = ErrorJournal::new(4);
+        let decode = anyhow::anyhow!("zlib stream is corrupt").context("cannot decode object");
+        journal.record("read", Some(b"/HEAD/a"), &io::Error::other(decode));
+        let line: serde_json::Value = serde_json::from_slice(&journal.render()).unwrap();
+        assert_eq!(line["error"], "EIO");
+        assert_eq!(
+            line["cause"],
+            "cannot decode object: zlib stream is corrupt"
+        );
+    }
+}
@@ -0,0 +1,152 @@
+//! Priority lanes for the multi-threaded FUSE server.
+//!
+//! Requests are split into a metadata lane (lookups, getattr, readdir, ...)
+//! and a data lane (file reads). Workers always drain the metadata lane first,
+//! and at most `max_data` of them may be busy with reads at any time, so
+//! `ls` stays responsive while someone extracts a large snapshot.
+
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+
+/// Which queue a request is placed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    Metadata,
+    Data,
+}
+
+/// FUSE opcode of `FUSE_READ`.
+const FUSE_READ: u32 = 15;
+
+impl Lane {
+    /// Classify a raw FUSE request by the opcode in its `fuse_in_header`.
+    #[must_use]
+    pub fn for_request(message: &[u8]) -> Self {
+        let opcode = message
+            .get(4..8)
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(u32::from_ne_bytes);
+        if opcode == Some(FUSE_READ) {
+            Lane::Data
+        } else {
+            Lane::Metadata
+        }
+    }
+}
+
+/// A two-lane work queue shared between the receiving thread and the workers.
+pub struct Lanes<T> {
+    state: Mutex<State<T>>,
+    ready: Condvar,
+    max_data: usize,
+}
+
+struct State<T> {
+    metadata: VecDeque<T>,
+    data: VecDeque<T>,
+    data_in_flight: usize,
+    closed: bool,
+}
+
+impl<T> Lanes<T> {
+    /// Create a queue that lets at most `max_data` data requests run concurrently.
+    #[must_use]
+    pub fn new(max_data: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                metadata: VecDeque::new(),
+                data: VecDeque::new(),
+                data_in_flight: 0,
+                closed: false,
+            }),
+            ready: Condvar::new(),
+            max_data: max_data.max(1),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Queue `item` on `lane`.
+    pub fn push(&self, lane: Lane, item: T) {
+        let mut state = self.lock();
+        match lane {
+            Lane::Metadata => state.metadata.push_back(item),
+            Lane::Data => state.data.push_back(item),
+        }
+        drop(state);
+        self.ready.notify_one();
+    }
+
+    /// Block until a request may run. Returns `None` once the queue is closed and drained.
+    ///
+    /// Every item taken from [`Lane::Data`] must be followed by a call to [`Lanes::finish`].
+    pub fn pop(&self) -> Option<(Lane, T)> {
+        let mut state = self.lock();
+        loop {
+            if let Some(item) = state.metadata.pop_front() {
+                return Some((Lane::Metadata, item));
+            }
+            if state.data_in_flight < self.max_data {
+                if let Some(item) = state.data.pop_front() {
+                    state.data_in_flight += 1;
+                    return Some((Lane::Data, item));
+                }
+            }
+            if state.closed && state.data.is_empty() {
+                return None;
+            }
+            state = self
+                .ready
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// Mark a request taken from `lane` as completed.
+    pub fn finish(&self, lane: Lane) {
+        if lane == Lane::Data {
+            let mut state = self.lock();
+            state.data_in_flight = state.data_in_flight.saturating_sub(1);
+            drop(state);
+            self.ready.notify_all();
+        }
+    }
+
+    /// Stop accepting work; workers exit once the queues are empty.
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.ready.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_first_and_data_capped() {
+        let lanes = Lanes::new(1);
+        lanes.push(Lane::Data, "read-1");
+        lanes.push(Lane::Data, "read-2");
+        lanes.push(Lane::Metadata, "lookup");
+        lanes.close();
+
+        assert_eq!(lanes.pop(), Some((Lane::Metadata, "lookup")));
+        assert_eq!(lanes.pop(), Some((Lane::Data, "read-1")));
+        lanes.finish(Lane::Data);
+        assert_eq!(lanes.pop(), Some((Lane::Data, "read-2")));
+        lanes.finish(Lane::Data);
+        assert_eq!(lanes.pop(), None);
+    }
+
+    #[test]
+    fn classifies_by_opcode() {
+        let mut header = [0u8; 40];
+        header[4..8].copy_from_slice(&15u32.to_ne_bytes());
+        assert_eq!(Lane::for_request(&header), Lane::Data);
+        header[4..8].copy_from_slice(&1u32.to_ne_bytes());
+        assert_eq!(Lane::for_request(&header), Lane::Metadata);
+    }
+}
@@ -0,0 +1,107 @@
+//! Git LFS pointer files shown as sparse placeholders (`--lfs-sparse`).
+//!
+//! A repository using LFS stores small pointer blobs in place of large
+//! files; the content lives elsewhere and gitsnapfs never fetches it. By
+//! default a pointer is served as the text it is. With `--lfs-sparse` it is
+//! served as the file it stands for: with the size the pointer records and
+//! content of zeros, which takes no memory to produce, so that `du`, size
+//! checks and build manifests come out right. The extended attribute
+//! `user.gitsnapfs.lfs` carries the pointer's `oid` (`sha256:<hex>`) and
+//! tells placeholders apart from files that are really all zeros.
+
+use std::str;
+
+/// Extended attribute naming the LFS object a placeholder stands for.
+pub const LFS_XATTR: &[u8] = b"user.gitsnapfs.lfs";
+
+/// Largest blob taken for a pointer; the specification keeps them below.
+pub const MAX_POINTER_SIZE: u64 = 1024;
+
+const VERSION_LINE: &str = "version https://git-lfs.github.com/spec/v1";
+
+/// What a pointer file says about the object it stands for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pointer {
+    /// Hex SHA-256 of the object's content.
+    pub sha256: String,
+    /// Size of the object's content in bytes.
+    pub size: u64,
+}
+
+impl Pointer {
+    /// Value of [`LFS_XATTR`].
+    #[must_use]
+    pub fn oid(&self) -> String {
+        format!("sha256:{}", self.sha256)
+    }
+}
+
+/// The pointer `data` is, if it is one: the version line first, then
+/// `key value` lines including a SHA-256 `oid` and a `size`.
+#[must_use]
+pub fn parse(data: &[u8]) -> Option<Pointer> {
+    if data.len() as u64 > MAX_POINTER_SIZE {
+        return None;
+    }
+    let text = str::from_utf8(data).ok()?;
+    let mut lines = text.strip_suffix('\n')?.split('\n');
+    if lines.next()? != VERSION_LINE {
+        return None;
+    }
+    let (mut sha256, mut size) = (None, None);
+    for line in lines {
+        let (key, value) = line.split_once(' ')?;
+        match key {
+            "oid" => {
+                let hex = value.strip_prefix("sha256:")?;
+                if hex.len() != 64 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                    return None;
+                }
+                sha256 = Some(hex.to_ascii_lowercase());
+            }
+            "size" => size = Some(value.parse().ok()?),
+            _ => {}
+        }
+    }
+    Some(Pointer {
+        sha256: sha256?,
+        size: size?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OID: &str = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+
+    #[test]
+    fn parses_pointers_and_nothing_else() {
+        let pointer = format!("{VERSION_LINE}\noid sha256:{OID}\nsize 12345\n");
+        assert_eq!(
+            parse(pointer.as_bytes()),
+            Some(Pointer {
+                sha256: OID.to_owned(),
+                size: 12345,
+            })
+        );
+        let extended =
+            format!("{VERSION_LINE}\next-0-foo sha256:{OID}\noid sha256:{OID}\nsize 7\n");
+        assert_eq!(parse(extended.as_bytes()).unwrap().size, 7);
+
+        for text in [
+            format!("{VERSION_LINE}\noid sha256:{OID}\nsize 12345"),
+            format!("{VERSION_LINE}\noid sha256:{OID}\n"),
+            format!("{VERSION_LINE}\noid md5:{OID}\nsize 1\n"),
+            format!("{VERSION_LINE}\noid sha256:{}\nsize 1\n", &OID[1..]),
+            format!("{VERSION_LINE}\noid sha256:{OID}\nsize -1\n"),
+            format!("oid sha256:{OID}\nsize 1\n"),
+            "hello\n".to_owned(),
+        ] {
+            assert_eq!(parse(text.as_bytes()), None, "{text:?}");
+        }
+        let mut padded = pointer.into_bytes();
+        padded.resize(2048, b'\n');
+        assert_eq!(parse(&padded), None);
+    }
+}
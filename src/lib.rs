@@ -1,4 +1,49 @@
+pub mod admission;
+pub mod archive;
+#[cfg(feature = "async-io")]
+pub mod async_io;
+pub mod attributes;
+pub mod audit;
+pub mod authz;
+pub mod bundle;
+pub mod cache;
+pub mod config;
+pub mod diff;
 pub mod fs;
+pub mod health;
+pub mod http;
 pub mod inode;
+pub mod inspect;
+pub mod interrupt;
+pub mod journal;
+pub mod lanes;
+pub mod lazy;
+pub mod lfs;
+pub mod limits;
+pub mod logging;
+pub mod lookups;
+pub mod loose;
+pub mod materialize;
+pub mod meta;
+pub mod mount;
+pub mod names;
+pub mod readahead;
+pub mod ref_inodes;
+pub mod refs_debug;
+pub mod releases;
 pub mod repo;
+pub mod reverse;
+pub mod scrub;
+pub mod settings;
+pub mod sftp;
+pub mod sha256;
+pub mod shared_cache;
+pub mod sniff;
+pub mod splice;
+pub mod stats;
+pub mod submodule;
+pub mod switch;
+pub mod synth;
+pub mod throttle;
 pub mod upgrade;
+pub mod vfs;
@@ -0,0 +1,110 @@
+//! Bounds on whole-tree walks.
+//!
+//! Merge conflict detection, export-ignore filtering and the `.git-meta/DU`
+//! and `MANIFEST` files walk entire trees. A crafted repository can nest
+//! trees thousands of levels deep, or name one large subtree many times so
+//! that a small repository expands to billions of paths. Each walk therefore
+//! carries a [`Budget`] and stops with [`LimitExceeded`] once it goes too
+//! deep or visits too many entries. The filesystem reports this as `ELOOP`
+//! or `EFBIG` instead of overflowing a worker's stack or running for hours.
+
+use thiserror::Error;
+
+/// Walk limits (`--max-tree-depth`, `--max-tree-entries`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TreeLimits {
+    /// Deepest nesting of trees below a walk's root.
+    pub max_depth: usize,
+    /// Most tree entries a single walk may visit.
+    pub max_entries: u64,
+}
+
+impl Default for TreeLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 1024,
+            max_entries: 1_000_000,
+        }
+    }
+}
+
+/// A walk went past its [`TreeLimits`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum LimitExceeded {
+    #[error("trees nested more than {0} levels deep")]
+    Depth(usize),
+    #[error("tree walk visited more than {0} entries")]
+    Entries(u64),
+}
+
+impl LimitExceeded {
+    /// Errno reported for the walk's result.
+    #[must_use]
+    pub fn errno(self) -> i32 {
+        match self {
+            LimitExceeded::Depth(_) => libc::ELOOP,
+            LimitExceeded::Entries(_) => libc::EFBIG,
+        }
+    }
+}
+
+/// What is left of the limits during one walk.
+#[derive(Debug)]
+pub struct Budget {
+    limits: TreeLimits,
+    entries: u64,
+}
+
+impl Budget {
+    #[must_use]
+    pub fn new(limits: TreeLimits) -> Self {
+        Self { limits, entries: 0 }
+    }
+
+    /// Check before descending into a tree `depth` levels below the root.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`LimitExceeded::Depth`] past the maximum depth.
+    pub fn descend(&self, depth: usize) -> Result<(), LimitExceeded> {
+        if depth > self.limits.max_depth {
+            return Err(LimitExceeded::Depth(self.limits.max_depth));
+        }
+        Ok(())
+    }
+
+    /// Count one visited entry.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`LimitExceeded::Entries`] once the walk visits too many.
+    pub fn visit(&mut self) -> Result<(), LimitExceeded> {
+        self.entries += 1;
+        if self.entries > self.limits.max_entries {
+            return Err(LimitExceeded::Entries(self.limits.max_entries));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget_stops_deep_and_wide_walks() {
+        let mut budget = Budget::new(TreeLimits {
+            max_depth: 2,
+            max_entries: 3,
+        });
+        assert!(budget.descend(2).is_ok());
+        assert_eq!(budget.descend(3), Err(LimitExceeded::Depth(2)));
+        for _ in 0..3 {
+            budget.visit().unwrap();
+        }
+        let err = budget.visit().unwrap_err();
+        assert_eq!(err, LimitExceeded::Entries(3));
+        assert_eq!(err.errno(), libc::EFBIG);
+        assert_eq!(LimitExceeded::Depth(2).errno(), libc::ELOOP);
+    }
+}
@@ -0,0 +1,213 @@
+//! Rate limiting of repeated log lines (`--log-rate-limit`).
+//!
+//! A client hammering bad paths can make the serve loop log the same error
+//! thousands of times a second. [`RateLimit`] lets a few lines per second
+//! of each distinct message through and counts the rest; a background
+//! thread periodically logs how many were suppressed, with an example.
+//! Messages that differ only in numbers (request ids, inodes, byte counts)
+//! count as the same message.
+
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::sys::signal::{SigSet, SigmaskHow, Signal};
+use tracing::field::{Field, Visit};
+use tracing::{debug, error, info, trace, warn, Event, Level, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// How often suppressed lines are summarised.
+const SUMMARY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Distinct messages tracked at once; beyond this, new messages share one
+/// budget per level so a flood of unique lines is still limited.
+const MAX_TRACKED: usize = 4096;
+
+/// Layer vetoing log lines beyond the per-message budget.
+pub struct RateLimit {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    per_second: u32,
+    tallies: Mutex<HashMap<String, Tally>>,
+}
+
+/// Lines seen for one message in the current one-second window.
+struct Tally {
+    level: Level,
+    window: Instant,
+    count: u32,
+    suppressed: u64,
+    example: String,
+}
+
+impl RateLimit {
+    /// Allow `per_second` lines of each message per second, and start the
+    /// thread summarising the lines suppressed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the summary thread cannot be started, or the
+    /// signal mask it inherits cannot be set.
+    pub fn new(per_second: u32) -> std::io::Result<Self> {
+        let shared = Arc::new(Shared {
+            per_second,
+            tallies: Mutex::new(HashMap::new()),
+        });
+        let summaries = Arc::clone(&shared);
+        // Started before `main` blocks SIGHUP and SIGUSR2, so block them for
+        // the thread to inherit; left unblocked there, they would kill the
+        // process.
+        let signals = SigSet::from_iter([Signal::SIGHUP, Signal::SIGUSR2]);
+        let previous = signals.thread_swap_mask(SigmaskHow::SIG_BLOCK)?;
+        let spawned = thread::Builder::new()
+            .name("gitsnapfs-log-summary".into())
+            .spawn(move || loop {
+                thread::sleep(SUMMARY_INTERVAL);
+                for (level, suppressed, example) in summaries.drain(Instant::now()) {
+                    summarise(level, suppressed, &example);
+                }
+            });
+        previous.thread_set_mask()?;
+        spawned?;
+        Ok(Self { shared })
+    }
+}
+
+impl<S: Subscriber> Layer<S> for RateLimit {
+    fn event_enabled(&self, event: &Event<'_>, _ctx: Context<'_, S>) -> bool {
+        // Summaries are already limited by their interval.
+        if event.metadata().module_path() == Some(module_path!()) {
+            return true;
+        }
+        let mut text = Text::default();
+        event.record(&mut text);
+        let key = format!("{} {}", event.metadata().level(), mask_numbers(&text.0));
+        let level = *event.metadata().level();
+        self.shared.admit(key, level, text.0, Instant::now())
+    }
+}
+
+/// Log a summary at the level of the lines it stands for, so it passes the
+/// same filter they did.
+fn summarise(level: Level, suppressed: u64, example: &str) {
+    match level {
+        Level::ERROR => error!("suppressed {suppressed} more log lines like: {example}"),
+        Level::WARN => warn!("suppressed {suppressed} more log lines like: {example}"),
+        Level::INFO => info!("suppressed {suppressed} more log lines like: {example}"),
+        Level::DEBUG => debug!("suppressed {suppressed} more log lines like: {example}"),
+        Level::TRACE => trace!("suppressed {suppressed} more log lines like: {example}"),
+    }
+}
+
+impl Shared {
+    /// Count a line with `key` and decide whether it is logged.
+    fn admit(&self, key: String, level: Level, example: String, now: Instant) -> bool {
+        let mut tallies = self
+            .tallies
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let key = if tallies.len() < MAX_TRACKED || tallies.contains_key(&key) {
+            key
+        } else {
+            key.split(' ').next().unwrap_or_default().to_owned()
+        };
+        let tally = tallies.entry(key).or_insert_with(|| Tally {
+            level,
+            window: now,
+            count: 0,
+            suppressed: 0,
+            example,
+        });
+        if now.duration_since(tally.window) >= Duration::from_secs(1) {
+            tally.window = now;
+            tally.count = 0;
+        }
+        if tally.count < self.per_second {
+            tally.count += 1;
+            true
+        } else {
+            tally.suppressed += 1;
+            false
+        }
+    }
+
+    /// Take the suppressed counts, with an example line each, and forget
+    /// messages not seen for a whole summary interval.
+    fn drain(&self, now: Instant) -> Vec<(Level, u64, String)> {
+        let mut tallies = self
+            .tallies
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let mut summaries = Vec::new();
+        tallies.retain(|_, tally| {
+            if tally.suppressed > 0 {
+                summaries.push((tally.level, tally.suppressed, tally.example.clone()));
+                tally.suppressed = 0;
+                return true;
+            }
+            now.duration_since(tally.window) < SUMMARY_INTERVAL
+        });
+        summaries
+    }
+}
+
+/// An event's message and its other fields as `name=value`; the `log.*`
+/// fields of records bridged from the `log` crate are left out.
+#[derive(Default)]
+struct Text(String);
+
+impl Visit for Text {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let separator = if self.0.is_empty() { "" } else { " " };
+        match field.name() {
+            "message" => {
+                let _ = write!(self.0, "{separator}{value:?}");
+            }
+            name if name.starts_with("log.") => {}
+            name => {
+                let _ = write!(self.0, "{separator}{name}={value:?}");
+            }
+        }
+    }
+}
+
+/// `text` with every run of digits replaced by `#`.
+fn mask_numbers(text: &str) -> String {
+    let mut masked = String::with_capacity(text.len());
+    for c in text.chars() {
+        if !c.is_ascii_digit() {
+            masked.push(c);
+        } else if !masked.ends_with('#') {
+            masked.push('#');
+        }
+    }
+    masked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_each_message_per_second() {
+        let shared = Shared {
+            per_second: 2,
+            tallies: Mutex::new(HashMap::new()),
+        };
+        let start = Instant::now();
+        let key = |text: &str| format!("ERROR {}", mask_numbers(text));
+        let admit = |text: &str, now| shared.admit(key(text), Level::ERROR, String::new(), now);
+        let admitted = (0..5)
+            .filter(|n| admit(&format!("unique: {n}"), start))
+            .count();
+        assert_eq!(admitted, 2);
+        assert!(admit("other", start));
+        assert!(admit("unique: 9", start + Duration::from_secs(1)));
+        assert_eq!(shared.drain(start), [(Level::ERROR, 3, String::new())]);
+        assert!(shared.drain(start).is_empty());
+    }
+}
@@ -0,0 +1,108 @@
+//! Kernel lookup counts of the inodes handed out.
+//!
+//! Every entry the kernel receives from `lookup` or `readdirplus` adds one
+//! reference to its inode, and `forget` drops references again once the
+//! kernel evicts the inode. Synthetic inodes are backed by registries that
+//! map them back to what they mean; [`LookupCounts`] tells when the kernel
+//! holds no more references, so those entries can be freed.
+//!
+//! An inode whose count drops to zero is released only after a grace
+//! period: a lookup running concurrently with the `forget` may already have
+//! registered the inode again without having counted it yet.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long an unreferenced inode is kept before it is released.
+const RELEASE_GRACE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default)]
+pub struct LookupCounts {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// References the kernel holds, by inode; absent means none.
+    counts: HashMap<u64, u64>,
+    /// Inodes whose count dropped to zero, oldest first.
+    unreferenced: VecDeque<(Instant, u64)>,
+    /// When each inode in `unreferenced` last dropped to zero.
+    zeroed: HashMap<u64, Instant>,
+}
+
+impl LookupCounts {
+    /// Count one entry for `inode` handed to the kernel.
+    pub fn remember(&self, inode: u64) {
+        *self.lock().counts.entry(inode).or_default() += 1;
+    }
+
+    /// Drop `count` references to `inode` and return the inodes that have
+    /// had none for the whole grace period, which may now be released.
+    pub fn forget(&self, inode: u64, count: u64, now: Instant) -> Vec<u64> {
+        let mut inner = self.lock();
+        if let Some(references) = inner.counts.get_mut(&inode) {
+            *references = references.saturating_sub(count);
+            if *references == 0 {
+                inner.counts.remove(&inode);
+                inner.unreferenced.push_back((now, inode));
+                inner.zeroed.insert(inode, now);
+            }
+        }
+        let mut released = Vec::new();
+        while let Some(&(since, inode)) = inner.unreferenced.front() {
+            if now.duration_since(since) < RELEASE_GRACE {
+                break;
+            }
+            inner.unreferenced.pop_front();
+            // Only the latest drop to zero counts, and only if it still holds.
+            if inner.zeroed.get(&inode) == Some(&since) {
+                inner.zeroed.remove(&inode);
+                if !inner.counts.contains_key(&inode) {
+                    released.push(inode);
+                }
+            }
+        }
+        released
+    }
+
+    /// References the kernel holds to `inode`.
+    #[must_use]
+    pub fn count(&self, inode: u64) -> u64 {
+        self.lock().counts.get(&inode).copied().unwrap_or_default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn releases_after_the_last_forget_and_grace() {
+        let counts = LookupCounts::default();
+        let start = Instant::now();
+        counts.remember(7);
+        counts.remember(7);
+        counts.remember(8);
+        assert!(counts.forget(7, 1, start).is_empty());
+        assert_eq!(counts.count(7), 1);
+        assert!(counts.forget(7, 1, start).is_empty());
+        assert!(counts.forget(8, 1, start).is_empty());
+        // Looked up again within the grace period: kept.
+        counts.remember(8);
+        let later = start + RELEASE_GRACE;
+        assert_eq!(counts.forget(99, 1, later), [7]);
+        assert_eq!(counts.count(8), 1);
+        // Forgotten again: the grace period starts over.
+        assert!(counts.forget(8, 1, later).is_empty());
+        assert!(counts.forget(99, 1, later + RELEASE_GRACE / 2).is_empty());
+        assert_eq!(counts.forget(99, 1, later + RELEASE_GRACE), [8]);
+    }
+}
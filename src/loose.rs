@@ -0,0 +1,275 @@
+//! Loose blobs stored uncompressed, served from a read-only mapping
+//! (`--mmap-loose`).
+//!
+//! A loose object file is a zlib stream of `<kind> <size>\0<content>`.
+//! Written with `core.looseCompression=0`, or by a tool preparing a
+//! repository for serving the same way, the stream is a series of stored
+//! deflate blocks: the content lies in the file as is, cut into runs of at
+//! most 64 KiB by five-byte block headers. Such a blob is served by mapping
+//! the file and copying out the runs a read asks for, without inflating it
+//! or holding all of it in memory.
+//!
+//! The framing is checked end to end before a file is used: the zlib header,
+//! every block stored with a length matching its complement, the last block
+//! marked final and followed by nothing but the checksum, and an object
+//! header naming a blob of exactly the size found. Anything else, including
+//! compressed and packed objects, is left to gix. The Adler-32 checksum is
+//! not computed, as that would read the whole blob; `--verify-reads` turns
+//! the mapping off instead. Git never rewrites an object file in place, so a
+//! mapping stays valid even if its object is pruned while mapped.
+
+use std::ffi::c_void;
+use std::fs::File;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::ptr::NonNull;
+
+use gix::ObjectId;
+use nix::sys::mman::{mmap, munmap, MapFlags, ProtFlags};
+
+const ZLIB_HEADER_LEN: usize = 2;
+const BLOCK_HEADER_LEN: usize = 5;
+const CHECKSUM_LEN: usize = 4;
+/// Longest object header taken, `blob ` and twenty digits with room to spare.
+const MAX_OBJECT_HEADER_LEN: usize = 32;
+
+/// A blob whose content lies uncompressed in a mapped loose object file.
+#[derive(Debug)]
+pub struct MappedBlob {
+    map: NonNull<c_void>,
+    map_len: usize,
+    runs: Vec<Run>,
+    size: usize,
+}
+
+/// A stretch of content stored in one block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Run {
+    /// Offset in the file.
+    file: usize,
+    /// Offset in the blob.
+    blob: usize,
+    len: usize,
+}
+
+// SAFETY: the mapping is private and read-only, and is only ever read.
+unsafe impl Send for MappedBlob {}
+// SAFETY: as above.
+unsafe impl Sync for MappedBlob {}
+
+impl MappedBlob {
+    /// Map the loose object `oid` in `objects_dir`, if it is a blob stored
+    /// uncompressed. `None` for anything gix has to decode.
+    #[must_use]
+    pub fn open(objects_dir: &Path, oid: ObjectId) -> Option<Self> {
+        let hex = oid.to_string();
+        let file = File::open(objects_dir.join(&hex[..2]).join(&hex[2..])).ok()?;
+        let metadata = file.metadata().ok()?;
+        if !metadata.is_file() {
+            return None;
+        }
+        let map_len = NonZeroUsize::new(usize::try_from(metadata.len()).ok()?)?;
+        // SAFETY: a new private read-only mapping, of a file git never
+        // changes once written.
+        let map = unsafe {
+            mmap(
+                None,
+                map_len,
+                ProtFlags::PROT_READ,
+                MapFlags::MAP_PRIVATE,
+                &file,
+                0,
+            )
+        }
+        .ok()?;
+        let mut blob = Self {
+            map,
+            map_len: map_len.get(),
+            runs: Vec::new(),
+            size: 0,
+        };
+        (blob.runs, blob.size) = parse(blob.bytes())?;
+        Some(blob)
+    }
+
+    /// Size of the blob in bytes.
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.size as u64
+    }
+
+    /// The content from `offset` up to `len` bytes on, in pieces as stored.
+    #[must_use]
+    pub fn slices(&self, offset: u64, len: usize) -> Vec<&[u8]> {
+        let data = self.bytes();
+        let start = usize::try_from(offset).unwrap_or(usize::MAX).min(self.size);
+        let end = start.saturating_add(len).min(self.size);
+        let first = self.runs.partition_point(|run| run.blob + run.len <= start);
+        self.runs[first..]
+            .iter()
+            .take_while(|run| run.blob < end)
+            .map(|run| {
+                let from = start.max(run.blob) - run.blob;
+                let to = end.min(run.blob + run.len) - run.blob;
+                &data[run.file + from..run.file + to]
+            })
+            .collect()
+    }
+
+    fn bytes(&self) -> &[u8] {
+        // SAFETY: `map` points at `map_len` readable bytes until dropped.
+        unsafe { std::slice::from_raw_parts(self.map.as_ptr().cast::<u8>(), self.map_len) }
+    }
+}
+
+impl Drop for MappedBlob {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was made in `open` and nothing borrows it now.
+        let _ = unsafe { munmap(self.map, self.map_len) };
+    }
+}
+
+/// The content runs of the loose object file `data` and the blob's size, if
+/// it is a blob made of stored blocks only.
+fn parse(data: &[u8]) -> Option<(Vec<Run>, usize)> {
+    let (&cmf, &flg) = (data.first()?, data.get(1)?);
+    let deflate = cmf & 0x0f == 8 && cmf >> 4 <= 7;
+    let preset_dictionary = flg & 0x20 != 0;
+    if !deflate || preset_dictionary || (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0 {
+        return None;
+    }
+    let mut stored = Vec::new();
+    let mut pos = ZLIB_HEADER_LEN;
+    loop {
+        let header = data.get(pos..pos + BLOCK_HEADER_LEN)?;
+        if (header[0] >> 1) & 3 != 0 {
+            return None;
+        }
+        let len = u16::from_le_bytes([header[1], header[2]]);
+        if len != !u16::from_le_bytes([header[3], header[4]]) {
+            return None;
+        }
+        let start = pos + BLOCK_HEADER_LEN;
+        pos = start + usize::from(len);
+        if pos > data.len() {
+            return None;
+        }
+        if len > 0 {
+            stored.push((start, usize::from(len)));
+        }
+        if header[0] & 1 == 1 {
+            break;
+        }
+    }
+    if data.len() - pos != CHECKSUM_LEN {
+        return None;
+    }
+
+    let mut prefix = Vec::with_capacity(MAX_OBJECT_HEADER_LEN);
+    for &(start, len) in &stored {
+        let take = len.min(MAX_OBJECT_HEADER_LEN - prefix.len());
+        prefix.extend_from_slice(&data[start..start + take]);
+        if prefix.len() == MAX_OBJECT_HEADER_LEN {
+            break;
+        }
+    }
+    let header_len = prefix.iter().position(|&byte| byte == 0)? + 1;
+    let digits = prefix[..header_len - 1].strip_prefix(b"blob ")?;
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let size: usize = std::str::from_utf8(digits).ok()?.parse().ok()?;
+    let total: usize = stored.iter().map(|&(_, len)| len).sum();
+    if total.checked_sub(header_len)? != size {
+        return None;
+    }
+
+    let mut runs = Vec::with_capacity(stored.len());
+    let (mut skip, mut blob) = (header_len, 0);
+    for (start, len) in stored {
+        if skip >= len {
+            skip -= len;
+            continue;
+        }
+        runs.push(Run {
+            file: start + skip,
+            blob,
+            len: len - skip,
+        });
+        blob += len - skip;
+        skip = 0;
+    }
+    Some((runs, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A zlib stream of `payload` in stored blocks of at most `block` bytes,
+    /// with a checksum that is not checked.
+    fn stored(payload: &[u8], block: usize) -> Vec<u8> {
+        let mut out = vec![0x78, 0x01];
+        let mut chunks = payload.chunks(block).peekable();
+        while let Some(chunk) = chunks.next() {
+            let len = u16::try_from(chunk.len()).unwrap();
+            out.push(u8::from(chunks.peek().is_none()));
+            out.extend_from_slice(&len.to_le_bytes());
+            out.extend_from_slice(&(!len).to_le_bytes());
+            out.extend_from_slice(chunk);
+        }
+        out.extend_from_slice(&[0; CHECKSUM_LEN]);
+        out
+    }
+
+    fn content(data: &[u8], runs: &[Run]) -> Vec<u8> {
+        runs.iter()
+            .flat_map(|run| &data[run.file..run.file + run.len])
+            .copied()
+            .collect()
+    }
+
+    #[test]
+    fn finds_the_content_between_block_headers() {
+        let file = stored(b"blob 11\0hello world", 3);
+        let (runs, size) = parse(&file).unwrap();
+        assert_eq!(size, 11);
+        assert_eq!(content(&file, &runs), b"hello world");
+        // The header ends inside the third block.
+        assert_eq!(runs[0].blob, 0);
+        assert_eq!(runs[0].len, 1);
+
+        let file = stored(b"blob 0\0", 64);
+        assert_eq!(parse(&file), Some((Vec::new(), 0)));
+    }
+
+    #[test]
+    fn rejects_anything_but_stored_blobs() {
+        let good = stored(b"blob 5\0hello", 4);
+        assert!(parse(&good).is_some());
+
+        let mut corrupt = good.clone();
+        corrupt[5] ^= 1;
+        let mut compressed = good.clone();
+        compressed[2] = 0b011;
+        let mut trailing = good.clone();
+        trailing.push(0);
+        let mut truncated = good.clone();
+        truncated.pop();
+        let mut dictionary = good.clone();
+        dictionary[1] = 0x20;
+        for (name, file) in [
+            ("length", corrupt),
+            ("compressed", compressed),
+            ("trailing", trailing),
+            ("truncated", truncated),
+            ("dictionary", dictionary),
+            ("tree", stored(b"tree 5\0hello", 4)),
+            ("short", stored(b"blob 6\0hello", 4)),
+            ("signed", stored(b"blob +5\0hello", 4)),
+            ("unterminated", stored(b"blob 5", 4)),
+        ] {
+            assert_eq!(parse(&file), None, "{name}");
+        }
+    }
+}
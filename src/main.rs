@@ -1,91 +1,1782 @@
+use std::ffi::{CString, OsString};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::os::fd::{AsRawFd, BorrowedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::path::{Path, PathBuf};
+use std::process::{self, Child, ExitStatus, Stdio};
 use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use anyhow::{bail, Result};
-use clap::Parser;
+use anyhow::{bail, Context, Result};
+use clap::{ArgGroup, Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use fuse_backend_rs::api::filesystem::FileSystem;
 use fuse_backend_rs::api::server::Server;
-use fuse_backend_rs::transport::FuseSession;
-use tracing::error;
+use fuse_backend_rs::transport::{FuseBuf, FuseChannel, FuseDevWriter, FuseSession, Reader};
+use nix::sched::{sched_getaffinity, sched_setaffinity, CpuSet};
+use nix::sys::signal::{SigSet, Signal};
+use nix::unistd::Pid;
+use serde::Serialize;
+use tracing::{error, warn};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::EnvFilter;
 
+use gitsnapfs::archive::{self, ArchiveFormat, TarOptions};
+use gitsnapfs::audit::AuditLog;
+use gitsnapfs::authz::Hook;
+use gitsnapfs::bundle;
+use gitsnapfs::config::{
+    self, DirSize, FakeDotGit, FsConfig, ModeOverride, ModeOverrides, RefFilter, SortOrder,
+};
+use gitsnapfs::diff;
 use gitsnapfs::fs::GitSnapFs;
-use gitsnapfs::repo::Repository;
+use gitsnapfs::health::{self, Health};
+use gitsnapfs::http;
+use gitsnapfs::inode::{MODE_VIEWS, OBJECT_PREFIX_BITS};
+use gitsnapfs::inspect;
+use gitsnapfs::interrupt::{Interrupts, Token};
+use gitsnapfs::journal::ErrorJournal;
+use gitsnapfs::lanes::{Lane, Lanes};
+use gitsnapfs::lazy::LazyFs;
+use gitsnapfs::limits::TreeLimits;
+use gitsnapfs::logging::RateLimit;
+use gitsnapfs::materialize;
+use gitsnapfs::mount;
+use gitsnapfs::ref_inodes::RefInodes;
+use gitsnapfs::releases::ReleasePattern;
+use gitsnapfs::repo::{ObjectCaches, Repository};
+use gitsnapfs::scrub::{self, Scrub};
+use gitsnapfs::settings;
+use gitsnapfs::sftp;
+use gitsnapfs::shared_cache;
+use gitsnapfs::splice::{ReplyPipe, Writes};
+use gitsnapfs::submodule;
+use gitsnapfs::switch;
+use gitsnapfs::synth;
+use gitsnapfs::throttle;
+use gitsnapfs::vfs::Vfs;
+
+/// How the FUSE requests of a mount are read and answered.
+#[derive(Debug, Clone, Copy)]
+enum Serving {
+    /// On this many threads sharing one channel (`--threads`).
+    Threads(u16),
+    /// On a channel and worker per CPU (`--per-cpu-channels`), pinned to
+    /// it with `--cpu-affinity`.
+    PerCpu { pin: bool },
+}
+
+impl Serving {
+    fn of(cli: &Cli) -> Self {
+        if cli.per_cpu_channels {
+            Self::PerCpu {
+                pin: cli.cpu_affinity,
+            }
+        } else {
+            Self::Threads(cli.threads)
+        }
+    }
+}
+
+/// CPUs this process may run on.
+fn allowed_cpus() -> Result<Vec<usize>> {
+    let allowed = sched_getaffinity(Pid::from_raw(0)).context("failed to read the CPU affinity")?;
+    Ok((0..CpuSet::count())
+        .filter(|&cpu| allowed.is_set(cpu).unwrap_or(false))
+        .collect())
+}
+
+/// Run the calling thread only on `cpu`.
+fn pin_to_cpu(cpu: usize) -> nix::Result<()> {
+    let mut only = CpuSet::new();
+    only.set(cpu)?;
+    sched_setaffinity(Pid::from_raw(0), &only)
+}
+
+/// Blob cache budget used by `--preload` when `--max-memory` is not given.
+const DEFAULT_PRELOAD_BUDGET: u64 = 1 << 30;
+
+/// Stack of each `--threads` worker: as large as the main thread's, so tree
+/// walks up to the default `--max-tree-depth` fit with room to spare.
+const WORKER_STACK_SIZE: usize = 8 << 20;
 
 #[derive(Debug, Parser)]
 #[command(
     name = "gitsnapfs",
     version,
-    about = "Git snapshots as a read-only FUSE filesystem"
+    about = "Git snapshots as a read-only FUSE filesystem",
+    subcommand_negates_reqs = true,
+    args_conflicts_with_subcommands = true
 )]
+#[allow(clippy::struct_excessive_bools)]
 struct Cli {
-    /// Path to the target Git repository (.git dir or bare repo).
-    #[arg(long)]
-    repo: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Git repository to serve: a worktree or any directory inside one, a .git dir,
+    /// a bare repo, or a bundle file. `GIT_DIR` takes precedence when set.
+    #[arg(long, required_unless_present = "pack_stdin")]
+    repo: Option<PathBuf>,
+
+    /// Serve a packfile or bundle read from stdin instead of --repo; it is indexed
+    /// into a temporary repository that is removed when the mount ends.
+    #[arg(long, conflicts_with_all = ["repo", "sftp_stdio"])]
+    pack_stdin: bool,
 
     /// Mount point for the FUSE filesystem.
-    #[arg(long)]
-    mountpoint: PathBuf,
+    #[arg(long, required_unless_present_any = ["http_listen", "sftp_stdio"])]
+    mountpoint: Option<PathBuf>,
+
+    /// If a gitsnapfs mount is already at the mount point, live or with its
+    /// daemon gone, detach it lazily and mount in its place. Files open in
+    /// the old mount keep working until closed.
+    #[arg(long, requires = "mountpoint")]
+    replace: bool,
+
+    /// Create the mount point, and any missing parents, if it does not exist.
+    #[arg(long, requires = "mountpoint")]
+    create_mountpoint: bool,
+
+    /// Permissions, in octal, of a mount point made by --create-mountpoint.
+    #[arg(
+        long,
+        value_name = "MODE",
+        default_value = "755",
+        value_parser = config::parse_mode,
+        requires = "create_mountpoint"
+    )]
+    mountpoint_mode: u32,
+
+    /// Remove the mount point made by --create-mountpoint again once the
+    /// filesystem is unmounted and the daemon exits cleanly. A mount point
+    /// that already existed is left alone.
+    #[arg(long, requires = "create_mountpoint")]
+    remove_mountpoint: bool,
+
+    /// Serve the snapshot hierarchy over read-only HTTP on this address instead of mounting.
+    #[arg(long, value_name = "ADDR", conflicts_with = "mountpoint")]
+    http_listen: Option<SocketAddr>,
+
+    /// Speak the SFTP subsystem protocol on stdin/stdout (e.g. as an sshd `Subsystem`).
+    #[arg(long, conflicts_with_all = ["mountpoint", "http_listen"])]
+    sftp_stdio: bool,
+
+    /// Mount at once and open the repository in the background; until it is
+    /// open, every request fails with EAGAIN (EIO if opening fails).
+    #[arg(long, conflicts_with_all = ["http_listen", "sftp_stdio"])]
+    lazy_init: bool,
 
     /// Allow other users to access the mount.
     #[arg(long)]
     allow_other: bool,
 
+    /// fusermount helper to use instead of searching for fusermount3/fusermount.
+    #[arg(long, value_name = "PATH")]
+    fusermount_path: Option<PathBuf>,
+
+    /// Source of the mount, shown as SOURCE by findmnt and as the device in
+    /// /proc/mounts.
+    #[arg(long, value_name = "NAME", default_value = mount::DEFAULT_NAME, value_parser = mount::parse_mount_name)]
+    fsname: String,
+
+    /// Subtype of the mount: its filesystem type is fuse.SUBTYPE. Only
+    /// mounts of the same subtype are recognised by --replace.
+    #[arg(long, value_name = "NAME", default_value = mount::DEFAULT_NAME, value_parser = mount::parse_mount_name)]
+    subtype: String,
+
+    /// Number of threads serving FUSE requests. With more than one, metadata
+    /// requests are prioritised over file reads.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    threads: u16,
+
+    /// Serve with one worker per CPU instead of --threads, each reading
+    /// requests from its own clone of the FUSE device (`FUSE_DEV_IOC_CLONE`)
+    /// rather than all sharing one channel.
+    #[arg(long, conflicts_with = "threads")]
+    per_cpu_channels: bool,
+
+    /// Run each --per-cpu-channels worker only on its CPU.
+    #[arg(long, requires = "per_cpu_channels")]
+    cpu_affinity: bool,
+
+    /// Serve requests as tasks on a tokio runtime with --threads workers.
+    /// Handlers reaching into the repository run on a bounded blocking
+    /// pool, so slow blob reads do not hold up the workers receiving
+    /// requests. No priority lanes.
+    #[cfg(feature = "async-io")]
+    #[arg(long, conflicts_with_all = ["per_cpu_channels", "lazy_init", "splice"])]
+    async_runtime: bool,
+
+    /// Send replies to the FUSE device with splice(2) from a pipe per
+    /// worker, going back to buffered writes for good if splicing fails.
+    /// With this or --no-splice, .gitsnapfs/info reports the mode in use
+    /// and how many replies each mode wrote, and how fast.
+    #[arg(long, overrides_with = "no_splice")]
+    splice: bool,
+
+    /// Send replies with buffered writes, the default; for container
+    /// runtimes that break with splice.
+    #[arg(long, overrides_with = "splice")]
+    no_splice: bool,
+
     /// Adopt an existing FUSE file descriptor instead of mounting.
     #[arg(long)]
     takeover_fuse_fd: Option<i32>,
 
-    /// Optional path to persist inode collision state.
+    /// File keeping the inode numbers given to branch and tag names, and to
+    /// objects with --inode-prefix-bits, so they stay the same across mounts.
     #[arg(long)]
     state_file: Option<PathBuf>,
+
+    /// Keep only the first N bits of object ids in object inodes, telling
+    /// objects that share them apart with a table kept in memory and in
+    /// --state-file. The default keeps 56 bits and needs no table.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(16..=56))]
+    inode_prefix_bits: Option<u32>,
+
+    /// Serve objects as stored, ignoring `refs/replace/` (like `git --no-replace-objects`).
+    #[arg(long)]
+    no_replace_objects: bool,
+
+    /// Serve branches, tags and HEAD from a snapshot of the references taken
+    /// at mount and at each SIGHUP or SIGUSR2, so a push in progress is never
+    /// seen half done.
+    #[arg(long)]
+    consistent_refs: bool,
+
+    /// Hide paths marked `export-ignore` in .gitattributes, like `git archive` does.
+    #[arg(long)]
+    respect_export_ignore: bool,
+
+    /// JSON file declaring files to synthesize at the root of every commit snapshot.
+    #[arg(long)]
+    synthetic_files: Option<PathBuf>,
+
+    /// Add a `.git` file to every commit snapshot so tools detect a checkout.
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "repo")]
+    fake_dotgit: Option<FakeDotGit>,
+
+    /// Order of directory entries.
+    #[arg(long, value_enum, default_value_t = SortOrder::Git)]
+    sort: SortOrder,
+
+    /// Deepest tree nesting walked for .git-meta files, merge conflicts and
+    /// export-ignore; deeper snapshots fail those with ELOOP.
+    #[arg(long, value_name = "N", default_value_t = TreeLimits::default().max_depth)]
+    max_tree_depth: usize,
+
+    /// Most tree entries one such walk may visit before failing with EFBIG,
+    /// protecting the daemon from crafted repositories.
+    #[arg(long, value_name = "N", default_value_t = TreeLimits::default().max_entries)]
+    max_tree_entries: u64,
+
+    /// Size reported for snapshot directories, for sync tools that distrust 0.
+    /// `entries` and `tree-bytes` also count subdirectories into nlink.
+    #[arg(long, value_enum, default_value_t = DirSize::Zero)]
+    dir_size: DirSize,
+
+    /// Decode every ref-reachable object before mounting and keep blobs in memory.
+    #[arg(long)]
+    preload: bool,
+
+    /// Memory budget for cached blob contents (bytes, or with a K/M/G suffix).
+    /// Defaults to 1G with --preload; without it, enables a lazily filled cache.
+    #[arg(long, value_name = "SIZE", value_parser = config::parse_byte_size)]
+    max_memory: Option<u64>,
+
+    /// Size of gix's cache of decoded objects, in MiB; 0 disables it. Defaults
+    /// to a 64th of the size of the repository's packs, from 4 to 64.
+    #[arg(long, value_name = "MB")]
+    gix_object_cache_mb: Option<usize>,
+
+    /// Size of gix's cache of delta bases inflated from packs, in MiB; 0
+    /// disables it. Defaults to a 16th of the size of the packs, from 16 to 256.
+    #[arg(long, value_name = "MB")]
+    gix_pack_cache_mb: Option<usize>,
+
+    /// Limit file data served per second (bytes, or with a K/M/G suffix).
+    #[arg(long, value_name = "RATE", value_parser = throttle::parse_byte_rate)]
+    max_read_bandwidth: Option<u64>,
+
+    /// Limit read requests served per second.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_iops: Option<u32>,
+
+    /// Refuse requests with EAGAIN once a uid has this many in flight, so one user
+    /// cannot occupy every worker of a shared (`--allow-other`) mount. Needs --threads > 1.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    max_requests_per_uid: Option<u16>,
+
+    /// Count open handles and bytes served per snapshot, readable from /.gitsnapfs/stats.
+    #[arg(long)]
+    stats: bool,
+
+    /// Check every SECS seconds that HEAD and --health-path still resolve, and
+    /// report the outcome in /.gitsnapfs/health.
+    #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+    health_interval: Option<u64>,
+
+    /// Path inside the mount resolved by each health check.
+    #[arg(
+        long,
+        value_name = "PATH",
+        default_value = "HEAD",
+        requires = "health_interval"
+    )]
+    health_path: String,
+
+    /// Inflate large blobs once per open file, on a background thread ahead of the
+    /// first read, instead of once per 128 KiB read. Disables zero-message opens.
+    #[arg(long)]
+    readahead: bool,
+
+    /// Read blobs through the `gitsnapfs cache-daemon` listening on this socket,
+    /// so mounts of the same repository share one copy of decoded blobs.
+    #[arg(long, value_name = "SOCKET", conflicts_with_all = ["preload", "max_memory", "readahead"])]
+    shared_cache: Option<PathBuf>,
+
+    /// Check every blob against its object id when it is decoded for serving,
+    /// failing reads of corrupt objects with EIO.
+    #[arg(long, conflicts_with = "shared_cache")]
+    verify_reads: bool,
+
+    /// Re-hash this fraction (0 to 1) of the files read from their start in
+    /// the background, counting blobs that no longer match their ids in
+    /// `.gitsnapfs/scrub` without failing any read.
+    #[arg(
+        long,
+        value_name = "FRACTION",
+        value_parser = scrub::parse_fraction,
+        conflicts_with = "verify_reads"
+    )]
+    verify_sample: Option<f64>,
+
+    /// Serve loose blobs stored uncompressed (core.looseCompression=0) by
+    /// mapping their object files instead of decoding them; other objects
+    /// are read as usual.
+    #[arg(long, conflicts_with_all = ["shared_cache", "verify_reads"])]
+    mmap_loose: bool,
+
+    /// Largest file served (bytes, or with a K/M/G suffix). Larger files keep
+    /// their size, but reads fail with EFBIG instead of decompressing them.
+    #[arg(long, value_name = "SIZE", value_parser = config::parse_byte_size)]
+    max_file_size: Option<u64>,
+
+    /// Serve structure only, for indexers and crawlers: stat, readdir, readlink
+    /// and xattrs work, but reading a file's content fails with EPERM.
+    #[arg(long, conflicts_with_all = ["preload", "readahead", "shared_cache"])]
+    metadata_only: bool,
+
+    /// Serve no extended attributes at all, not even user.gitsnapfs.*. The
+    /// kernel then stops forwarding getxattr and listxattr, which tooling
+    /// querying security.* on every file would otherwise send per file.
+    #[arg(long)]
+    no_xattrs: bool,
+
+    /// Serve Git LFS pointer files as the files they stand for: with the size
+    /// the pointer records, reading as zeros, and the LFS oid in the
+    /// user.gitsnapfs.lfs xattr. LFS content is never fetched.
+    #[arg(long)]
+    lfs_sparse: bool,
+
+    /// Show authors and committers in .git-meta/commit.json, changelogs and
+    /// synthetic files as recorded, instead of as the repository's .mailmap
+    /// maps them.
+    #[arg(long)]
+    no_mailmap: bool,
+
+    /// Name commits and trees in symlink targets (HEAD, branches/, tags/, refs/,
+    /// .git-meta/) by unique prefixes of at least N hex digits, like
+    /// `git rev-parse --short=N`. Full ids keep working in lookups.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u8).range(4..=64))]
+    abbrev: Option<u8>,
+
+    /// Ask this program whether a uid may look up or read a path: it is run as
+    /// `PROGRAM <uid> <lookup|read> <path>` and allows by exiting with 0.
+    /// Decisions are cached for 30 seconds or until SIGHUP.
+    #[arg(long, value_name = "PROGRAM", conflicts_with_all = ["http_listen", "sftp_stdio"])]
+    authz_cmd: Option<PathBuf>,
+
+    /// Like --authz-cmd, but ask the service on this Unix socket, one
+    /// `CHECK <uid> <op> <path>` line per question, answered `ALLOW` or `DENY`.
+    #[arg(long, value_name = "SOCKET", conflicts_with_all = ["authz_cmd", "http_listen", "sftp_stdio"])]
+    authz_socket: Option<PathBuf>,
+
+    /// Append a JSON line for every read served (time, uid, pid, path, blob id,
+    /// byte range) to this file, failing reads with EIO if it cannot be written.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["http_listen", "sftp_stdio"])]
+    audit_log: Option<PathBuf>,
+
+    /// Size at which the audit log is rotated to FILE.1 (up to FILE.5).
+    #[arg(
+        long,
+        value_name = "SIZE",
+        default_value = "64M",
+        value_parser = config::parse_byte_size,
+        requires = "audit_log"
+    )]
+    audit_log_max_size: u64,
+
+    /// Keep the last N failed requests (operation, path, errno and the error
+    /// behind it) and serve them as JSON lines in /.gitsnapfs/errors.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    error_journal: Option<u16>,
+
+    /// Serve /current showing the commit REV names, switchable while mounted
+    /// with `gitsnapfs switch-current`.
+    #[arg(long, value_name = "REV")]
+    current: Option<String>,
+
+    /// Link HEAD's README and license files from the mount root, so `ls` of the
+    /// mount shows what the repository is.
+    #[arg(long)]
+    root_readme: bool,
+
+    /// Serve /releases, the tags named like semantic versions arranged as
+    /// MAJOR/MINOR/PATCH links to their commits, with a `latest` link at
+    /// every level.
+    #[arg(long)]
+    releases: bool,
+
+    /// How tags name release versions: `*` stands for MAJOR.MINOR.PATCH.
+    #[arg(long, value_name = "PATTERN", default_value = "v*", value_parser = ReleasePattern::parse, requires = "releases")]
+    release_pattern: ReleasePattern,
+
+    /// Serve only this commit and its descendants (any revision naming a commit);
+    /// other commits, references to them and trees/ are hidden.
+    #[arg(long, value_name = "REV")]
+    only_descendants_of: Option<String>,
+
+    /// Serve only branches whose name matches this glob (`release/*`); `*`
+    /// also matches `/`. Repeatable; others are hidden everywhere, including
+    /// from the reachability xattrs.
+    #[arg(long, value_name = "GLOB")]
+    branch_filter: Vec<String>,
+
+    /// Like --branch-filter, for tags (`v*`). Repeatable.
+    #[arg(long, value_name = "GLOB")]
+    tag_filter: Vec<String>,
+
+    /// Serve paths of commit snapshots matching GLOB with these permission bits
+    /// (`bin/**=0755`), whatever mode is stored. Globs match as in
+    /// .gitattributes; the last matching one wins. Repeatable.
+    #[arg(long, value_name = "GLOB=MODE", value_parser = config::parse_mode_override)]
+    mode_override: Vec<ModeOverride>,
+
+    /// Serve the repository's value of this git config key
+    /// (`remote.origin.url`) as the file .gitsnapfs/config/KEY, one value per
+    /// line. Repeatable; keys not given are never exposed.
+    #[arg(long, value_name = "KEY", value_parser = config::parse_git_config_key)]
+    expose_config: Vec<String>,
+
+    /// Line rendered per commit in branches-meta/<branch>/CHANGELOG, with the
+    /// placeholders of --synthetic-files.
+    #[arg(long, value_name = "TEMPLATE")]
+    changelog_template: Option<String>,
+
+    /// Add refs-debug/, listing every reference as stored: loose or packed,
+    /// its value, what it peels to and whether the mount serves it. Also
+    /// lists references hidden by --branch-filter and --tag-filter.
+    #[arg(long)]
+    refs_debug: bool,
+
+    /// Follow only the first parent of merges wherever history is walked:
+    /// branches-meta changelogs, objects/<blob>/referenced-by and the
+    /// user.gitsnapfs.reachable-from xattr, for a linear mainline history.
+    #[arg(long)]
+    first_parent: bool,
+
+    /// Number this many commits of each branch's first-parent history under
+    /// commits-on/<branch>, the tip being 0 [default: 100].
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    history_depth: Option<u16>,
+
+    /// Serve each branch as a directory holding the tip's snapshot instead of a symlink.
+    #[arg(long)]
+    branches_as_dirs: bool,
+
+    /// Answer opendir with real handles so the mount can serve as an overlayfs lowerdir.
+    #[arg(long)]
+    overlay_compat: bool,
+
+    /// Serve a local checkout for the submodule with this name or path instead of
+    /// a placeholder. Repeatable.
+    #[arg(long, value_name = "NAME=PATH", value_parser = submodule::parse_checkout)]
+    submodule_path: Vec<submodule::Checkout>,
+
+    /// Most log lines per second for each distinct message; repeats beyond it are
+    /// counted and summarised every 10 seconds. 0 logs every line.
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    log_rate_limit: u32,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Examine a repository without mounting it.
+    Inspect(InspectArgs),
+    /// Hold decoded blobs for every mount started with --shared-cache.
+    CacheDaemon(CacheDaemonArgs),
+    /// Print the paths that differ between two snapshots, with their sizes.
+    DiffMounts(DiffMountsArgs),
+    /// Run a command inside a snapshot mounted just for it.
+    Shell(ShellArgs),
+    /// Write a snapshot as a tar archive, shaped like the mount, without mounting.
+    Export(ExportArgs),
+    /// Copy a directory out of a mount to disk, without going through FUSE.
+    Materialize(MaterializeArgs),
+    /// Point /current of a mount started with --current at another commit.
+    SwitchCurrent(SwitchCurrentArgs),
+    /// Mount another snapshot and flip a mountpoint symlink over to it,
+    /// retiring the mount it pointed into.
+    Switch(SwitchArgs),
+}
+
+#[derive(Debug, Args)]
+struct SwitchArgs {
+    /// Git repository to serve, found like --repo of the mount.
+    #[arg(long)]
+    repo: PathBuf,
+
+    /// Symlink to point at the new snapshot; created if missing.
+    #[arg(long)]
+    mountpoint: PathBuf,
+
+    /// Snapshot to switch to: any revision naming a commit.
+    #[arg(long = "ref", value_name = "REV")]
+    rev: String,
+
+    /// fusermount helper to use instead of searching for fusermount3/fusermount.
+    #[arg(long, value_name = "PATH")]
+    fusermount_path: Option<PathBuf>,
+
+    /// Further options for the new mount's daemon, after `--`.
+    #[arg(last = true, value_name = "OPTIONS")]
+    options: Vec<OsString>,
+}
+
+#[derive(Debug, Args)]
+struct SwitchCurrentArgs {
+    /// Mountpoint of the mount to switch.
+    mountpoint: PathBuf,
+
+    /// Commit to show, as any revision the mounted repository resolves.
+    rev: String,
+}
+
+#[derive(Debug, Args)]
+struct ExportArgs {
+    /// Git repository to export from, found like --repo of the mount.
+    #[arg(long)]
+    repo: PathBuf,
+
+    /// Snapshot to export: any revision naming a commit.
+    #[arg(long = "ref", value_name = "REV", default_value = "HEAD")]
+    rev: String,
+
+    /// Archive format; defaults to the one --output's extension names, else tar.
+    #[arg(long, value_enum)]
+    format: Option<ArchiveFormat>,
+
+    /// Write the archive to this file instead of stdout.
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Prepend this to every path in the archive, like `git archive --prefix`.
+    #[arg(long, default_value = "")]
+    prefix: String,
+
+    #[command(flatten)]
+    content: ContentArgs,
+}
+
+#[derive(Debug, Args)]
+struct MaterializeArgs {
+    /// Git repository behind the mount, found like --repo of the mount.
+    #[arg(long)]
+    repo: PathBuf,
+
+    /// Directory inside a gitsnapfs mount to copy.
+    source: PathBuf,
+
+    /// Directory to create with the copy.
+    dest: PathBuf,
+
+    /// Number of threads copying; defaults to the number of CPUs.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: Option<u16>,
+
+    #[command(flatten)]
+    content: ContentArgs,
+}
+
+/// Settings shaping a snapshot for the commands that read one without
+/// mounting; they match the mount options of the same names.
+#[derive(Debug, Args)]
+#[allow(
+    clippy::struct_excessive_bools,
+    reason = "each is an independent flag, mirroring the mount option"
+)]
+struct ContentArgs {
+    /// Read objects as stored, ignoring `refs/replace/`.
+    #[arg(long)]
+    no_replace_objects: bool,
+
+    /// Leave out paths marked `export-ignore`, as the mount does with this flag.
+    #[arg(long)]
+    respect_export_ignore: bool,
+
+    /// JSON file declaring files to synthesize at the root of the snapshot.
+    #[arg(long)]
+    synthetic_files: Option<PathBuf>,
+
+    /// Fail instead of copying files larger than this (bytes, or with a K/M/G suffix).
+    #[arg(long, value_name = "SIZE", value_parser = config::parse_byte_size)]
+    max_file_size: Option<u64>,
+
+    /// Write Git LFS pointer files as zeros of the size they record, as the
+    /// mount does with this flag.
+    #[arg(long)]
+    lfs_sparse: bool,
+
+    /// Name authors in synthetic files as recorded, ignoring `.mailmap`, as
+    /// the mount does with this flag.
+    #[arg(long)]
+    no_mailmap: bool,
+
+    /// Copy a local checkout for the submodule with this name or path
+    /// instead of a placeholder. Repeatable.
+    #[arg(long, value_name = "NAME=PATH", value_parser = submodule::parse_checkout)]
+    submodule_path: Vec<submodule::Checkout>,
+
+    /// Deepest tree nesting walked for export-ignore before failing.
+    #[arg(long, value_name = "N", default_value_t = TreeLimits::default().max_depth)]
+    max_tree_depth: usize,
+
+    /// Most tree entries such a walk may visit before failing.
+    #[arg(long, value_name = "N", default_value_t = TreeLimits::default().max_entries)]
+    max_tree_entries: u64,
+}
+
+impl ContentArgs {
+    fn fs_config(&self) -> Result<FsConfig> {
+        Ok(FsConfig {
+            respect_export_ignore: self.respect_export_ignore,
+            synthetic_files: match &self.synthetic_files {
+                Some(path) => synth::load(path)?,
+                None => Vec::new(),
+            },
+            max_file_size: self.max_file_size,
+            lfs_sparse: self.lfs_sparse,
+            ignore_mailmap: self.no_mailmap,
+            submodule_checkouts: self.submodule_path.clone(),
+            tree_limits: TreeLimits {
+                max_depth: self.max_tree_depth,
+                max_entries: self.max_tree_entries,
+            },
+            ..FsConfig::default()
+        })
+    }
+}
+
+#[derive(Debug, Args)]
+struct ShellArgs {
+    /// Git repository to serve, found like --repo of the mount.
+    #[arg(long)]
+    repo: PathBuf,
+
+    /// Snapshot to run in: any revision naming a commit.
+    #[arg(long = "ref", value_name = "REV", default_value = "HEAD")]
+    rev: String,
+
+    /// fusermount helper to use instead of searching for fusermount3/fusermount.
+    #[arg(long, value_name = "PATH")]
+    fusermount_path: Option<PathBuf>,
+
+    /// Command to run, after `--`; defaults to $SHELL.
+    #[arg(last = true, value_name = "COMMAND")]
+    command: Vec<OsString>,
+}
+
+#[derive(Debug, Args)]
+struct DiffMountsArgs {
+    /// Git repository to compare in, found like --repo of the mount.
+    #[arg(long)]
+    repo: PathBuf,
+
+    /// Old side: any revision naming a commit, tag or tree.
+    old: String,
+
+    /// New side: any revision naming a commit, tag or tree.
+    new: String,
+
+    /// Deepest tree nesting walked before failing.
+    #[arg(long, value_name = "N", default_value_t = TreeLimits::default().max_depth)]
+    max_tree_depth: usize,
+
+    /// Most tree entries visited before failing.
+    #[arg(long, value_name = "N", default_value_t = TreeLimits::default().max_entries)]
+    max_tree_entries: u64,
+}
+
+#[derive(Debug, Args)]
+struct CacheDaemonArgs {
+    /// Unix socket to listen on (created accessible to its owner only).
+    #[arg(long, value_name = "PATH")]
+    socket: PathBuf,
+
+    /// Memory budget for cached blob contents per repository (bytes, or with a
+    /// K/M/G suffix).
+    #[arg(long, value_name = "SIZE", default_value = "1G", value_parser = config::parse_byte_size)]
+    max_memory: u64,
+}
+
+#[derive(Debug, Args)]
+#[command(group(ArgGroup::new("check").required(true).multiple(false)))]
+struct InspectArgs {
+    /// Git repository to serve: a worktree or any directory inside one, a .git dir,
+    /// a bare repo, or a bundle file. `GIT_DIR` takes precedence when set.
+    #[arg(long)]
+    repo: PathBuf,
+
+    /// Decode every ref-reachable object and emit a JSON report of unreadable ones.
+    #[arg(long, group = "check")]
+    fsck: bool,
+
+    /// Check that a directory of the mount satisfies overlayfs lowerdir
+    /// requirements and emit a JSON report of violations.
+    #[arg(long, group = "check")]
+    overlay_check: bool,
+
+    /// Summarize packs, delta-chain depths and expected read amplification,
+    /// with advice on when to repack.
+    #[arg(long, group = "check")]
+    pack_stats: bool,
+
+    /// List the references (and HEAD) whose history contains COMMIT.
+    #[arg(long, value_name = "COMMIT", group = "check")]
+    reachability: Option<String>,
+
+    /// List the reference-reachable commits whose snapshots contain BLOB.
+    #[arg(long, value_name = "BLOB", group = "check")]
+    find_blob: Option<String>,
+
+    /// Explain an inode number seen through a mount (`ls -i`, `stat`, error
+    /// reports): its object, kind and example paths. Decimal or 0x-prefixed hex.
+    #[arg(long, value_name = "INO", group = "check", value_parser = inspect::parse_inode)]
+    inode: Option<u64>,
+
+    /// The mount's --state-file, to name the branch or tag behind an --inode.
+    #[arg(long, value_name = "PATH", requires = "inode")]
+    state_file: Option<PathBuf>,
+
+    /// List the files at HEAD and every reference tip that a mount with
+    /// `--max-file-size SIZE` refuses to read.
+    #[arg(long, value_name = "SIZE", group = "check", value_parser = config::parse_byte_size)]
+    oversized_files: Option<u64>,
+
+    /// Directory inside the mount checked by --overlay-check.
+    #[arg(
+        long,
+        value_name = "PATH",
+        default_value = "HEAD",
+        requires = "overlay_check"
+    )]
+    lowerdir: String,
+
+    /// Write the report to this file instead of stdout.
+    #[arg(long)]
+    output: Option<PathBuf>,
 }
 
 fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let cli = parse_cli()?;
 
+    let rate_limit = match cli.log_rate_limit {
+        0 => None,
+        per_second => Some(RateLimit::new(per_second)?),
+    };
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
         .with_target(false)
+        .with_writer(io::stderr)
+        .finish()
+        .with(rate_limit)
         .init();
 
+    match cli.command {
+        Some(Command::Inspect(args)) => return run_inspect(&args),
+        Some(Command::CacheDaemon(args)) => {
+            let budget = usize::try_from(args.max_memory).unwrap_or(usize::MAX);
+            return shared_cache::serve(&args.socket, budget);
+        }
+        Some(Command::DiffMounts(args)) => return run_diff_mounts(&args),
+        Some(Command::Shell(args)) => return run_shell(&args),
+        Some(Command::Export(args)) => return run_export(&args),
+        Some(Command::Materialize(args)) => return run_materialize(&args),
+        Some(Command::SwitchCurrent(args)) => return run_switch_current(&args),
+        Some(Command::Switch(args)) => return run_switch(&args),
+        None => {}
+    }
+    let scratch = cli.pack_stdin.then(read_stdin_pack).transpose()?;
+    let repo_path = match (&scratch, cli.repo.clone()) {
+        (Some(scratch), _) => scratch.path().to_path_buf(),
+        (None, Some(path)) => path,
+        (None, None) => bail!("--repo is required"),
+    };
+
     if cli.takeover_fuse_fd.is_some() {
         bail!("takeover via existing FUSE fd is not supported yet in the MVP");
     }
 
-    let repo = Repository::open(&cli.repo)?;
-    let fs = GitSnapFs::new(repo);
+    let config = fs_config(&cli)?;
+    // Block SIGHUP and SIGUSR2 before any thread exists so only the handler
+    // thread sees them.
+    let signals = SigSet::from_iter([Signal::SIGHUP, Signal::SIGUSR2]);
+    signals.thread_block()?;
+    if cli.max_requests_per_uid.is_some() && cli.threads == 1 {
+        warn!("--max-requests-per-uid has no effect with a single serving thread");
+    }
+    if let Some(mountpoint) = cli.mountpoint.as_deref() {
+        replace_existing_mount(
+            mountpoint,
+            &cli.subtype,
+            cli.replace,
+            cli.fusermount_path.as_deref(),
+        )?;
+    }
+    let created_mountpoint = match &cli.mountpoint {
+        Some(mountpoint) if cli.create_mountpoint => {
+            mount::create_mountpoint(mountpoint, cli.mountpoint_mode)?
+        }
+        _ => false,
+    };
+    let removed_at_exit = cli
+        .mountpoint
+        .clone()
+        .filter(|_| created_mountpoint && cli.remove_mountpoint);
+    let served = if cli.lazy_init {
+        run_lazy_mount(&cli, &repo_path, config, signals)
+    } else {
+        run_mount(&cli, &repo_path, config, signals)
+    };
+    if let (Ok(()), Some(mountpoint)) = (&served, removed_at_exit) {
+        remove_mountpoint(&mountpoint);
+    }
+    served
+}
+
+/// Fail if a gitsnapfs mount of subtype `subtype` is already at
+/// `mountpoint`, or with `replace`, detach it.
+fn replace_existing_mount(
+    mountpoint: &Path,
+    subtype: &str,
+    replace: bool,
+    fusermount: Option<&Path>,
+) -> Result<()> {
+    if !mount::gitsnapfs_mounted_at(mountpoint, subtype)? {
+        return Ok(());
+    }
+    let dead = mountpoint
+        .metadata()
+        .is_err_and(|err| err.raw_os_error() == Some(libc::ENOTCONN));
+    let state = if dead { "a dead" } else { "a running" };
+    if !replace {
+        bail!(
+            "{} already has {state} gitsnapfs mount; unmount it with `fusermount -u {0}`, \
+             or pass --replace to detach it",
+            mountpoint.display()
+        );
+    }
+    tracing::info!(
+        "detaching {state} gitsnapfs mount at {}",
+        mountpoint.display()
+    );
+    mount::detach(mountpoint, fusermount)
+}
+
+/// Open the repository and serve it on the mount point, over HTTP or over
+/// SFTP, until unmounted or the connection ends.
+fn run_mount(cli: &Cli, repo_path: &Path, config: FsConfig, signals: SigSet) -> Result<()> {
+    let writes = Arc::new(Writes::new(cli.splice));
+    let fs = open_fs(cli, repo_path, config, &writes)?;
+
+    if let Some(addr) = cli.http_listen {
+        spawn_signal_handler(signals, Arc::clone(&fs), None)?;
+        return http::serve(Vfs::new(fs), addr);
+    }
+    if cli.sftp_stdio {
+        spawn_signal_handler(signals, Arc::clone(&fs), None)?;
+        return sftp::serve(&Vfs::new(fs), io::stdin().lock(), io::stdout().lock());
+    }
+    let serving = Serving::of(cli);
+    #[cfg(feature = "async-io")]
+    let async_workers = cli.async_runtime.then_some(cli.threads);
+    let Some(mountpoint) = &cli.mountpoint else {
+        bail!("--mountpoint is required");
+    };
 
     tracing::info!(
         "GitSnapFS mounting (repo: {}, mountpoint: {})",
-        cli.repo.display(),
-        cli.mountpoint.display()
+        repo_path.display(),
+        mountpoint.display()
+    );
+
+    let runtime = FuseRuntime::new(
+        Arc::clone(&fs),
+        mountpoint,
+        MountNames {
+            fsname: &cli.fsname,
+            subtype: &cli.subtype,
+        },
+        cli.allow_other,
+        cli.fusermount_path.as_deref(),
+    )?
+    .with_writes(writes);
+    spawn_signal_handler(signals, Arc::clone(&fs), Some(runtime.kernel_notifier()?))?;
+    spawn_latest_watcher(Arc::clone(&fs), runtime.kernel_notifier()?)?;
+    #[cfg(feature = "async-io")]
+    if let Some(workers) = async_workers {
+        return runtime.serve_async(&fs, workers);
+    }
+    runtime.run(serving)
+}
+
+/// Mount first and open the repository behind the mount in the background
+/// (`--lazy-init`).
+fn run_lazy_mount(cli: &Cli, repo_path: &Path, config: FsConfig, signals: SigSet) -> Result<()> {
+    let Some(mountpoint) = &cli.mountpoint else {
+        bail!("--mountpoint is required");
+    };
+    let lazy = Arc::new(LazyFs::new(config.clone()));
+    let writes = Arc::new(Writes::new(cli.splice));
+    let runtime = FuseRuntime::new(
+        Arc::clone(&lazy),
+        mountpoint,
+        MountNames {
+            fsname: &cli.fsname,
+            subtype: &cli.subtype,
+        },
+        cli.allow_other,
+        cli.fusermount_path.as_deref(),
+    )?
+    .with_writes(Arc::clone(&writes));
+    tracing::info!(
+        "GitSnapFS mounted at {}, opening {} in the background",
+        mountpoint.display(),
+        repo_path.display()
+    );
+    let serving = Serving::of(cli);
+    let signal_notifier = runtime.kernel_notifier()?;
+    let latest_notifier = runtime.kernel_notifier()?;
+    // Scoped, so the opening thread can borrow `cli`; it is long done when
+    // the mount ends.
+    thread::scope(|scope| {
+        thread::Builder::new()
+            .name("gitsnapfs-init".into())
+            .spawn_scoped(scope, move || {
+                let started = Instant::now();
+                let fs = match open_fs(cli, repo_path, config, &writes) {
+                    Ok(fs) => fs,
+                    Err(err) => {
+                        error!("{err:#}; answering every request with EIO");
+                        lazy.fail();
+                        return;
+                    }
+                };
+                lazy.ready(Arc::clone(&fs));
+                tracing::info!("repository opened in {:.1?}", started.elapsed());
+                let watchers =
+                    spawn_signal_handler(signals, Arc::clone(&fs), Some(signal_notifier))
+                        .and_then(|()| spawn_latest_watcher(fs, latest_notifier));
+                if let Err(err) = watchers {
+                    error!("{err:#}");
+                }
+            })?;
+        runtime.run(serving)
+    })
+}
+
+/// Open the repository at `repo_path` and build the filesystem on it,
+/// preloading it with `--preload`. With `--splice` or `--no-splice` it
+/// serves `writes` as `.gitsnapfs/info`.
+fn open_fs(
+    cli: &Cli,
+    repo_path: &Path,
+    mut config: FsConfig,
+    writes: &Arc<Writes>,
+) -> Result<Arc<GitSnapFs>> {
+    let repo = Repository::discover(repo_path, !cli.no_replace_objects)?.with_object_caches(
+        ObjectCaches {
+            object_mb: cli.gix_object_cache_mb,
+            pack_mb: cli.gix_pack_cache_mb,
+        },
     );
+    let (object_cache, pack_cache) = repo.object_cache_bytes();
+    tracing::debug!("gix object cache {object_cache} bytes, pack cache {pack_cache} bytes");
+    if cli.consistent_refs {
+        repo.freeze_refs()?;
+    }
+    config.only_descendants_of = only_descendants_of(cli, &repo)?;
+    let current = cli
+        .current
+        .as_deref()
+        .map(|rev| {
+            repo.resolve_full_commit_id(rev)
+                .with_context(|| format!("--current {rev} does not name a commit"))
+        })
+        .transpose()?;
+    let mut fs = GitSnapFs::with_config(repo, config);
+    let ref_inodes = match &cli.state_file {
+        Some(path) => RefInodes::open(path)?,
+        None => RefInodes::default(),
+    };
+    let prefix_bits = cli.inode_prefix_bits.unwrap_or(OBJECT_PREFIX_BITS);
+    fs = fs.with_ref_inodes(ref_inodes.with_prefix_bits(prefix_bits));
+    if cli.splice || cli.no_splice {
+        fs = fs.with_writes(Arc::clone(writes));
+    }
+    if let Some(path) = &cli.audit_log {
+        let log = AuditLog::open(path.clone(), cli.audit_log_max_size)
+            .with_context(|| format!("failed to open audit log {}", path.display()))?;
+        fs = fs.with_audit_log(log);
+    }
+    if let Some(commit) = current {
+        fs = fs.with_current(commit);
+    }
+    if let Some(capacity) = cli.error_journal {
+        fs = fs.with_error_journal(ErrorJournal::new(usize::from(capacity)));
+    }
+    if let Some(secs) = cli.health_interval {
+        let interval = Duration::from_secs(secs);
+        fs = fs.with_health(Health::new(cli.health_path.clone().into_bytes(), interval));
+    }
+    if let Some(fraction) = cli.verify_sample {
+        fs = fs.with_scrub(Scrub::new(fraction));
+    }
+    let fs = Arc::new(fs);
+    health::spawn(&fs)?;
+    scrub::spawn(&fs)?;
+    if cli.preload {
+        let started = Instant::now();
+        let stats = fs.preload()?;
+        tracing::info!(
+            "preloaded {} objects ({} blobs, {} bytes cached) in {:.1?}",
+            stats.objects,
+            stats.cached_blobs,
+            stats.cached_bytes,
+            started.elapsed()
+        );
+    }
+    Ok(fs)
+}
+
+/// The commit `--only-descendants-of` names in `repo`.
+fn only_descendants_of(cli: &Cli, repo: &Repository) -> Result<Option<gix::ObjectId>> {
+    cli.only_descendants_of
+        .as_deref()
+        .map(|rev| {
+            repo.resolve_full_commit_id(rev)
+                .with_context(|| format!("--only-descendants-of {rev} does not name a commit"))
+        })
+        .transpose()
+}
+
+/// Filesystem settings chosen on the command line, apart from those that
+/// need the repository (see [`open_fs`]).
+fn fs_config(cli: &Cli) -> Result<FsConfig> {
+    let synthetic_files = match &cli.synthetic_files {
+        Some(path) => synth::load(path)?,
+        None => Vec::new(),
+    };
+    let config = FsConfig {
+        respect_export_ignore: cli.respect_export_ignore,
+        synthetic_files,
+        fake_dotgit: cli.fake_dotgit,
+        max_read_bandwidth: cli.max_read_bandwidth,
+        max_iops: cli.max_iops,
+        sort: cli.sort,
+        dir_size: cli.dir_size,
+        tree_limits: TreeLimits {
+            max_depth: cli.max_tree_depth,
+            max_entries: cli.max_tree_entries,
+        },
+        cache_budget: cli
+            .max_memory
+            .or(cli.preload.then_some(DEFAULT_PRELOAD_BUDGET)),
+        overlay_compat: cli.overlay_compat,
+        branches_as_dirs: cli.branches_as_dirs,
+        stats: cli.stats,
+        submodule_checkouts: cli.submodule_path.clone(),
+        max_requests_per_uid: cli.max_requests_per_uid.map(usize::from),
+        readahead: cli.readahead,
+        shared_cache: cli.shared_cache.clone(),
+        root_readme: cli.root_readme,
+        releases: cli.releases.then(|| cli.release_pattern.clone()),
+        only_descendants_of: None,
+        changelog_template: cli.changelog_template.clone(),
+        refs_debug: cli.refs_debug,
+        first_parent: cli.first_parent,
+        history_depth: cli.history_depth.map(usize::from),
+        verify_reads: cli.verify_reads,
+        mmap_loose: cli.mmap_loose,
+        max_file_size: cli.max_file_size,
+        metadata_only: cli.metadata_only,
+        no_xattrs: cli.no_xattrs,
+        lfs_sparse: cli.lfs_sparse,
+        ignore_mailmap: cli.no_mailmap,
+        abbrev: cli.abbrev.map(usize::from),
+        authz: cli
+            .authz_cmd
+            .clone()
+            .map(Hook::Command)
+            .or_else(|| cli.authz_socket.clone().map(Hook::Socket)),
+        ref_filter: RefFilter {
+            branches: cli.branch_filter.clone(),
+            tags: cli.tag_filter.clone(),
+        },
+        mode_overrides: ModeOverrides {
+            rules: cli.mode_override.clone(),
+        },
+        git_config_keys: cli.expose_config.clone(),
+    };
+    if config.mode_overrides.modes().len() > MODE_VIEWS {
+        bail!("--mode-override can use at most {MODE_VIEWS} distinct modes");
+    }
+    Ok(config)
+}
+
+/// Index the pack or bundle piped in for `--pack-stdin`.
+fn read_stdin_pack() -> Result<bundle::ScratchRepo> {
+    let scratch = bundle::ScratchRepo::from_reader(io::stdin().lock())
+        .context("failed to read a pack from stdin")?;
+    let tips: Vec<String> = scratch.tips().iter().map(ToString::to_string).collect();
+    tracing::info!("indexed pack from stdin, tips: {}", tips.join(" "));
+    Ok(scratch)
+}
+
+/// Parse the command line, with `GITSNAPFS_*` variables and the config file
+/// filling in flags it leaves out. Usage errors, `--help` and `--version`
+/// exit the process.
+fn parse_cli() -> Result<Cli> {
+    read_cli().map_err(|err| match err.downcast::<clap::Error>() {
+        Ok(err) => err.exit(),
+        Err(err) => err,
+    })
+}
+
+/// [`parse_cli`], returning usage errors instead of exiting.
+fn read_cli() -> Result<Cli> {
+    let cmd = settings::with_env(Cli::command());
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    if let Some(path) = settings::config_path() {
+        args = settings::load(&path)
+            .and_then(|settings| settings::apply(&cmd, args, &settings))
+            .with_context(|| format!("invalid config file {}", path.display()))?;
+    }
+    Ok(Cli::from_arg_matches(&cmd.try_get_matches_from(args)?)?)
+}
+
+fn run_inspect(args: &InspectArgs) -> Result<()> {
+    let repo = Repository::discover(&args.repo, true)?;
+    if let Some(spec) = &args.reachability {
+        return write_report(&inspect::reachability(&repo, spec)?, args.output.as_deref());
+    }
+    if let Some(spec) = &args.find_blob {
+        return write_report(&inspect::find_blob(&repo, spec)?, args.output.as_deref());
+    }
+    if let Some(inode) = args.inode {
+        let report = inspect::resolve_inode(&repo, inode, args.state_file.as_deref())?;
+        return write_report(&report, args.output.as_deref());
+    }
+    if let Some(limit) = args.oversized_files {
+        let report = inspect::oversized_files(&repo, limit, TreeLimits::default())?;
+        return write_report(&report, args.output.as_deref());
+    }
+    if args.pack_stats {
+        return write_report(&inspect::pack_stats(&repo)?, args.output.as_deref());
+    }
+    if args.overlay_check {
+        let config = FsConfig {
+            overlay_compat: true,
+            ..FsConfig::default()
+        };
+        let fs = Arc::new(GitSnapFs::with_config(repo, config));
+        let report = inspect::overlay_check(&fs, args.lowerdir.as_bytes())?;
+        write_report(&report, args.output.as_deref())?;
+        if !report.is_clean() {
+            bail!("overlay check found {} problem(s)", report.problems.len());
+        }
+        return Ok(());
+    }
+    let report = inspect::fsck(&repo)?;
+    write_report(&report, args.output.as_deref())?;
+    if !report.is_clean() {
+        bail!("fsck found {} problem(s)", report.problems.len());
+    }
+    Ok(())
+}
+
+fn run_diff_mounts(args: &DiffMountsArgs) -> Result<()> {
+    let repository = Repository::discover(&args.repo, true)?;
+    let repo = repository.thread_local();
+    let old = diff::resolve_tree(&repo, &args.old)?;
+    let new = diff::resolve_tree(&repo, &args.new)?;
+    let limits = TreeLimits {
+        max_depth: args.max_tree_depth,
+        max_entries: args.max_tree_entries,
+    };
+    let changes = diff::diff_trees(&repo, Some(old), Some(new), limits)?;
+    io::stdout()
+        .lock()
+        .write_all(&diff::render_changes(&repo, &changes)?)?;
+    Ok(())
+}
+
+nix::ioctl_write_ptr!(
+    switch_current_ioctl,
+    b'G',
+    1,
+    [u8; gitsnapfs::fs::SWITCH_CURRENT_LEN]
+);
+
+/// Ask the mount at `args.mountpoint` to show `args.rev` as `/current`.
+fn run_switch_current(args: &SwitchCurrentArgs) -> Result<()> {
+    let mut rev = [0u8; gitsnapfs::fs::SWITCH_CURRENT_LEN];
+    if args.rev.is_empty() || args.rev.len() >= rev.len() || args.rev.contains('\0') {
+        bail!(
+            "{:?} is not a revision /current can be switched to",
+            args.rev
+        );
+    }
+    rev[..args.rev.len()].copy_from_slice(args.rev.as_bytes());
+    let control = args.mountpoint.join(".gitsnapfs/current");
+    let file = File::open(&control).with_context(|| {
+        format!(
+            "cannot open {}; was the mount started with --current?",
+            control.display()
+        )
+    })?;
+    // SAFETY: the kernel reads `SWITCH_CURRENT_LEN` bytes from `rev`.
+    unsafe { switch_current_ioctl(file.as_raw_fd(), std::ptr::from_ref(&rev)) }.with_context(
+        || {
+            format!(
+                "cannot switch {} to {}",
+                args.mountpoint.display(),
+                args.rev
+            )
+        },
+    )?;
+    Ok(())
+}
+
+/// How long `gitsnapfs switch` waits for the new mount to answer.
+const SWITCH_MOUNT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Mount `args.rev` beside `args.mountpoint`, point the symlink there once
+/// it answers, and detach the mount it pointed into before.
+fn run_switch(args: &SwitchArgs) -> Result<()> {
+    let repo = Repository::discover(&args.repo, true)?;
+    let commit = repo
+        .resolve_full_commit_id(&args.rev)
+        .with_context(|| format!("--ref {} does not name a commit", args.rev))?;
+    let old = switch::current_mount_dir(&args.mountpoint)?;
+    let dir = switch::mount_dir(&args.mountpoint, commit)?;
+    std::fs::DirBuilder::new()
+        .mode(0o755)
+        .create(&dir)
+        .with_context(|| format!("failed to create {}", dir.display()))?;
+    let mut command = process::Command::new(std::env::current_exe()?);
+    command
+        .arg("--repo")
+        .arg(&args.repo)
+        .arg("--mountpoint")
+        .arg(&dir);
+    if let Some(path) = &args.fusermount_path {
+        command.arg("--fusermount-path").arg(path);
+    }
+    // Its own process group keeps the daemon out of the terminal's signals;
+    // its log still goes to our stderr.
+    command
+        .args(&args.options)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .process_group(0);
+    let mut daemon = command
+        .spawn()
+        .context("failed to start the daemon for the new snapshot")?;
+    let ready = wait_for_snapshot(&mut daemon, &dir.join("commits").join(commit.to_string()))
+        .and_then(|()| switch::flip(&args.mountpoint, &dir, commit));
+    if let Err(err) = ready {
+        let _ = daemon.kill();
+        let _ = daemon.wait();
+        let _ = std::fs::remove_dir(&dir);
+        return Err(err);
+    }
+    tracing::info!(
+        "{} now shows {commit} from {}",
+        args.mountpoint.display(),
+        dir.display()
+    );
+    if let Some(old) = old {
+        switch::detach(&old, args.fusermount_path.as_deref())?;
+    }
+    Ok(())
+}
+
+/// Wait until `daemon` serves `snapshot`.
+fn wait_for_snapshot(daemon: &mut Child, snapshot: &Path) -> Result<()> {
+    let deadline = Instant::now() + SWITCH_MOUNT_TIMEOUT;
+    while !snapshot.is_dir() {
+        if let Some(status) = daemon.try_wait()? {
+            bail!("the daemon for the new snapshot exited with {status}");
+        }
+        if Instant::now() >= deadline {
+            bail!(
+                "{} did not appear within {SWITCH_MOUNT_TIMEOUT:?}",
+                snapshot.display()
+            );
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    Ok(())
+}
+
+/// Archive the snapshot `args.rev` through the same view a mount with the
+/// given content flags would serve.
+fn run_export(args: &ExportArgs) -> Result<()> {
+    let repo = Repository::discover(&args.repo, !args.content.no_replace_objects)?;
+    let commit = repo
+        .resolve_full_commit_id(&args.rev)
+        .with_context(|| format!("--ref {} does not name a commit", args.rev))?;
+    let mtime = repo.thread_local().find_commit(commit)?.time()?.seconds;
+    let config = args.content.fs_config()?;
+    let vfs = Vfs::new(Arc::new(GitSnapFs::with_config(repo, config)));
+    let dir = vfs.resolve(format!("commits/{commit}").as_bytes(), true)?;
+    let format = args
+        .format
+        .or_else(|| args.output.as_deref().and_then(ArchiveFormat::from_path))
+        .unwrap_or(ArchiveFormat::Tar);
+    let options = TarOptions {
+        prefix: args.prefix.clone().into_bytes(),
+        mtime,
+        commit: Some(commit),
+    };
+    archive::export(&vfs, &dir, &options, format, args.output.as_deref())
+}
+
+/// Copy `args.source` out of its mount by reading the repository directly.
+fn run_materialize(args: &MaterializeArgs) -> Result<()> {
+    let (mount_root, inside) = materialize::locate(&args.source)?;
+    let repo = Repository::discover(&args.repo, !args.content.no_replace_objects)?;
+    let vfs = Vfs::new(Arc::new(GitSnapFs::with_config(
+        repo,
+        args.content.fs_config()?,
+    )));
+    let mismatch = || {
+        format!(
+            "{} is not {} in {}; pass the mount's --repo and content options",
+            args.source.display(),
+            inside.display(),
+            args.repo.display()
+        )
+    };
+    let source = vfs
+        .resolve(inside.as_os_str().as_bytes(), true)
+        .with_context(mismatch)?;
+    // Inodes derive from object ids and paths, so a mismatch means the mount
+    // serves another repository or shapes the snapshot differently.
+    if std::fs::metadata(mount_root.join(&inside))?.ino() != source.inode {
+        bail!(mismatch());
+    }
+    let jobs = args.jobs.map_or_else(
+        || thread::available_parallelism().map_or(1, usize::from),
+        usize::from,
+    );
+    let started = Instant::now();
+    let totals = materialize::materialize(&vfs, &source, &args.dest, jobs)?;
+    tracing::info!(
+        "copied {} files ({} bytes), {} directories and {} symlinks in {:.1?}",
+        totals.files,
+        totals.bytes,
+        totals.dirs,
+        totals.symlinks,
+        started.elapsed()
+    );
+    Ok(())
+}
+
+/// Whether `err` from reading the FUSE device means the kernel closed it
+/// because the filesystem was unmounted, which ends serving normally.
+fn closed_by_unmount(err: &fuse_backend_rs::transport::Error) -> bool {
+    matches!(err, fuse_backend_rs::transport::Error::SessionFailure(message) if message == "epoll error")
+}
+
+/// How long to wait for the helper to finish unmounting before a mount
+/// point made for the mount is removed.
+const UNMOUNT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Remove the directory `mountpoint` made for a mount that has ended.
+fn remove_mountpoint(mountpoint: &Path) {
+    let deadline = Instant::now() + UNMOUNT_TIMEOUT;
+    // The helper unmounts asynchronously; until it has, the directory is busy.
+    while let Err(err) = std::fs::remove_dir(mountpoint) {
+        if Instant::now() >= deadline {
+            warn!(%err, "could not remove {}", mountpoint.display());
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// Mount the snapshot `args.rev` on a temporary directory, run the command
+/// inside it and exit with its status once everything is torn down.
+fn run_shell(args: &ShellArgs) -> Result<()> {
+    let repo = Repository::discover(&args.repo, true)?;
+    let commit = repo
+        .resolve_full_commit_id(&args.rev)
+        .with_context(|| format!("--ref {} does not name a commit", args.rev))?;
+    // Root can keep the mount out of everyone else's sight; anyone else gets
+    // a directory only they can enter.
+    if mount::can_mount_directly() {
+        if let Err(err) = mount::enter_private_namespace() {
+            warn!("{err:#}; the snapshot mount will be visible system-wide");
+        }
+    }
+    // Interrupts are for the command, which unblocks them again.
+    shell_interrupts().thread_block()?;
+
+    let mountpoint = std::env::temp_dir().join(format!("gitsnapfs-shell-{}", std::process::id()));
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&mountpoint)
+        .with_context(|| format!("failed to create {}", mountpoint.display()))?;
+    let status = run_in_mount(args, repo, commit, &mountpoint);
+    remove_mountpoint(&mountpoint);
+    let status = status?;
+    std::process::exit(
+        status
+            .code()
+            .or_else(|| status.signal().map(|signal| 128 + signal))
+            .unwrap_or(1),
+    )
+}
+
+/// Signals `gitsnapfs shell` leaves to the command it runs.
+fn shell_interrupts() -> SigSet {
+    SigSet::from_iter([Signal::SIGINT, Signal::SIGQUIT])
+}
+
+fn run_in_mount(
+    args: &ShellArgs,
+    repo: Repository,
+    commit: gix::ObjectId,
+    mountpoint: &Path,
+) -> Result<ExitStatus> {
+    let fs = Arc::new(GitSnapFs::with_config(repo, FsConfig::default()));
+    let runtime = FuseRuntime::new(
+        fs,
+        mountpoint,
+        MountNames::default(),
+        false,
+        args.fusermount_path.as_deref(),
+    )?;
+    let (program, rest) = match args.command.split_first() {
+        Some((program, rest)) => (program.clone(), rest),
+        None => (
+            std::env::var_os("SHELL").unwrap_or_else(|| "/bin/sh".into()),
+            &[][..],
+        ),
+    };
+    let mut command = process::Command::new(&program);
+    command
+        .args(rest)
+        .current_dir(mountpoint.join("commits").join(commit.to_string()));
+    // SAFETY: pthread_sigmask is async-signal-safe and allocates nothing.
+    unsafe {
+        command.pre_exec(|| shell_interrupts().thread_unblock().map_err(io::Error::from));
+    }
+    runtime.run_command(&mut command).with_context(|| {
+        format!(
+            "failed to run {} in {}",
+            Path::new(&program).display(),
+            commit
+        )
+    })
+}
+
+fn write_report(report: &impl Serialize, output: Option<&Path>) -> Result<()> {
+    let json = serde_json::to_string_pretty(report)?;
+    match output {
+        Some(path) => std::fs::write(path, json + "\n")
+            .with_context(|| format!("failed to write report to {}", path.display()))?,
+        None => println!("{json}"),
+    }
+    Ok(())
+}
+
+/// Invalidates kernel-cached entries and directory contents.
+type KernelNotifier = Box<dyn FnMut(&[(u64, Vec<u8>)], &[u64]) + Send>;
+
+/// On SIGHUP, drop every internal cache and ask the kernel to forget
+/// reference-level entries, so a push becomes visible immediately. SIGUSR2
+/// does the same after reloading the settings (see [`reload_config`]).
+fn spawn_signal_handler(
+    signals: SigSet,
+    fs: Arc<GitSnapFs>,
+    mut notify: Option<KernelNotifier>,
+) -> Result<()> {
+    thread::Builder::new()
+        .name("gitsnapfs-signals".into())
+        .spawn(move || loop {
+            let signal = match signals.wait() {
+                Ok(signal) => signal,
+                Err(err) => {
+                    error!(%err, "waiting for signals failed");
+                    return;
+                }
+            };
+            let refreshed = if signal == Signal::SIGUSR2 {
+                reload_config(&fs).map(|fixed| {
+                    for flag in fixed {
+                        warn!("--{flag} changed, but only takes effect on the next mount");
+                    }
+                })
+            } else {
+                fs.flush_caches()
+            };
+            if let Err(err) = refreshed {
+                error!("{signal}: {err:#}");
+                continue;
+            }
+            let entries = fs.take_reference_entries();
+            if let Some(notify) = &mut notify {
+                notify(&entries, &fs.reference_dirs());
+            }
+            let action = if signal == Signal::SIGUSR2 {
+                "configuration reloaded"
+            } else {
+                "caches flushed"
+            };
+            tracing::info!(
+                "{signal}: {action}, {} reference entries invalidated",
+                entries.len()
+            );
+        })?;
+    Ok(())
+}
+
+/// Parse the command line, environment and config file again and serve
+/// subsequent requests with the settings they give now. Returns the flags
+/// that changed but cannot be applied to a running mount.
+fn reload_config(fs: &GitSnapFs) -> Result<Vec<&'static str>> {
+    // Usage errors come with a hint to try --help, which does not help a daemon.
+    let cli = read_cli().map_err(|err| match err.downcast::<clap::Error>() {
+        Ok(err) => {
+            let message = err.to_string();
+            let first = message.lines().next().unwrap_or_default();
+            anyhow::anyhow!("{}", first.trim_start_matches("error: "))
+        }
+        Err(err) => err,
+    })?;
+    let mut config = fs_config(&cli)?;
+    config.only_descendants_of = only_descendants_of(&cli, fs.repository())?;
+    fs.reconfigure(config)
+}
+
+/// How often the branch tips behind `latest/` entries are checked.
+const LATEST_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Poll the branches behind `latest/` entries the kernel holds and
+/// invalidate those that moved, so CI jobs see a push without waiting for
+/// the entry timeout.
+fn spawn_latest_watcher(fs: Arc<GitSnapFs>, mut notify: KernelNotifier) -> Result<()> {
+    thread::Builder::new()
+        .name("gitsnapfs-latest".into())
+        .spawn(move || loop {
+            thread::sleep(LATEST_POLL_INTERVAL);
+            match fs.take_moved_tip_entries() {
+                Ok(moved) if !moved.is_empty() => {
+                    tracing::debug!("invalidating {} moved latest/ entries", moved.len());
+                    notify(&moved, &[]);
+                }
+                Ok(_) => {}
+                Err(err) => warn!(%err, "checking latest/ branch tips failed"),
+            }
+            if let Some(entry) = fs.take_current_switch() {
+                notify(&[entry], &[]);
+            }
+        })?;
+    Ok(())
+}
 
-    let runtime = FuseRuntime::new(fs, &cli.mountpoint, cli.allow_other)?;
-    runtime.serve()
+/// Source and subtype a mount is made with (`--fsname`, `--subtype`).
+#[derive(Clone, Copy)]
+struct MountNames<'a> {
+    fsname: &'a str,
+    subtype: &'a str,
+}
+
+impl Default for MountNames<'_> {
+    fn default() -> Self {
+        Self {
+            fsname: mount::DEFAULT_NAME,
+            subtype: mount::DEFAULT_NAME,
+        }
+    }
 }
 
-struct FuseRuntime {
-    server: Arc<Server<Arc<GitSnapFs>>>,
+struct FuseRuntime<F: FileSystem + Send + Sync> {
+    server: Arc<Server<Arc<F>>>,
     session: FuseSession,
+    writes: Arc<Writes>,
 }
 
-impl FuseRuntime {
-    fn new(fs: GitSnapFs, mountpoint: &Path, allow_other: bool) -> Result<Self> {
-        let server = Arc::new(Server::new(Arc::new(fs)));
-        let mut session =
-            FuseSession::new_with_autounmount(mountpoint, "gitsnapfs", "gitsnapfs", true, true)?;
+impl<F> FuseRuntime<F>
+where
+    F: FileSystem<Inode = u64, Handle = u64> + Send + Sync + 'static,
+{
+    fn new(
+        fs: Arc<F>,
+        mountpoint: &Path,
+        names: MountNames<'_>,
+        allow_other: bool,
+        fusermount: Option<&Path>,
+    ) -> Result<Self> {
+        mount::preflight(mountpoint)?;
+        if mount::in_user_namespace() {
+            tracing::info!("running inside a user namespace");
+        }
+        let server = Arc::new(Server::new(fs));
+        let mut session = match mount::find_fusermount(fusermount) {
+            Ok(helper) => {
+                let mut session = FuseSession::new_with_autounmount(
+                    mountpoint,
+                    names.fsname,
+                    names.subtype,
+                    true,
+                    true,
+                )?;
+                session.set_fusermount(mount::helper_str(&helper)?);
+                session
+            }
+            // Root in a user namespace may mount FUSE itself; without the
+            // helper there is no auto-unmount if we die, so say so.
+            Err(err) if fusermount.is_none() && mount::can_mount_directly() => {
+                warn!("{err:#}; mounting directly, without auto-unmount on crash");
+                FuseSession::new(mountpoint, names.fsname, names.subtype, true)?
+            }
+            Err(err) => return Err(err),
+        };
         session.set_allow_other(allow_other);
-        session.mount()?;
-        Ok(Self { server, session })
+        session.mount().with_context(|| {
+            format!(
+                "failed to mount {} (via {}); in a container this needs /dev/fuse and either \
+                 a setuid fusermount3 or CAP_SYS_ADMIN in the user namespace",
+                mountpoint.display(),
+                session.get_fusermount()
+            )
+        })?;
+        Ok(Self {
+            server,
+            session,
+            writes: Arc::default(),
+        })
+    }
+
+    /// Write replies as `writes` says and count them there.
+    fn with_writes(mut self, writes: Arc<Writes>) -> Self {
+        self.writes = writes;
+        self
+    }
+
+    /// Descriptor of the session's FUSE device.
+    fn device_fd(&self) -> Result<RawFd> {
+        Ok(self
+            .session
+            .get_fuse_file()
+            .context("FUSE session has no device file")?
+            .as_raw_fd())
+    }
+
+    /// Build a [`KernelNotifier`] writing to this session's device.
+    fn kernel_notifier(&self) -> Result<KernelNotifier> {
+        let fd = self.device_fd()?;
+        let server = Arc::clone(&self.server);
+        let mut buffer = vec![0u8; self.session.bufsize()];
+        Ok(Box::new(move |entries, dirs| {
+            // The kernel answers ENOENT for entries it has not cached; that is fine.
+            for (parent, name) in entries {
+                let Ok(name) = CString::new(name.clone()) else {
+                    continue;
+                };
+                if let Ok(writer) = FuseDevWriter::<()>::new(fd, &mut buffer) {
+                    let _ = server.notify_inval_entry(writer, *parent, &name);
+                }
+            }
+            for dir in dirs {
+                if let Ok(writer) = FuseDevWriter::<()>::new(fd, &mut buffer) {
+                    let _ = server.notify_inval_inode(writer, *dir, 0, 0);
+                }
+            }
+        }))
+    }
+
+    /// Serve requests until unmounted, as `serving` says.
+    fn run(self, serving: Serving) -> Result<()> {
+        match serving {
+            Serving::PerCpu { pin } => self.serve_per_cpu(pin),
+            Serving::Threads(threads) if threads > 1 => self.serve_threaded(usize::from(threads)),
+            Serving::Threads(_) => self.serve(),
+        }
+    }
+
+    /// Serve requests on a background thread while `command` runs, then unmount.
+    fn run_command(mut self, command: &mut process::Command) -> Result<ExitStatus> {
+        let channel = self.session.new_channel()?;
+        let fd = self.device_fd()?;
+        let server = Arc::clone(&self.server);
+        let replies = Replies::new(Arc::clone(&self.writes), self.session.bufsize());
+        thread::Builder::new()
+            .name("gitsnapfs-serve".into())
+            .spawn(move || {
+                // Ends with an error once the helper has unmounted; that is expected.
+                let _ = Self::serve_channel(&server, channel, fd, replies);
+            })?;
+        let status = command.status();
+        self.session.umount()?;
+        Ok(status?)
     }
 
     fn serve(self) -> Result<()> {
-        let mut channel = self.session.new_channel()?;
-        while let Some((reader, writer)) = channel.get_request()? {
-            if let Err(err) = self
-                .server
-                .handle_message(reader, writer.into(), None, None)
-            {
+        let replies = Replies::new(Arc::clone(&self.writes), self.session.bufsize());
+        Self::serve_channel(
+            &self.server,
+            self.session.new_channel()?,
+            self.device_fd()?,
+            replies,
+        )
+    }
+
+    /// Answer the requests of `channel` on the device `fd`, the one it reads.
+    fn serve_channel(
+        server: &Server<Arc<F>>,
+        mut channel: FuseChannel,
+        fd: RawFd,
+        mut replies: Replies,
+    ) -> Result<()> {
+        loop {
+            let (reader, _writer) = match channel.get_request() {
+                Ok(Some(request)) => request,
+                Ok(None) => break,
+                Err(err) if closed_by_unmount(&err) => break,
+                Err(err) => return Err(err.into()),
+            };
+            if let Err(err) = replies.answer(server, fd, reader) {
                 match err {
                     fuse_backend_rs::Error::EncodeMessage(ioe) => {
                         if let Some(libc::EBADF) = ioe.raw_os_error() {
@@ -99,4 +1790,255 @@ impl FuseRuntime {
         }
         Ok(())
     }
+
+    /// Serve with `threads` workers fed through priority [`Lanes`].
+    ///
+    /// The calling thread only receives requests; each one is copied out of
+    /// the channel buffer so it can wait in its lane. One worker is always
+    /// kept free of file reads so metadata requests never queue behind them.
+    /// Interrupts are applied as they arrive, abandoning the request they
+    /// name (see [`gitsnapfs::interrupt`]).
+    fn serve_threaded(self, threads: usize) -> Result<()> {
+        let fd = self.device_fd()?;
+        let bufsize = self.session.bufsize();
+        let lanes: Arc<Lanes<(Vec<u8>, Token)>> = Arc::new(Lanes::new(threads - 1));
+        let interrupts = Arc::new(Interrupts::default());
+        let workers = (0..threads)
+            .map(|index| {
+                let lanes = Arc::clone(&lanes);
+                let interrupts = Arc::clone(&interrupts);
+                let server = Arc::clone(&self.server);
+                let writes = Arc::clone(&self.writes);
+                thread::Builder::new()
+                    .name(format!("gitsnapfs-worker-{index}"))
+                    .stack_size(WORKER_STACK_SIZE)
+                    .spawn(move || {
+                        let mut replies = Replies::new(writes, bufsize);
+                        while let Some((lane, (mut message, token))) = lanes.pop() {
+                            let entered = token.enter();
+                            Self::handle_copied(&server, fd, &mut message, &mut replies);
+                            drop(entered);
+                            interrupts.answered(&message);
+                            lanes.finish(lane);
+                        }
+                    })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut channel = self.session.new_channel()?;
+        let received = loop {
+            match channel.get_request() {
+                Ok(Some((mut reader, _writer))) => {
+                    let mut message = vec![0u8; reader.available_bytes()];
+                    if let Err(err) = reader.read_exact(&mut message) {
+                        error!(?err, "copying FUSE request failed");
+                        continue;
+                    }
+                    // Interrupts take effect here rather than waiting in a lane.
+                    if let Some(token) = interrupts.received(&message) {
+                        lanes.push(Lane::for_request(&message), (message, token));
+                    }
+                }
+                Ok(None) => break Ok(()),
+                Err(err) if closed_by_unmount(&err) => break Ok(()),
+                Err(err) => break Err(err.into()),
+            }
+        };
+        lanes.close();
+        for worker in workers {
+            let _ = worker.join();
+        }
+        received
+    }
+
+    /// Serve with one worker per CPU the process may run on, each with its
+    /// own clone of the FUSE device: it reads requests from its clone and
+    /// answers them there, so workers do not contend for one channel and
+    /// small requests are served in parallel. There are no priority lanes;
+    /// a worker busy with a read holds up only the requests it takes next.
+    /// With `pin`, each worker runs only on its CPU.
+    fn serve_per_cpu(self, pin: bool) -> Result<()> {
+        let bufsize = self.session.bufsize();
+        let interrupts = Arc::new(Interrupts::default());
+        let workers = allowed_cpus()?
+            .into_iter()
+            .map(|cpu| {
+                let device = self
+                    .session
+                    .clone_fuse_file()
+                    .context("failed to clone the FUSE device")?;
+                let interrupts = Arc::clone(&interrupts);
+                let server = Arc::clone(&self.server);
+                let replies = Replies::new(Arc::clone(&self.writes), bufsize);
+                let worker = thread::Builder::new()
+                    .name(format!("gitsnapfs-cpu-{cpu}"))
+                    .stack_size(WORKER_STACK_SIZE)
+                    .spawn(move || {
+                        if pin {
+                            if let Err(err) = pin_to_cpu(cpu) {
+                                warn!("cannot pin worker to CPU {cpu}: {err}");
+                            }
+                        }
+                        Self::serve_device(&server, &interrupts, &device, replies)
+                    })?;
+                Ok(worker)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut served = Ok(());
+        for worker in workers {
+            match worker.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => {
+                    error!(?err, "reading FUSE requests failed");
+                    served = Err(err.into());
+                }
+                Err(_) => {
+                    error!("a FUSE worker panicked");
+                    served = Err(anyhow::anyhow!("a FUSE worker panicked"));
+                }
+            }
+        }
+        served
+    }
+
+    /// Answer requests read from `device` until the filesystem is unmounted.
+    fn serve_device(
+        server: &Server<Arc<F>>,
+        interrupts: &Interrupts,
+        device: &File,
+        mut replies: Replies,
+    ) -> io::Result<()> {
+        let mut message = vec![0u8; replies.buffer.len()];
+        loop {
+            let len = match (&*device).read(&mut message) {
+                Ok(len) => len,
+                // ENOENT: the request was interrupted before it was read.
+                Err(err)
+                    if matches!(
+                        err.raw_os_error(),
+                        Some(libc::EINTR | libc::EAGAIN | libc::ENOENT)
+                    ) =>
+                {
+                    continue
+                }
+                Err(err) if err.raw_os_error() == Some(libc::ENODEV) => return Ok(()),
+                Err(err) => return Err(err),
+            };
+            let message = &mut message[..len];
+            if let Some(token) = interrupts.received(message) {
+                let entered = token.enter();
+                Self::handle_copied(server, device.as_raw_fd(), message, &mut replies);
+                drop(entered);
+                interrupts.answered(message);
+            }
+        }
+    }
+
+    fn handle_copied(
+        server: &Server<Arc<F>>,
+        fd: RawFd,
+        message: &mut [u8],
+        replies: &mut Replies,
+    ) {
+        let reader = match Reader::<()>::from_fuse_buffer(FuseBuf::new(message)) {
+            Ok(reader) => reader,
+            Err(err) => {
+                error!(?err, "wrapping FUSE request failed");
+                return;
+            }
+        };
+        if let Err(err) = replies.answer(server, fd, reader) {
+            error!(?err, "handling FUSE message failed");
+        }
+    }
+}
+
+#[cfg(feature = "async-io")]
+impl FuseRuntime<GitSnapFs> {
+    /// Serve requests to `fs` as tasks on a runtime with `workers` threads
+    /// (`--async-runtime`); see [`gitsnapfs::async_io`].
+    fn serve_async(self, fs: &Arc<GitSnapFs>, workers: u16) -> Result<()> {
+        let device = self
+            .session
+            .get_fuse_file()
+            .context("FUSE session has no device file")?;
+        gitsnapfs::async_io::serve(
+            fs,
+            Arc::clone(&self.server),
+            device,
+            self.session.bufsize(),
+            usize::from(workers),
+            Arc::clone(&self.writes),
+        )?;
+        Ok(())
+    }
+}
+
+/// What a serving thread answers requests with: its reply buffer and,
+/// while replies are spliced (`--splice`), its pipe.
+struct Replies {
+    writes: Arc<Writes>,
+    buffer: Vec<u8>,
+    pipe: Option<ReplyPipe>,
+}
+
+impl Replies {
+    fn new(writes: Arc<Writes>, bufsize: usize) -> Self {
+        let pipe = if writes.splicing() {
+            ReplyPipe::new(bufsize)
+                .inspect_err(|err| writes.fall_back(err))
+                .ok()
+        } else {
+            None
+        };
+        Self {
+            writes,
+            buffer: vec![0u8; bufsize],
+            pipe,
+        }
+    }
+
+    /// Handle the request in `reader` and send the reply to the device `fd`.
+    fn answer<F>(
+        &mut self,
+        server: &Server<Arc<F>>,
+        fd: RawFd,
+        reader: Reader<'_>,
+    ) -> fuse_backend_rs::Result<()>
+    where
+        F: FileSystem<Inode = u64, Handle = u64> + Send + Sync,
+    {
+        let started = Instant::now();
+        let pipe = self.pipe.as_mut().filter(|_| self.writes.splicing());
+        let device = pipe.as_ref().map_or(fd, |pipe| pipe.input());
+        let writer = FuseDevWriter::new(device, &mut self.buffer)
+            .map_err(|err| fuse_backend_rs::Error::EncodeMessage(io::Error::other(err)))?;
+        let handled = server.handle_message(reader, writer.into(), None, None);
+        let Some(pipe) = pipe else {
+            let len = handled?;
+            if len > 0 {
+                self.writes.record(false, len, started.elapsed());
+            }
+            return Ok(());
+        };
+        if let Err(err) = handled {
+            // Whatever part of a reply made it into the pipe is useless.
+            let _ = pipe.drain();
+            return Err(err);
+        }
+        match pipe.splice_into(fd) {
+            Ok(0) => {}
+            Ok(len) => self.writes.record(true, len, started.elapsed()),
+            Err(err) => {
+                self.writes.fall_back(&err);
+                let reply = pipe.drain();
+                // SAFETY: the device stays open while requests are served.
+                let device = unsafe { BorrowedFd::borrow_raw(fd) };
+                nix::unistd::write(device, &reply)
+                    .map_err(|err| fuse_backend_rs::Error::EncodeMessage(err.into()))?;
+                self.writes.record(false, reply.len(), started.elapsed());
+            }
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,236 @@
+//! Copying a subtree of a mount to disk, for `gitsnapfs materialize`.
+//!
+//! `cp -r` through the mount pays a FUSE round trip for every lookup,
+//! getattr, open and 128 KiB read. [`materialize`] instead walks the same
+//! hierarchy in-process through a [`Vfs`] opened on the repository behind
+//! the mount, and writes each file from the object database with a single
+//! write, spreading files and directories over several threads.
+//!
+//! [`locate`] maps a path inside a mount to the mount root and the path
+//! below it, checking that the mount is a gitsnapfs one. Callers should
+//! compare the inode the mount reports with the one the [`Vfs`] resolves:
+//! they differ when the repository or the settings shaping the snapshot do
+//! not match the mount's.
+//!
+//! Files and directories keep their permission bits, made writable by the
+//! owner so the copy can be worked in; symlinks are recreated as they are.
+
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+use anyhow::{bail, Context, Result};
+
+use crate::meta::META_DIR_NAME;
+use crate::mount;
+use crate::vfs::{Node, NodeKind, Vfs};
+
+/// What [`materialize`] wrote.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Totals {
+    pub files: u64,
+    pub dirs: u64,
+    pub symlinks: u64,
+    pub bytes: u64,
+}
+
+/// The root of the gitsnapfs mount holding `path`, and `path` relative to
+/// it with symlinks inside the mount resolved.
+///
+/// # Errors
+///
+/// Returns an error if `path` does not exist or is not inside a gitsnapfs
+/// mount.
+pub fn locate(path: &Path) -> Result<(PathBuf, PathBuf)> {
+    let path =
+        fs::canonicalize(path).with_context(|| format!("failed to resolve {}", path.display()))?;
+    let dev = fs::metadata(&path)?.dev();
+    let mut root = path.as_path();
+    while let Some(parent) = root.parent() {
+        if fs::metadata(parent)?.dev() != dev {
+            break;
+        }
+        root = parent;
+    }
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")
+        .context("failed to read /proc/self/mountinfo")?;
+    if !mountinfo
+        .lines()
+        .any(|line| mount::is_gitsnapfs_mount(line, root, mount::DEFAULT_NAME))
+    {
+        bail!("{} is not inside a gitsnapfs mount", path.display());
+    }
+    let inside = path
+        .strip_prefix(root)
+        .unwrap_or(Path::new(""))
+        .to_path_buf();
+    Ok((root.to_path_buf(), inside))
+}
+
+/// Copy the directory `source` of `vfs` to the new directory `dest` using
+/// `jobs` threads. A `.git-meta` directory directly in `source` is left
+/// out, as it describes the commit and links outside the copy.
+///
+/// # Errors
+///
+/// Returns the first error met: a node that cannot be read (for example a
+/// file above `--max-file-size`), `dest` existing already, or a write that
+/// fails. What was copied until then is left in place.
+pub fn materialize(vfs: &Vfs, source: &Node, dest: &Path, jobs: usize) -> Result<Totals> {
+    if source.kind != NodeKind::Directory {
+        bail!("only directories can be materialized");
+    }
+    let queue = Queue {
+        state: Mutex::new(QueueState {
+            jobs: vec![(*source, dest.to_path_buf())],
+            pending: 1,
+            failure: None,
+        }),
+        changed: Condvar::new(),
+    };
+    let totals = Counters::default();
+    thread::scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            scope
+                .spawn(|| queue.work(|node, path| copy(vfs, node, &path, source, &queue, &totals)));
+        }
+    });
+    let state = queue
+        .state
+        .into_inner()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    if let Some(err) = state.failure {
+        return Err(err);
+    }
+    Ok(Totals {
+        files: totals.files.into_inner(),
+        dirs: totals.dirs.into_inner(),
+        symlinks: totals.symlinks.into_inner(),
+        bytes: totals.bytes.into_inner(),
+    })
+}
+
+#[derive(Default)]
+struct Counters {
+    files: AtomicU64,
+    dirs: AtomicU64,
+    symlinks: AtomicU64,
+    bytes: AtomicU64,
+}
+
+/// Nodes still to copy, shared by the worker threads.
+struct Queue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+}
+
+struct QueueState {
+    jobs: Vec<(Node, PathBuf)>,
+    /// Jobs queued or being worked on; the copy is done when none are.
+    pending: usize,
+    failure: Option<anyhow::Error>,
+}
+
+impl Queue {
+    /// Take jobs and run `copy` on them until every job is done or one failed.
+    fn work(&self, copy: impl Fn(Node, PathBuf) -> Result<()>) {
+        loop {
+            let mut state = self.lock();
+            let (node, path) = loop {
+                if state.failure.is_some() || state.pending == 0 {
+                    return;
+                }
+                if let Some(job) = state.jobs.pop() {
+                    break job;
+                }
+                state = self
+                    .changed
+                    .wait(state)
+                    .unwrap_or_else(std::sync::PoisonError::into_inner);
+            };
+            drop(state);
+            let result = copy(node, path);
+            let mut state = self.lock();
+            state.pending -= 1;
+            if let Err(err) = result {
+                state.failure.get_or_insert(err);
+            }
+            self.changed.notify_all();
+        }
+    }
+
+    fn push(&self, jobs: Vec<(Node, PathBuf)>) {
+        let mut state = self.lock();
+        state.pending += jobs.len();
+        state.jobs.extend(jobs);
+        self.changed.notify_all();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, QueueState> {
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// Copy one node to `path`; a directory's children are queued.
+fn copy(
+    vfs: &Vfs,
+    node: Node,
+    path: &Path,
+    source: &Node,
+    queue: &Queue,
+    totals: &Counters,
+) -> Result<()> {
+    match node.kind {
+        NodeKind::Directory => {
+            DirBuilder::new()
+                .mode(node.mode | 0o700)
+                .create(path)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            let entries = vfs
+                .read_dir(&node)
+                .with_context(|| format!("failed to list {}", path.display()))?;
+            let children = entries
+                .into_iter()
+                .filter(|entry| node != *source || entry.name != META_DIR_NAME)
+                .map(|entry| {
+                    (
+                        entry.node,
+                        path.join(std::ffi::OsStr::from_bytes(&entry.name)),
+                    )
+                })
+                .collect();
+            queue.push(children);
+            totals.dirs.fetch_add(1, Ordering::Relaxed);
+        }
+        NodeKind::File => {
+            let data = vfs
+                .read(&node)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(node.mode | 0o200)
+                .open(path)
+                .and_then(|mut file| file.write_all(&data))
+                .with_context(|| format!("failed to write {}", path.display()))?;
+            totals.files.fetch_add(1, Ordering::Relaxed);
+            totals.bytes.fetch_add(data.len() as u64, Ordering::Relaxed);
+        }
+        NodeKind::Symlink => {
+            let target = vfs
+                .read_link(&node)
+                .with_context(|| format!("failed to read link {}", path.display()))?;
+            std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(&target), path)
+                .with_context(|| format!("failed to create {}", path.display()))?;
+            totals.symlinks.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    Ok(())
+}
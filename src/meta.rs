@@ -0,0 +1,787 @@
+//! Synthetic `.git-meta/` directory exposed at the root of every commit snapshot.
+//!
+//! Unlike tree and blob inodes, these nodes do not correspond to a Git object,
+//! so the filesystem keeps a small registry that maps their synthetic inodes
+//! back to a [`MetaNode`] describing what to render.
+//!
+//! Layout:
+//!
+//! ```text
+//! /commits/<id>/.git-meta/
+//! ├─ DU               disk-usage summary of the snapshot
+//! ├─ MANIFEST         every file with mode, oid and size (`git ls-tree -r -l`)
+//! ├─ commit.json      the commit's headers, message and trailers as one JSON object
+//! ├─ parents/<n>      -> ../../../../commits/<parent-id>  (1-based, in commit order)
+//! ├─ SHALLOW          parents cut off by a shallow clone  (boundary commits only)
+//! └─ conflicts/<path> -> ../../<path>                     (merge commits only)
+//! ```
+//!
+//! The same directory appears under `/branches/<name>/` with
+//! `--branches-as-dirs`; link targets go through `/commits/` so they resolve
+//! from either place.
+//!
+//! `DU` holds one tab-separated line per top-level entry of the commit's tree
+//! (`<bytes>\t<files>\t<name>`, directories with a trailing `/`) followed by
+//! a `.` line with the totals. Sizes are logical blob sizes summed
+//! recursively; submodules count as empty. It describes the commit's tree as
+//! stored, before any `export-ignore` filtering.
+//!
+//! `MANIFEST` has exactly the lines `git ls-tree -r -l <commit>` prints,
+//! including git's quoting of unusual paths, so build caches can hash one
+//! file instead of walking the snapshot. Like `DU` it ignores `export-ignore`.
+//!
+//! `commit.json` holds the id, tree, parents, author and committer (name,
+//! email, Unix time, UTC offset in seconds and an RFC 3339 date), the full
+//! message and its subject, the message trailers in order, the
+//! `Co-authored-by` trailers parsed into name and email, and whether the
+//! commit carries a signature. Text that is not UTF-8 is converted lossily.
+//! Names and emails are the canonical ones the repository's `.mailmap`
+//! gives, unless the mount runs with `--no-mailmap`.
+//!
+//! Commits on the shallow boundary of a shallow clone record parents the
+//! repository does not have. Like git, the snapshot treats them as root
+//! commits: `parents/` is empty and `conflicts/` absent, and `SHALLOW`
+//! lists the recorded parent ids, one per line.
+//!
+//! `conflicts/` lists the paths of a merge result that differ from every
+//! parent. Entry names are the repository paths escaped with
+//! [`crate::names::escape_name`] so nested paths fit into a single flat
+//! directory.
+//!
+//! Submodule placeholders (see [`crate::submodule`]) share the registry as
+//! well, keyed by the pinned commit and their path in the snapshot.
+//!
+//! Per-branch metadata lives in the same registry under `/branches-meta/`:
+//!
+//! ```text
+//! /branches-meta/<branch>/
+//! └─ CHANGELOG        one line per commit since the newest tag on the branch
+//! ```
+//!
+//! Each `<branch>` directory is keyed by the branch tip, so it gets a new
+//! inode whenever the branch moves, like the directories under `/latest/`.
+//! `CHANGELOG` renders every commit reachable from the tip but not from the
+//! newest tagged commit in its history, newest first, one line each from
+//! the `--changelog-template` (placeholders as in [`crate::synth`]).
+//!
+//! Blobs get a small directory of their own under `/objects/`, also kept in
+//! the registry:
+//!
+//! ```text
+//! /objects/<blob-id>/
+//! └─ referenced-by/<commit-id> -> ../../../commits/<commit-id>
+//! ```
+//!
+//! `referenced-by/` lists the commits reachable from the references whose
+//! snapshots contain the blob, answered from a [`crate::reverse::ReverseIndex`].
+//!
+//! Files configured via `--synthetic-files` (see [`crate::synth`]) and the
+//! `--fake-dotgit` `.git` file live in the same registry even though they
+//! appear directly in the snapshot root.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Write as _;
+use std::hash::BuildHasher;
+
+use anyhow::Result;
+use gix::bstr::ByteSlice;
+use gix::mailmap::Snapshot;
+use gix::object::tree::EntryKind;
+use gix::ObjectId;
+use serde::Serialize;
+
+use crate::diff::combined_changes;
+use crate::limits::{Budget, TreeLimits};
+use crate::submodule::Gitlink;
+use crate::synth;
+
+/// Name of the synthetic metadata directory injected into commit roots.
+pub const META_DIR_NAME: &[u8] = b".git-meta";
+
+/// Name of the disk-usage summary inside `.git-meta`.
+pub const DISK_USAGE_NAME: &[u8] = b"DU";
+
+/// Name of the file listing inside `.git-meta`.
+pub const MANIFEST_NAME: &[u8] = b"MANIFEST";
+
+/// Name of the JSON description of the commit inside `.git-meta`.
+pub const COMMIT_JSON_NAME: &[u8] = b"commit.json";
+
+/// Name of the shallow-boundary marker inside `.git-meta`.
+pub const SHALLOW_NAME: &[u8] = b"SHALLOW";
+
+/// Name of the per-branch changelog inside `/branches-meta/<branch>`.
+pub const CHANGELOG_NAME: &[u8] = b"CHANGELOG";
+
+/// Line rendered per commit in `CHANGELOG` unless `--changelog-template` is given.
+pub const DEFAULT_CHANGELOG_TEMPLATE: &str = "* {subject} ({short})";
+
+/// Commits numbered in `/commits-on/<branch>` unless `--history-depth` is given.
+pub const DEFAULT_HISTORY_DEPTH: usize = 100;
+
+/// Name of the synthetic gitdir file injected by `--fake-dotgit`.
+pub const DOTGIT_NAME: &[u8] = b".git";
+
+/// How a [`MetaNode`] is presented to the kernel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetaKind {
+    Dir,
+    Link,
+    File,
+}
+
+/// A synthetic node attached to a commit snapshot, keyed by the commit it describes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MetaNode {
+    /// The `.git-meta` directory itself.
+    Root(ObjectId),
+    /// `.git-meta/parents/`.
+    ParentsDir(ObjectId),
+    /// `.git-meta/parents/<n>`, pointing at the `n`-th parent (1-based).
+    Parent(ObjectId, usize),
+    /// `.git-meta/conflicts/`.
+    ConflictsDir(ObjectId),
+    /// `.git-meta/conflicts/<encoded path>`.
+    Conflict(ObjectId, Vec<u8>),
+    /// The `n`-th configured synthetic file in the snapshot root.
+    Injected(ObjectId, usize),
+    /// The `.git` file in the snapshot root (`--fake-dotgit`).
+    DotGit(ObjectId),
+    /// `.git-meta/DU`.
+    DiskUsage(ObjectId),
+    /// `.git-meta/MANIFEST`.
+    Manifest(ObjectId),
+    /// `.git-meta/commit.json`.
+    CommitJson(ObjectId),
+    /// `.git-meta/SHALLOW`.
+    Shallow(ObjectId),
+    /// Placeholder directory for a submodule without a local checkout.
+    Submodule(Gitlink),
+    /// The `SUBMODULE` file inside a placeholder.
+    SubmoduleInfo(Gitlink),
+    /// A submodule with a checkout attached by `--submodule-path`.
+    SubmoduleCheckout(Gitlink),
+    /// `/branches-meta/<branch>/`, keyed by the branch tip.
+    BranchMeta(ObjectId),
+    /// `/branches-meta/<branch>/CHANGELOG`.
+    Changelog(ObjectId),
+    /// `/commits-on/<branch>/`, keyed by the branch tip.
+    History(ObjectId),
+    /// `/commits-on/<branch>/<n>`, pointing at the tip's `n`-th first-parent
+    /// ancestor (`<tip>~<n>`).
+    Ancestor(ObjectId, usize),
+    /// `/objects/<blob>/`.
+    ObjectDir(ObjectId),
+    /// `/objects/<blob>/referenced-by/`.
+    ReferencedBy(ObjectId),
+    /// `/objects/<blob>/referenced-by/<commit>`, keyed by blob and commit.
+    Referrer(ObjectId, ObjectId),
+}
+
+impl MetaNode {
+    /// Commit this node belongs to, or the blob for nodes under `/objects/`.
+    #[must_use]
+    pub fn commit(&self) -> ObjectId {
+        match self {
+            MetaNode::Root(id)
+            | MetaNode::ParentsDir(id)
+            | MetaNode::Parent(id, _)
+            | MetaNode::ConflictsDir(id)
+            | MetaNode::Conflict(id, _)
+            | MetaNode::Injected(id, _)
+            | MetaNode::DotGit(id)
+            | MetaNode::DiskUsage(id)
+            | MetaNode::Manifest(id)
+            | MetaNode::CommitJson(id)
+            | MetaNode::Shallow(id)
+            | MetaNode::BranchMeta(id)
+            | MetaNode::Changelog(id)
+            | MetaNode::History(id)
+            | MetaNode::Ancestor(id, _)
+            | MetaNode::ObjectDir(id)
+            | MetaNode::ReferencedBy(id)
+            | MetaNode::Referrer(id, _) => *id,
+            MetaNode::Submodule(gitlink)
+            | MetaNode::SubmoduleInfo(gitlink)
+            | MetaNode::SubmoduleCheckout(gitlink) => gitlink.commit,
+        }
+    }
+
+    /// How the node is rendered.
+    #[must_use]
+    pub fn kind(&self) -> MetaKind {
+        match self {
+            MetaNode::Root(_)
+            | MetaNode::ParentsDir(_)
+            | MetaNode::ConflictsDir(_)
+            | MetaNode::Submodule(_)
+            | MetaNode::BranchMeta(_)
+            | MetaNode::History(_)
+            | MetaNode::ObjectDir(_)
+            | MetaNode::ReferencedBy(_) => MetaKind::Dir,
+            MetaNode::Parent(..)
+            | MetaNode::Ancestor(..)
+            | MetaNode::Conflict(..)
+            | MetaNode::SubmoduleCheckout(_)
+            | MetaNode::Referrer(..) => MetaKind::Link,
+            MetaNode::Injected(..)
+            | MetaNode::DotGit(_)
+            | MetaNode::DiskUsage(_)
+            | MetaNode::Manifest(_)
+            | MetaNode::CommitJson(_)
+            | MetaNode::Shallow(_)
+            | MetaNode::SubmoduleInfo(_)
+            | MetaNode::Changelog(_) => MetaKind::File,
+        }
+    }
+
+    /// Stable key used to derive the node's synthetic inode.
+    #[must_use]
+    pub fn key(&self) -> Vec<u8> {
+        let mut key = self.commit().as_bytes().to_vec();
+        match self {
+            MetaNode::Root(_) => key.push(0),
+            MetaNode::ParentsDir(_) => key.push(1),
+            MetaNode::Parent(_, index) => {
+                key.push(2);
+                key.extend_from_slice(&index.to_be_bytes());
+            }
+            MetaNode::ConflictsDir(_) => key.push(3),
+            MetaNode::Conflict(_, path) => {
+                key.push(4);
+                key.extend_from_slice(path);
+            }
+            MetaNode::Injected(_, index) => {
+                key.push(5);
+                key.extend_from_slice(&index.to_be_bytes());
+            }
+            MetaNode::DotGit(_) => key.push(6),
+            MetaNode::DiskUsage(_) => key.push(7),
+            MetaNode::Submodule(gitlink) => push_gitlink(&mut key, 8, gitlink),
+            MetaNode::SubmoduleInfo(gitlink) => push_gitlink(&mut key, 9, gitlink),
+            MetaNode::SubmoduleCheckout(gitlink) => push_gitlink(&mut key, 10, gitlink),
+            MetaNode::Manifest(_) => key.push(11),
+            MetaNode::BranchMeta(_) => key.push(12),
+            MetaNode::Changelog(_) => key.push(13),
+            MetaNode::CommitJson(_) => key.push(14),
+            MetaNode::ObjectDir(_) => key.push(15),
+            MetaNode::ReferencedBy(_) => key.push(16),
+            MetaNode::Referrer(_, commit) => {
+                key.push(17);
+                key.extend_from_slice(commit.as_bytes());
+            }
+            MetaNode::Shallow(_) => key.push(18),
+            MetaNode::History(_) => key.push(19),
+            MetaNode::Ancestor(_, n) => {
+                key.push(20);
+                key.extend_from_slice(&n.to_be_bytes());
+            }
+        }
+        key
+    }
+}
+
+fn push_gitlink(key: &mut Vec<u8>, tag: u8, gitlink: &Gitlink) {
+    key.push(tag);
+    if let Some(root) = gitlink.root {
+        key.extend_from_slice(root.as_bytes());
+    }
+    key.extend_from_slice(&gitlink.path);
+}
+
+/// The `CHANGELOG` of a branch at `tip`: every commit since the newest of
+/// the `tagged` commits in its history (all of history if there is none),
+/// newest first, each rendered from `template` on a line of its own. With
+/// `first_parent`, only the first-parent history is listed and searched
+/// for a tag.
+///
+/// # Errors
+///
+/// Returns an error if the history cannot be walked or the template uses an
+/// unknown placeholder.
+pub fn changelog<S: BuildHasher>(
+    repo: &gix::Repository,
+    tip: ObjectId,
+    tagged: &HashSet<ObjectId, S>,
+    template: &str,
+    mailmap: Option<&Snapshot>,
+    first_parent: bool,
+) -> Result<Vec<u8>> {
+    let newest_first = || {
+        let walk = repo
+            .rev_walk([tip])
+            .sorting(gix::revision::walk::Sorting::ByCommitTime(
+                gix::traverse::commit::simple::CommitTimeOrder::NewestFirst,
+            ));
+        if first_parent {
+            walk.first_parent_only()
+        } else {
+            walk
+        }
+    };
+    let mut base = None;
+    for info in newest_first().all()? {
+        let id = info?.id;
+        if tagged.contains(&id) {
+            base = Some(id);
+            break;
+        }
+    }
+    let template = synth::Template::parse(template)?;
+    let mut out = Vec::new();
+    for info in newest_first().with_hidden(base).all()? {
+        out.extend(template.render(repo, info?.id, mailmap)?);
+        out.push(b'\n');
+    }
+    Ok(out)
+}
+
+/// Parent commit ids of `commit`, in the order recorded in the commit
+/// object; none if it is on the shallow boundary, as its parents are missing.
+///
+/// # Errors
+///
+/// Returns an error if the commit cannot be read or decoded.
+pub fn parent_ids(repo: &gix::Repository, commit: ObjectId) -> Result<Vec<ObjectId>> {
+    if crate::repo::is_shallow_boundary(repo, commit) {
+        return Ok(Vec::new());
+    }
+    recorded_parent_ids(repo, commit)
+}
+
+/// `tip` and its first-parent ancestors, `tip~1`, `tip~2` and so on, at most
+/// `depth` of them; fewer if the chain reaches a root or the shallow boundary.
+///
+/// # Errors
+///
+/// Returns an error if a commit along the chain cannot be read or decoded.
+pub fn first_parents(repo: &gix::Repository, tip: ObjectId, depth: usize) -> Result<Vec<ObjectId>> {
+    let mut chain = Vec::new();
+    let mut next = Some(tip);
+    while let Some(commit) = next.filter(|_| chain.len() < depth) {
+        chain.push(commit);
+        next = parent_ids(repo, commit)?.first().copied();
+    }
+    Ok(chain)
+}
+
+/// The content of `SHALLOW` for a commit on the shallow boundary: the
+/// parent ids the commit records but the repository lacks, one per line.
+///
+/// # Errors
+///
+/// Returns an error if the commit cannot be read or decoded.
+pub fn shallow_marker(repo: &gix::Repository, commit: ObjectId) -> Result<Vec<u8>> {
+    let mut out = String::new();
+    for parent in recorded_parent_ids(repo, commit)? {
+        let _ = writeln!(out, "{parent}");
+    }
+    Ok(out.into_bytes())
+}
+
+fn recorded_parent_ids(repo: &gix::Repository, commit: ObjectId) -> Result<Vec<ObjectId>> {
+    let commit = repo.find_commit(commit)?;
+    Ok(commit.parent_ids().map(gix::Id::detach).collect())
+}
+
+/// Paths of the merge `commit` that differ from all of its parents.
+///
+/// Returns an empty set for commits with fewer than two parents.
+///
+/// # Errors
+///
+/// Returns an error if the commit or any involved tree cannot be read.
+pub fn conflict_paths(
+    repo: &gix::Repository,
+    commit: ObjectId,
+    limits: TreeLimits,
+) -> Result<BTreeSet<Vec<u8>>> {
+    let parents = parent_ids(repo, commit)?;
+    if parents.len() < 2 {
+        return Ok(BTreeSet::new());
+    }
+    let result_tree = repo.find_commit(commit)?.tree_id()?.detach();
+    let parent_trees = parents
+        .into_iter()
+        .map(|parent| Ok(repo.find_commit(parent)?.tree_id()?.detach()))
+        .collect::<Result<Vec<_>>>()?;
+    combined_changes(repo, &parent_trees, result_tree, limits)
+}
+
+/// Logical size of a tree, summed over every blob below it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    /// Total blob bytes.
+    pub bytes: u64,
+    /// Number of blobs (regular files and symlinks).
+    pub files: u64,
+}
+
+impl Usage {
+    fn add(&mut self, other: Usage) {
+        self.bytes += other.bytes;
+        self.files += other.files;
+    }
+}
+
+/// A walk measuring trees, consulting the usages already `known` and
+/// recording the ones it measures in `fresh`.
+struct Measure<'a> {
+    repo: &'a gix::Repository,
+    known: &'a dyn Fn(&ObjectId) -> Option<Usage>,
+    fresh: HashMap<ObjectId, Usage>,
+    budget: Budget,
+}
+
+impl Measure<'_> {
+    fn tree(&mut self, tree: ObjectId, depth: usize) -> Result<Usage> {
+        if let Some(usage) = self
+            .fresh
+            .get(&tree)
+            .copied()
+            .or_else(|| (self.known)(&tree))
+        {
+            return Ok(usage);
+        }
+        self.budget.descend(depth)?;
+        let mut usage = Usage::default();
+        for entry in self.repo.find_tree(tree)?.iter() {
+            let entry = entry?;
+            usage.add(self.entry(entry.inner.mode.kind(), entry.inner.oid.to_owned(), depth)?);
+        }
+        self.fresh.insert(tree, usage);
+        Ok(usage)
+    }
+
+    fn entry(&mut self, kind: EntryKind, oid: ObjectId, depth: usize) -> Result<Usage> {
+        self.budget.visit()?;
+        Ok(match kind {
+            EntryKind::Tree => self.tree(oid, depth + 1)?,
+            EntryKind::Blob | EntryKind::BlobExecutable | EntryKind::Link => Usage {
+                bytes: self.repo.find_header(oid)?.size(),
+                files: 1,
+            },
+            EntryKind::Commit => Usage::default(),
+        })
+    }
+}
+
+/// Render the `DU` summary for `commit`, reusing the usages `known` returns
+/// and returning the ones measured to add to them.
+///
+/// # Errors
+///
+/// Returns an error if the commit or any object below its tree cannot be
+/// read, or measuring the trees not `known` yet exceeds `limits`.
+pub fn disk_usage(
+    repo: &gix::Repository,
+    commit: ObjectId,
+    known: &dyn Fn(&ObjectId) -> Option<Usage>,
+    limits: TreeLimits,
+) -> Result<(Vec<u8>, HashMap<ObjectId, Usage>)> {
+    let tree = repo.find_commit(commit)?.tree_id()?.detach();
+    let mut measure = Measure {
+        repo,
+        known,
+        fresh: HashMap::new(),
+        budget: Budget::new(limits),
+    };
+    let mut out = String::new();
+    let mut total = Usage::default();
+    for entry in repo.find_tree(tree)?.iter() {
+        let entry = entry?;
+        let kind = entry.inner.mode.kind();
+        let usage = measure.entry(kind, entry.inner.oid.to_owned(), 0)?;
+        total.add(usage);
+        let suffix = if kind == EntryKind::Tree { "/" } else { "" };
+        let _ = writeln!(
+            out,
+            "{}\t{}\t{}{suffix}",
+            usage.bytes, usage.files, entry.inner.filename
+        );
+    }
+    measure.fresh.insert(tree, total);
+    let _ = writeln!(out, "{}\t{}\t.", total.bytes, total.files);
+    Ok((out.into_bytes(), measure.fresh))
+}
+
+#[derive(Serialize)]
+struct CommitJson {
+    id: String,
+    tree: String,
+    parents: Vec<String>,
+    author: Person,
+    committer: Person,
+    subject: String,
+    message: String,
+    trailers: Vec<Trailer>,
+    co_authors: Vec<Identity>,
+    signed: bool,
+}
+
+#[derive(Serialize)]
+struct Person {
+    name: String,
+    email: String,
+    time: i64,
+    offset: i32,
+    date: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Trailer {
+    key: String,
+    value: String,
+}
+
+#[derive(Serialize)]
+struct Identity {
+    name: String,
+    email: String,
+}
+
+impl Person {
+    fn new(signature: gix::actor::SignatureRef<'_>, mailmap: Option<&Snapshot>) -> Self {
+        let time = signature.time().unwrap_or_default();
+        let signature = synth::mapped(signature, mailmap);
+        // RFC 3339 cannot express offsets with a seconds part; those get no date.
+        let date = time::UtcOffset::from_whole_seconds(time.offset)
+            .ok()
+            .and_then(|offset| {
+                time::OffsetDateTime::from_unix_timestamp(time.seconds)
+                    .ok()?
+                    .to_offset(offset)
+                    .format(&time::format_description::well_known::Rfc3339)
+                    .ok()
+            });
+        Self {
+            name: signature.name.to_str_lossy().into_owned(),
+            email: signature.email.to_str_lossy().into_owned(),
+            time: time.seconds,
+            offset: time.offset,
+            date,
+        }
+    }
+}
+
+/// Split a `Name <email>` trailer value; `None` without an email.
+fn parse_identity(value: &str, mailmap: Option<&Snapshot>) -> Option<Identity> {
+    let (name, rest) = value.split_once('<')?;
+    let (email, _) = rest.split_once('>')?;
+    let signature = gix::actor::SignatureRef {
+        name: name.trim().into(),
+        email: email.trim().into(),
+        time: "",
+    };
+    let signature = synth::mapped(signature, mailmap);
+    Some(Identity {
+        name: signature.name.to_str_lossy().into_owned(),
+        email: signature.email.to_str_lossy().into_owned(),
+    })
+}
+
+/// Render `.git-meta/commit.json` for `commit`, naming people as `mailmap`
+/// maps them.
+///
+/// # Errors
+///
+/// Returns an error if the commit cannot be read or decoded.
+pub fn commit_json(
+    repo: &gix::Repository,
+    commit: ObjectId,
+    mailmap: Option<&Snapshot>,
+) -> Result<Vec<u8>> {
+    let object = repo.find_commit(commit)?;
+    let decoded = object.decode()?;
+    let message = decoded.message();
+    let trailers: Vec<Trailer> = message
+        .body()
+        .map(|body| {
+            body.trailers()
+                .map(|trailer| Trailer {
+                    key: trailer.token.to_str_lossy().into_owned(),
+                    value: trailer.value.to_str_lossy().into_owned(),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let co_authors = trailers
+        .iter()
+        .filter(|trailer| trailer.key.eq_ignore_ascii_case("co-authored-by"))
+        .filter_map(|trailer| parse_identity(&trailer.value, mailmap))
+        .collect();
+    let signed = decoded
+        .extra_headers
+        .iter()
+        .any(|(name, _)| *name == "gpgsig" || *name == "gpgsig-sha256");
+    let json = CommitJson {
+        id: commit.to_string(),
+        tree: decoded.tree().to_string(),
+        parents: decoded.parents().map(|id| id.to_string()).collect(),
+        author: Person::new(decoded.author(), mailmap),
+        committer: Person::new(decoded.committer(), mailmap),
+        subject: message.summary().to_str_lossy().into_owned(),
+        message: decoded.message.to_str_lossy().into_owned(),
+        trailers,
+        co_authors,
+        signed,
+    };
+    let mut out = serde_json::to_vec_pretty(&json)?;
+    out.push(b'\n');
+    Ok(out)
+}
+
+/// Render the `MANIFEST` listing for `commit`.
+///
+/// # Errors
+///
+/// Returns an error if the commit or any object below its tree cannot be
+/// read, or the listing exceeds `limits`.
+pub fn manifest(repo: &gix::Repository, commit: ObjectId, limits: TreeLimits) -> Result<Vec<u8>> {
+    let tree = repo.find_commit(commit)?.tree_id()?.detach();
+    let mut out = Vec::new();
+    manifest_tree(
+        repo,
+        tree,
+        &mut Vec::new(),
+        0,
+        &mut Budget::new(limits),
+        &mut out,
+    )?;
+    Ok(out)
+}
+
+fn manifest_tree(
+    repo: &gix::Repository,
+    tree: ObjectId,
+    prefix: &mut Vec<u8>,
+    depth: usize,
+    budget: &mut Budget,
+    out: &mut Vec<u8>,
+) -> Result<()> {
+    budget.descend(depth)?;
+    for entry in repo.find_tree(tree)?.iter() {
+        let entry = entry?;
+        budget.visit()?;
+        let oid = entry.inner.oid.to_owned();
+        let prefix_len = prefix.len();
+        prefix.extend_from_slice(entry.inner.filename);
+        let (mode, size) = match entry.inner.mode.kind() {
+            EntryKind::Tree => {
+                prefix.push(b'/');
+                manifest_tree(repo, oid, prefix, depth + 1, budget, out)?;
+                prefix.truncate(prefix_len);
+                continue;
+            }
+            EntryKind::Commit => ("160000 commit", "-".to_owned()),
+            kind => {
+                let mode = match kind {
+                    EntryKind::BlobExecutable => "100755 blob",
+                    EntryKind::Link => "120000 blob",
+                    _ => "100644 blob",
+                };
+                (mode, repo.find_header(oid)?.size().to_string())
+            }
+        };
+        out.extend_from_slice(format!("{mode} {oid} {size:>7}\t").as_bytes());
+        quote_path(prefix, out);
+        out.push(b'\n');
+        prefix.truncate(prefix_len);
+    }
+    Ok(())
+}
+
+/// Append `path` quoted the way git does with `core.quotePath` enabled.
+pub(crate) fn quote_path(path: &[u8], out: &mut Vec<u8>) {
+    let needs_quotes = path
+        .iter()
+        .any(|&byte| !(0x20..0x7f).contains(&byte) || byte == b'"' || byte == b'\\');
+    if !needs_quotes {
+        out.extend_from_slice(path);
+        return;
+    }
+    out.push(b'"');
+    for &byte in path {
+        match byte {
+            0x07 => out.extend_from_slice(b"\\a"),
+            0x08 => out.extend_from_slice(b"\\b"),
+            b'\t' => out.extend_from_slice(b"\\t"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            0x0b => out.extend_from_slice(b"\\v"),
+            0x0c => out.extend_from_slice(b"\\f"),
+            b'\r' => out.extend_from_slice(b"\\r"),
+            b'"' | b'\\' => out.extend_from_slice(&[b'\\', byte]),
+            0x20..=0x7e => out.push(byte),
+            _ => out.extend_from_slice(format!("\\{byte:03o}").as_bytes()),
+        }
+    }
+    out.push(b'"');
+}
+
+/// Symlink target for `node`, if it is a link. Commit ids are shortened to
+/// `abbrev` digits, if given (see [`crate::repo::abbreviate`]).
+#[must_use]
+pub fn link_target(
+    repo: &gix::Repository,
+    node: &MetaNode,
+    abbrev: Option<usize>,
+) -> Option<Vec<u8>> {
+    let name = |id: ObjectId| match abbrev {
+        Some(len) => crate::repo::abbreviate(repo, id, len),
+        None => id.to_string(),
+    };
+    match node {
+        MetaNode::Parent(commit, index) => {
+            let parents = parent_ids(repo, *commit).ok()?;
+            let parent = name(*parents.get(index.checked_sub(1)?)?);
+            Some(format!("../../../../commits/{parent}").into_bytes())
+        }
+        MetaNode::Ancestor(tip, n) => {
+            let ancestor = *first_parents(repo, *tip, n + 1).ok()?.get(*n)?;
+            Some(format!("../../commits/{}", name(ancestor)).into_bytes())
+        }
+        MetaNode::Conflict(_, path) => {
+            let mut target = b"../../".to_vec();
+            target.extend_from_slice(path);
+            Some(target)
+        }
+        MetaNode::Referrer(_, commit) => {
+            Some(format!("../../../commits/{}", name(*commit)).into_bytes())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_paths_like_git() {
+        let quoted = |path: &[u8]| {
+            let mut out = Vec::new();
+            quote_path(path, &mut out);
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(quoted(b"src/main.rs"), "src/main.rs");
+        assert_eq!(quoted(b"a b"), "a b");
+        assert_eq!(quoted(b"tab\there"), "\"tab\\there\"");
+        assert_eq!(quoted(b"say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(quoted("caf\u{e9}".as_bytes()), "\"caf\\303\\251\"");
+    }
+    #[test]
+    fn parses_co_author_identities() {
+        let identity = parse_identity(" Bob Smith <bob@example.com> ", None).unwrap();
+        assert_eq!(identity.name, "Bob Smith");
+        assert_eq!(identity.email, "bob@example.com");
+        assert!(parse_identity("Bob Smith", None).is_none());
+        assert!(parse_identity("Bob <unterminated", None).is_none());
+
+        let mailmap = Snapshot::from_bytes(b"Robert Smith <rob@example.com> <bob@example.com>\n");
+        let identity = parse_identity("Bob Smith <bob@example.com>", Some(&mailmap)).unwrap();
+        assert_eq!(identity.name, "Robert Smith");
+        assert_eq!(identity.email, "rob@example.com");
+        let identity = parse_identity("Al <al@example.com>", Some(&mailmap)).unwrap();
+        assert_eq!(identity.name, "Al");
+    }
+}
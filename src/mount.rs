@@ -0,0 +1,403 @@
+//! Mount preparation for unprivileged and containerised environments.
+//!
+//! Outside of the host's initial namespaces the usual FUSE plumbing tends to
+//! fail in unhelpful ways: `/dev/fuse` is missing or not accessible, `PATH` is
+//! stripped down, or only the old `fusermount` binary is installed. This
+//! module checks those prerequisites up front and explains what is missing,
+//! and finds a usable `fusermount` helper. When none exists but the process
+//! is root inside its user namespace (as in rootless containers), the mount
+//! can still be done directly with `mount(2)`.
+
+use std::ffi::OsString;
+use std::fs::{DirBuilder, OpenOptions};
+use std::io;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use gix::bstr::ByteSlice;
+
+/// Device node every FUSE mount needs.
+const FUSE_DEVICE: &str = "/dev/fuse";
+
+/// Default source (`--fsname`) and subtype (`--subtype`) of gitsnapfs
+/// mounts; the filesystem type is `fuse.<subtype>`.
+pub const DEFAULT_NAME: &str = "gitsnapfs";
+
+/// Helper binaries to look for, most recent first.
+const FUSERMOUNT_NAMES: &[&str] = &["fusermount3", "fusermount"];
+
+/// Directories searched when the helper is not on `PATH`.
+const FALLBACK_DIRS: &[&str] = &["/usr/bin", "/bin", "/usr/local/bin", "/usr/sbin", "/sbin"];
+
+/// Check that `/dev/fuse` can be opened and `mountpoint` is a directory.
+///
+/// # Errors
+///
+/// Returns an error describing the missing prerequisite and how to provide it.
+pub fn preflight(mountpoint: &Path) -> Result<()> {
+    if let Err(err) = OpenOptions::new().read(true).write(true).open(FUSE_DEVICE) {
+        let hint = match err.kind() {
+            io::ErrorKind::NotFound => {
+                "the FUSE device is not available here; load the `fuse` kernel module, \
+                 or start the container with `--device /dev/fuse`"
+            }
+            io::ErrorKind::PermissionDenied => {
+                "the FUSE device is not accessible to this user; check its permissions, \
+                 and the container's device cgroup (e.g. `--device /dev/fuse` with podman/docker)"
+            }
+            _ => "the FUSE device could not be opened",
+        };
+        bail!("cannot open {FUSE_DEVICE}: {err}; {hint}");
+    }
+    let meta = mountpoint
+        .metadata()
+        .with_context(|| format!("mount point {} is not accessible", mountpoint.display()))?;
+    if !meta.is_dir() {
+        bail!("mount point {} is not a directory", mountpoint.display());
+    }
+    Ok(())
+}
+
+/// Create the directory `mountpoint` with permissions `mode`, and any
+/// missing parents, unless it exists; whether it was created.
+///
+/// # Errors
+///
+/// Returns an error if the directory cannot be created.
+pub fn create_mountpoint(mountpoint: &Path, mode: u32) -> Result<bool> {
+    if mountpoint.symlink_metadata().is_ok() {
+        return Ok(false);
+    }
+    match DirBuilder::new()
+        .recursive(true)
+        .mode(mode)
+        .create(mountpoint)
+    {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => return Ok(false),
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("failed to create mount point {}", mountpoint.display()))
+        }
+    }
+    // The umask narrows the mode given at creation; the caller asked for this one.
+    std::fs::set_permissions(mountpoint, std::fs::Permissions::from_mode(mode)).with_context(
+        || {
+            format!(
+                "failed to set the mode of mount point {}",
+                mountpoint.display()
+            )
+        },
+    )?;
+    Ok(true)
+}
+
+/// Parse a `--fsname` or `--subtype`, which must not be empty and may not
+/// hold whitespace, control characters or the commas separating mount
+/// options.
+///
+/// # Errors
+///
+/// Returns an error if `input` is not usable as such a name.
+pub fn parse_mount_name(input: &str) -> Result<String> {
+    if input.is_empty() {
+        bail!("the name must not be empty");
+    }
+    if let Some(bad) = input
+        .chars()
+        .find(|&c| c == ',' || c.is_whitespace() || c.is_control())
+    {
+        bail!("the name '{input}' contains {bad:?}, which mount options cannot carry");
+    }
+    Ok(input.to_owned())
+}
+
+/// Whether a gitsnapfs mount of subtype `subtype` is at `mountpoint`,
+/// whether or not its daemon is still running. Symlinks up to the last
+/// component are resolved.
+///
+/// # Errors
+///
+/// Returns an error if the parent of `mountpoint` cannot be resolved or the
+/// mount table cannot be read.
+pub fn gitsnapfs_mounted_at(mountpoint: &Path, subtype: &str) -> Result<bool> {
+    let absolute = std::path::absolute(mountpoint)
+        .with_context(|| format!("failed to resolve {}", mountpoint.display()))?;
+    // A dead mount cannot be resolved itself, only the directory it is in.
+    let resolved = match (absolute.parent(), absolute.file_name()) {
+        (Some(parent), Some(name)) => match std::fs::canonicalize(parent) {
+            Ok(parent) => parent.join(name),
+            // Nothing is mounted below a directory that does not exist.
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to resolve {}", parent.display()))
+            }
+        },
+        _ => absolute,
+    };
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")
+        .context("failed to read /proc/self/mountinfo")?;
+    Ok(mountinfo
+        .lines()
+        .any(|line| is_gitsnapfs_mount(line, &resolved, subtype)))
+}
+
+/// Whether the `mountinfo` line describes a gitsnapfs mount of subtype
+/// `subtype` at `root`.
+pub(crate) fn is_gitsnapfs_mount(line: &str, root: &Path, subtype: &str) -> bool {
+    let Some((mount, filesystem)) = line.split_once(" - ") else {
+        return false;
+    };
+    let mount_point = mount.split(' ').nth(4).map(unescape_mountinfo);
+    filesystem
+        .split(' ')
+        .next()
+        .and_then(|kind| kind.strip_prefix("fuse."))
+        == Some(subtype)
+        && mount_point
+            .is_some_and(|point| point.as_os_str().as_bytes() == root.as_os_str().as_bytes())
+}
+
+/// Undo the octal escapes (`\040` for a space) of a `mountinfo` path.
+fn unescape_mountinfo(field: &str) -> PathBuf {
+    let bytes = field.as_bytes();
+    let mut path = Vec::with_capacity(bytes.len());
+    let mut at = 0;
+    while at < bytes.len() {
+        let escaped = bytes
+            .get(at + 1..at + 4)
+            .filter(|_| bytes[at] == b'\\')
+            .and_then(|digits| u8::from_str_radix(digits.to_str().ok()?, 8).ok());
+        if let Some(byte) = escaped {
+            path.push(byte);
+            at += 4;
+        } else {
+            path.push(bytes[at]);
+            at += 1;
+        }
+    }
+    PathBuf::from(OsString::from_vec(path))
+}
+
+/// Detach the mount at `mountpoint` lazily: it leaves the tree at once, and
+/// files still open in it keep working until closed.
+///
+/// # Errors
+///
+/// Returns an error if the mount cannot be detached.
+pub fn detach(mountpoint: &Path, fusermount: Option<&Path>) -> Result<()> {
+    if can_mount_directly() {
+        let path = std::ffi::CString::new(mountpoint.as_os_str().as_bytes())?;
+        // SAFETY: `path` is a valid C string.
+        if unsafe { libc::umount2(path.as_ptr(), libc::MNT_DETACH) } != 0 {
+            return Err(io::Error::last_os_error())
+                .with_context(|| format!("failed to detach {}", mountpoint.display()));
+        }
+        return Ok(());
+    }
+    let helper = find_fusermount(fusermount)?;
+    let status = Command::new(&helper)
+        .arg("-u")
+        .arg("-z")
+        .arg(mountpoint)
+        .status()
+        .with_context(|| format!("failed to run {}", helper.display()))?;
+    if !status.success() {
+        bail!(
+            "{} -u -z {} failed: {status}",
+            helper.display(),
+            mountpoint.display()
+        );
+    }
+    Ok(())
+}
+
+/// Locate the `fusermount` helper: `explicit` if given, otherwise
+/// `fusermount3` or `fusermount` on `PATH` or in the usual system directories.
+///
+/// # Errors
+///
+/// Returns an error if `explicit` is not an executable file, or no helper is found.
+pub fn find_fusermount(explicit: Option<&Path>) -> Result<PathBuf> {
+    if let Some(path) = explicit {
+        if !is_executable(path) {
+            bail!(
+                "--fusermount-path {} is not an executable file",
+                path.display()
+            );
+        }
+        return Ok(path.to_path_buf());
+    }
+    let path_dirs = std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).collect::<Vec<_>>())
+        .unwrap_or_default();
+    let dirs = path_dirs
+        .into_iter()
+        .chain(FALLBACK_DIRS.iter().map(PathBuf::from))
+        .collect::<Vec<_>>();
+    find_in(&dirs, FUSERMOUNT_NAMES).with_context(|| {
+        format!(
+            "no {} helper found on PATH or in {}; install the fuse3 package or pass --fusermount-path",
+            FUSERMOUNT_NAMES.join("/"),
+            FALLBACK_DIRS.join(", ")
+        )
+    })
+}
+
+fn find_in(dirs: &[PathBuf], names: &[&str]) -> Option<PathBuf> {
+    names.iter().find_map(|name| {
+        dirs.iter()
+            .map(|dir| dir.join(name))
+            .find(|candidate| is_executable(candidate))
+    })
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+/// Whether this process may call `mount(2)` itself: it is root in its user
+/// namespace, which rootless container runtimes arrange for.
+#[must_use]
+pub fn can_mount_directly() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Whether the process runs inside a user namespace other than the initial one.
+#[must_use]
+pub fn in_user_namespace() -> bool {
+    std::fs::read("/proc/self/uid_map").is_ok_and(|map| {
+        let fields = map
+            .split(u8::is_ascii_whitespace)
+            .filter(|field| !field.is_empty())
+            .collect::<Vec<_>>();
+        fields != [&b"0"[..], b"0", b"4294967295"]
+    })
+}
+
+/// Move this process into a mount namespace of its own, so that mounts made
+/// from now on are invisible to the rest of the system and disappear with
+/// it. Needs `CAP_SYS_ADMIN`, and must run before any thread is spawned:
+/// only the calling thread changes namespace.
+///
+/// # Errors
+///
+/// Returns an error if the namespace cannot be created.
+pub fn enter_private_namespace() -> Result<()> {
+    nix::sched::unshare(nix::sched::CloneFlags::CLONE_NEWNS)
+        .context("failed to create a mount namespace")?;
+    // Mounts below a shared parent would otherwise propagate back out.
+    let root = c"/";
+    // SAFETY: `root` is a valid C string; mount(2) accepts null for the
+    // source, filesystem type and data when only changing propagation.
+    let result = unsafe {
+        libc::mount(
+            std::ptr::null(),
+            root.as_ptr(),
+            std::ptr::null(),
+            libc::MS_REC | libc::MS_PRIVATE,
+            std::ptr::null(),
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error()).context("failed to make mounts private");
+    }
+    Ok(())
+}
+
+/// Render `path` for passing to the session, which only accepts UTF-8.
+///
+/// # Errors
+///
+/// Returns an error for non-UTF-8 paths.
+pub fn helper_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .with_context(|| format!("fusermount path {} is not valid UTF-8", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefers_fusermount3_in_search_order() {
+        let root = std::env::temp_dir().join(format!("gitsnapfs-mount-{}", std::process::id()));
+        let (first, second) = (root.join("a"), root.join("b"));
+        std::fs::create_dir_all(&first).unwrap();
+        std::fs::create_dir_all(&second).unwrap();
+        for path in [first.join("fusermount"), second.join("fusermount3")] {
+            std::fs::write(&path, b"#!/bin/sh\n").unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        std::fs::write(first.join("fusermount3"), b"not executable").unwrap();
+
+        let dirs = [first.clone(), second.clone()];
+        assert_eq!(
+            find_in(&dirs, FUSERMOUNT_NAMES),
+            Some(second.join("fusermount3"))
+        );
+        assert_eq!(
+            find_in(&dirs, &["fusermount"]),
+            Some(first.join("fusermount"))
+        );
+        assert_eq!(find_in(&dirs, &["missing"]), None);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn creates_missing_mountpoints_with_the_mode_asked_for() {
+        let root = tempfile::tempdir().unwrap();
+        let mountpoint = root.path().join("mnt/git");
+        assert!(create_mountpoint(&mountpoint, 0o750).unwrap());
+        let mode = mountpoint.metadata().unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o750);
+        assert!(!create_mountpoint(&mountpoint, 0o700).unwrap());
+        let mode = mountpoint.metadata().unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o750);
+
+        let file = root.path().join("file");
+        std::fs::write(&file, b"").unwrap();
+        assert!(!create_mountpoint(&file, 0o755).unwrap());
+    }
+
+    #[test]
+    fn recognises_gitsnapfs_mounts_in_mountinfo() {
+        let line = "61 25 0:52 / /mnt/git\\040snap rw,nosuid,nodev - fuse.gitsnapfs gitsnapfs rw";
+        assert!(is_gitsnapfs_mount(
+            line,
+            Path::new("/mnt/git snap"),
+            DEFAULT_NAME
+        ));
+        assert!(!is_gitsnapfs_mount(
+            line,
+            Path::new("/mnt/git"),
+            DEFAULT_NAME
+        ));
+        assert!(!is_gitsnapfs_mount(
+            line,
+            Path::new("/mnt/git snap"),
+            "docs"
+        ));
+        let other = "62 25 0:53 / /mnt/git rw - fuse.sshfs host:/ rw";
+        assert!(!is_gitsnapfs_mount(
+            other,
+            Path::new("/mnt/git"),
+            DEFAULT_NAME
+        ));
+        let named = "63 25 0:54 / /mnt/docs rw - fuse.docs repo-docs rw";
+        assert!(is_gitsnapfs_mount(named, Path::new("/mnt/docs"), "docs"));
+    }
+
+    #[test]
+    fn mount_names_must_fit_in_mount_options() {
+        assert_eq!(parse_mount_name("repo-docs").unwrap(), "repo-docs");
+        for bad in ["", "a,b", "a b", "a\tb"] {
+            assert!(parse_mount_name(bad).is_err(), "{bad:?}");
+        }
+    }
+}
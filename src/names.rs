@@ -0,0 +1,72 @@
+//! Naming policy for directory entries.
+//!
+//! Tree entries are served with their raw bytes: Git stores them as arbitrary
+//! byte strings and the kernel passes them back unchanged on lookup. Names we
+//! derive from other sources, such as ref names (which may contain `/` or
+//! invalid UTF-8) or the flattened paths under `.git-meta/conflicts/`, go
+//! through [`escape_name`] so that each listed name is a single, valid UTF-8
+//! path component that maps back to exactly one original.
+//!
+//! The escaping is percent-encoding of `%`, `/` and every byte that is not
+//! part of a valid UTF-8 sequence; everything else is kept as-is.
+
+use gix::bstr::ByteSlice;
+
+/// Escape `raw` into a single UTF-8 directory entry name.
+#[must_use]
+pub fn escape_name(raw: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(raw.len());
+    for (start, end, ch) in raw.char_indices() {
+        let bytes = &raw[start..end];
+        match ch {
+            '%' => out.extend_from_slice(b"%25"),
+            '/' => out.extend_from_slice(b"%2F"),
+            // `char_indices` reports invalid sequences as U+FFFD; a literal
+            // U+FFFD is three bytes long and passes through unchanged.
+            '\u{FFFD}' if bytes != "\u{FFFD}".as_bytes() => {
+                for byte in bytes {
+                    out.extend_from_slice(format!("%{byte:02X}").as_bytes());
+                }
+            }
+            _ => out.extend_from_slice(bytes),
+        }
+    }
+    out
+}
+
+/// Reverse [`escape_name`]. Returns `None` for malformed escapes.
+#[must_use]
+pub fn unescape_name(name: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(name.len());
+    let mut bytes = name.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte != b'%' {
+            out.push(byte);
+            continue;
+        }
+        let high = char::from(*bytes.next()?).to_digit(16)?;
+        let low = char::from(*bytes.next()?).to_digit(16)?;
+        out.push(u8::try_from(high * 16 + low).ok()?);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaping_roundtrip() {
+        let path = b"src/100%/lib.rs";
+        let escaped = escape_name(path);
+        assert_eq!(escaped, b"src%2F100%25%2Flib.rs");
+        assert_eq!(unescape_name(&escaped).as_deref(), Some(&path[..]));
+        assert_eq!(unescape_name(b"bad%zz"), None);
+
+        let raw = b"caf\xc3\xa9-\xff\xfe-\xef\xbf\xbd";
+        let escaped = escape_name(raw);
+        assert_eq!(escaped, b"caf\xc3\xa9-%FF%FE-\xef\xbf\xbd");
+        assert!(std::str::from_utf8(&escaped).is_ok());
+        assert_eq!(unescape_name(&escaped).as_deref(), Some(&raw[..]));
+    }
+}
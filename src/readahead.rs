@@ -0,0 +1,150 @@
+//! Per-handle readahead for large blobs (`--readahead`).
+//!
+//! Sequential readers such as `cat` or `tar` fetch a file in 128 KiB chunks,
+//! and without a blob cache every chunk would inflate the whole blob again.
+//! Opening a large blob instead starts inflating it on a background thread,
+//! ahead of the first read, and the handle keeps the contents until it is
+//! released, so each chunk after the first is a plain copy. `gix` inflates
+//! objects whole, so the window read ahead is always the entire blob.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use tracing::debug;
+
+use crate::interrupt;
+
+/// Blobs up to this size fit in a single FUSE read and gain nothing.
+pub const MIN_BLOB_SIZE: u64 = 128 * 1024;
+
+/// How often a read waiting for its handle's contents checks for an interrupt.
+const INTERRUPT_POLL: Duration = Duration::from_millis(20);
+
+/// Open handles with contents being or already inflated.
+#[derive(Debug, Default)]
+pub struct Readahead {
+    next_handle: AtomicU64,
+    streams: Mutex<HashMap<u64, Arc<Stream>>>,
+}
+
+#[derive(Debug)]
+struct Stream {
+    inode: u64,
+    content: Mutex<Slot>,
+}
+
+#[derive(Debug)]
+enum Slot {
+    Pending(JoinHandle<io::Result<Arc<[u8]>>>),
+    Ready(Arc<[u8]>),
+    Failed(i32),
+}
+
+impl Readahead {
+    /// Start `decode` on a background thread for a new handle of `inode`
+    /// and return the handle.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the thread cannot be spawned.
+    pub fn start<F>(&self, inode: u64, decode: F) -> io::Result<u64>
+    where
+        F: FnOnce() -> io::Result<Arc<[u8]>> + Send + 'static,
+    {
+        let worker = thread::Builder::new()
+            .name("gitsnapfs-readahead".into())
+            .spawn(decode)?;
+        let handle = self.next_handle.fetch_add(1, Ordering::Relaxed);
+        debug!(inode, handle, "reading ahead");
+        self.lock().insert(
+            handle,
+            Arc::new(Stream {
+                inode,
+                content: Mutex::new(Slot::Pending(worker)),
+            }),
+        );
+        Ok(handle)
+    }
+
+    /// Contents read ahead for `handle`, waiting for the decoding thread if
+    /// it is still running. `None` if `handle` is not a readahead handle of
+    /// `inode`.
+    pub fn content(&self, handle: u64, inode: u64) -> Option<io::Result<Arc<[u8]>>> {
+        let stream = self
+            .lock()
+            .get(&handle)
+            .filter(|stream| stream.inode == inode)
+            .map(Arc::clone)?;
+        let mut slot = stream
+            .content
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        // The inflating thread keeps going if the request is interrupted; a
+        // later read of the handle picks up its result.
+        while matches!(&*slot, Slot::Pending(worker) if !worker.is_finished()) {
+            if let Err(interrupted) = interrupt::check() {
+                return Some(Err(interrupted.into()));
+            }
+            thread::sleep(INTERRUPT_POLL);
+        }
+        if matches!(*slot, Slot::Pending(_)) {
+            // A panicking worker leaves the handle failed rather than pending.
+            if let Slot::Pending(worker) = std::mem::replace(&mut *slot, Slot::Failed(libc::EIO)) {
+                *slot = match worker.join() {
+                    Ok(Ok(data)) => Slot::Ready(data),
+                    Ok(Err(err)) => Slot::Failed(err.raw_os_error().unwrap_or(libc::EIO)),
+                    Err(_) => Slot::Failed(libc::EIO),
+                };
+            }
+        }
+        Some(match &*slot {
+            Slot::Ready(data) => Ok(Arc::clone(data)),
+            Slot::Failed(errno) => Err(io::Error::from_raw_os_error(*errno)),
+            Slot::Pending(_) => unreachable!("joined above"),
+        })
+    }
+
+    /// Drop what was read ahead for `handle`.
+    pub fn release(&self, handle: u64) {
+        self.lock().remove(&handle);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<u64, Arc<Stream>>> {
+        self.streams.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serves_decoded_contents_per_handle() {
+        let readahead = Readahead::default();
+        let handle = readahead
+            .start(42, || Ok(Arc::from(&b"contents"[..])))
+            .unwrap();
+        let failing = readahead
+            .start(43, || Err(io::Error::from_raw_os_error(libc::ENOENT)))
+            .unwrap();
+
+        assert_eq!(
+            &*readahead.content(handle, 42).unwrap().unwrap(),
+            b"contents"
+        );
+        assert_eq!(
+            &*readahead.content(handle, 42).unwrap().unwrap(),
+            b"contents"
+        );
+        assert!(readahead.content(handle, 43).is_none());
+        let err = readahead.content(failing, 43).unwrap().unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+
+        readahead.release(handle);
+        assert!(readahead.content(handle, 42).is_none());
+    }
+}
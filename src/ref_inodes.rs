@@ -0,0 +1,396 @@
+//! Inode numbers of branch and tag entries, and of objects with
+//! `--inode-prefix-bits`.
+//!
+//! Branch and tag entries have no object id to derive an inode from, and a
+//! hash of the name could map two names to one inode. [`RefInodes`] instead
+//! numbers names in the order they are first handed out, separately for
+//! [`Space::Branch`] and [`Space::Tag`], so distinct names never share an
+//! inode. The numbers of names that disappear are not reused.
+//!
+//! With `--state-file`, every allocation is appended to that file and the
+//! file is read back on the next mount, so a name keeps its inode across
+//! remounts. Each line holds the space (`branch` or `tag`), the number and
+//! the name in hex, after a header line naming the format.
+//!
+//! Object inodes normally keep 56 bits of the object id and need no
+//! registry. With fewer bits, as a SHA-256 repository may call for, the
+//! prefix is far more likely to be shared, so objects sharing it are given
+//! slots in the order they are first handed out (see
+//! [`crate::inode::narrow_object_inode`]) and every object handed out is
+//! recorded, in memory and as an `object` line with the inode and the id
+//! in hex. The registry then grows with the objects looked up.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write as _};
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+use gix::ObjectId;
+
+use crate::inode::{narrow_object_inode, numbered_inode, plain_inode, Space, OBJECT_PREFIX_BITS};
+
+/// First line of a state file, naming its format.
+const HEADER: &str = "gitsnapfs ref inodes 2";
+
+/// Header of state files written before objects were recorded, still read.
+const HEADER_V1: &str = "gitsnapfs ref inodes 1";
+
+/// Allocated inodes by name and by inode, for the branch and tag spaces,
+/// and by object id for the object space.
+#[derive(Debug)]
+pub struct RefInodes {
+    inner: Mutex<Inner>,
+    /// Bits of the object id kept in object inodes.
+    prefix_bits: u32,
+}
+
+impl Default for RefInodes {
+    fn default() -> Self {
+        Self {
+            inner: Mutex::default(),
+            prefix_bits: OBJECT_PREFIX_BITS,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    by_name: HashMap<(Space, Vec<u8>), u64>,
+    by_inode: HashMap<u64, Vec<u8>>,
+    /// Plain inodes handed out to objects, and the reverse.
+    by_object: HashMap<ObjectId, u64>,
+    objects: HashMap<u64, ObjectId>,
+    /// Highest number allocated so far, per space.
+    last: HashMap<Space, u64>,
+    /// Where new allocations are recorded, if anywhere.
+    state: Option<File>,
+}
+
+impl RefInodes {
+    /// A registry persisted in `path`, starting with the allocations already
+    /// recorded there; the file is created if missing.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened or is not a state file.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut inner = match Self::read(path) {
+            Ok(registry) => registry
+                .inner
+                .into_inner()
+                .unwrap_or_else(std::sync::PoisonError::into_inner),
+            Err(err)
+                if err
+                    .downcast_ref::<io::Error>()
+                    .is_some_and(|err| err.kind() == io::ErrorKind::NotFound) =>
+            {
+                Inner::default()
+            }
+            Err(err) => return Err(err),
+        };
+        // Rewriting the file drops a last line a crash may have cut short.
+        let partial = path.with_extension("partial");
+        std::fs::write(&partial, inner.render())
+            .and_then(|()| std::fs::rename(&partial, path))
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        inner.state = Some(file);
+        Ok(Self {
+            inner: Mutex::new(inner),
+            ..Self::default()
+        })
+    }
+
+    /// The allocations recorded in `path`, without recording new ones, for
+    /// looking at the state file of a running mount.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is not a state file.
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let mut inner = Inner::default();
+        inner
+            .load(&text)
+            .with_context(|| format!("invalid state file {}", path.display()))?;
+        Ok(Self {
+            inner: Mutex::new(inner),
+            ..Self::default()
+        })
+    }
+
+    /// Keep only the first `bits` bits of object ids in object inodes,
+    /// between [`crate::inode::MIN_OBJECT_PREFIX_BITS`] and
+    /// [`OBJECT_PREFIX_BITS`], and tell objects sharing them apart by slot.
+    #[must_use]
+    pub fn with_prefix_bits(mut self, bits: u32) -> Self {
+        self.prefix_bits = bits;
+        self
+    }
+
+    /// Bits of the object id kept in object inodes.
+    #[must_use]
+    pub fn prefix_bits(&self) -> u32 {
+        self.prefix_bits
+    }
+
+    /// The inode of `name` in `space`, allocating the next free one for a
+    /// name not seen before.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a new allocation cannot be recorded in the state file.
+    pub fn inode(&self, space: Space, name: &[u8]) -> io::Result<u64> {
+        let mut inner = self.lock();
+        if let Some(&inode) = inner.by_name.get(&(space, name.to_vec())) {
+            return Ok(inode);
+        }
+        let number = inner.last.get(&space).copied().unwrap_or_default() + 1;
+        if let Some(state) = &mut inner.state {
+            writeln!(state, "{} {number} {}", space_name(space), hex(name))?;
+        }
+        Ok(inner.insert(space, number, name.to_vec()))
+    }
+
+    /// The name `inode` was allocated to.
+    #[must_use]
+    pub fn name(&self, inode: u64) -> Option<Vec<u8>> {
+        self.lock().by_inode.get(&inode).cloned()
+    }
+
+    /// The plain inode of `oid`. With the default prefix length it is
+    /// derived from the id alone; with fewer bits the first free slot
+    /// for the prefix is allocated to an object not seen before.
+    ///
+    /// # Errors
+    ///
+    /// Returns `EOVERFLOW` if every slot of the prefix is taken, or an
+    /// error if a new allocation cannot be recorded in the state file.
+    pub fn object_inode(&self, oid: &ObjectId) -> io::Result<u64> {
+        if self.prefix_bits == OBJECT_PREFIX_BITS {
+            return Ok(narrow_object_inode(oid, OBJECT_PREFIX_BITS, 0).unwrap_or_default());
+        }
+        let mut inner = self.lock();
+        if let Some(&inode) = inner.by_object.get(oid) {
+            return Ok(inode);
+        }
+        let inode = (0..)
+            .map_while(|slot| narrow_object_inode(oid, self.prefix_bits, slot))
+            .find(|inode| !inner.objects.contains_key(inode))
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::EOVERFLOW))?;
+        if let Some(state) = &mut inner.state {
+            writeln!(state, "object {inode:x} {oid}")?;
+        }
+        inner.insert_object(inode, *oid);
+        Ok(inode)
+    }
+
+    /// The object object inode `inode` was allocated to, in any view.
+    #[must_use]
+    pub fn object(&self, inode: u64) -> Option<ObjectId> {
+        self.lock().objects.get(&plain_inode(inode)).copied()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+impl Inner {
+    fn insert(&mut self, space: Space, number: u64, name: Vec<u8>) -> u64 {
+        let inode = numbered_inode(space, number);
+        let last = self.last.entry(space).or_default();
+        *last = (*last).max(number);
+        self.by_inode.insert(inode, name.clone());
+        self.by_name.insert((space, name), inode);
+        inode
+    }
+
+    fn insert_object(&mut self, inode: u64, oid: ObjectId) {
+        self.by_object.insert(oid, inode);
+        self.objects.insert(inode, oid);
+    }
+
+    fn load(&mut self, text: &str) -> Result<()> {
+        let complete = text.rfind('\n').map_or("", |end| &text[..end]);
+        let mut lines = complete.lines();
+        if lines
+            .next()
+            .is_some_and(|header| header != HEADER && header != HEADER_V1)
+        {
+            bail!("not a gitsnapfs state file, or one of another version");
+        }
+        for (index, line) in lines.enumerate() {
+            if let Some(object) = line.strip_prefix("object ") {
+                let parsed = object.split_once(' ').and_then(|(inode, oid)| {
+                    let inode = u64::from_str_radix(inode, 16).ok()?;
+                    let oid = ObjectId::from_hex(oid.as_bytes()).ok()?;
+                    (Space::of(inode) == Some(Space::Object)).then_some((inode, oid))
+                });
+                let Some((inode, oid)) = parsed else {
+                    bail!("line {} is malformed", index + 2);
+                };
+                self.insert_object(inode, oid);
+                continue;
+            }
+            let parsed = line.split_once(' ').and_then(|(space, rest)| {
+                let space = match space {
+                    "branch" => Space::Branch,
+                    "tag" => Space::Tag,
+                    _ => return None,
+                };
+                let (number, name) = rest.split_once(' ')?;
+                Some((space, number.parse().ok()?, unhex(name)?))
+            });
+            let Some((space, number, name)) = parsed else {
+                bail!("line {} is malformed", index + 2);
+            };
+            self.insert(space, number, name);
+        }
+        Ok(())
+    }
+
+    fn render(&self) -> String {
+        let mut entries: Vec<_> = self.by_name.iter().collect();
+        entries.sort_by_key(|(_, inode)| **inode);
+        let mut text = format!("{HEADER}\n");
+        for ((space, name), inode) in entries {
+            let number = inode & !numbered_inode(*space, 0);
+            let _ = writeln!(text, "{} {number} {}", space_name(*space), hex(name));
+        }
+        let mut objects: Vec<_> = self.objects.iter().collect();
+        objects.sort_by_key(|(inode, _)| **inode);
+        for (inode, oid) in objects {
+            let _ = writeln!(text, "object {inode:x} {oid}");
+        }
+        text
+    }
+}
+
+fn space_name(space: Space) -> &'static str {
+    if space == Space::Tag {
+        "tag"
+    } else {
+        "branch"
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(text, "{byte:02x}");
+    }
+    text
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(text.get(at..at + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inode::{narrow_hex_prefix, view_inode, ObjectView};
+
+    #[test]
+    fn numbers_names_and_reloads_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state");
+        let inodes = RefInodes::open(&path).unwrap();
+        let main = inodes.inode(Space::Branch, b"main").unwrap();
+        let tag = inodes.inode(Space::Tag, b"main").unwrap();
+        let odd = inodes.inode(Space::Branch, b"bad%FFname").unwrap();
+        assert_eq!(inodes.inode(Space::Branch, b"main").unwrap(), main);
+        assert_eq!(Space::of(tag), Some(Space::Tag));
+        assert_ne!(main, odd);
+        drop(inodes);
+
+        let reloaded = RefInodes::open(&path).unwrap();
+        assert_eq!(reloaded.name(odd).as_deref(), Some(&b"bad%FFname"[..]));
+        assert_eq!(reloaded.inode(Space::Tag, b"main").unwrap(), tag);
+        let next = reloaded.inode(Space::Branch, b"next").unwrap();
+        assert!(next != main && next != odd);
+
+        // A line cut short by a crash is dropped.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"branch 9 6d").unwrap();
+        drop(reloaded);
+        let reloaded = RefInodes::open(&path).unwrap();
+        assert_eq!(reloaded.inode(Space::Branch, b"next").unwrap(), next);
+
+        std::fs::write(&path, "something else\n").unwrap();
+        assert!(RefInodes::open(&path).is_err());
+    }
+
+    /// Ids sharing their first `bits` bits with `base`, the rest random.
+    fn colliding(rng: &mut fastrand::Rng, base: &[u8; 20], bits: u32) -> ObjectId {
+        let mut bytes: [u8; 20] = std::array::from_fn(|_| rng.u8(..));
+        let whole = (bits / 8) as usize;
+        bytes[..whole].copy_from_slice(&base[..whole]);
+        if !bits.is_multiple_of(8) {
+            let keep = 0xff_u8 << (8 - bits % 8);
+            bytes[whole] = (base[whole] & keep) | (bytes[whole] & !keep);
+        }
+        ObjectId::from_bytes_or_panic(&bytes)
+    }
+
+    #[test]
+    fn colliding_prefixes_resolve_to_their_objects() {
+        let mut rng = fastrand::Rng::with_seed(0x0c01_11de);
+        let dir = tempfile::tempdir().unwrap();
+        for round in 0..50 {
+            let bits = rng.u32(16..=48);
+            let path = dir.path().join(format!("state-{round}"));
+            let inodes = RefInodes::open(&path).unwrap().with_prefix_bits(bits);
+            let base: [u8; 20] = std::array::from_fn(|_| rng.u8(..));
+            let ids: Vec<ObjectId> = (0..rng.usize(1..40))
+                .map(|_| colliding(&mut rng, &base, bits))
+                .collect();
+
+            let mut seen = HashMap::new();
+            for id in &ids {
+                let inode = inodes.object_inode(id).unwrap();
+                assert_eq!(Space::of(inode), Some(Space::Object));
+                assert_eq!(inodes.object_inode(id).unwrap(), inode);
+                if let Some(other) = seen.insert(inode, *id) {
+                    assert_eq!(other, *id, "two objects share inode {inode:#x}");
+                }
+            }
+            for view in [ObjectView::Plain, ObjectView::Executable, ObjectView::Link] {
+                for (&inode, id) in &seen {
+                    assert_eq!(inodes.object(view_inode(inode, view)), Some(*id));
+                }
+            }
+            // The first object of the prefix can also be found by prefix.
+            let first = inodes.object_inode(&ids[0]).unwrap();
+            assert!(ids[0]
+                .to_string()
+                .starts_with(&narrow_hex_prefix(first, bits).unwrap()));
+            drop(inodes);
+
+            let reloaded = RefInodes::open(&path).unwrap().with_prefix_bits(bits);
+            for (&inode, id) in &seen {
+                assert_eq!(reloaded.object(inode), Some(*id));
+                assert_eq!(reloaded.object_inode(id).unwrap(), inode);
+            }
+            let late = colliding(&mut rng, &base, bits);
+            let inode = reloaded.object_inode(&late).unwrap();
+            assert!(seen.get(&inode).is_none_or(|id| *id == late));
+        }
+    }
+}
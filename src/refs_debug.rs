@@ -0,0 +1,168 @@
+//! Every reference as stored, for diagnosing why one is not served
+//! (`--refs-debug`).
+//!
+//! `/refs-debug/refs` lists `HEAD` and every loose and packed reference,
+//! including those hidden by filters or too broken to read, one per line
+//! with tab-separated columns:
+//!
+//! ```text
+//! HEAD                loose         ref: refs/heads/main  4b82…  served
+//! refs/heads/main     loose+packed  4b82…                 4b82…  served
+//! refs/tags/v1.0      packed        9f1c…                 4b82…  served
+//! refs/heads/wip      loose         -                     -      unparsable
+//! ```
+//!
+//! The columns are the full name, where the value was read from
+//! (`loose+packed` for a loose file shadowing a packed entry), the value
+//! verbatim (an object id, or `ref: <name>` for symbolic references), the
+//! object it peels to and whether the mount serves it. `/refs-debug/packed-refs`
+//! is the `packed-refs` file itself.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use anyhow::Result;
+use gix::refs::file::iter::loose_then_packed::Error as LooseError;
+use gix::refs::Target;
+use gix::ObjectId;
+
+/// Where a reference was read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Loose,
+    Packed,
+    /// A loose file, which takes precedence over a packed entry of the same name.
+    LooseOverPacked,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Source::Loose => "loose",
+            Source::Packed => "packed",
+            Source::LooseOverPacked => "loose+packed",
+        })
+    }
+}
+
+/// One reference as stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefRecord {
+    /// Full name, or for unparsable entries the file or `packed-refs` line.
+    pub name: Vec<u8>,
+    pub source: Source,
+    /// The stored value; `None` if it could not be parsed.
+    pub target: Option<Vec<u8>>,
+    /// The object the reference peels to; `None` if it is dangling or broken.
+    pub peeled: Option<ObjectId>,
+}
+
+/// `HEAD` and every loose and packed reference of `repo`, read directly
+/// from the reference store, in name order after `HEAD`.
+///
+/// # Errors
+///
+/// Returns an error if the reference directories or `packed-refs` cannot
+/// be read at all; single broken entries are recorded instead.
+pub fn read(repo: &gix::Repository) -> Result<Vec<RefRecord>> {
+    let store = &repo.refs;
+    let mut records: BTreeMap<Vec<u8>, RefRecord> = BTreeMap::new();
+    if let Some(buffer) = store.open_packed_buffer()? {
+        for entry in buffer.iter()? {
+            let record = match entry {
+                Ok(reference) => RefRecord {
+                    name: reference.name.as_bstr().to_vec(),
+                    source: Source::Packed,
+                    target: Some(reference.target.to_vec()),
+                    peeled: None,
+                },
+                Err(err) => RefRecord {
+                    name: format!("packed-refs: {err}").into_bytes(),
+                    source: Source::Packed,
+                    target: None,
+                    peeled: None,
+                },
+            };
+            records.insert(record.name.clone(), record);
+        }
+    }
+    for entry in store.loose_iter()? {
+        let (name, target) = match entry {
+            Ok(reference) => (
+                reference.name.as_bstr().to_vec(),
+                Some(render_target(&reference.target)),
+            ),
+            Err(LooseError::ReferenceCreation { relative_path, .. }) => (
+                relative_path.to_string_lossy().into_owned().into_bytes(),
+                None,
+            ),
+            Err(err) => return Err(err.into()),
+        };
+        let source = match records.get(&name) {
+            Some(packed) if packed.target.is_some() => Source::LooseOverPacked,
+            _ => Source::Loose,
+        };
+        records.insert(
+            name.clone(),
+            RefRecord {
+                name,
+                source,
+                target,
+                peeled: None,
+            },
+        );
+    }
+    let head = store
+        .try_find_loose("HEAD")
+        .ok()
+        .flatten()
+        .map(|head| RefRecord {
+            name: b"HEAD".to_vec(),
+            source: Source::Loose,
+            target: Some(render_target(&head.target)),
+            peeled: None,
+        });
+    let mut records: Vec<RefRecord> = head.into_iter().chain(records.into_values()).collect();
+    for record in &mut records {
+        if record.target.is_none() {
+            continue;
+        }
+        record.peeled = std::str::from_utf8(&record.name)
+            .ok()
+            .and_then(|name| repo.find_reference(name).ok())
+            .and_then(|mut reference| reference.peel_to_id().ok())
+            .map(gix::Id::detach);
+    }
+    Ok(records)
+}
+
+/// A stored value as Git writes it into a loose file.
+fn render_target(target: &Target) -> Vec<u8> {
+    match target {
+        Target::Object(id) => id.to_string().into_bytes(),
+        Target::Symbolic(name) => format!("ref: {}", name.as_bstr()).into_bytes(),
+    }
+}
+
+/// The contents of `/refs-debug/refs`: `records` with the status `status`
+/// gives each.
+#[must_use]
+pub fn render(records: &[RefRecord], status: impl Fn(&RefRecord) -> &'static str) -> Vec<u8> {
+    let mut out = Vec::new();
+    for record in records {
+        out.extend_from_slice(&record.name);
+        out.push(b'\t');
+        out.extend_from_slice(record.source.to_string().as_bytes());
+        out.push(b'\t');
+        out.extend_from_slice(record.target.as_deref().unwrap_or(b"-"));
+        out.push(b'\t');
+        match record.peeled {
+            Some(id) => out.extend_from_slice(id.to_string().as_bytes()),
+            None => out.push(b'-'),
+        }
+        out.push(b'\t');
+        out.extend_from_slice(status(record).as_bytes());
+        out.push(b'\n');
+    }
+    out
+}
@@ -0,0 +1,309 @@
+//! Tags named like semantic versions, arranged by version under `/releases`
+//! (`--releases`).
+//!
+//! `--release-pattern` (`v*` by default) says how a tag names a version: the
+//! `*` stands for `MAJOR.MINOR.PATCH`, optionally followed by `+build`
+//! metadata, which is ignored. Pre-releases (`1.0.0-rc.1`) and numbers with
+//! leading zeros are not releases. Each level of the hierarchy is a
+//! directory of the numbers found at it, in numeric order, plus `latest`
+//! naming the highest of them:
+//!
+//! ```text
+//! releases/2/5/1       -> ../../../commits/<commit v2.5.1 names>
+//! releases/2/5/latest  -> 1
+//! releases/2/latest    -> 5/1
+//! releases/latest      -> 2/5/1
+//! ```
+//!
+//! If several tags name the same version (`v1.0.0` and `v1.0.0+build.7`),
+//! the first in name order wins.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use anyhow::{bail, Result};
+use gix::ObjectId;
+
+/// Name of the link to the highest version at each level.
+pub const LATEST: &[u8] = b"latest";
+
+/// How tag names carry a version: the text around the `*` standing for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReleasePattern {
+    prefix: String,
+    suffix: String,
+}
+
+impl ReleasePattern {
+    /// Parse a `--release-pattern` such as `v*` or `release-*`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error unless `input` holds exactly one `*`.
+    pub fn parse(input: &str) -> Result<Self> {
+        match input.split_once('*') {
+            Some((prefix, suffix)) if !suffix.contains('*') => Ok(Self {
+                prefix: prefix.to_owned(),
+                suffix: suffix.to_owned(),
+            }),
+            _ => bail!("expected exactly one '*' standing for the version, got '{input}'"),
+        }
+    }
+
+    /// The version the tag `name` (below `refs/tags/`) names, if it is a release.
+    #[must_use]
+    pub fn version(&self, name: &str) -> Option<Version> {
+        name.strip_prefix(&self.prefix)?
+            .strip_suffix(&self.suffix)?
+            .parse()
+            .ok()
+    }
+}
+
+impl Default for ReleasePattern {
+    fn default() -> Self {
+        Self {
+            prefix: "v".to_owned(),
+            suffix: String::new(),
+        }
+    }
+}
+
+impl fmt::Display for ReleasePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}*{}", self.prefix, self.suffix)
+    }
+}
+
+/// A release version, ordered numerically.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl Version {
+    fn levels(self) -> [u64; 3] {
+        [self.major, self.minor, self.patch]
+    }
+}
+
+impl std::str::FromStr for Version {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<Self, ()> {
+        let core = input.split_once('+').map_or(input, |(core, _)| core);
+        let mut numbers = core.split('.').map(parse_number);
+        match (
+            numbers.next(),
+            numbers.next(),
+            numbers.next(),
+            numbers.next(),
+        ) {
+            (Some(Some(major)), Some(Some(minor)), Some(Some(patch)), None) => Ok(Self {
+                major,
+                minor,
+                patch,
+            }),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A version component: digits without leading zeros.
+fn parse_number(input: &str) -> Option<u64> {
+    let canonical = input == "0" || !input.starts_with('0');
+    if !canonical || input.is_empty() || !input.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    input.parse().ok()
+}
+
+/// What a path below `/releases` is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReleaseNode {
+    /// `/releases` itself or a major or minor version.
+    Dir,
+    /// A release, linking to the commit its tag names.
+    Release(ObjectId),
+    /// A `latest` link, with the path it points to relative to its directory.
+    Latest(Vec<u8>),
+}
+
+/// The releases found among the tags.
+#[derive(Debug, Default)]
+pub struct Releases(BTreeMap<Version, ObjectId>);
+
+impl Releases {
+    /// Index `tags`, `(short name, commit)` in name order, by the versions
+    /// `pattern` finds in their names.
+    #[must_use]
+    pub fn new<'a>(
+        pattern: &ReleasePattern,
+        tags: impl IntoIterator<Item = (&'a str, ObjectId)>,
+    ) -> Self {
+        let mut releases = BTreeMap::new();
+        for (name, commit) in tags {
+            if let Some(version) = pattern.version(name) {
+                releases.entry(version).or_insert(commit);
+            }
+        }
+        Self(releases)
+    }
+
+    /// What `path` (below `/releases`, empty for itself) is, if anything.
+    #[must_use]
+    pub fn node(&self, path: &[u8]) -> Option<ReleaseNode> {
+        let (levels, latest) = split_path(path)?;
+        if latest && levels.len() == 3 {
+            return None;
+        }
+        let mut matching = self.matching(&levels);
+        if latest {
+            let (version, _) = matching.next_back()?;
+            let rest = version.levels()[levels.len()..]
+                .iter()
+                .map(u64::to_string)
+                .collect::<Vec<_>>()
+                .join("/");
+            return Some(ReleaseNode::Latest(rest.into_bytes()));
+        }
+        let first = matching.next();
+        match (levels.len(), first) {
+            (3, Some((_, &commit))) => Some(ReleaseNode::Release(commit)),
+            (0, _) | (1 | 2, Some(_)) => Some(ReleaseNode::Dir),
+            _ => None,
+        }
+    }
+
+    /// Names in the directory `path`, in numeric order and then `latest`;
+    /// `None` if `path` is not a directory.
+    #[must_use]
+    pub fn children(&self, path: &[u8]) -> Option<Vec<Vec<u8>>> {
+        if self.node(path)? != ReleaseNode::Dir {
+            return None;
+        }
+        let (levels, _) = split_path(path)?;
+        let mut names: Vec<Vec<u8>> = Vec::new();
+        let mut last = None;
+        for (version, _) in self.matching(&levels) {
+            let number = version.levels()[levels.len()];
+            if last != Some(number) {
+                names.push(number.to_string().into_bytes());
+                last = Some(number);
+            }
+        }
+        if !names.is_empty() {
+            names.push(LATEST.to_vec());
+        }
+        Some(names)
+    }
+
+    /// Releases whose leading components are `levels`, in version order.
+    fn matching<'a>(
+        &'a self,
+        levels: &'a [u64],
+    ) -> impl DoubleEndedIterator<Item = (&'a Version, &'a ObjectId)> + 'a {
+        self.0
+            .iter()
+            .filter(move |(version, _)| version.levels().starts_with(levels))
+    }
+}
+
+/// The version numbers along `path` and whether it ends in `latest`.
+fn split_path(path: &[u8]) -> Option<(Vec<u64>, bool)> {
+    if path.is_empty() {
+        return Some((Vec::new(), false));
+    }
+    let path = std::str::from_utf8(path).ok()?;
+    let mut parts: Vec<&str> = path.split('/').collect();
+    let latest = parts.last() == Some(&"latest");
+    if latest {
+        parts.pop();
+    }
+    if parts.len() > 3 {
+        return None;
+    }
+    let levels = parts
+        .into_iter()
+        .map(parse_number)
+        .collect::<Option<Vec<_>>>()?;
+    Some((levels, latest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(byte: u8) -> ObjectId {
+        ObjectId::from_bytes_or_panic(&[byte; 20])
+    }
+
+    #[test]
+    fn arranges_tags_by_version() {
+        let pattern = ReleasePattern::default();
+        let tags = [
+            ("v1.10.0", id(1)),
+            ("v1.2.3", id(2)),
+            ("v1.2.3+build.7", id(3)),
+            ("v1.9.0-rc.1", id(4)),
+            ("v2.0.0", id(5)),
+            ("v01.0.0", id(6)),
+            ("nightly", id(7)),
+        ];
+        let releases = Releases::new(&pattern, tags);
+        let names = |path: &[u8]| {
+            releases.children(path).map(|names| {
+                names
+                    .into_iter()
+                    .map(|name| String::from_utf8(name).unwrap())
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(names(b"").unwrap(), ["1", "2", "latest"]);
+        assert_eq!(names(b"1").unwrap(), ["2", "10", "latest"]);
+        assert_eq!(names(b"1/2").unwrap(), ["3", "latest"]);
+        assert_eq!(names(b"1/2/3"), None);
+        assert_eq!(names(b"3"), None);
+
+        assert_eq!(releases.node(b"1/2/3"), Some(ReleaseNode::Release(id(2))));
+        assert_eq!(
+            releases.node(b"latest"),
+            Some(ReleaseNode::Latest(b"2/0/0".to_vec()))
+        );
+        assert_eq!(
+            releases.node(b"1/latest"),
+            Some(ReleaseNode::Latest(b"10/0".to_vec()))
+        );
+        assert_eq!(
+            releases.node(b"1/2/latest"),
+            Some(ReleaseNode::Latest(b"3".to_vec()))
+        );
+        for missing in [&b"1/9"[..], b"1/2/3/latest", b"1/02", b"1/2/3/4", b"x"] {
+            assert_eq!(
+                releases.node(missing),
+                None,
+                "{}",
+                String::from_utf8_lossy(missing)
+            );
+        }
+    }
+
+    #[test]
+    fn patterns_hold_one_star() {
+        let pattern = ReleasePattern::parse("release-*-final").unwrap();
+        assert_eq!(
+            pattern.version("release-3.1.4-final"),
+            Some(Version {
+                major: 3,
+                minor: 1,
+                patch: 4
+            })
+        );
+        assert_eq!(pattern.version("v3.1.4"), None);
+        assert_eq!(pattern.to_string(), "release-*-final");
+        assert!(ReleasePattern::parse("v").is_err());
+        assert!(ReleasePattern::parse("*.*").is_err());
+    }
+}
@@ -3,29 +3,483 @@
 //! These abstractions wrap `gix` primitives so the filesystem code can remain
 //! largely agnostic of the underlying git library.
 
-use std::path::Path;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fmt::Write as _;
+use std::ops::Deref;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError, RwLock};
+use std::time::Duration;
+use std::{io, thread};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 
+use crate::bundle;
 use crate::inode::inode_to_hex_prefix;
+use crate::interrupt;
+use crate::names::escape_name;
+use crate::sha256;
 use gix::{self, bstr::ByteSlice, ObjectId, ThreadSafeRepository};
 
+/// Blobs at least this large are decoded on a helper thread when the request
+/// reading them can be interrupted, so the worker can stop waiting.
+const BACKGROUND_DECODE_SIZE: u64 = 8 << 20;
+
+/// How often a worker waiting for a decode checks for an interrupt.
+const INTERRUPT_POLL: Duration = Duration::from_millis(20);
+
+/// Helper threads decoding, or holding a decoded blob no worker came back
+/// for, at once; past this, large blobs are decoded on the worker.
+const MAX_BACKGROUND_DECODES: usize = 4;
+
+/// Passes over the references [`Repository::freeze_refs`] makes at most
+/// while waiting for two in a row to agree.
+const SNAPSHOT_ATTEMPTS: usize = 10;
+
+/// Pause between passes that disagreed, giving a push time to finish.
+const SNAPSHOT_RETRY_DELAY: Duration = Duration::from_millis(20);
+
+/// Bounds of the object cache size picked from the repository's size when
+/// `--gix-object-cache-mb` is not given.
+const MIN_OBJECT_CACHE: usize = 4 << 20;
+const MAX_OBJECT_CACHE: usize = 64 << 20;
+
+/// Bounds of the pack (delta base) cache size picked from the repository's
+/// size when `--gix-pack-cache-mb` is not given.
+const MIN_PACK_CACHE: usize = 16 << 20;
+const MAX_PACK_CACHE: usize = 256 << 20;
+
+/// Name [`Repository::worktree_heads`] gives the main worktree, after the
+/// `main-worktree/HEAD` pseudo-ref git reads its `HEAD` by.
+pub const MAIN_WORKTREE: &str = "main-worktree";
+
+/// Error of a `HEAD` naming a branch that does not exist yet, as in a
+/// freshly initialized repository.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnbornHead;
+
+impl fmt::Display for UnbornHead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("repository HEAD is unborn and has no target commit")
+    }
+}
+
+impl std::error::Error for UnbornHead {}
+
+/// The references and `HEAD` as read at one point (`--consistent-refs`).
+#[derive(Debug, PartialEq, Eq)]
+struct RefSnapshot {
+    /// Every reference below `refs/` by full name, peeled.
+    refs: Vec<(Vec<u8>, ObjectId)>,
+    /// The commit `HEAD` resolved to, if it did.
+    head: Option<ObjectId>,
+}
+
+/// Large blobs decoded on helper threads, by id. A read retried after an
+/// interrupt waits for the decode its first attempt started, and takes its
+/// result, rather than starting another.
+#[derive(Debug, Default)]
+struct Decodes {
+    started: Mutex<HashMap<ObjectId, Arc<Decode>>>,
+}
+
+/// One background decode and, once done, the blob or the errno it failed
+/// with.
+#[derive(Debug, Default)]
+struct Decode {
+    result: Mutex<Option<Result<Arc<[u8]>, i32>>>,
+    done: Condvar,
+}
+
+impl Decodes {
+    /// The decode of `oid`, started on a helper thread with `repo` unless
+    /// one was started already; `None` if there is no room for another.
+    fn join_or_start(
+        &self,
+        repo: &gix::Repository,
+        oid: ObjectId,
+    ) -> io::Result<Option<Arc<Decode>>> {
+        let mut started = self.started.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(decode) = started.get(&oid) {
+            return Ok(Some(Arc::clone(decode)));
+        }
+        if started.len() >= MAX_BACKGROUND_DECODES {
+            // Make room by dropping a blob nobody came back for.
+            let Some(unclaimed) = started
+                .iter()
+                .find(|(_, decode)| decode.is_done())
+                .map(|(id, _)| *id)
+            else {
+                return Ok(None);
+            };
+            started.remove(&unclaimed);
+        }
+        let decode = Arc::new(Decode::default());
+        let finishing = Arc::clone(&decode);
+        let repo = repo.clone();
+        thread::Builder::new()
+            .name("gitsnapfs-decode".into())
+            .spawn(move || {
+                let decoded = panic::catch_unwind(AssertUnwindSafe(|| decode_blob(&repo, oid)));
+                finishing.finish(match decoded {
+                    Ok(Ok(data)) => Ok(data),
+                    Ok(Err(_)) => Err(libc::ENOENT),
+                    Err(_) => Err(libc::EIO),
+                });
+            })?;
+        started.insert(oid, Arc::clone(&decode));
+        Ok(Some(decode))
+    }
+
+    /// Wait for `decode` of `oid` to finish, unless the request waiting is
+    /// interrupted first, and forget it once it has.
+    fn wait(&self, oid: ObjectId, decode: &Arc<Decode>) -> io::Result<Arc<[u8]>> {
+        let mut result = decode.result.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            if let Some(done) = &*result {
+                let done = done.clone().map_err(io::Error::from_raw_os_error);
+                drop(result);
+                let mut started = self.started.lock().unwrap_or_else(PoisonError::into_inner);
+                if started
+                    .get(&oid)
+                    .is_some_and(|known| Arc::ptr_eq(known, decode))
+                {
+                    started.remove(&oid);
+                }
+                return done;
+            }
+            interrupt::check()?;
+            result = decode
+                .done
+                .wait_timeout(result, INTERRUPT_POLL)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+}
+
+impl Decode {
+    fn is_done(&self) -> bool {
+        self.result
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_some()
+    }
+
+    fn finish(&self, result: Result<Arc<[u8]>, i32>) {
+        *self.result.lock().unwrap_or_else(PoisonError::into_inner) = Some(result);
+        self.done.notify_all();
+    }
+}
+
+/// Sizes of the caches gix decodes objects through (`--gix-object-cache-mb`,
+/// `--gix-pack-cache-mb`), in MiB. A size left out is picked from the size
+/// of the repository's packs; 0 disables the cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ObjectCaches {
+    /// Cache of decoded objects, keyed by id.
+    pub object_mb: Option<usize>,
+    /// Cache of delta bases inflated while decoding packed objects.
+    pub pack_mb: Option<usize>,
+}
+
+impl ObjectCaches {
+    /// Object and pack cache sizes in bytes for a repository whose packs
+    /// take `pack_bytes`: a 64th and a 16th of them by default, so that
+    /// the trees and delta bases a walk keeps coming back to stay decoded,
+    /// within bounds that keep small repositories fast and large ones from
+    /// claiming too much on every worker thread.
+    #[must_use]
+    pub fn bytes(self, pack_bytes: u64) -> (usize, usize) {
+        let share = |divisor: u64, min: usize, max: usize| {
+            usize::try_from(pack_bytes / divisor)
+                .unwrap_or(usize::MAX)
+                .clamp(min, max)
+        };
+        let object = self.object_mb.map_or_else(
+            || share(64, MIN_OBJECT_CACHE, MAX_OBJECT_CACHE),
+            |mb| mb << 20,
+        );
+        let pack = self
+            .pack_mb
+            .map_or_else(|| share(16, MIN_PACK_CACHE, MAX_PACK_CACHE), |mb| mb << 20);
+        (object, pack)
+    }
+}
+
+/// Source of [`Repository::handle_key`] values, unique in the process.
+static HANDLE_KEYS: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// The handle this thread last returned, with the key of the repository
+    /// it was made for.
+    static IDLE_HANDLE: RefCell<Option<(u64, gix::Repository)>> = const { RefCell::new(None) };
+}
+
+/// A thread's handle on a [`Repository`], from [`Repository::thread_local`].
+///
+/// Dropping it hands it back to the thread, so the next request there
+/// decodes through the same object and pack caches.
+pub struct RepoHandle {
+    key: u64,
+    repo: Option<gix::Repository>,
+}
+
+impl Deref for RepoHandle {
+    type Target = gix::Repository;
+
+    fn deref(&self) -> &gix::Repository {
+        self.repo.as_ref().expect("only taken when dropped")
+    }
+}
+
+impl Drop for RepoHandle {
+    fn drop(&mut self) {
+        let Some(repo) = self.repo.take() else {
+            return;
+        };
+        // A handle taken while another was out is dropped; so is one made
+        // before the repository was reopened.
+        let _ = IDLE_HANDLE.try_with(|idle| {
+            let mut idle = idle.borrow_mut();
+            if idle.is_none() {
+                *idle = Some((self.key, repo));
+            }
+        });
+    }
+}
+
 /// Minimal repository wrapper that keeps a thread-safe handle.
 #[derive(Debug)]
 pub struct Repository {
-    inner: ThreadSafeRepository,
+    path: PathBuf,
+    use_replace_refs: bool,
+    inner: RwLock<ThreadSafeRepository>,
+    overlay: ObjectsOverlay,
+    /// References served instead of the live ones, once frozen.
+    ref_snapshot: RwLock<Option<Arc<RefSnapshot>>>,
+    caches: ObjectCaches,
+    /// Total size of the packs, measured when the repository is (re)opened.
+    pack_bytes: AtomicU64,
+    /// Identifies the handles made from the current store and cache sizes;
+    /// renewed whenever either changes.
+    handle_key: AtomicU64,
+    decodes: Decodes,
 }
 
 impl Repository {
-    /// Open a repository at `path`.
+    /// Open a repository at `path`, honoring `refs/replace/` like git does.
+    ///
+    /// `path` may also name a Git bundle file, which is unpacked into a cached
+    /// bare repository first (see [`crate::bundle`]).
     ///
     /// # Errors
     ///
     /// Returns an error if `gix` cannot open the repository at the given path.
     pub fn open(path: &Path) -> Result<Self> {
-        let repo = ThreadSafeRepository::open(path)
-            .with_context(|| format!("failed to open repository at {}", path.display()))?;
-        Ok(Self { inner: repo })
+        Self::open_opts(path, true)
+    }
+
+    /// Open a repository at `path`. With `use_replace_refs` false, objects are
+    /// served as stored even if `refs/replace/` says otherwise, as with
+    /// `git --no-replace-objects`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `gix` cannot open the repository at the given path.
+    pub fn open_opts(path: &Path, use_replace_refs: bool) -> Result<Self> {
+        if bundle::is_bundle(path) {
+            let unbundled = bundle::unbundle_cached(path)
+                .with_context(|| format!("failed to unbundle {}", path.display()))?;
+            return Self::open_opts(&unbundled, use_replace_refs);
+        }
+        let overlay = ObjectsOverlay::new();
+        let repo = open_store(path, use_replace_refs, &overlay)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            use_replace_refs,
+            pack_bytes: AtomicU64::new(pack_bytes(&repo)),
+            inner: RwLock::new(repo),
+            overlay,
+            ref_snapshot: RwLock::new(None),
+            caches: ObjectCaches::default(),
+            handle_key: AtomicU64::new(HANDLE_KEYS.fetch_add(1, Ordering::Relaxed)),
+            decodes: Decodes::default(),
+        })
+    }
+
+    /// Decode objects through caches of the sizes `caches` asks for. Every
+    /// thread keeps a [`Self::thread_local`] handle with caches of its own
+    /// from one request to the next, so they speed up work within a request,
+    /// such as a tree walk or a delta chain, and the requests that follow.
+    #[must_use]
+    pub fn with_object_caches(mut self, caches: ObjectCaches) -> Self {
+        self.caches = caches;
+        self.renew_handles();
+        self
+    }
+
+    /// Object and pack cache sizes in bytes each handle is given.
+    #[must_use]
+    pub fn object_cache_bytes(&self) -> (usize, usize) {
+        self.caches.bytes(self.pack_bytes.load(Ordering::Relaxed))
+    }
+
+    /// Find the repository controlling `path` the way the `git` CLI does and
+    /// open it with [`Self::open_opts`]: `path` may be a worktree or any
+    /// directory inside one, and `GIT_DIR` (with `GIT_WORK_TREE`,
+    /// `GIT_CEILING_DIRECTORIES` and `GIT_DISCOVERY_ACROSS_FILESYSTEM`) is
+    /// honored when set. Bundle files are opened as-is.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no repository is found or it cannot be opened.
+    pub fn discover(path: &Path, use_replace_refs: bool) -> Result<Self> {
+        if bundle::is_bundle(path) {
+            return Self::open_opts(path, use_replace_refs);
+        }
+        let found = ThreadSafeRepository::discover_with_environment_overrides(path)
+            .with_context(|| format!("no repository found at or above {}", path.display()))?;
+        Self::open_opts(found.git_dir(), use_replace_refs)
+    }
+
+    /// Path the repository was opened from (the unpacked repository for bundles).
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// A UUID naming this repository at its current `HEAD`, the same every
+    /// time until `HEAD` moves (`user.gitsnapfs.uuid` on the mount root).
+    ///
+    /// It is the SHA-256 of the absolute repository path and the `HEAD`
+    /// commit, cut to 128 bits and marked as an RFC 9562 version 8 UUID.
+    #[must_use]
+    pub fn volume_uuid(&self) -> String {
+        let mut name = std::path::absolute(&self.path)
+            .unwrap_or_else(|_| self.path.clone())
+            .into_os_string()
+            .into_encoded_bytes();
+        name.push(0);
+        if let Ok(head) = self.resolve_head() {
+            name.extend_from_slice(head.as_bytes());
+        }
+        let mut bytes = sha256::digest(&name);
+        bytes[6] = (bytes[6] & 0x0f) | 0x80;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        let hex = bytes[..16].iter().fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        });
+        format!(
+            "{}-{}-{}-{}-{}",
+            &hex[..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..]
+        )
+    }
+
+    /// Every value of the config `key` (`remote.origin.url`), in the order
+    /// Git reads them; empty if it is not set.
+    #[must_use]
+    pub fn config_values(&self, key: &str) -> Vec<Vec<u8>> {
+        self.thread_local()
+            .config_snapshot()
+            .plumbing()
+            .strings(key)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|value| value.to_vec())
+            .collect()
+    }
+
+    /// Whether `refs/replace/` is honored.
+    #[must_use]
+    pub fn uses_replace_refs(&self) -> bool {
+        self.use_replace_refs
+    }
+
+    /// Reopen the repository from disk, dropping the object and reference
+    /// caches held by the current handle.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the repository can no longer be opened; the
+    /// current handle stays in use in that case.
+    pub fn reload(&self) -> Result<()> {
+        let repo = open_store(&self.path, self.use_replace_refs, &self.overlay)?;
+        self.pack_bytes.store(pack_bytes(&repo), Ordering::Relaxed);
+        *self.inner.write().unwrap_or_else(PoisonError::into_inner) = repo;
+        self.renew_handles();
+        if self.snapshot().is_some() {
+            self.freeze_refs()?;
+        }
+        Ok(())
+    }
+
+    /// Answer every question about references and `HEAD` from a snapshot
+    /// taken now, and from a new one at each [`Self::reload`], instead of
+    /// reading them live (`--consistent-refs`). Refs updated one by one, as
+    /// by a push, thus never show half of the update.
+    ///
+    /// Loose refs and `packed-refs` cannot be read atomically, so the
+    /// references are read until two passes in a row agree; if they never
+    /// do, the last pass is used.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the references cannot be enumerated.
+    pub fn freeze_refs(&self) -> Result<()> {
+        let mut snapshot = self.read_ref_snapshot()?;
+        for _ in 1..SNAPSHOT_ATTEMPTS {
+            let again = self.read_ref_snapshot()?;
+            if again == snapshot {
+                break;
+            }
+            snapshot = again;
+            thread::sleep(SNAPSHOT_RETRY_DELAY);
+        }
+        *self
+            .ref_snapshot
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(snapshot));
+        Ok(())
+    }
+
+    fn read_ref_snapshot(&self) -> Result<RefSnapshot> {
+        let mut refs = self.live_refs()?;
+        refs.sort();
+        Ok(RefSnapshot {
+            refs,
+            head: self.live_head().ok(),
+        })
+    }
+
+    fn snapshot(&self) -> Option<Arc<RefSnapshot>> {
+        self.ref_snapshot
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// References of the snapshot below `prefix`, by escaped short name.
+    fn snapshot_refs(snapshot: &RefSnapshot, prefix: &[u8]) -> Vec<(String, ObjectId)> {
+        snapshot
+            .refs
+            .iter()
+            .filter_map(|(name, id)| {
+                let short = name.strip_prefix(prefix)?;
+                Some((
+                    String::from_utf8_lossy(&escape_name(short)).into_owned(),
+                    *id,
+                ))
+            })
+            .collect()
     }
 
     /// Resolve a hex commit id string to its full 40-byte `ObjectId`.
@@ -34,7 +488,7 @@ impl Repository {
     ///
     /// Returns an error if the hex string does not point to a commit reachable in the repository.
     pub fn resolve_full_commit_id(&self, hex: &str) -> Result<ObjectId> {
-        let repo = self.inner.to_thread_local();
+        let repo = self.thread_local();
         let id = repo.rev_parse_single(hex.as_bytes().as_bstr())?.detach();
         let commit = repo.find_commit(id)?;
         Ok(commit.id)
@@ -44,25 +498,121 @@ impl Repository {
     ///
     /// # Errors
     ///
-    /// Returns an error if `HEAD` cannot be peeled to a commit (for example in an unborn branch).
+    /// Returns an error if `HEAD` cannot be peeled to a commit, [`UnbornHead`]
+    /// if it names a branch that does not exist yet.
     pub fn resolve_head(&self) -> Result<ObjectId> {
-        let repo = self.inner.to_thread_local();
+        match self.snapshot() {
+            Some(snapshot) => snapshot.head.ok_or_else(|| UnbornHead.into()),
+            None => self.live_head(),
+        }
+    }
+
+    fn live_head(&self) -> Result<ObjectId> {
+        let repo = self.thread_local();
         let mut head = repo.head()?;
-        let id = head
-            .try_peel_to_id()?
-            .ok_or_else(|| anyhow!("repository HEAD is unborn and has no target commit"))?
-            .detach();
+        let id = head.try_peel_to_id()?.ok_or(UnbornHead)?.detach();
         let commit = repo.find_commit(id)?;
         Ok(commit.id)
     }
 
+    /// The commit `HEAD` stood at `time`, in seconds since the epoch.
+    ///
+    /// The reflog of the branch `HEAD` names (or of `HEAD` itself when
+    /// detached) answers when it reaches back that far: the last entry
+    /// written at or before `time` wins. Otherwise the first-parent history
+    /// of `HEAD` is walked back to the first commit committed by then.
+    /// `None` if there was no such commit.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the reflog or a commit cannot be read.
+    pub fn commit_at(&self, time: i64) -> Result<Option<ObjectId>> {
+        let repo = self.thread_local();
+        let reference = match repo.head_ref()? {
+            Some(reference) => Some(reference),
+            None => repo.try_find_reference("HEAD")?,
+        };
+        if let Some(reference) = reference {
+            let mut log = reference.log_iter();
+            if let Some(lines) = log.all()? {
+                let mut found = None;
+                for line in lines {
+                    let line = line?;
+                    if line.signature.time()?.seconds > time {
+                        break;
+                    }
+                    found = Some(line.new_oid());
+                }
+                // A null id records the branch being deleted.
+                if let Some(id) = found {
+                    return Ok(Some(id).filter(|id| !id.is_null()));
+                }
+            }
+        }
+        let mut id = match self.resolve_head() {
+            Ok(id) => id,
+            Err(err) if err.is::<UnbornHead>() => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        loop {
+            interrupt::check()?;
+            let commit = repo.find_commit(id)?;
+            if commit.time()?.seconds <= time {
+                return Ok(Some(id));
+            }
+            // The parent may also be missing past a shallow boundary.
+            let parent = commit.parent_ids().next().map(gix::Id::detach);
+            match parent {
+                Some(parent) if repo.has_object(parent) => id = parent,
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    /// The worktrees of the repository and the commits their `HEAD`s are at:
+    /// the main worktree as `main-worktree`, then each linked worktree by
+    /// its escaped id, the name of its directory below `worktrees/` in the
+    /// common directory. Every `HEAD` is read live, and worktrees whose
+    /// `HEAD` is unborn or unreadable are left out.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the worktrees cannot be enumerated.
+    pub fn worktree_heads(&self) -> Result<Vec<(String, ObjectId)>> {
+        let repo = self.thread_local();
+        let head = |name: &str| {
+            repo.try_find_reference(name)
+                .ok()
+                .flatten()
+                .and_then(|mut reference| reference.peel_to_id().ok())
+                .map(gix::Id::detach)
+        };
+        let mut heads = Vec::new();
+        if let Some(id) = head("main-worktree/HEAD") {
+            heads.push((MAIN_WORKTREE.to_owned(), id));
+        }
+        for worktree in repo.worktrees()? {
+            let id = worktree.id().to_owned();
+            if let Some(head) = head(&format!("worktrees/{id}/HEAD")) {
+                heads.push((
+                    String::from_utf8_lossy(&escape_name(&id)).into_owned(),
+                    head,
+                ));
+            }
+        }
+        Ok(heads)
+    }
+
     /// Enumerate local branches and the commits they reference.
     ///
     /// # Errors
     ///
     /// Returns an error if the reference database cannot be enumerated.
     pub fn list_branches(&self) -> Result<Vec<(String, ObjectId)>> {
-        let repo = self.inner.to_thread_local();
+        if let Some(snapshot) = self.snapshot() {
+            return Ok(Self::snapshot_refs(&snapshot, b"refs/heads/"));
+        }
+        let repo = self.thread_local();
         let platform = repo.references()?;
         let iter = platform.local_branches()?.peeled()?;
         collect_refs(iter, b"refs/heads/")
@@ -74,29 +624,558 @@ impl Repository {
     ///
     /// Returns an error if the reference database cannot be enumerated.
     pub fn list_tags(&self) -> Result<Vec<(String, ObjectId)>> {
-        let repo = self.inner.to_thread_local();
+        if let Some(snapshot) = self.snapshot() {
+            return Ok(Self::snapshot_refs(&snapshot, b"refs/tags/"));
+        }
+        let repo = self.thread_local();
         let platform = repo.references()?;
         let iter = platform.tags()?.peeled()?;
         collect_refs(iter, b"refs/tags/")
     }
 
-    pub fn thread_local(&self) -> gix::Repository {
-        self.inner.to_thread_local()
+    /// Whether `commit` is `root` or descends from it, reusing the answers
+    /// `known` returns and returning those for every commit visited.
+    ///
+    /// Adding the returned answers to what `known` consults lets a caller
+    /// asking about many commits of one history walk each commit only once.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a commit on the way cannot be read.
+    pub fn descends_from(
+        &self,
+        commit: ObjectId,
+        root: ObjectId,
+        known: &dyn Fn(&ObjectId) -> Option<bool>,
+    ) -> Result<(bool, HashMap<ObjectId, bool>)> {
+        let repo = self.thread_local();
+        let mut stack = vec![commit];
+        let mut fresh = HashMap::new();
+        // Replacement refs can make the history cyclic; a commit met again
+        // while its ancestors are still being examined counts as unrelated.
+        let mut visiting = HashSet::new();
+        while let Some(&id) = stack.last() {
+            if fresh.contains_key(&id) {
+                stack.pop();
+                continue;
+            }
+            if let Some(answer) = known(&id) {
+                fresh.insert(id, answer);
+                stack.pop();
+                continue;
+            }
+            if id == root {
+                fresh.insert(id, true);
+                stack.pop();
+                continue;
+            }
+            interrupt::check()?;
+            visiting.insert(id);
+            let parents: Vec<ObjectId> = repo
+                .find_commit(id)?
+                .parent_ids()
+                .map(gix::Id::detach)
+                .collect();
+            if parents
+                .iter()
+                .any(|parent| fresh.get(parent) == Some(&true))
+            {
+                fresh.insert(id, true);
+                stack.pop();
+                continue;
+            }
+            let pending: Vec<ObjectId> = parents
+                .into_iter()
+                .filter(|parent| !fresh.contains_key(parent) && !visiting.contains(parent))
+                .collect();
+            if pending.is_empty() {
+                fresh.insert(id, false);
+                stack.pop();
+            } else {
+                stack.extend(pending);
+            }
+        }
+        Ok((fresh.get(&commit) == Some(&true), fresh))
+    }
+
+    /// Generation number of `commit`: 1 for a root commit, else one more than
+    /// the highest of its parents, as `git commit-graph` records it.
+    ///
+    /// Commits the commit-graph file covers are read from it, unless
+    /// replacement refs may change the history it describes; the rest are
+    /// computed, reusing the numbers `known` returns. The numbers of every
+    /// commit visited are returned with it, to add to what `known` consults.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a commit on the way cannot be read.
+    pub fn generation(
+        &self,
+        commit: ObjectId,
+        known: &dyn Fn(&ObjectId) -> Option<u32>,
+    ) -> Result<(u32, HashMap<ObjectId, u32>)> {
+        let repo = self.thread_local();
+        let replaced = self.use_replace_refs
+            && repo
+                .references()?
+                .prefixed(b"refs/replace/".as_bstr())?
+                .next()
+                .is_some();
+        let graph = if replaced {
+            None
+        } else {
+            repo.commit_graph_if_enabled().ok().flatten()
+        };
+        let mut stack = vec![commit];
+        let mut fresh = HashMap::new();
+        // Replacement refs can make the history cyclic; a parent met again
+        // while its ancestors are still being examined is left out.
+        let mut visiting = HashSet::new();
+        while let Some(&id) = stack.last() {
+            if fresh.contains_key(&id) {
+                stack.pop();
+                continue;
+            }
+            if let Some(generation) = known(&id) {
+                fresh.insert(id, generation);
+                stack.pop();
+                continue;
+            }
+            if let Some(generation) = graph
+                .as_ref()
+                .and_then(|graph| graph.commit_by_id(id))
+                .map(|entry| entry.generation())
+            {
+                fresh.insert(id, generation);
+                stack.pop();
+                continue;
+            }
+            interrupt::check()?;
+            let parents: Vec<ObjectId> = repo
+                .find_commit(id)?
+                .parent_ids()
+                .map(gix::Id::detach)
+                .filter(|parent| !visiting.contains(parent) || fresh.contains_key(parent))
+                .collect();
+            let pending: Vec<ObjectId> = parents
+                .iter()
+                .copied()
+                .filter(|parent| !fresh.contains_key(parent))
+                .collect();
+            if !pending.is_empty() && visiting.insert(id) {
+                stack.extend(pending);
+                continue;
+            }
+            let highest = parents
+                .iter()
+                .filter_map(|parent| fresh.get(parent))
+                .max()
+                .copied()
+                .unwrap_or(0);
+            fresh.insert(id, highest.saturating_add(1));
+            stack.pop();
+        }
+        Ok((fresh[&commit], fresh))
+    }
+
+    /// Enumerate every reference below `refs/` by full name, peeled to the
+    /// object it ultimately points at.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the reference database cannot be enumerated or a
+    /// reference cannot be peeled.
+    pub fn list_refs(&self) -> Result<Vec<(Vec<u8>, ObjectId)>> {
+        match self.snapshot() {
+            Some(snapshot) => Ok(snapshot.refs.clone()),
+            None => self.live_refs(),
+        }
+    }
+
+    fn live_refs(&self) -> Result<Vec<(Vec<u8>, ObjectId)>> {
+        let repo = self.thread_local();
+        let mut refs = Vec::new();
+        for reference in repo.references()?.all()?.peeled()? {
+            let mut reference = reference.map_err(|err| anyhow!(err))?;
+            let name = reference.name().as_bstr().to_vec();
+            if name.starts_with(b"refs/") {
+                refs.push((name, reference.peel_to_id()?.detach()));
+            }
+        }
+        Ok(refs)
+    }
+
+    /// Full names of the references whose history contains `commit`, with
+    /// `HEAD` first if it does; with `first_parent`, only their first-parent
+    /// history counts. Tags are peeled; replacement refs and refs to
+    /// anything but commits are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the reference database cannot be enumerated.
+    pub fn reachable_from(&self, commit: ObjectId, first_parent: bool) -> Result<Vec<String>> {
+        let repo = self.thread_local();
+        let mut tips: Vec<(String, ObjectId)> = self
+            .resolve_head()
+            .map(|head| ("HEAD".to_owned(), head))
+            .into_iter()
+            .collect();
+        if let Some(snapshot) = self.snapshot() {
+            tips.extend(
+                snapshot
+                    .refs
+                    .iter()
+                    .filter(|(name, _)| !name.starts_with(b"refs/replace/"))
+                    .map(|(name, id)| (name.to_str_lossy().into_owned(), *id)),
+            );
+        } else {
+            for reference in repo.references()?.all()?.peeled()? {
+                let Ok(reference) = reference else {
+                    continue;
+                };
+                let name = reference.name().as_bstr().to_str_lossy().into_owned();
+                if !name.starts_with("refs/replace/") {
+                    tips.push((name, reference.id().detach()));
+                }
+            }
+        }
+
+        // Whether each visited commit has `commit` in its history, shared
+        // across tips so every commit is inspected once.
+        let mut reaches = HashMap::from([(commit, true)]);
+        let mut parents: HashMap<ObjectId, Vec<ObjectId>> = HashMap::new();
+        let mut names = Vec::new();
+        for (name, tip) in tips {
+            let mut stack = vec![tip];
+            while let Some(&id) = stack.last() {
+                if reaches.contains_key(&id) {
+                    stack.pop();
+                } else if let Some(ids) = parents.get(&id) {
+                    let found = ids.iter().any(|parent| reaches.get(parent) == Some(&true));
+                    reaches.insert(id, found);
+                    stack.pop();
+                } else {
+                    interrupt::check()?;
+                    // Missing commits (shallow history, non-commit refs) end the walk.
+                    let mut ids: Vec<ObjectId> = repo
+                        .find_commit(id)
+                        .map(|found| found.parent_ids().map(gix::Id::detach).collect())
+                        .unwrap_or_default();
+                    if first_parent {
+                        ids.truncate(1);
+                    }
+                    stack.extend(ids.iter().filter(|parent| !reaches.contains_key(*parent)));
+                    parents.insert(id, ids);
+                }
+            }
+            if reaches[&tip] {
+                names.push(name);
+            }
+        }
+        Ok(names)
+    }
+
+    /// The content of blob `oid`.
+    ///
+    /// A large blob read for a request that can be interrupted (see
+    /// [`crate::interrupt`]) is decoded on a helper thread, at most
+    /// [`MAX_BACKGROUND_DECODES`] at once. If the request is interrupted
+    /// first, the worker returns `EINTR` at once; the decode runs on, and
+    /// the retried read takes its result.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ENOENT` if `oid` is not a readable blob and `EINTR` if the
+    /// request was interrupted.
+    pub fn read_blob(&self, oid: ObjectId) -> io::Result<Arc<[u8]>> {
+        interrupt::check()?;
+        let repo = self.thread_local();
+        let large = || {
+            repo.find_header(oid)
+                .is_ok_and(|header| header.size() >= BACKGROUND_DECODE_SIZE)
+        };
+        if interrupt::current().is_none() || !large() {
+            return decode_blob(&repo, oid);
+        }
+        match self.decodes.join_or_start(&repo, oid)? {
+            Some(decode) => self.decodes.wait(oid, &decode),
+            None => decode_blob(&repo, oid),
+        }
+    }
+
+    /// A handle for this thread: the one it last dropped, caches and all,
+    /// unless the repository has been reopened since.
+    pub fn thread_local(&self) -> RepoHandle {
+        let key = self.handle_key.load(Ordering::Acquire);
+        let idle = IDLE_HANDLE
+            .try_with(|idle| idle.borrow_mut().take())
+            .ok()
+            .flatten()
+            .filter(|(idle_key, _)| *idle_key == key);
+        let repo = match idle {
+            Some((_, repo)) => repo,
+            None => self.new_handle(),
+        };
+        RepoHandle {
+            key,
+            repo: Some(repo),
+        }
+    }
+
+    fn new_handle(&self) -> gix::Repository {
+        let mut repo = self
+            .inner
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .to_thread_local();
+        let (object, pack) = self.object_cache_bytes();
+        repo.object_cache_size(object);
+        if pack == 0 {
+            repo.objects.unset_pack_cache();
+        } else {
+            repo.objects.set_pack_cache(move || {
+                Box::new(gix_pack::cache::lru::MemoryCappedHashmap::new(pack))
+            });
+        }
+        repo
+    }
+
+    /// Retire the handles threads keep, once they no longer match the
+    /// store or the cache sizes.
+    fn renew_handles(&self) {
+        self.handle_key.store(
+            HANDLE_KEYS.fetch_add(1, Ordering::Relaxed),
+            Ordering::Release,
+        );
     }
 
     /// Resolve an inode value back to a unique object id by treating it as a hexadecimal prefix.
     ///
     /// # Errors
     ///
-    /// Returns an error if the hexadecimal prefix cannot be resolved to an object in the repository.
+    /// Returns an error if the inode is not an object inode, or its hexadecimal
+    /// prefix cannot be resolved to an object in the repository.
     pub fn resolve_inode(&self, inode: u64) -> Result<ObjectId> {
-        let hex = inode_to_hex_prefix(inode);
-        let repo = self.inner.to_thread_local();
+        let Some(hex) = inode_to_hex_prefix(inode) else {
+            bail!("inode {inode:#x} does not name an object");
+        };
+        let repo = self.thread_local();
         let id = repo.rev_parse_single(hex.as_bytes().as_bstr())?.detach();
         Ok(id)
     }
 }
 
+/// Whether `commit` is on the shallow boundary of `repo`: listed in its
+/// `shallow` file, so the parents it records are not in the repository.
+#[must_use]
+pub fn is_shallow_boundary(repo: &gix::Repository, commit: ObjectId) -> bool {
+    repo.shallow_commits()
+        .ok()
+        .flatten()
+        .is_some_and(|boundary| boundary.contains(&commit))
+}
+
+/// `id` in hex, shortened to the shortest prefix of at least `min_len` digits
+/// naming no other object in `repo`, as `git rev-parse --short` does. The
+/// full id is returned if the object database cannot be searched.
+#[must_use]
+pub fn abbreviate(repo: &gix::Repository, id: ObjectId, min_len: usize) -> String {
+    let len = min_len.clamp(4, id.kind().len_in_hex());
+    gix::odb::store::prefix::disambiguate::Candidate::new(id, len)
+        .ok()
+        .and_then(|candidate| repo.objects.disambiguate_prefix(candidate).ok().flatten())
+        .map_or_else(|| id.to_string(), |prefix| prefix.to_string())
+}
+
+/// The content of blob `oid`, or `ENOENT` if it is not a readable blob.
+fn decode_blob(repo: &gix::Repository, oid: ObjectId) -> io::Result<Arc<[u8]>> {
+    repo.find_blob(oid)
+        .map(|blob| Arc::from(blob.detach().data))
+        .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))
+}
+
+/// Whether `data`, read from `repo` as blob `oid`, is the content that `oid`
+/// names, or that the object replacing it names where `refs/replace/` is
+/// honored (`--verify-reads`).
+#[must_use]
+pub fn blob_matches(repo: &gix::Repository, oid: ObjectId, data: &[u8]) -> bool {
+    let Ok(actual) = gix::objs::compute_hash(repo.object_hash(), gix::object::Kind::Blob, data)
+    else {
+        return false;
+    };
+    actual == oid
+        || repo
+            .objects
+            .store_ref()
+            .replacements()
+            .any(|(original, replacement)| original == oid && replacement == actual)
+}
+
+fn open_store(
+    path: &Path,
+    use_replace_refs: bool,
+    overlay: &ObjectsOverlay,
+) -> Result<ThreadSafeRepository> {
+    let mut repo = ThreadSafeRepository::open(path)
+        .with_context(|| format!("failed to open repository at {}", path.display()))?;
+    let objects_dir = overlay
+        .objects_dir(repo.objects.path())
+        .context("failed to apply GIT_OBJECT_DIRECTORY or GIT_ALTERNATE_OBJECT_DIRECTORIES")?;
+    let replacements = if use_replace_refs {
+        replacements(&repo.to_thread_local())?
+    } else {
+        Vec::new()
+    };
+    // gix only applies replacements when explicitly configured, so install
+    // our own mapping; an empty one also clears whatever gix picked up.
+    let store = gix::odb::Store::at_opts(
+        objects_dir,
+        &mut replacements.into_iter(),
+        gix::odb::store::init::Options {
+            object_hash: repo.to_thread_local().object_hash(),
+            ..Default::default()
+        },
+    )
+    .with_context(|| format!("failed to open object store of {}", path.display()))?;
+    repo.objects = store.into();
+    Ok(repo)
+}
+
+/// Total size of the packs in the repository's own object directory.
+fn pack_bytes(repo: &ThreadSafeRepository) -> u64 {
+    let dir = repo
+        .to_thread_local()
+        .common_dir()
+        .join("objects")
+        .join("pack");
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "pack"))
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+/// Object directory honoring `GIT_OBJECT_DIRECTORY` and
+/// `GIT_ALTERNATE_OBJECT_DIRECTORIES`, as set up by `git receive-pack` for
+/// quarantined pushes or by CI object caches.
+///
+/// gix reads alternates only from `objects/info/alternates`, so with extra
+/// alternates the store is opened at a scratch directory whose alternates
+/// file lists the primary object directory first and the extra ones after
+/// it, which is the order git searches them in. The directory is removed
+/// with the repository, and leftovers of processes that exited without
+/// dropping it are removed by the next process that needs one.
+#[derive(Debug)]
+struct ObjectsOverlay {
+    dir: PathBuf,
+}
+
+const OVERLAY_PREFIX: &str = "gitsnapfs-objects-";
+
+impl ObjectsOverlay {
+    fn new() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "{OVERLAY_PREFIX}{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        Self { dir }
+    }
+
+    /// Remove overlays left behind by processes that no longer run.
+    fn remove_stale() {
+        let Ok(entries) = std::fs::read_dir(std::env::temp_dir()) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let Some(pid) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(OVERLAY_PREFIX))
+                .and_then(|rest| rest.split('-').next())
+                .and_then(|pid| pid.parse().ok())
+            else {
+                continue;
+            };
+            let gone = nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), None)
+                == Err(nix::errno::Errno::ESRCH);
+            if gone {
+                let _ = std::fs::remove_dir_all(entry.path());
+            }
+        }
+    }
+
+    /// Directory to open the object store at, given the repository's own
+    /// `objects` directory.
+    fn objects_dir(&self, own: &Path) -> std::io::Result<PathBuf> {
+        let primary = match std::env::var_os("GIT_OBJECT_DIRECTORY") {
+            Some(dir) if !dir.is_empty() => std::path::absolute(dir)?,
+            _ => own.to_path_buf(),
+        };
+        let alternates = std::env::var_os("GIT_ALTERNATE_OBJECT_DIRECTORIES")
+            .map(|dirs| {
+                std::env::split_paths(&dirs)
+                    .filter(|dir| !dir.as_os_str().is_empty())
+                    .map(std::path::absolute)
+                    .collect::<std::io::Result<Vec<_>>>()
+            })
+            .transpose()?
+            .unwrap_or_default();
+        if alternates.is_empty() {
+            return Ok(primary);
+        }
+        if !self.dir.exists() {
+            Self::remove_stale();
+        }
+        let mut list = Vec::new();
+        for dir in std::iter::once(&primary).chain(&alternates) {
+            list.extend_from_slice(dir.as_os_str().as_encoded_bytes());
+            list.push(b'\n');
+        }
+        std::fs::create_dir_all(self.dir.join("info"))?;
+        std::fs::create_dir_all(self.dir.join("pack"))?;
+        std::fs::write(self.dir.join("info").join("alternates"), list)?;
+        Ok(self.dir.clone())
+    }
+}
+
+impl Drop for ObjectsOverlay {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// `(original, replacement)` pairs from `refs/replace/`, unless disabled by
+/// `GIT_NO_REPLACE_OBJECTS` or `core.useReplaceRefs = false`.
+fn replacements(repo: &gix::Repository) -> Result<Vec<(ObjectId, ObjectId)>> {
+    if std::env::var_os("GIT_NO_REPLACE_OBJECTS").is_some()
+        || repo.config_snapshot().boolean("core.useReplaceRefs") == Some(false)
+    {
+        return Ok(Vec::new());
+    }
+    let platform = repo.references()?;
+    let mut pairs = Vec::new();
+    for reference in platform.prefixed(b"refs/replace/".as_bstr())? {
+        let Ok(reference) = reference else {
+            continue;
+        };
+        let original = reference
+            .name()
+            .as_bstr()
+            .strip_prefix(b"refs/replace/")
+            .and_then(|hex| ObjectId::from_hex(hex).ok());
+        if let (Some(original), Some(target)) = (original, reference.target().try_id()) {
+            pairs.push((original, target.to_owned()));
+        }
+    }
+    Ok(pairs)
+}
+
 fn collect_refs(
     iter: gix::reference::iter::Iter<'_, '_>,
     prefix: &[u8],
@@ -108,7 +1187,8 @@ fn collect_refs(
                 anyhow!(err)
             })?;
 
-            let ref_name_string = reference.name().as_bstr().to_string();
+            let ref_name = reference.name().as_bstr().to_vec();
+            let ref_name_string = ref_name.to_str_lossy().into_owned();
             let id = reference
                 .peel_to_id()
                 .map_err(|err| {
@@ -117,10 +1197,76 @@ fn collect_refs(
                 })?
                 .detach();
 
-            let name_bytes = ref_name_string.as_bytes();
-            let short_bytes = name_bytes.strip_prefix(prefix).unwrap_or(name_bytes);
-            let short = String::from_utf8_lossy(short_bytes).into_owned();
+            let short_bytes = ref_name.strip_prefix(prefix).unwrap_or(&ref_name);
+            // Escaped names are valid UTF-8 by construction.
+            let short = String::from_utf8_lossy(&escape_name(short_bytes)).into_owned();
             Ok((short, id))
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_sizes_follow_pack_size_within_bounds() {
+        let default = ObjectCaches::default();
+        assert_eq!(default.bytes(0), (MIN_OBJECT_CACHE, MIN_PACK_CACHE));
+        assert_eq!(default.bytes(1 << 30), (16 << 20, 64 << 20));
+        assert_eq!(default.bytes(1 << 40), (MAX_OBJECT_CACHE, MAX_PACK_CACHE));
+        let explicit = ObjectCaches {
+            object_mb: Some(0),
+            pack_mb: Some(512),
+        };
+        assert_eq!(explicit.bytes(1 << 40), (0, 512 << 20));
+    }
+
+    #[test]
+    fn threads_keep_their_handle_until_a_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        gix::init_bare(dir.path()).unwrap();
+        let repo = Repository::open(dir.path()).unwrap();
+        let idle = || IDLE_HANDLE.with_borrow(|idle| idle.as_ref().map(|(key, _)| *key));
+
+        let handle = repo.thread_local();
+        let key = handle.key;
+        drop(handle);
+        assert_eq!(idle(), Some(key));
+        let handle = repo.thread_local();
+        assert_eq!(handle.key, key);
+        assert_eq!(idle(), None);
+        drop(handle);
+
+        repo.reload().unwrap();
+        let handle = repo.thread_local();
+        assert_ne!(handle.key, key);
+        assert_eq!(idle(), None);
+    }
+
+    #[test]
+    fn a_read_takes_the_decode_already_started() {
+        let dir = tempfile::tempdir().unwrap();
+        let content = vec![7u8; usize::try_from(BACKGROUND_DECODE_SIZE).unwrap()];
+        let oid = gix::init_bare(dir.path())
+            .unwrap()
+            .write_blob(&content)
+            .unwrap()
+            .detach();
+        let repo = Repository::open(dir.path()).unwrap();
+        // As left behind by an earlier attempt that was interrupted.
+        let started = repo
+            .decodes
+            .join_or_start(&repo.thread_local(), oid)
+            .unwrap()
+            .unwrap();
+
+        let token = interrupt::Token::default();
+        let _entered = token.enter();
+        let data = repo.read_blob(oid).unwrap();
+        assert_eq!(*data, content[..]);
+        let finished = started.result.lock().unwrap().clone().unwrap().unwrap();
+        assert!(Arc::ptr_eq(&data, &finished));
+        assert!(repo.decodes.started.lock().unwrap().is_empty());
+    }
+}
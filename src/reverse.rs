@@ -0,0 +1,362 @@
+//! Reverse index from blobs to the commits whose snapshots contain them.
+//!
+//! Git only links downwards, from commits to trees to blobs, so finding the
+//! commits that contain a blob means walking history. [`ReverseIndex`] does
+//! that walk once: it records, for every tree and blob reachable from the
+//! references, the trees listing it, and for every root tree the commits
+//! pointing at it. A query then climbs from the blob up to the root trees.
+//!
+//! With `--first-parent`, only the first-parent history of each tip is
+//! indexed, so merged-in side branches do not count as containing a blob.
+//!
+//! Objects never change, so the index depends only on the reference tips
+//! and that choice. It is saved in the cache directory under a name derived from the
+//! repository and its tips, and reused until a reference moves.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use gix::object::tree::EntryKind;
+use gix::object::Kind;
+use gix::ObjectId;
+use tracing::warn;
+
+use crate::repo::Repository;
+use crate::settings;
+
+/// First bytes of a saved index, naming its format.
+const MAGIC: &[u8] = b"gitsnapfs reverse index 1\n";
+
+/// Which trees and commits lead to each reachable object.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReverseIndex {
+    /// Trees listing each reachable tree or blob as an entry.
+    parents: HashMap<ObjectId, Vec<ObjectId>>,
+    /// Commits whose root tree each tree is.
+    commits: HashMap<ObjectId, Vec<ObjectId>>,
+}
+
+impl ReverseIndex {
+    /// The index of `repo` at `tips` (see [`tips`]), following only first
+    /// parents if `first_parent`: the saved one if the references have not
+    /// moved since it was built, otherwise a new one, which is saved in
+    /// turn. Failing to save only costs a warning.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an object reachable from `tips` cannot be read.
+    pub fn load_or_build(repo: &Repository, tips: &[ObjectId], first_parent: bool) -> Result<Self> {
+        let path = index_path(repo, tips, first_parent);
+        let hash_len = repo.thread_local().object_hash().len_in_bytes();
+        if let Some(data) = path.as_ref().and_then(|path| fs::read(path).ok()) {
+            match Self::decode(&data, hash_len) {
+                Ok(index) => return Ok(index),
+                Err(err) => warn!("ignoring saved reverse index: {err:#}"),
+            }
+        }
+        let index = Self::build(&repo.thread_local(), tips, first_parent)?;
+        if let Some(path) = &path {
+            if let Err(err) = index.save(path) {
+                warn!("{err:#}");
+            }
+        }
+        Ok(index)
+    }
+
+    /// Walk every commit reachable from the commits among `tips`, or only
+    /// along first parents if `first_parent`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a commit or tree cannot be read.
+    pub fn build(repo: &gix::Repository, tips: &[ObjectId], first_parent: bool) -> Result<Self> {
+        let mut index = Self::default();
+        let commit_tips: Vec<ObjectId> = tips
+            .iter()
+            .copied()
+            .filter(|&id| {
+                repo.find_header(id)
+                    .is_ok_and(|header| header.kind() == Kind::Commit)
+            })
+            .collect();
+        let mut pending = Vec::new();
+        let mut walk = repo.rev_walk(commit_tips);
+        if first_parent {
+            walk = walk.first_parent_only();
+        }
+        for info in walk.all()? {
+            let id = info?.id;
+            let tree = repo.find_commit(id)?.tree_id()?.detach();
+            let commits = index.commits.entry(tree).or_default();
+            if commits.is_empty() {
+                pending.push(tree);
+            }
+            commits.push(id);
+        }
+        let mut seen: HashSet<ObjectId> = pending.iter().copied().collect();
+        while let Some(tree) = pending.pop() {
+            for entry in repo.find_tree(tree)?.iter() {
+                let entry = entry?;
+                let child = entry.inner.oid.to_owned();
+                match entry.inner.mode.kind() {
+                    EntryKind::Tree => {
+                        if seen.insert(child) {
+                            pending.push(child);
+                        }
+                    }
+                    EntryKind::Blob | EntryKind::BlobExecutable | EntryKind::Link => {}
+                    // Submodule commits live in another repository.
+                    EntryKind::Commit => continue,
+                }
+                let parents = index.parents.entry(child).or_default();
+                // A tree naming the same object twice only counts once.
+                if parents.last() != Some(&tree) {
+                    parents.push(tree);
+                }
+            }
+        }
+        Ok(index)
+    }
+
+    /// Commits whose snapshots contain `object` anywhere, sorted by id.
+    #[must_use]
+    pub fn commits_containing(&self, object: ObjectId) -> Vec<ObjectId> {
+        let mut found = Vec::new();
+        let mut seen = HashSet::from([object]);
+        let mut pending = vec![object];
+        while let Some(id) = pending.pop() {
+            if let Some(commits) = self.commits.get(&id) {
+                found.extend_from_slice(commits);
+            }
+            for &parent in self.parents.get(&id).into_iter().flatten() {
+                if seen.insert(parent) {
+                    pending.push(parent);
+                }
+            }
+        }
+        found.sort_unstable();
+        found
+    }
+
+    /// Up to `limit` paths below a commit's snapshot at which `object` is
+    /// found, as `(commit, path)` with the path slash-separated. Each tree
+    /// on the way is read to find the entry names.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a tree on the way cannot be read.
+    pub fn paths_to(
+        &self,
+        repo: &gix::Repository,
+        object: ObjectId,
+        limit: usize,
+    ) -> Result<Vec<(ObjectId, Vec<u8>)>> {
+        let mut found = Vec::new();
+        let mut pending = vec![(object, Vec::new())];
+        while let Some((id, path)) = pending.pop() {
+            for &commit in self.commits.get(&id).into_iter().flatten() {
+                if found.len() == limit {
+                    return Ok(found);
+                }
+                found.push((commit, path.clone()));
+            }
+            // Pushed in reverse so the first parent is climbed first.
+            for &parent in self.parents.get(&id).into_iter().flatten().rev() {
+                for entry in repo.find_tree(parent)?.iter() {
+                    let entry = entry?;
+                    if entry.inner.oid == id {
+                        let mut above = entry.inner.filename.to_vec();
+                        if !path.is_empty() {
+                            above.push(b'/');
+                            above.extend_from_slice(&path);
+                        }
+                        pending.push((parent, above));
+                    }
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        let dir = path.parent().context("reverse index path has no parent")?;
+        fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+        // Indexes for older tips of the same repository are of no further use.
+        if let Some(prefix) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.split_once('-'))
+            .map(|(repo_key, _)| format!("{repo_key}-"))
+        {
+            for entry in fs::read_dir(dir)?.flatten() {
+                if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                    let _ = fs::remove_file(entry.path());
+                }
+            }
+        }
+        let partial = path.with_extension("partial");
+        fs::write(&partial, self.encode())
+            .and_then(|()| fs::rename(&partial, path))
+            .with_context(|| format!("failed to save reverse index {}", path.display()))
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        for map in [&self.parents, &self.commits] {
+            out.extend_from_slice(&(map.len() as u64).to_le_bytes());
+            for (id, list) in map {
+                out.extend_from_slice(id.as_bytes());
+                out.extend_from_slice(&(list.len() as u64).to_le_bytes());
+                for id in list {
+                    out.extend_from_slice(id.as_bytes());
+                }
+            }
+        }
+        out
+    }
+
+    fn decode(data: &[u8], hash_len: usize) -> Result<Self> {
+        let Some(mut rest) = data.strip_prefix(MAGIC) else {
+            bail!("not a reverse index, or one of another version");
+        };
+        let mut take = |len: usize| -> Result<&[u8]> {
+            if rest.len() < len {
+                bail!("reverse index is truncated");
+            }
+            let (head, tail) = rest.split_at(len);
+            rest = tail;
+            Ok(head)
+        };
+        let mut maps = [HashMap::new(), HashMap::new()];
+        for map in &mut maps {
+            let count = u64::from_le_bytes(take(8)?.try_into()?);
+            for _ in 0..count {
+                let id = gix::hash::oid::try_from_bytes(take(hash_len)?)?.to_owned();
+                let len = u64::from_le_bytes(take(8)?.try_into()?);
+                let list = (0..len)
+                    .map(|_| Ok(gix::hash::oid::try_from_bytes(take(hash_len)?)?.to_owned()))
+                    .collect::<Result<Vec<_>>>()?;
+                map.insert(id, list);
+            }
+        }
+        let [parents, commits] = maps;
+        Ok(Self { parents, commits })
+    }
+}
+
+/// The target of every reference `keep` accepts by full name, plus `HEAD`,
+/// sorted: what an index depends on.
+///
+/// # Errors
+///
+/// Returns an error if the references cannot be listed.
+pub fn tips(repo: &Repository, keep: impl Fn(&[u8]) -> bool) -> Result<Vec<ObjectId>> {
+    let mut tips: Vec<ObjectId> = repo
+        .list_refs()?
+        .into_iter()
+        .filter(|(name, _)| keep(name))
+        .map(|(_, id)| id)
+        .collect();
+    tips.extend(repo.resolve_head().ok());
+    tips.sort_unstable();
+    tips.dedup();
+    Ok(tips)
+}
+
+/// Where the index of `repo` at `tips` is saved, if there is a cache directory.
+fn index_path(repo: &Repository, tips: &[ObjectId], first_parent: bool) -> Option<PathBuf> {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    repo.path().hash(&mut hasher);
+    repo.uses_replace_refs().hash(&mut hasher);
+    first_parent.hash(&mut hasher);
+    let repo_key = hasher.finish();
+    tips.hash(&mut hasher);
+    let tips_key = hasher.finish();
+    let dir = settings::cache_dir().ok()?.join("reverse-index");
+    Some(dir.join(format!("{repo_key:016x}-{tips_key:016x}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn climbs_to_commits_and_survives_encoding() {
+        let id = |byte| ObjectId::from_bytes_or_panic(&[byte; 20]);
+        let (blob, subtree, root, other_root) = (id(1), id(2), id(3), id(4));
+        let mut index = ReverseIndex::default();
+        index.parents.insert(blob, vec![subtree, other_root]);
+        index.parents.insert(subtree, vec![root]);
+        index.commits.insert(root, vec![id(10), id(11)]);
+        index.commits.insert(other_root, vec![id(12)]);
+        assert_eq!(index.commits_containing(blob), [id(10), id(11), id(12)]);
+        assert_eq!(index.commits_containing(subtree), [id(10), id(11)]);
+        assert!(index.commits_containing(id(9)).is_empty());
+
+        let decoded = ReverseIndex::decode(&index.encode(), 20).unwrap();
+        assert_eq!(decoded, index);
+        assert!(ReverseIndex::decode(&index.encode()[..40], 20).is_err());
+    }
+
+    #[test]
+    fn names_the_paths_leading_to_an_object() {
+        use gix::objs::tree;
+
+        let dir = tempfile::tempdir().unwrap();
+        let repo = gix::init_bare(dir.path()).unwrap();
+        let blob = repo.write_blob(b"shared\n").unwrap().detach();
+        let entry = |name: &str, mode: tree::EntryKind, oid| tree::Entry {
+            mode: mode.into(),
+            filename: name.into(),
+            oid,
+        };
+        let sub = repo
+            .write_object(&gix::objs::Tree {
+                entries: vec![entry("a", tree::EntryKind::Blob, blob)],
+            })
+            .unwrap()
+            .detach();
+        let root = repo
+            .write_object(&gix::objs::Tree {
+                entries: vec![
+                    entry("b", tree::EntryKind::Blob, blob),
+                    entry("sub", tree::EntryKind::Tree, sub),
+                ],
+            })
+            .unwrap()
+            .detach();
+        let signature = gix::actor::Signature {
+            name: "Test".into(),
+            email: "test@example.com".into(),
+            time: gix::date::Time::new(1_700_000_000, 0),
+        };
+        let commit = repo
+            .write_object(&gix::objs::Commit {
+                tree: root,
+                parents: Vec::new().into(),
+                author: signature.clone(),
+                committer: signature,
+                encoding: None,
+                message: "init\n".into(),
+                extra_headers: Vec::new(),
+            })
+            .unwrap()
+            .detach();
+
+        let index = ReverseIndex::build(&repo, &[commit], false).unwrap();
+        let mut paths = index.paths_to(&repo, blob, 5).unwrap();
+        paths.sort();
+        assert_eq!(
+            paths,
+            [(commit, b"b".to_vec()), (commit, b"sub/a".to_vec())]
+        );
+        assert_eq!(index.paths_to(&repo, blob, 1).unwrap().len(), 1);
+        assert_eq!(
+            index.paths_to(&repo, root, 5).unwrap(),
+            [(commit, Vec::new())]
+        );
+    }
+}
@@ -0,0 +1,218 @@
+//! Sampled re-verification of served blobs (`--verify-sample`).
+//!
+//! `--verify-reads` hashes every blob before serving it, which roughly
+//! doubles the cost of a cold read. To watch storage that is merely suspect,
+//! a sample is enough: a fraction of the files read from their start is
+//! handed to a background thread, which reads each blob again from the
+//! repository and hashes it against its id. Reads are never held up or
+//! failed by this; it only counts, in `/.gitsnapfs/scrub`:
+//!
+//! ```text
+//! sampled 120
+//! verified 117
+//! corrupt 1
+//! unreadable 0
+//! dropped 2
+//! last_corrupt 4b825dc642cb6eb9a060e54bf8d69288fbbed49a
+//! ```
+//!
+//! `sampled` counts the blobs picked and `dropped` those skipped because the
+//! thread was still busy with earlier ones. A blob is `corrupt` if its
+//! content does not hash to its id (or its replacement's, under
+//! `refs/replace/`) and `unreadable` if it could not be read at all; each is
+//! logged as an error, and the last corrupt id is shown.
+
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::{bail, Result};
+use gix::ObjectId;
+use tracing::error;
+
+use crate::fs::GitSnapFs;
+use crate::repo;
+
+/// Blobs waiting for the thread before further samples are dropped.
+const QUEUE_LEN: usize = 64;
+
+/// Sampling state and the counts of what the samples showed.
+#[derive(Debug)]
+pub struct Scrub {
+    fraction: f64,
+    offered: AtomicU64,
+    sender: SyncSender<ObjectId>,
+    receiver: Mutex<Option<Receiver<ObjectId>>>,
+    sampled: AtomicU64,
+    verified: AtomicU64,
+    corrupt: AtomicU64,
+    unreadable: AtomicU64,
+    dropped: AtomicU64,
+    last_corrupt: Mutex<Option<ObjectId>>,
+}
+
+impl Scrub {
+    /// Sample `fraction` of the blobs offered.
+    #[must_use]
+    pub fn new(fraction: f64) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_LEN);
+        Self {
+            fraction,
+            offered: AtomicU64::new(0),
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+            sampled: AtomicU64::new(0),
+            verified: AtomicU64::new(0),
+            corrupt: AtomicU64::new(0),
+            unreadable: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            last_corrupt: Mutex::new(None),
+        }
+    }
+
+    /// Note that the blob `oid` is being served, queueing it for
+    /// verification if it falls in the sample.
+    ///
+    /// Every blob offered advances a running total by `fraction`; one is
+    /// picked each time the total passes a whole number, so the sample is
+    /// spread evenly over the reads rather than left to chance.
+    pub fn offer(&self, oid: ObjectId) {
+        let n = self.offered.fetch_add(1, Ordering::Relaxed);
+        #[allow(clippy::cast_precision_loss)]
+        let picked = ((n + 1) as f64 * self.fraction).floor() > (n as f64 * self.fraction).floor();
+        if !picked {
+            return;
+        }
+        self.sampled.fetch_add(1, Ordering::Relaxed);
+        match self.sender.try_send(oid) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Read `oid` again from `repo` and count what its content hashes to.
+    fn verify(&self, repo: &gix::Repository, oid: ObjectId) {
+        match repo.find_blob(oid) {
+            Ok(blob) if repo::blob_matches(repo, oid, &blob.data) => {
+                self.verified.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(_) => {
+                error!("blob {oid} does not match its id; the repository storage is corrupt");
+                self.corrupt.fetch_add(1, Ordering::Relaxed);
+                *self
+                    .last_corrupt
+                    .lock()
+                    .unwrap_or_else(std::sync::PoisonError::into_inner) = Some(oid);
+            }
+            Err(err) => {
+                error!("blob {oid} served earlier can no longer be read: {err}");
+                self.unreadable.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// The contents of `/.gitsnapfs/scrub`.
+    #[must_use]
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        for (key, counter) in [
+            ("sampled", &self.sampled),
+            ("verified", &self.verified),
+            ("corrupt", &self.corrupt),
+            ("unreadable", &self.unreadable),
+            ("dropped", &self.dropped),
+        ] {
+            let _ = writeln!(text, "{key} {}", counter.load(Ordering::Relaxed));
+        }
+        if let Some(oid) = *self
+            .last_corrupt
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+        {
+            let _ = writeln!(text, "last_corrupt {oid}");
+        }
+        text
+    }
+}
+
+/// Parse the `--verify-sample` fraction, greater than 0 and at most 1.
+///
+/// # Errors
+///
+/// Returns an error if `input` is not a number in that range.
+pub fn parse_fraction(input: &str) -> Result<f64> {
+    match input.trim().parse::<f64>() {
+        Ok(fraction) if fraction > 0.0 && fraction <= 1.0 => Ok(fraction),
+        _ => bail!("expected a fraction greater than 0 and at most 1, got '{input}'"),
+    }
+}
+
+/// Start the thread verifying the blobs `fs` samples; does nothing if it
+/// does not sample. The thread ends once `fs` is dropped.
+///
+/// # Errors
+///
+/// Returns an error if the thread cannot be started.
+pub fn spawn(fs: &Arc<GitSnapFs>) -> std::io::Result<()> {
+    let Some(receiver) = fs.scrub().and_then(|scrub| {
+        scrub
+            .receiver
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .take()
+    }) else {
+        return Ok(());
+    };
+    // Dropping `fs` drops the sender, which ends the loop.
+    let fs = Arc::downgrade(fs);
+    thread::Builder::new()
+        .name("gitsnapfs-scrub".into())
+        .spawn(move || {
+            for oid in receiver {
+                let Some(fs) = fs.upgrade() else {
+                    break;
+                };
+                if let Some(scrub) = fs.scrub() {
+                    scrub.verify(&fs.repository().thread_local(), oid);
+                }
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_the_fraction_asked_for() {
+        let scrub = Scrub::new(0.25);
+        let oid = ObjectId::null(gix::hash::Kind::Sha1);
+        for _ in 0..40 {
+            scrub.offer(oid);
+        }
+        assert_eq!(scrub.sampled.load(Ordering::Relaxed), 10);
+        assert_eq!(
+            scrub
+                .receiver
+                .lock()
+                .unwrap()
+                .as_ref()
+                .unwrap()
+                .try_iter()
+                .count(),
+            10
+        );
+        assert!(scrub.render().starts_with("sampled 10\nverified 0\n"));
+
+        assert!((parse_fraction("0.01").unwrap() - 0.01).abs() < f64::EPSILON);
+        assert!((parse_fraction("1").unwrap() - 1.0).abs() < f64::EPSILON);
+        for bad in ["0", "-0.5", "1.5", "NaN", "often"] {
+            assert!(parse_fraction(bad).is_err(), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! Defaults for command-line flags from the environment and a config file.
+//!
+//! Every long flag `--some-flag` can also be given as `GITSNAPFS_SOME_FLAG`
+//! in the environment, or as `some-flag = ...` (or `some_flag`) in
+//! `$XDG_CONFIG_HOME/gitsnapfs/config.toml`, which defaults to
+//! `~/.config/gitsnapfs/config.toml`; `GITSNAPFS_CONFIG` names a different
+//! file. The command line wins over the environment, which wins over the
+//! file, so service units can keep their settings in one place and override
+//! single flags when needed.
+//!
+//! Config files are TOML with every flag at the top level, set to a string,
+//! an integer, a boolean or, for repeatable flags, an array of them. `true`
+//! passes a switch, `false` leaves it off.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::parser::ValueSource;
+use clap::{Arg, Command};
+
+/// Prefix of the environment variable standing in for each long flag.
+const ENV_PREFIX: &str = "GITSNAPFS_";
+
+/// A value assigned to a flag in the config file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Bool(bool),
+    Text(String),
+}
+
+/// One `key = value` assignment; arrays yield one setting per element.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Setting {
+    /// Long flag name, with `_` normalised to `-`.
+    pub key: String,
+    pub value: Value,
+}
+
+/// Let every long flag of `cmd` and its subcommands be set through its
+/// `GITSNAPFS_*` environment variable.
+#[must_use]
+pub fn with_env(cmd: Command) -> Command {
+    fn add_env(arg: Arg) -> Arg {
+        match arg.get_long() {
+            // Builders take `'static` names; the command lives as long as the process.
+            Some(long) => {
+                let var: &'static str = Box::leak(env_var(long).into_boxed_str());
+                arg.env(var)
+            }
+            None => arg,
+        }
+    }
+    cmd.mut_args(add_env)
+        .mut_subcommands(|sub| sub.mut_args(add_env))
+}
+
+/// Environment variable for the flag `--long`.
+fn env_var(long: &str) -> String {
+    format!("{ENV_PREFIX}{}", long.to_uppercase().replace('-', "_"))
+}
+
+/// Config file to read: `GITSNAPFS_CONFIG`, or `gitsnapfs/config.toml` in
+/// the XDG config directory. `None` if neither location can be determined.
+#[must_use]
+pub fn config_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("GITSNAPFS_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("gitsnapfs").join("config.toml"))
+}
+
+/// Directory for data gitsnapfs can rebuild: `gitsnapfs` in the XDG cache
+/// directory.
+///
+/// # Errors
+///
+/// Returns an error if neither `XDG_CACHE_HOME` nor `HOME` is set.
+pub fn cache_dir() -> Result<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .context("neither XDG_CACHE_HOME nor HOME is set; there is no cache directory")?;
+    Ok(base.join("gitsnapfs"))
+}
+
+/// Read the settings in `path`; a missing file holds none.
+///
+/// # Errors
+///
+/// Returns an error if the file exists but cannot be read or parsed.
+pub fn load(path: &Path) -> Result<Vec<Setting>> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {}", path.display())),
+    };
+    parse(&text)
+}
+
+/// Parse config file contents.
+///
+/// # Errors
+///
+/// Returns an error for invalid TOML, tables, and values no flag takes,
+/// naming the offending line or key.
+pub fn parse(text: &str) -> Result<Vec<Setting>> {
+    let table: toml::Table = toml::from_str(text)?;
+    let mut settings = Vec::new();
+    for (key, value) in table {
+        let values = match value {
+            toml::Value::Array(items) => items,
+            toml::Value::Table(_) => {
+                bail!("'{key}': tables are not supported; set flags at the top level")
+            }
+            value => vec![value],
+        };
+        for value in values {
+            let value = scalar(value).with_context(|| format!("'{key}'"))?;
+            settings.push(Setting {
+                key: key.replace('_', "-"),
+                value,
+            });
+        }
+    }
+    Ok(settings)
+}
+
+/// The flag value a string, integer or boolean stands for.
+fn scalar(value: toml::Value) -> Result<Value> {
+    Ok(match value {
+        toml::Value::Boolean(value) => Value::Bool(value),
+        toml::Value::String(text) => Value::Text(text),
+        toml::Value::Integer(number) => Value::Text(number.to_string()),
+        other => bail!("unsupported {} value; use a string", other.type_str()),
+    })
+}
+
+/// Add `settings` to the command line `args` for `cmd` wherever the flag was
+/// given neither on the command line nor through the environment. Settings
+/// apply to the top-level command or to the subcommand being run; keys that
+/// only other subcommands accept are skipped.
+///
+/// # Errors
+///
+/// Returns an error for keys no command accepts, or switches set to a string.
+pub fn apply(
+    cmd: &Command,
+    mut args: Vec<OsString>,
+    settings: &[Setting],
+) -> Result<Vec<OsString>> {
+    if settings.is_empty() {
+        return Ok(args);
+    }
+    // Errors such as missing required flags may be fixed by the settings;
+    // anything else (`--help`) is left for the real parse to report.
+    let Ok(matches) = cmd.clone().ignore_errors(true).try_get_matches_from(&args) else {
+        return Ok(args);
+    };
+    let (target, matches, insert_at) = match matches.subcommand() {
+        Some((name, sub_matches)) => {
+            let position = args.iter().position(|arg| arg == name).unwrap_or(0);
+            let Some(sub) = cmd.find_subcommand(name) else {
+                return Ok(args);
+            };
+            (sub, sub_matches, position + 1)
+        }
+        None => (cmd, &matches, 1.min(args.len())),
+    };
+    let mut extra = Vec::new();
+    for setting in settings {
+        let Some(arg) = long_arg(target, &setting.key) else {
+            let known = long_arg(cmd, &setting.key).is_some()
+                || cmd
+                    .get_subcommands()
+                    .any(|sub| long_arg(sub, &setting.key).is_some());
+            if known {
+                continue;
+            }
+            bail!("unknown setting '{}'", setting.key);
+        };
+        if matches!(
+            matches.value_source(arg.get_id().as_str()),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+        let takes_value = arg.get_action().takes_values();
+        match (&setting.value, takes_value) {
+            (Value::Bool(true), false) => extra.push(OsString::from(format!("--{}", setting.key))),
+            (Value::Bool(false), false) => {}
+            (Value::Bool(value), true) => {
+                extra.push(OsString::from(format!("--{}={value}", setting.key)));
+            }
+            (Value::Text(text), true) => {
+                extra.push(OsString::from(format!("--{}={text}", setting.key)));
+            }
+            (Value::Text(_), false) => {
+                bail!("'{}' is a switch; set it to true or false", setting.key);
+            }
+        }
+    }
+    args.splice(insert_at..insert_at, extra);
+    Ok(args)
+}
+
+fn long_arg<'a>(cmd: &'a Command, long: &str) -> Option<&'a Arg> {
+    cmd.get_arguments().find(|arg| arg.get_long() == Some(long))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_flat_toml() {
+        let settings = parse(
+            "# service defaults\n\
+             repo = \"/srv/git/project.git\"\n\
+             max_memory = '2G'  # per mount\n\
+             threads = 8\n\
+             stats = true\n\
+             submodule-path = [\n  \"lib=/srv/lib\",\n  'doc=/srv/doc',\n]\n",
+        )
+        .unwrap();
+        let text = |key: &str, value: &str| Setting {
+            key: key.into(),
+            value: Value::Text(value.into()),
+        };
+        assert_eq!(
+            settings,
+            [
+                text("repo", "/srv/git/project.git"),
+                text("max-memory", "2G"),
+                text("threads", "8"),
+                Setting {
+                    key: "stats".into(),
+                    value: Value::Bool(true),
+                },
+                text("submodule-path", "lib=/srv/lib"),
+                text("submodule-path", "doc=/srv/doc"),
+            ]
+        );
+        assert!(parse("[mount]\n").is_err());
+        assert!(parse("repo = /srv\n").is_err());
+        assert!(parse("repo = \"/srv\n").is_err());
+        assert!(parse("max-memory = 1.5\n").is_err());
+    }
+
+    #[test]
+    fn command_line_wins_over_settings() {
+        let cmd = Command::new("gitsnapfs")
+            .arg(Arg::new("repo").long("repo").required(true))
+            .arg(Arg::new("threads").long("threads"))
+            .arg(
+                Arg::new("stats")
+                    .long("stats")
+                    .action(clap::ArgAction::SetTrue),
+            );
+        let settings = parse("repo = '/file'\nthreads = 4\nstats = true\n").unwrap();
+        let args = apply(
+            &cmd,
+            vec!["gitsnapfs".into(), "--threads=2".into()],
+            &settings,
+        )
+        .unwrap();
+        let matches = cmd.clone().try_get_matches_from(args).unwrap();
+        assert_eq!(matches.get_one::<String>("repo").unwrap(), "/file");
+        assert_eq!(matches.get_one::<String>("threads").unwrap(), "2");
+        assert!(matches.get_flag("stats"));
+
+        let unknown = parse("mountpoitn = '/mnt'\n").unwrap();
+        assert!(apply(&cmd, vec!["gitsnapfs".into()], &unknown).is_err());
+    }
+}
@@ -0,0 +1,454 @@
+//! Read-only SFTP (protocol version 3) server over a byte stream.
+//!
+//! The server speaks the SFTP subsystem protocol on stdin/stdout and leaves
+//! the SSH transport to OpenSSH: register it as a subsystem in `sshd_config`
+//!
+//! ```text
+//! Subsystem gitsnapfs /usr/bin/gitsnapfs --repo /srv/repo.git --sftp-stdio
+//! ```
+//!
+//! or run it locally with `sftp -D "gitsnapfs --repo ... --sftp-stdio"`.
+//! Requests are answered from the [`Vfs`] layer; every operation that would
+//! modify the tree fails with `SSH_FX_PERMISSION_DENIED`.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use anyhow::Result;
+use time::OffsetDateTime;
+
+use crate::vfs::{DirEntry, Node, NodeKind, Vfs};
+
+const SFTP_VERSION: u32 = 3;
+
+const FXP_INIT: u8 = 1;
+const FXP_VERSION: u8 = 2;
+const FXP_OPEN: u8 = 3;
+const FXP_CLOSE: u8 = 4;
+const FXP_READ: u8 = 5;
+const FXP_WRITE: u8 = 6;
+const FXP_LSTAT: u8 = 7;
+const FXP_FSTAT: u8 = 8;
+const FXP_SETSTAT: u8 = 9;
+const FXP_FSETSTAT: u8 = 10;
+const FXP_OPENDIR: u8 = 11;
+const FXP_READDIR: u8 = 12;
+const FXP_REMOVE: u8 = 13;
+const FXP_MKDIR: u8 = 14;
+const FXP_RMDIR: u8 = 15;
+const FXP_REALPATH: u8 = 16;
+const FXP_STAT: u8 = 17;
+const FXP_RENAME: u8 = 18;
+const FXP_READLINK: u8 = 19;
+const FXP_SYMLINK: u8 = 20;
+const FXP_STATUS: u8 = 101;
+const FXP_HANDLE: u8 = 102;
+const FXP_DATA: u8 = 103;
+const FXP_NAME: u8 = 104;
+const FXP_ATTRS: u8 = 105;
+
+const FX_OK: u32 = 0;
+const FX_EOF: u32 = 1;
+const FX_NO_SUCH_FILE: u32 = 2;
+const FX_PERMISSION_DENIED: u32 = 3;
+const FX_FAILURE: u32 = 4;
+const FX_BAD_MESSAGE: u32 = 5;
+const FX_OP_UNSUPPORTED: u32 = 8;
+
+const FXF_WRITE: u32 = 0x02;
+const FXF_APPEND: u32 = 0x04;
+const FXF_CREAT: u32 = 0x08;
+const FXF_TRUNC: u32 = 0x10;
+
+const ATTR_SIZE: u32 = 0x01;
+const ATTR_PERMISSIONS: u32 = 0x04;
+const ATTR_ACMODTIME: u32 = 0x08;
+
+/// Largest packet we accept from the client (OpenSSH uses the same limit).
+const MAX_PACKET: usize = 256 * 1024;
+/// Number of names returned per `READDIR` response.
+const READDIR_BATCH: usize = 128;
+/// Files and directories a client may hold open at once; each open file
+/// keeps its whole content in memory.
+const MAX_HANDLES: usize = 256;
+
+/// Serve SFTP requests read from `input` until the client disconnects.
+///
+/// # Errors
+///
+/// Returns an error if the stream fails or the client sends a malformed packet frame.
+pub fn serve(vfs: &Vfs, mut input: impl Read, mut output: impl Write) -> Result<()> {
+    let mut session = Session {
+        vfs,
+        handles: HashMap::new(),
+        next_handle: 0,
+    };
+    loop {
+        let mut len = [0u8; 4];
+        match input.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err.into()),
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len == 0 || len > MAX_PACKET {
+            anyhow::bail!("invalid SFTP packet length {len}");
+        }
+        let mut packet = vec![0u8; len];
+        input.read_exact(&mut packet)?;
+        let reply = session.dispatch(&packet);
+        output.write_all(&(u32::try_from(reply.len())?).to_be_bytes())?;
+        output.write_all(&reply)?;
+        output.flush()?;
+    }
+}
+
+enum Handle {
+    File(Vec<u8>),
+    Dir { entries: Vec<DirEntry>, next: usize },
+}
+
+struct Session<'a> {
+    vfs: &'a Vfs,
+    handles: HashMap<u32, Handle>,
+    next_handle: u32,
+}
+
+/// Cursor over a request payload.
+struct Request<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Request<'a> {
+    fn u32(&mut self) -> Option<u32> {
+        let (head, rest) = self.data.split_first_chunk::<4>()?;
+        self.data = rest;
+        Some(u32::from_be_bytes(*head))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let (head, rest) = self.data.split_first_chunk::<8>()?;
+        self.data = rest;
+        Some(u64::from_be_bytes(*head))
+    }
+
+    fn string(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        if len > self.data.len() {
+            return None;
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(head)
+    }
+}
+
+/// Builder for a response packet (without the length prefix).
+struct Reply(Vec<u8>);
+
+impl Reply {
+    fn new(kind: u8, id: u32) -> Self {
+        let mut reply = Reply(vec![kind]);
+        reply.u32(id);
+        reply
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn string(&mut self, value: &[u8]) {
+        self.u32(u32::try_from(value.len()).unwrap_or(u32::MAX));
+        self.0.extend_from_slice(value);
+    }
+
+    fn attrs(&mut self, node: &Node) {
+        self.u32(ATTR_SIZE | ATTR_PERMISSIONS | ATTR_ACMODTIME);
+        self.u64(node.size);
+        self.u32(full_mode(node));
+        let mtime = u32::try_from(node.mtime).unwrap_or_default();
+        self.u32(mtime);
+        self.u32(mtime);
+    }
+}
+
+fn status(id: u32, code: u32, message: &str) -> Vec<u8> {
+    let mut reply = Reply::new(FXP_STATUS, id);
+    reply.u32(code);
+    reply.string(message.as_bytes());
+    reply.string(b"");
+    reply.0
+}
+
+fn io_status(id: u32, err: &io::Error) -> Vec<u8> {
+    let code = match err.raw_os_error() {
+        Some(libc::ENOENT | libc::ENOTDIR) => FX_NO_SUCH_FILE,
+        Some(libc::EROFS | libc::EACCES | libc::EPERM) => FX_PERMISSION_DENIED,
+        _ => FX_FAILURE,
+    };
+    status(id, code, &err.to_string())
+}
+
+impl Session<'_> {
+    fn dispatch(&mut self, packet: &[u8]) -> Vec<u8> {
+        let (&kind, payload) = packet.split_first().unwrap_or((&0, &[]));
+        let mut request = Request { data: payload };
+        if kind == FXP_INIT {
+            let mut reply = Reply(vec![FXP_VERSION]);
+            reply.u32(SFTP_VERSION);
+            return reply.0;
+        }
+        let Some(id) = request.u32() else {
+            return status(0, FX_BAD_MESSAGE, "truncated request");
+        };
+        self.request(kind, id, &mut request)
+            .unwrap_or_else(|| status(id, FX_BAD_MESSAGE, "malformed request"))
+    }
+
+    fn request(&mut self, kind: u8, id: u32, request: &mut Request<'_>) -> Option<Vec<u8>> {
+        Some(match kind {
+            FXP_OPEN => {
+                let path = request.string()?;
+                let flags = request.u32()?;
+                if flags & (FXF_WRITE | FXF_APPEND | FXF_CREAT | FXF_TRUNC) != 0 {
+                    return Some(status(id, FX_PERMISSION_DENIED, "read-only filesystem"));
+                }
+                match self
+                    .vfs
+                    .resolve(path, true)
+                    .and_then(|node| self.vfs.read(&node))
+                {
+                    Ok(content) => self.open_handle(id, Handle::File(content)),
+                    Err(err) => io_status(id, &err),
+                }
+            }
+            FXP_OPENDIR => {
+                let path = request.string()?;
+                match self
+                    .vfs
+                    .resolve(path, true)
+                    .and_then(|node| self.vfs.read_dir(&node))
+                {
+                    Ok(entries) => self.open_handle(id, Handle::Dir { entries, next: 0 }),
+                    Err(err) => io_status(id, &err),
+                }
+            }
+            FXP_CLOSE => {
+                let handle = parse_handle(request.string()?);
+                match handle.and_then(|handle| self.handles.remove(&handle)) {
+                    Some(_) => status(id, FX_OK, ""),
+                    None => status(id, FX_FAILURE, "invalid handle"),
+                }
+            }
+            FXP_READ => {
+                let handle = parse_handle(request.string()?);
+                let offset = request.u64()?;
+                let len = request.u32()? as usize;
+                self.read(id, handle, offset, len)
+            }
+            FXP_READDIR => {
+                let handle = parse_handle(request.string()?);
+                self.read_dir(id, handle)
+            }
+            FXP_STAT | FXP_LSTAT => {
+                let path = request.string()?;
+                match self.vfs.resolve(path, kind == FXP_STAT) {
+                    Ok(node) => {
+                        let mut reply = Reply::new(FXP_ATTRS, id);
+                        reply.attrs(&node);
+                        reply.0
+                    }
+                    Err(err) => io_status(id, &err),
+                }
+            }
+            FXP_FSTAT => {
+                let handle = parse_handle(request.string()?);
+                self.fstat(id, handle)
+            }
+            FXP_REALPATH => {
+                let path = normalize(request.string()?);
+                name_reply(id, std::iter::once((&path[..], None)))
+            }
+            FXP_READLINK => {
+                let path = request.string()?;
+                match self
+                    .vfs
+                    .resolve(path, false)
+                    .and_then(|node| self.vfs.read_link(&node))
+                {
+                    Ok(target) => name_reply(id, std::iter::once((&target[..], None))),
+                    Err(err) => io_status(id, &err),
+                }
+            }
+            FXP_WRITE | FXP_SETSTAT | FXP_FSETSTAT | FXP_REMOVE | FXP_MKDIR | FXP_RMDIR
+            | FXP_RENAME | FXP_SYMLINK => status(id, FX_PERMISSION_DENIED, "read-only filesystem"),
+            _ => status(id, FX_OP_UNSUPPORTED, "operation not supported"),
+        })
+    }
+
+    fn read(&self, id: u32, handle: Option<u32>, offset: u64, len: usize) -> Vec<u8> {
+        let Some(Handle::File(content)) = handle.and_then(|handle| self.handles.get(&handle))
+        else {
+            return status(id, FX_FAILURE, "invalid handle");
+        };
+        let start = usize::try_from(offset).unwrap_or(usize::MAX);
+        if start >= content.len() {
+            return status(id, FX_EOF, "");
+        }
+        let end = start.saturating_add(len.min(MAX_PACKET)).min(content.len());
+        let mut reply = Reply::new(FXP_DATA, id);
+        reply.string(&content[start..end]);
+        reply.0
+    }
+
+    fn read_dir(&mut self, id: u32, handle: Option<u32>) -> Vec<u8> {
+        let Some(Handle::Dir { entries, next }) =
+            handle.and_then(|handle| self.handles.get_mut(&handle))
+        else {
+            return status(id, FX_FAILURE, "invalid handle");
+        };
+        let batch = &entries[*next..entries.len().min(*next + READDIR_BATCH)];
+        if batch.is_empty() {
+            return status(id, FX_EOF, "");
+        }
+        *next += batch.len();
+        name_reply(
+            id,
+            batch
+                .iter()
+                .map(|entry| (&entry.name[..], Some(&entry.node))),
+        )
+    }
+
+    fn fstat(&self, id: u32, handle: Option<u32>) -> Vec<u8> {
+        let mut reply = Reply::new(FXP_ATTRS, id);
+        match handle.and_then(|handle| self.handles.get(&handle)) {
+            Some(Handle::File(content)) => {
+                reply.u32(ATTR_SIZE | ATTR_PERMISSIONS);
+                reply.u64(content.len() as u64);
+                reply.u32(libc::S_IFREG | 0o444);
+            }
+            Some(Handle::Dir { .. }) => {
+                reply.u32(ATTR_PERMISSIONS);
+                reply.u32(libc::S_IFDIR | 0o555);
+            }
+            None => return status(id, FX_FAILURE, "invalid handle"),
+        }
+        reply.0
+    }
+
+    fn open_handle(&mut self, id: u32, handle: Handle) -> Vec<u8> {
+        if self.handles.len() >= MAX_HANDLES {
+            return status(id, FX_FAILURE, "too many open handles");
+        }
+        let mut key = self.next_handle;
+        while self.handles.contains_key(&key) {
+            key = key.wrapping_add(1);
+        }
+        self.next_handle = key.wrapping_add(1);
+        self.handles.insert(key, handle);
+        let mut reply = Reply::new(FXP_HANDLE, id);
+        reply.string(key.to_string().as_bytes());
+        reply.0
+    }
+}
+
+fn parse_handle(raw: &[u8]) -> Option<u32> {
+    std::str::from_utf8(raw).ok()?.parse().ok()
+}
+
+fn name_reply<'n>(
+    id: u32,
+    names: impl ExactSizeIterator<Item = (&'n [u8], Option<&'n Node>)>,
+) -> Vec<u8> {
+    let mut reply = Reply::new(FXP_NAME, id);
+    reply.u32(u32::try_from(names.len()).unwrap_or(u32::MAX));
+    for (name, node) in names {
+        reply.string(name);
+        if let Some(node) = node {
+            reply.string(&long_name(name, node));
+            reply.attrs(node);
+        } else {
+            reply.string(name);
+            reply.u32(0);
+        }
+    }
+    reply.0
+}
+
+/// `ls -l` style line shown by clients for directory listings.
+fn long_name(name: &[u8], node: &Node) -> Vec<u8> {
+    let kind = match node.kind {
+        NodeKind::Directory => 'd',
+        NodeKind::Symlink => 'l',
+        NodeKind::File => '-',
+    };
+    let mut perms = String::with_capacity(9);
+    for shift in [6, 3, 0] {
+        let bits = (node.mode >> shift) & 0o7;
+        perms.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        perms.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        perms.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+    let date = OffsetDateTime::from_unix_timestamp(node.mtime)
+        .map(|time| {
+            let month = time.month().to_string();
+            format!("{} {:>2} {:>5}", &month[..3], time.day(), time.year())
+        })
+        .unwrap_or_default();
+    let mut line = format!(
+        "{kind}{perms}    1 0        0        {:>8} {date} ",
+        node.size
+    )
+    .into_bytes();
+    line.extend_from_slice(name);
+    line
+}
+
+fn full_mode(node: &Node) -> u32 {
+    let kind = match node.kind {
+        NodeKind::Directory => libc::S_IFDIR,
+        NodeKind::Symlink => libc::S_IFLNK,
+        NodeKind::File => libc::S_IFREG,
+    };
+    kind | node.mode
+}
+
+/// Lexically normalise `path` into an absolute path without `.` or `..`.
+fn normalize(path: &[u8]) -> Vec<u8> {
+    let mut parts: Vec<&[u8]> = Vec::new();
+    for component in path.split(|byte| *byte == b'/') {
+        match component {
+            b"" | b"." => {}
+            b".." => {
+                parts.pop();
+            }
+            other => parts.push(other),
+        }
+    }
+    let mut out = Vec::with_capacity(path.len() + 1);
+    for part in &parts {
+        out.push(b'/');
+        out.extend_from_slice(part);
+    }
+    if out.is_empty() {
+        out.push(b'/');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_paths() {
+        assert_eq!(normalize(b"."), b"/");
+        assert_eq!(normalize(b"/HEAD/./d/../top"), b"/HEAD/top");
+        assert_eq!(normalize(b"../.."), b"/");
+    }
+}
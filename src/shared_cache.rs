@@ -0,0 +1,334 @@
+//! Blob cache shared between mounts through a local daemon.
+//!
+//! Hosts that mount many branches of one large repository would otherwise
+//! hold the same decoded blobs once per `gitsnapfs` process. `gitsnapfs
+//! cache-daemon --socket PATH` keeps them once instead, and mounts started
+//! with `--shared-cache PATH` ask it for the byte ranges they are asked to
+//! read rather than inflating blobs themselves.
+//!
+//! The protocol is one request line per read over a Unix stream socket:
+//!
+//! ```text
+//! READ <oid> <offset> <len> <replace-refs 0|1> <repository path>\n
+//! ```
+//!
+//! answered by `OK <n>\n` followed by `n` bytes, or `ERR <errno>\n`. The
+//! socket is created accessible to its owner only, since the daemon reads
+//! any repository its own user can.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+use anyhow::{Context, Result};
+use gix::ObjectId;
+use nix::sys::stat::{umask, Mode};
+use tracing::{debug, info, warn};
+
+use crate::cache::BlobCache;
+use crate::repo::Repository;
+
+/// Longest request line accepted; repository paths are the only unbounded part.
+const MAX_REQUEST_LINE: u64 = 8192;
+
+/// A repository opened by the daemon, with its own cache budget.
+struct Served {
+    repo: Repository,
+    cache: BlobCache,
+}
+
+/// The daemon: repositories opened on demand, keyed by path and whether
+/// replacement refs apply.
+struct Daemon {
+    budget: usize,
+    repos: Mutex<HashMap<(PathBuf, bool), Arc<Served>>>,
+}
+
+/// Serve blob ranges on `socket` until the process is stopped, caching up
+/// to `budget` bytes of decoded blobs per repository.
+///
+/// # Errors
+///
+/// Returns an error if the socket cannot be created.
+pub fn serve(socket: &Path, budget: usize) -> Result<()> {
+    // A socket left behind by a previous daemon would make bind fail.
+    if UnixStream::connect(socket).is_err() {
+        let _ = std::fs::remove_file(socket);
+    }
+    // Created owner-only, so no other user can connect between the bind
+    // and a chmod.
+    let previous = umask(Mode::from_bits_truncate(0o077));
+    let bound = UnixListener::bind(socket);
+    umask(previous);
+    let listener = bound.with_context(|| format!("failed to listen on {}", socket.display()))?;
+    info!("shared cache listening on {}", socket.display());
+    let daemon = Arc::new(Daemon {
+        budget,
+        repos: Mutex::new(HashMap::new()),
+    });
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!(%err, "accepting shared cache client failed");
+                continue;
+            }
+        };
+        let daemon = Arc::clone(&daemon);
+        thread::Builder::new()
+            .name("gitsnapfs-cache-client".into())
+            .spawn(move || {
+                if let Err(err) = daemon.client(stream) {
+                    debug!(%err, "shared cache client disconnected");
+                }
+            })?;
+    }
+    Ok(())
+}
+
+impl Daemon {
+    fn client(&self, stream: UnixStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            (&mut reader)
+                .take(MAX_REQUEST_LINE)
+                .read_until(b'\n', &mut line)?;
+            // End of stream, or a line too long to be a request.
+            if !line.ends_with(b"\n") {
+                return Ok(());
+            }
+            match self.answer(&line) {
+                Ok(data) => {
+                    writer.write_all(format!("OK {}\n", data.len()).as_bytes())?;
+                    writer.write_all(&data)?;
+                }
+                Err(err) => {
+                    let errno = err.raw_os_error().unwrap_or(libc::EIO);
+                    writer.write_all(format!("ERR {errno}\n").as_bytes())?;
+                }
+            }
+        }
+    }
+
+    fn answer(&self, line: &[u8]) -> io::Result<Vec<u8>> {
+        let request =
+            Request::parse(line).ok_or_else(|| io::Error::from_raw_os_error(libc::EINVAL))?;
+        let served = self.repo(&request.repo, request.replace_refs)?;
+        let data = if let Some(data) = served.cache.get(&request.oid) {
+            data
+        } else {
+            let repo = served.repo.thread_local();
+            let blob = repo
+                .find_blob(request.oid)
+                .map_err(|_| io::Error::from_raw_os_error(libc::ENOENT))?;
+            let data: Arc<[u8]> = Arc::from(blob.detach().data);
+            served.cache.insert(request.oid, Arc::clone(&data));
+            data
+        };
+        let start = usize::try_from(request.offset)
+            .unwrap_or(usize::MAX)
+            .min(data.len());
+        let end = start.saturating_add(request.len).min(data.len());
+        Ok(data[start..end].to_vec())
+    }
+
+    fn repo(&self, path: &Path, replace_refs: bool) -> io::Result<Arc<Served>> {
+        let key = (path.to_path_buf(), replace_refs);
+        if let Some(served) = self.lock().get(&key) {
+            return Ok(Arc::clone(served));
+        }
+        let repo = Repository::open_opts(path, replace_refs).map_err(|err| {
+            warn!("{err:#}");
+            io::Error::from_raw_os_error(libc::ENOENT)
+        })?;
+        info!("shared cache serving {}", path.display());
+        let served = Arc::new(Served {
+            repo,
+            cache: BlobCache::new(self.budget),
+        });
+        Ok(Arc::clone(self.lock().entry(key).or_insert(served)))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<(PathBuf, bool), Arc<Served>>> {
+        self.repos.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// One `READ` request.
+#[derive(Debug, PartialEq, Eq)]
+struct Request {
+    oid: ObjectId,
+    offset: u64,
+    len: usize,
+    replace_refs: bool,
+    repo: PathBuf,
+}
+
+impl Request {
+    fn parse(line: &[u8]) -> Option<Self> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let line = line.strip_suffix(b"\n")?;
+        let mut fields = line.splitn(6, |&b| b == b' ');
+        if fields.next()? != b"READ" {
+            return None;
+        }
+        let oid = ObjectId::from_hex(fields.next()?).ok()?;
+        let offset = std::str::from_utf8(fields.next()?).ok()?.parse().ok()?;
+        let len = std::str::from_utf8(fields.next()?).ok()?.parse().ok()?;
+        let replace_refs = match fields.next()? {
+            b"0" => false,
+            b"1" => true,
+            _ => return None,
+        };
+        let repo = PathBuf::from(std::ffi::OsStr::from_bytes(fields.next()?));
+        Some(Self {
+            oid,
+            offset,
+            len,
+            replace_refs,
+            repo,
+        })
+    }
+
+    fn encode(&self) -> io::Result<Vec<u8>> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let repo = self.repo.as_os_str().as_bytes();
+        if repo.contains(&b'\n') {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        let mut line = format!(
+            "READ {} {} {} {} ",
+            self.oid,
+            self.offset,
+            self.len,
+            u8::from(self.replace_refs)
+        )
+        .into_bytes();
+        line.extend_from_slice(repo);
+        line.push(b'\n');
+        Ok(line)
+    }
+}
+
+/// Connection to a cache daemon on behalf of one mounted repository.
+#[derive(Debug)]
+pub struct SharedCache {
+    socket: PathBuf,
+    repo: PathBuf,
+    replace_refs: bool,
+    // Connections not in use by a request; each worker thread takes its own.
+    idle: Mutex<Vec<BufReader<UnixStream>>>,
+    warned: AtomicBool,
+}
+
+impl SharedCache {
+    /// Read from the daemon listening on `socket` on behalf of `repo`.
+    #[must_use]
+    pub fn new(socket: PathBuf, repo: &Repository) -> Self {
+        let path = repo.path();
+        Self {
+            socket,
+            repo: path.canonicalize().unwrap_or_else(|_| path.to_path_buf()),
+            replace_refs: repo.uses_replace_refs(),
+            idle: Mutex::new(Vec::new()),
+            warned: AtomicBool::new(false),
+        }
+    }
+
+    /// Up to `len` bytes of blob `oid` starting at `offset`, or the errno
+    /// the daemon answered with.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the daemon could not be asked, for example
+    /// because it is not running.
+    pub fn read(&self, oid: ObjectId, offset: u64, len: usize) -> io::Result<Result<Vec<u8>, i32>> {
+        let request = Request {
+            oid,
+            offset,
+            len,
+            replace_refs: self.replace_refs,
+            repo: self.repo.clone(),
+        }
+        .encode()?;
+        let idle = self
+            .idle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop();
+        let mut conn = match idle {
+            Some(conn) => conn,
+            None => BufReader::new(UnixStream::connect(&self.socket)?),
+        };
+        conn.get_mut().write_all(&request)?;
+        let mut status = String::new();
+        conn.read_line(&mut status)?;
+        let data = match status.trim_end().split_once(' ') {
+            Some(("OK", n)) => {
+                let n: usize = n.parse().map_err(io::Error::other)?;
+                let mut data = vec![0; n];
+                conn.read_exact(&mut data)?;
+                Ok(data)
+            }
+            Some(("ERR", errno)) => Err(errno.parse().unwrap_or(libc::EIO)),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected shared cache reply {status:?}"),
+                ))
+            }
+        };
+        // The connection is only reused after a complete reply.
+        self.idle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(conn);
+        Ok(data)
+    }
+
+    /// Log that the daemon could not be reached, once per mount.
+    pub fn unavailable(&self, err: &io::Error) {
+        if !self.warned.swap(true, Ordering::Relaxed) {
+            warn!(
+                %err,
+                "shared cache at {} unavailable, decoding blobs locally",
+                self.socket.display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_roundtrip() {
+        let request = Request {
+            oid: ObjectId::from_hex(b"0123456789abcdef0123456789abcdef01234567").unwrap(),
+            offset: 131_072,
+            len: 4096,
+            replace_refs: true,
+            repo: PathBuf::from("/srv/git/mono repo.git"),
+        };
+        let line = request.encode().unwrap();
+        assert_eq!(Request::parse(&line), Some(request));
+        assert_eq!(Request::parse(b"READ nothex 0 1 1 /x\n"), None);
+        assert_eq!(Request::parse(b"READ 0123 0 1 1 /x"), None);
+
+        let newline = Request {
+            repo: PathBuf::from("/srv/a\nb"),
+            ..Request::parse(&line).unwrap()
+        };
+        assert!(newline.encode().is_err());
+    }
+}
@@ -0,0 +1,119 @@
+//! Content sniffing for the `user.gitsnapfs.mime` and `user.gitsnapfs.binary`
+//! extended attributes.
+//!
+//! File browsers and web gateways over a mount want to label entries
+//! without reading them whole. Only the first [`SNIFF_LEN`] bytes are
+//! looked at: a NUL byte among them makes a file binary, as in Git's own
+//! diff heuristic, and a short table of magic numbers names the common
+//! formats. Anything else is plain text or an unknown binary.
+
+/// Bytes of the start of a file looked at; the same window Git uses.
+pub const SNIFF_LEN: usize = 8000;
+
+/// What a file's first bytes say about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentType {
+    pub mime: &'static str,
+    pub binary: bool,
+}
+
+/// Formats recognised by a signature at the very start of the file.
+const MAGIC: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"II*\0", "image/tiff"),
+    (b"MM\0*", "image/tiff"),
+    (b"\0\0\x01\0", "image/vnd.microsoft.icon"),
+    (b"%PDF-", "application/pdf"),
+    (b"%!PS", "application/postscript"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"BZh", "application/x-bzip2"),
+    (b"\xfd7zXZ\0", "application/x-xz"),
+    (b"\x28\xb5\x2f\xfd", "application/zstd"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (b"\x7fELF", "application/x-executable"),
+    (b"MZ", "application/vnd.microsoft.portable-executable"),
+    (b"\0asm", "application/wasm"),
+    (b"SQLite format 3\0", "application/vnd.sqlite3"),
+    (b"ID3", "audio/mpeg"),
+    (b"OggS", "audio/ogg"),
+    (b"fLaC", "audio/flac"),
+    (b"{\\rtf", "text/rtf"),
+    (b"<?xml", "text/xml"),
+];
+
+/// Classify a file by `prefix`, its first [`SNIFF_LEN`] bytes (or all of it).
+#[must_use]
+pub fn sniff(prefix: &[u8]) -> ContentType {
+    let prefix = &prefix[..prefix.len().min(SNIFF_LEN)];
+    let binary = prefix.contains(&0);
+    let magic = MAGIC
+        .iter()
+        .find(|(signature, _)| prefix.starts_with(signature))
+        .map(|&(_, mime)| mime)
+        .or_else(|| riff_or_tar(prefix));
+    let mime = match magic {
+        Some(mime) => mime,
+        None if prefix.is_empty() => "inode/x-empty",
+        None if binary => "application/octet-stream",
+        None if is_utf8_prefix(prefix) => "text/plain; charset=utf-8",
+        None => "text/plain",
+    };
+    ContentType { mime, binary }
+}
+
+/// Formats whose signature is not at offset 0.
+fn riff_or_tar(prefix: &[u8]) -> Option<&'static str> {
+    if prefix.starts_with(b"RIFF") {
+        return match prefix.get(8..12)? {
+            b"WEBP" => Some("image/webp"),
+            b"WAVE" => Some("audio/wav"),
+            b"AVI " => Some("video/x-msvideo"),
+            _ => None,
+        };
+    }
+    (prefix.get(257..262)? == b"ustar").then_some("application/x-tar")
+}
+
+/// Whether `prefix` is UTF-8, allowing it to end in the middle of a character.
+fn is_utf8_prefix(prefix: &[u8]) -> bool {
+    match std::str::from_utf8(prefix) {
+        Ok(_) => true,
+        Err(err) => err.error_len().is_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_by_magic_and_text() {
+        assert_eq!(
+            sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+            ContentType {
+                mime: "image/png",
+                binary: true
+            }
+        );
+        assert_eq!(sniff(b"fn main() {}\n").mime, "text/plain; charset=utf-8");
+        assert!(!sniff(b"fn main() {}\n").binary);
+        // Cut in the middle of a two-byte character.
+        assert_eq!(sniff(b"caf\xc3").mime, "text/plain; charset=utf-8");
+        assert_eq!(sniff(b"caf\xe9 latin-1").mime, "text/plain");
+        assert_eq!(sniff(b"\x01\x02\0\x03").mime, "application/octet-stream");
+        assert_eq!(sniff(b"").mime, "inode/x-empty");
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 ").mime, "image/webp");
+
+        let mut tar = vec![0; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(sniff(&tar).mime, "application/x-tar");
+        // A NUL past the window does not make a file binary.
+        let mut late = vec![b'a'; SNIFF_LEN];
+        late.push(0);
+        assert!(!sniff(&late).binary);
+    }
+}
@@ -0,0 +1,254 @@
+//! How replies reach the FUSE device (`--splice`, `--no-splice`).
+//!
+//! By default each reply is written to the device with one `writev`. With
+//! `--splice` a worker writes it into a pipe of its own instead and moves
+//! it from there to the device with `splice(2)`. Some container runtimes
+//! interpose on `/dev/fuse` in ways `splice` does not survive; the first
+//! failure sends the reply with `write` after all and switches every worker
+//! back to buffered writes for the rest of the mount.
+//!
+//! Replies are counted per mode, with the time from starting to handle the
+//! request until its reply was written, and served together with the mode
+//! in use as `/.gitsnapfs/info`:
+//!
+//! ```text
+//! {"writer":"splice","buffered":{"replies":0,"bytes":0,"micros":0},"splice":{"replies":812,"bytes":10485760,"micros":90210}}
+//! ```
+//!
+//! `fallback` gives the error that ended splicing, if one did.
+
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use nix::fcntl::{fcntl, splice, FcntlArg, OFlag, SpliceFFlags};
+use serde::Serialize;
+
+/// Replies written per mode, and whether splicing is still in use.
+#[derive(Debug, Default)]
+pub struct Writes {
+    splice: AtomicBool,
+    fallback: Mutex<Option<String>>,
+    buffered: Tally,
+    spliced: Tally,
+}
+
+#[derive(Debug, Default)]
+struct Tally {
+    replies: AtomicU64,
+    bytes: AtomicU64,
+    micros: AtomicU64,
+}
+
+#[derive(Serialize)]
+struct TallyReport {
+    replies: u64,
+    bytes: u64,
+    micros: u64,
+}
+
+#[derive(Serialize)]
+struct Report {
+    writer: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fallback: Option<String>,
+    buffered: TallyReport,
+    splice: TallyReport,
+}
+
+impl Tally {
+    fn report(&self) -> TallyReport {
+        TallyReport {
+            replies: self.replies.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            micros: self.micros.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Writes {
+    /// Writes starting out spliced with `splice`, buffered otherwise.
+    #[must_use]
+    pub fn new(splice: bool) -> Self {
+        Self {
+            splice: AtomicBool::new(splice),
+            ..Self::default()
+        }
+    }
+
+    /// Whether replies are currently spliced.
+    #[must_use]
+    pub fn splicing(&self) -> bool {
+        self.splice.load(Ordering::Relaxed)
+    }
+
+    /// Count a reply of `bytes` written, `spliced` or not, `elapsed` after
+    /// its request was taken up.
+    pub fn record(&self, spliced: bool, bytes: usize, elapsed: Duration) {
+        let tally = if spliced {
+            &self.spliced
+        } else {
+            &self.buffered
+        };
+        tally.replies.fetch_add(1, Ordering::Relaxed);
+        tally.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        tally.micros.fetch_add(
+            u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Stop splicing because of `err`.
+    pub fn fall_back(&self, err: &dyn std::fmt::Display) {
+        if self.splice.swap(false, Ordering::Relaxed) {
+            tracing::warn!("splicing replies failed: {err}; writing them buffered from now on");
+            *self.fallback.lock().unwrap_or_else(PoisonError::into_inner) = Some(err.to_string());
+        }
+    }
+
+    /// The contents of `/.gitsnapfs/info`.
+    #[must_use]
+    pub fn render(&self) -> Vec<u8> {
+        let report = Report {
+            writer: if self.splicing() {
+                "splice"
+            } else {
+                "buffered"
+            },
+            fallback: self
+                .fallback
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+            buffered: self.buffered.report(),
+            splice: self.spliced.report(),
+        };
+        let mut text = serde_json::to_vec(&report).unwrap_or_default();
+        text.push(b'\n');
+        text
+    }
+}
+
+/// Bytes [`ReplyPipe::drain`] reads at a time.
+const DRAIN_CHUNK: usize = 64 << 10;
+
+/// A pipe replies are written into before being spliced to the device.
+#[derive(Debug)]
+pub struct ReplyPipe {
+    output: OwnedFd,
+    input: OwnedFd,
+    /// Buffer for draining the pipe, allocated once with it.
+    chunk: Vec<u8>,
+}
+
+impl ReplyPipe {
+    /// A pipe holding at least `capacity` bytes, so the largest reply fits
+    /// without a reader on the other end.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the pipe cannot be created or made that large;
+    /// beyond `/proc/sys/fs/pipe-max-size` that takes `CAP_SYS_RESOURCE`.
+    pub fn new(capacity: usize) -> io::Result<Self> {
+        let (output, input) = nix::unistd::pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK)?;
+        let wanted = i32::try_from(capacity).unwrap_or(i32::MAX);
+        let size = fcntl(&input, FcntlArg::F_SETPIPE_SZ(wanted))?;
+        if size < wanted {
+            return Err(io::Error::from_raw_os_error(libc::EPERM));
+        }
+        Ok(Self {
+            output,
+            input,
+            chunk: vec![0u8; DRAIN_CHUNK],
+        })
+    }
+
+    /// Where replies are written.
+    #[must_use]
+    pub fn input(&self) -> RawFd {
+        self.input.as_raw_fd()
+    }
+
+    /// Move the reply waiting in the pipe to `device`, all at once as the
+    /// device requires, and return its length.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `splice` fails or moves only part of the reply;
+    /// what is left of it stays in the pipe.
+    pub fn splice_into(&self, device: RawFd) -> io::Result<usize> {
+        let mut pending: libc::c_int = 0;
+        // SAFETY: FIONREAD stores the number of bytes in the pipe in `pending`.
+        if unsafe { libc::ioctl(self.output.as_raw_fd(), libc::FIONREAD, &mut pending) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let len = usize::try_from(pending).unwrap_or_default();
+        if len == 0 {
+            return Ok(0);
+        }
+        // SAFETY: the caller's device descriptor outlives this call.
+        let device = unsafe { BorrowedFd::borrow_raw(device) };
+        let moved = splice(
+            self.output.as_fd(),
+            None,
+            device,
+            None,
+            len,
+            SpliceFFlags::SPLICE_F_MOVE,
+        )?;
+        if moved == len {
+            Ok(len)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                format!("spliced {moved} of {len} bytes"),
+            ))
+        }
+    }
+
+    /// Take whatever is left in the pipe.
+    #[must_use]
+    pub fn drain(&mut self) -> Vec<u8> {
+        let mut pending = Vec::new();
+        while let Ok(read @ 1..) = nix::unistd::read(&self.output, &mut self.chunk) {
+            pending.extend_from_slice(&self.chunk[..read]);
+        }
+        pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pipe_holds_a_reply_and_counts_per_mode() {
+        let mut pipe = ReplyPipe::new(64 << 10).unwrap();
+        let reply = vec![7u8; 40_000];
+        // SAFETY: the pipe is alive for the whole test.
+        let input = unsafe { BorrowedFd::borrow_raw(pipe.input()) };
+        assert_eq!(nix::unistd::write(input, &reply).unwrap(), reply.len());
+        assert_eq!(pipe.drain(), reply);
+        assert!(pipe.drain().is_empty());
+
+        // Another pipe stands in for the device.
+        let mut device = ReplyPipe::new(64 << 10).unwrap();
+        nix::unistd::write(input, &reply).unwrap();
+        assert_eq!(pipe.splice_into(device.input()).unwrap(), reply.len());
+        assert_eq!(pipe.splice_into(device.input()).unwrap(), 0);
+        assert_eq!(device.drain(), reply);
+
+        let writes = Writes::new(true);
+        writes.record(true, 100, Duration::from_micros(30));
+        writes.fall_back(&"EINVAL");
+        writes.fall_back(&"ignored");
+        writes.record(false, 50, Duration::from_micros(20));
+        let info: serde_json::Value = serde_json::from_slice(&writes.render()).unwrap();
+        assert_eq!(info["writer"], "buffered");
+        assert_eq!(info["fallback"], "EINVAL");
+        assert_eq!(info["splice"]["bytes"], 100);
+        assert_eq!(info["buffered"]["micros"], 20);
+    }
+}
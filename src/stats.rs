@@ -0,0 +1,161 @@
+//! Per-snapshot usage counters behind `--stats`, served as `/.gitsnapfs/stats`.
+//!
+//! Blob and tree inodes are derived from object ids and shared by every
+//! snapshot containing the object, so a read cannot be attributed exactly.
+//! Instead each inode is attributed to the snapshot it was last reached
+//! through: commit roots own themselves, and children inherit their parent's
+//! owner on lookup or readdirplus, until the kernel forgets them. That is
+//! enough to tell which snapshots are busy before deleting old branches.
+
+use std::collections::HashMap;
+use std::sync::{PoisonError, RwLock};
+
+use gix::ObjectId;
+use serde::Serialize;
+
+/// Counters for one snapshot.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Counters {
+    /// File handles currently open.
+    pub open_handles: u64,
+    /// File opens since mount.
+    pub opens: u64,
+    /// File bytes served since mount.
+    pub bytes_served: u64,
+}
+
+/// Usage counters keyed by commit, plus the inode attribution they rely on.
+#[derive(Debug, Default)]
+pub struct SnapshotStats {
+    state: RwLock<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    owners: HashMap<u64, ObjectId>,
+    counters: HashMap<ObjectId, Counters>,
+}
+
+#[derive(Serialize)]
+struct Row {
+    commit: String,
+    #[serde(flatten)]
+    counters: Counters,
+}
+
+impl SnapshotStats {
+    /// Record that `inode` is the root directory of `commit`.
+    pub fn root(&self, inode: u64, commit: ObjectId) {
+        self.write().owners.insert(inode, commit);
+    }
+
+    /// Attribute `child` to the snapshot owning `parent`, if any.
+    pub fn inherit(&self, parent: u64, child: u64) {
+        let owner = self.read().owners.get(&parent).copied();
+        if let Some(owner) = owner {
+            let mut state = self.write();
+            if state.owners.get(&child) != Some(&owner) {
+                state.owners.insert(child, owner);
+            }
+        }
+    }
+
+    /// Drop the attribution of `inode`, which the kernel no longer holds.
+    pub fn forget(&self, inode: u64) {
+        self.write().owners.remove(&inode);
+    }
+
+    /// Count a new open handle on `inode`.
+    pub fn opened(&self, inode: u64) {
+        self.update(inode, |counters| {
+            counters.open_handles += 1;
+            counters.opens += 1;
+        });
+    }
+
+    /// Count a released handle on `inode`.
+    pub fn released(&self, inode: u64) {
+        self.update(inode, |counters| {
+            counters.open_handles = counters.open_handles.saturating_sub(1);
+        });
+    }
+
+    /// Count `bytes` served from `inode`.
+    pub fn served(&self, inode: u64, bytes: u64) {
+        self.update(inode, |counters| counters.bytes_served += bytes);
+    }
+
+    /// JSON array of every snapshot with activity, busiest first.
+    #[must_use]
+    pub fn render(&self) -> Vec<u8> {
+        let mut rows = self
+            .read()
+            .counters
+            .iter()
+            .map(|(commit, counters)| Row {
+                commit: commit.to_string(),
+                counters: *counters,
+            })
+            .collect::<Vec<_>>();
+        rows.sort_by(|a, b| {
+            (b.counters.open_handles, b.counters.bytes_served, &a.commit).cmp(&(
+                a.counters.open_handles,
+                a.counters.bytes_served,
+                &b.commit,
+            ))
+        });
+        let mut out = serde_json::to_vec_pretty(&rows).unwrap_or_default();
+        out.push(b'\n');
+        out
+    }
+
+    fn update(&self, inode: u64, apply: impl FnOnce(&mut Counters)) {
+        let mut state = self.write();
+        if let Some(owner) = state.owners.get(&inode).copied() {
+            apply(state.counters.entry(owner).or_default());
+        }
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, State> {
+        self.state.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, State> {
+        self.state.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attributes_through_lookups() {
+        let stats = SnapshotStats::default();
+        let commit = ObjectId::from_bytes_or_panic(&[7; 20]);
+        stats.root(1, commit);
+        stats.inherit(1, 2);
+        stats.inherit(2, 3);
+        stats.inherit(99, 4);
+
+        stats.opened(3);
+        stats.served(3, 10);
+        stats.opened(4);
+        stats.released(3);
+
+        let counters = stats.read().counters.get(&commit).copied().unwrap();
+        assert_eq!(
+            counters,
+            Counters {
+                open_handles: 0,
+                opens: 1,
+                bytes_served: 10,
+            }
+        );
+        assert_eq!(stats.read().counters.len(), 1);
+
+        stats.forget(3);
+        stats.forget(2);
+        assert_eq!(stats.read().owners.len(), 1);
+    }
+}
@@ -0,0 +1,167 @@
+//! Submodule placeholders.
+//!
+//! A gitlink names a commit in another repository, which is usually not
+//! available next to the superproject. Instead of a directory that fails to
+//! list, each gitlink is served as a directory holding a single `SUBMODULE`
+//! file with what `.gitmodules` says about it and the pinned commit. Local
+//! checkouts can be attached with `--submodule-path <name>=<path>`, in which
+//! case the gitlink becomes a symlink to that checkout.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use gix::bstr::{BStr, ByteSlice};
+use gix::ObjectId;
+
+/// Name of the info file inside a submodule placeholder.
+pub const INFO_NAME: &[u8] = b"SUBMODULE";
+
+/// A gitlink inside a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Gitlink {
+    /// Commit the superproject pins.
+    pub commit: ObjectId,
+    /// Root tree of the snapshot, if the gitlink was reached through one.
+    /// Without it only the entry name is known and `.gitmodules` is not consulted.
+    pub root: Option<ObjectId>,
+    /// Slash-separated path below `root`, or just the entry name.
+    pub path: Vec<u8>,
+}
+
+/// What `.gitmodules` declares for one submodule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Declared {
+    /// Submodule name (the `submodule.<name>` section).
+    pub name: Vec<u8>,
+    /// Configured URL, if any.
+    pub url: Option<String>,
+}
+
+/// Declared submodules of a snapshot, keyed by path.
+pub type Declarations = BTreeMap<Vec<u8>, Declared>;
+
+/// A local checkout attached to a submodule by name or path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkout {
+    /// Submodule name or path it applies to.
+    pub key: String,
+    /// Directory to expose in place of the gitlink.
+    pub path: PathBuf,
+}
+
+/// Submodules declared by the `.gitmodules` file at the root of `root_tree`,
+/// keyed by path. Returns an empty map if there is no such file.
+///
+/// # Errors
+///
+/// Returns an error if the tree or the `.gitmodules` blob cannot be read.
+pub fn declared(repo: &gix::Repository, root_tree: ObjectId) -> Result<Declarations> {
+    let tree = repo.find_tree(root_tree)?;
+    let Some(entry) = tree
+        .iter()
+        .filter_map(Result::ok)
+        .find(|entry| entry.inner.filename == ".gitmodules" && entry.inner.mode.is_blob())
+    else {
+        return Ok(Declarations::new());
+    };
+    let blob = repo.find_blob(entry.inner.oid.to_owned())?;
+    // Unparsable files declare nothing; gitlinks still get a placeholder.
+    let config = gix::config::File::default();
+    let Ok(modules) = gix::submodule::File::from_bytes(&blob.data, None, &config) else {
+        return Ok(Declarations::new());
+    };
+    Ok(modules
+        .names()
+        .filter_map(|name| {
+            let path = modules.path(name).ok()?;
+            let declared = Declared {
+                name: name.to_vec(),
+                url: modules
+                    .url(name)
+                    .ok()
+                    .map(|url| url.to_bstring().to_string()),
+            };
+            Some((path.to_vec(), declared))
+        })
+        .collect())
+}
+
+/// Render the `SUBMODULE` file for the gitlink at `path` pinned to `commit`.
+#[must_use]
+pub fn info_file(path: &[u8], commit: ObjectId, declared: Option<&Declared>) -> Vec<u8> {
+    let mut out = String::new();
+    let _ = writeln!(out, "path: {}", path.as_bstr());
+    let _ = writeln!(out, "commit: {commit}");
+    match declared {
+        Some(declared) => {
+            let _ = writeln!(out, "name: {}", declared.name.as_bstr());
+            if let Some(url) = &declared.url {
+                let _ = writeln!(out, "url: {url}");
+            }
+        }
+        None => {
+            let _ = writeln!(out, "note: not declared in .gitmodules");
+        }
+    }
+    let key = declared.map_or(path, |declared| &declared.name);
+    let _ = writeln!(
+        out,
+        "hint: attach a local checkout with --submodule-path {}=<dir>",
+        key.as_bstr()
+    );
+    out.into_bytes()
+}
+
+/// Checkout attached to the submodule at `path` (and called `name`, if known).
+#[must_use]
+pub fn checkout_for<'a>(
+    checkouts: &'a [Checkout],
+    path: &[u8],
+    name: Option<&[u8]>,
+) -> Option<&'a Checkout> {
+    checkouts.iter().find(|checkout| {
+        let key: &BStr = checkout.key.as_bytes().as_bstr();
+        name == Some(key.as_bytes()) || key == path
+    })
+}
+
+/// Parse a `--submodule-path` value of the form `<name>=<path>`.
+///
+/// # Errors
+///
+/// Returns an error if the `=` or either side is missing.
+pub fn parse_checkout(input: &str) -> Result<Checkout> {
+    let Some((key, path)) = input.split_once('=') else {
+        bail!("expected <name>=<path>, got '{input}'");
+    };
+    if key.is_empty() || path.is_empty() {
+        bail!("expected <name>=<path>, got '{input}'");
+    }
+    Ok(Checkout {
+        key: key.to_owned(),
+        path: PathBuf::from(path),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkouts_match_by_name_or_path() {
+        let checkouts = [
+            parse_checkout("vendor/lib=/src/lib").unwrap(),
+            parse_checkout("docs=/src/docs").unwrap(),
+        ];
+        assert!(parse_checkout("nopath").is_err());
+        assert!(parse_checkout("=x").is_err());
+
+        let by_path = checkout_for(&checkouts, b"vendor/lib", Some(b"library")).unwrap();
+        assert_eq!(by_path.path, PathBuf::from("/src/lib"));
+        let by_name = checkout_for(&checkouts, b"site", Some(b"docs")).unwrap();
+        assert_eq!(by_name.path, PathBuf::from("/src/docs"));
+        assert!(checkout_for(&checkouts, b"other", None).is_none());
+    }
+}
@@ -0,0 +1,153 @@
+//! Blue/green flips of a mountpoint between snapshots (`gitsnapfs switch`).
+//!
+//! The mountpoint is a symlink. Each snapshot is mounted by a daemon of its
+//! own in a hidden directory beside it, named after the mountpoint:
+//!
+//! ```text
+//! /srv/site -> .site.1f2e3d4c.4711/commits/1f2e3d4c...
+//! /srv/.site.1f2e3d4c.4711/      the daemon serving it
+//! ```
+//!
+//! Once the new daemon answers, the symlink is replaced with `rename(2)`, so
+//! every path lookup sees either the old snapshot or the new one, never
+//! nothing. The old mount is then detached lazily: it disappears from the
+//! tree at once, but files still open in it keep reading until they are
+//! closed, and only then does its daemon exit.
+
+use std::ffi::OsString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use gix::ObjectId;
+
+/// Where the snapshot of `commit` is mounted for `mountpoint`, unique to
+/// this process.
+///
+/// # Errors
+///
+/// Returns an error if `mountpoint` has no file name.
+pub fn mount_dir(mountpoint: &Path, commit: ObjectId) -> Result<PathBuf> {
+    let name = link_name(mountpoint)?;
+    let mut dir = OsString::from(".");
+    dir.push(name);
+    dir.push(format!(
+        ".{}.{}",
+        commit.to_hex_with_len(8),
+        std::process::id()
+    ));
+    Ok(mountpoint.with_file_name(dir))
+}
+
+/// The mount directory `mountpoint` currently links into, if it is one
+/// [`mount_dir`] made.
+///
+/// # Errors
+///
+/// Returns an error if `mountpoint` exists but is not a symlink, which
+/// `gitsnapfs switch` must not replace.
+pub fn current_mount_dir(mountpoint: &Path) -> Result<Option<PathBuf>> {
+    let target = match std::fs::read_link(mountpoint) {
+        Ok(target) => target,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) if err.raw_os_error() == Some(libc::EINVAL) => bail!(
+            "{} is not a symlink; gitsnapfs switch only replaces the symlinks it makes",
+            mountpoint.display()
+        ),
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to read {}", mountpoint.display()))
+        }
+    };
+    let mut prefix = b".".to_vec();
+    prefix.extend_from_slice(link_name(mountpoint)?.as_bytes());
+    prefix.push(b'.');
+    let dir = target.parent().and_then(Path::parent);
+    Ok(dir
+        .filter(|dir| dir.parent() == Some(Path::new("")))
+        .filter(|dir| dir.as_os_str().as_bytes().starts_with(&prefix))
+        .map(|dir| mountpoint.with_file_name(dir)))
+}
+
+/// Point `mountpoint` at `commit` in the snapshot mounted at `dir`,
+/// replacing whatever it pointed at in one step.
+///
+/// # Errors
+///
+/// Returns an error if the symlink cannot be created or moved into place.
+pub fn flip(mountpoint: &Path, dir: &Path, commit: ObjectId) -> Result<()> {
+    let dir_name = dir
+        .file_name()
+        .with_context(|| format!("{} has no file name", dir.display()))?;
+    let target = Path::new(dir_name).join("commits").join(commit.to_string());
+    let mut staged = dir_name.to_os_string();
+    staged.push(".link");
+    let staged = mountpoint.with_file_name(staged);
+    std::os::unix::fs::symlink(&target, &staged)
+        .with_context(|| format!("failed to create {}", staged.display()))?;
+    std::fs::rename(&staged, mountpoint).with_context(|| {
+        let _ = std::fs::remove_file(&staged);
+        format!("failed to replace {}", mountpoint.display())
+    })
+}
+
+/// Detach the mount at `dir` and remove the directory. Open files keep
+/// working until closed; the daemon serving them exits after that.
+///
+/// # Errors
+///
+/// Returns an error if the mount cannot be detached.
+pub fn detach(dir: &Path, fusermount: Option<&Path>) -> Result<()> {
+    crate::mount::detach(dir, fusermount)?;
+    std::fs::remove_dir(dir).with_context(|| format!("failed to remove {}", dir.display()))
+}
+
+fn link_name(mountpoint: &Path) -> Result<&std::ffi::OsStr> {
+    mountpoint
+        .file_name()
+        .with_context(|| format!("{} has no file name", mountpoint.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flips_the_link_and_finds_the_mount_behind_it() {
+        let root = tempfile::tempdir().unwrap();
+        let mountpoint = root.path().join("site");
+        let first = ObjectId::from_hex(b"1f2e3d4c5b6a79881f2e3d4c5b6a79881f2e3d4c").unwrap();
+        let second = ObjectId::from_hex(b"aa2e3d4c5b6a79881f2e3d4c5b6a79881f2e3d4c").unwrap();
+        assert_eq!(current_mount_dir(&mountpoint).unwrap(), None);
+
+        let dir = mount_dir(&mountpoint, first).unwrap();
+        assert!(dir
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with(".site.1f2e3d4c."));
+        flip(&mountpoint, &dir, first).unwrap();
+        assert_eq!(
+            std::fs::read_link(&mountpoint).unwrap(),
+            Path::new(dir.file_name().unwrap())
+                .join("commits")
+                .join(first.to_string())
+        );
+        assert_eq!(current_mount_dir(&mountpoint).unwrap(), Some(dir.clone()));
+
+        let next = root.path().join(".site.aa2e3d4c.2");
+        flip(&mountpoint, &next, second).unwrap();
+        assert_eq!(current_mount_dir(&mountpoint).unwrap(), Some(next));
+        assert_eq!(std::fs::read_dir(root.path()).unwrap().count(), 1);
+
+        // A link made by someone else names no mount to retire.
+        std::fs::remove_file(&mountpoint).unwrap();
+        std::os::unix::fs::symlink("/srv/elsewhere/commits/x", &mountpoint).unwrap();
+        assert_eq!(current_mount_dir(&mountpoint).unwrap(), None);
+
+        std::fs::remove_file(&mountpoint).unwrap();
+        std::fs::create_dir(&mountpoint).unwrap();
+        assert!(current_mount_dir(&mountpoint).is_err());
+    }
+}
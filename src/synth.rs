@@ -0,0 +1,218 @@
+//! User-declared files synthesized at the root of every commit snapshot.
+//!
+//! Build systems often expect release exports to carry files such as
+//! `VERSION` or `COMMIT`. Instead of committing them, users describe them in a
+//! JSON file passed via `--synthetic-files`:
+//!
+//! ```json
+//! { "files": [
+//!     { "name": "VERSION", "template": "{describe}\n" },
+//!     { "name": "COMMIT",  "template": "{commit}\n" }
+//! ] }
+//! ```
+//!
+//! Templates may use the placeholders `{commit}`, `{short}`, `{tree}`,
+//! `{describe}` (annotated tags, falling back to the abbreviated id like
+//! `git describe --always`), `{subject}`, `{author}`, `{author_email}` and
+//! `{commit_time}` (committer time as Unix seconds). `{{` and `}}` produce
+//! literal braces. The author is named as the repository's `.mailmap` maps
+//! them, unless the mount runs with `--no-mailmap`.
+
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use gix::bstr::ByteSlice;
+use gix::mailmap::snapshot::Signature;
+use gix::mailmap::Snapshot;
+use gix::ObjectId;
+use serde::Deserialize;
+
+/// A single synthesized file.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct SyntheticFile {
+    /// File name placed in the snapshot root. Must not contain `/`.
+    pub name: String,
+    /// Content template, see the module documentation for placeholders.
+    pub template: Template,
+}
+
+#[derive(Debug, Deserialize)]
+struct SyntheticFilesConfig {
+    files: Vec<SyntheticFile>,
+}
+
+/// Load synthesized file declarations from the JSON file at `path`.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or parsed, declares an invalid
+/// name or a template uses an unknown placeholder.
+pub fn load(path: &Path) -> Result<Vec<SyntheticFile>> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("failed to read synthetic files config {}", path.display()))?;
+    let config: SyntheticFilesConfig = serde_json::from_slice(&data)
+        .with_context(|| format!("failed to parse synthetic files config {}", path.display()))?;
+    for file in &config.files {
+        if file.name.is_empty() || file.name.contains('/') || file.name == "." || file.name == ".."
+        {
+            bail!("invalid synthetic file name '{}'", file.name);
+        }
+    }
+    Ok(config.files)
+}
+
+/// A parsed content template.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Template {
+    pieces: Vec<Piece>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Text(String),
+    Field(Field),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Commit,
+    Short,
+    Tree,
+    Describe,
+    Subject,
+    Author,
+    AuthorEmail,
+    CommitTime,
+}
+
+impl Template {
+    /// Parse `template`, resolving `{{`, `}}` and the placeholders.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a brace is unbalanced or a placeholder is unknown.
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut rest = template;
+        while let Some(pos) = rest.find(['{', '}']) {
+            text.push_str(&rest[..pos]);
+            let tail = &rest[pos..];
+            if let Some(stripped) = tail.strip_prefix("{{") {
+                text.push('{');
+                rest = stripped;
+            } else if let Some(stripped) = tail.strip_prefix("}}") {
+                text.push('}');
+                rest = stripped;
+            } else if let Some(body) = tail.strip_prefix('{') {
+                let end = body.find('}').with_context(|| {
+                    format!("unterminated placeholder in template '{template}'")
+                })?;
+                let field = match &body[..end] {
+                    "commit" => Field::Commit,
+                    "short" => Field::Short,
+                    "tree" => Field::Tree,
+                    "describe" => Field::Describe,
+                    "subject" => Field::Subject,
+                    "author" => Field::Author,
+                    "author_email" => Field::AuthorEmail,
+                    "commit_time" => Field::CommitTime,
+                    other => bail!("unknown template placeholder '{{{other}}}'"),
+                };
+                if !text.is_empty() {
+                    pieces.push(Piece::Text(std::mem::take(&mut text)));
+                }
+                pieces.push(Piece::Field(field));
+                rest = &body[end + 1..];
+            } else {
+                bail!("unbalanced '}}' in template '{template}'");
+            }
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        Ok(Self { pieces })
+    }
+
+    /// Render the template for `commit`, naming its author as `mailmap` maps them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the commit cannot be read.
+    pub fn render(
+        &self,
+        repo: &gix::Repository,
+        id: ObjectId,
+        mailmap: Option<&Snapshot>,
+    ) -> Result<Vec<u8>> {
+        let commit = repo.find_commit(id)?;
+        let mut out = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Text(text) => out.push_str(text),
+                Piece::Field(field) => out.push_str(&field.render(&commit, mailmap)?),
+            }
+        }
+        Ok(out.into_bytes())
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = anyhow::Error;
+
+    fn try_from(template: String) -> Result<Self> {
+        Self::parse(&template)
+    }
+}
+
+impl Field {
+    fn render(self, commit: &gix::Commit<'_>, mailmap: Option<&Snapshot>) -> Result<String> {
+        Ok(match self {
+            Field::Commit => commit.id.to_string(),
+            Field::Short => commit.id.to_hex_with_len(7).to_string(),
+            Field::Tree => commit.tree_id()?.to_string(),
+            Field::Describe => commit
+                .describe()
+                .names(gix::commit::describe::SelectRef::AnnotatedTags)
+                .id_as_fallback(true)
+                .format()?
+                .to_string(),
+            Field::Subject => commit.message()?.summary().to_str_lossy().into_owned(),
+            Field::Author => mapped(commit.author()?, mailmap)
+                .name
+                .to_str_lossy()
+                .into_owned(),
+            Field::AuthorEmail => mapped(commit.author()?, mailmap)
+                .email
+                .to_str_lossy()
+                .into_owned(),
+            Field::CommitTime => commit.time()?.seconds.to_string(),
+        })
+    }
+}
+
+/// `signature` with the canonical name and email `mailmap` has for it.
+pub(crate) fn mapped<'a>(
+    signature: gix::actor::SignatureRef<'a>,
+    mailmap: Option<&Snapshot>,
+) -> Signature<'a> {
+    match mailmap {
+        Some(mailmap) => mailmap.resolve_cow(signature),
+        None => signature.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rejects_bad_templates() {
+        assert!(Template::parse("{commit} {{literal}}\n").is_ok());
+        assert!(Template::parse("{version}").is_err());
+        assert!(Template::parse("{commit").is_err());
+        assert!(Template::parse("commit}").is_err());
+    }
+}
@@ -0,0 +1,128 @@
+//! Token-bucket throttling for the read path.
+//!
+//! A shared build host should not let one `tar` over the whole history starve
+//! the disk. Each bucket refills continuously at its configured rate and holds
+//! at most one second worth of tokens. Requests larger than the remaining
+//! balance are admitted but put the bucket into debt, and the caller sleeps
+//! until the debt is paid off, so the long-run rate never exceeds the limit.
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::config::parse_byte_size;
+use crate::interrupt;
+
+/// Longest sleep between checks for an interrupt while paying off debt.
+const INTERRUPT_POLL: Duration = Duration::from_millis(50);
+
+/// Limits applied to every read served by a mount.
+#[derive(Debug, Default)]
+pub struct ReadThrottle {
+    bandwidth: Option<Mutex<Bucket>>,
+    iops: Option<Mutex<Bucket>>,
+}
+
+impl ReadThrottle {
+    /// Build a throttle from optional bytes-per-second and reads-per-second limits.
+    #[must_use]
+    pub fn new(bytes_per_sec: Option<u64>, reads_per_sec: Option<u32>) -> Self {
+        Self {
+            bandwidth: bytes_per_sec.map(|rate| Mutex::new(Bucket::new(rate))),
+            iops: reads_per_sec.map(|rate| Mutex::new(Bucket::new(u64::from(rate)))),
+        }
+    }
+
+    /// Account for a read of `bytes`, blocking the caller until it fits the
+    /// limits or its request is interrupted.
+    pub fn acquire(&self, bytes: u64) {
+        let wait = [(&self.iops, 1), (&self.bandwidth, bytes)]
+            .into_iter()
+            .filter_map(|(bucket, amount)| {
+                let bucket = bucket.as_ref()?;
+                let mut bucket = bucket
+                    .lock()
+                    .unwrap_or_else(std::sync::PoisonError::into_inner);
+                Some(bucket.take(amount))
+            })
+            .max()
+            .unwrap_or_default();
+        let deadline = Instant::now() + wait;
+        while interrupt::check().is_ok() {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            thread::sleep(left.min(INTERRUPT_POLL));
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    #[allow(clippy::cast_precision_loss)]
+    fn new(rate: u64) -> Self {
+        let rate = rate.max(1) as f64;
+        Self {
+            rate,
+            tokens: rate,
+            last: Instant::now(),
+        }
+    }
+
+    /// Withdraw `amount` tokens and return how long the caller has to wait.
+    #[allow(clippy::cast_precision_loss)]
+    fn take(&mut self, amount: u64) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.tokens -= amount as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+/// Parse a byte rate such as `512K`, `20M/s` or `1G` (binary multiples) into bytes per second.
+///
+/// # Errors
+///
+/// Returns an error for empty, zero, malformed or overflowing values.
+pub fn parse_byte_rate(input: &str) -> Result<u64> {
+    let trimmed = input.trim();
+    parse_byte_size(trimmed.strip_suffix("/s").unwrap_or(trimmed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_byte_rates() {
+        assert_eq!(parse_byte_rate("4096").unwrap(), 4096);
+        assert_eq!(parse_byte_rate("512K").unwrap(), 512 * 1024);
+        assert_eq!(parse_byte_rate("20MiB/s").unwrap(), 20 << 20);
+        assert_eq!(parse_byte_rate("1g").unwrap(), 1 << 30);
+        assert!(parse_byte_rate("0").is_err());
+        assert!(parse_byte_rate("10X").is_err());
+    }
+
+    #[test]
+    fn debt_turns_into_wait() {
+        let mut bucket = Bucket::new(100);
+        assert_eq!(bucket.take(100), Duration::ZERO);
+        let wait = bucket.take(50);
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,311 @@
+//! Transport-agnostic, path-based view of the snapshot hierarchy.
+//!
+//! [`GitSnapFs`] answers inode-based FUSE requests. Frontends that speak in
+//! paths (the HTTP gateway, for example) go through [`Vfs`] instead, which
+//! walks names with the same lookup logic, follows the synthetic symlinks
+//! (`HEAD`, `branches/*`, `.git-meta/*`, ...) and reports nodes as plain
+//! values without any FUSE types. Code that holds a `GitSnapFs` directly can
+//! call [`GitSnapFs::resolve_path`] and work with the returned [`NodeRef`].
+
+use std::io;
+use std::sync::Arc;
+
+use fuse_backend_rs::abi::fuse_abi::{stat64, ROOT_ID};
+use libc::{S_IFDIR, S_IFLNK, S_IFMT};
+
+use crate::fs::GitSnapFs;
+
+/// Upper bound on symlinks followed while resolving a single path.
+const MAX_SYMLINK_HOPS: usize = 40;
+
+/// What kind of object a [`Node`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Directory,
+    File,
+    Symlink,
+}
+
+/// Attributes of a single node in the hierarchy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Node {
+    pub inode: u64,
+    pub kind: NodeKind,
+    /// Permission bits, without the file type.
+    pub mode: u32,
+    pub size: u64,
+    /// Modification time as Unix seconds.
+    pub mtime: i64,
+}
+
+/// A named child returned by [`Vfs::read_dir`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: Vec<u8>,
+    pub node: Node,
+}
+
+/// Path-oriented access to a [`GitSnapFs`] that can be shared between
+/// threads, as the network frontends need.
+pub struct Vfs {
+    fs: Arc<GitSnapFs>,
+}
+
+impl Vfs {
+    pub fn new(fs: Arc<GitSnapFs>) -> Self {
+        Self { fs }
+    }
+
+    /// The mount root.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the root attributes cannot be produced.
+    pub fn root(&self) -> io::Result<Node> {
+        root(&self.fs)
+    }
+
+    /// Look up `name` inside the directory `parent`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ENOENT` if the name does not exist.
+    pub fn lookup(&self, parent: &Node, name: &[u8]) -> io::Result<Node> {
+        lookup(&self.fs, parent, name)
+    }
+
+    /// Resolve a `/`-separated path relative to the root.
+    ///
+    /// Symlinks in intermediate components are always followed; the final
+    /// component is followed only if `follow_last` is set. `.` and `..` are
+    /// handled lexically, and `..` at the root stays at the root.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ENOENT` for missing components and `ELOOP` if too many
+    /// symlinks are encountered.
+    pub fn resolve(&self, path: &[u8], follow_last: bool) -> io::Result<Node> {
+        resolve(&self.fs, path, follow_last)
+    }
+
+    /// List the children of the directory `dir`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `dir` is not a directory or cannot be enumerated
+    /// (as is the case for `commits/` and `trees/`).
+    pub fn read_dir(&self, dir: &Node) -> io::Result<Vec<DirEntry>> {
+        read_dir(&self.fs, dir)
+    }
+
+    /// Full contents of the regular file `file`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `EISDIR` for directories and an error if the blob cannot be read.
+    pub fn read(&self, file: &Node) -> io::Result<Vec<u8>> {
+        check_file(file)?;
+        let charged = self.fs.charge_blob_read(file.inode, 0, usize::MAX);
+        let content = self.fs.file_content(file.inode)?;
+        self.fs.account_read(file.inode, content.len(), charged);
+        Ok(content.to_vec())
+    }
+
+    /// Target of the symlink `link`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `EINVAL` if `link` is not a symlink.
+    pub fn read_link(&self, link: &Node) -> io::Result<Vec<u8>> {
+        read_link(&self.fs, link)
+    }
+}
+
+/// A [`Node`] bound to the filesystem it was resolved in, so library users
+/// (tests, tools, language servers) get the mount's semantics without FUSE.
+#[derive(Clone, Copy)]
+pub struct NodeRef<'a> {
+    fs: &'a GitSnapFs,
+    node: Node,
+}
+
+impl GitSnapFs {
+    /// Resolve `path` the way `open` on a mount would, following every
+    /// symlink, so `/branches/main` yields the snapshot directory of the
+    /// branch tip.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ENOENT` for missing components and `ELOOP` if too many
+    /// symlinks are encountered.
+    pub fn resolve_path(&self, path: impl AsRef<[u8]>) -> io::Result<NodeRef<'_>> {
+        let node = resolve(self, path.as_ref(), true)?;
+        Ok(NodeRef { fs: self, node })
+    }
+}
+
+impl<'a> NodeRef<'a> {
+    #[must_use]
+    pub fn node(&self) -> Node {
+        self.node
+    }
+
+    /// The child `name` of this directory, without following it if it is a
+    /// symlink.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ENOTDIR` if this is not a directory and `ENOENT` if the name
+    /// does not exist.
+    pub fn lookup(&self, name: &[u8]) -> io::Result<NodeRef<'a>> {
+        let node = lookup(self.fs, &self.node, name)?;
+        Ok(NodeRef { fs: self.fs, node })
+    }
+
+    /// The children of this directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if this is not a directory or cannot be enumerated.
+    pub fn read_dir(&self) -> io::Result<Vec<DirEntry>> {
+        read_dir(self.fs, &self.node)
+    }
+
+    /// Up to `len` bytes of this file starting at `offset`; fewer at the end
+    /// of the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns `EISDIR` for directories and an error if the blob cannot be read.
+    pub fn read_at(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        check_file(&self.node)?;
+        self.fs.sample_read(self.node.inode, offset);
+        let charged = self.fs.charge_blob_read(self.node.inode, offset, len);
+        let data = self.fs.read_range(self.node.inode, offset, len)?;
+        self.fs.account_read(self.node.inode, data.len(), charged);
+        Ok(data)
+    }
+
+    /// Target of this symlink.
+    ///
+    /// # Errors
+    ///
+    /// Returns `EINVAL` if this is not a symlink.
+    pub fn read_link(&self) -> io::Result<Vec<u8>> {
+        read_link(self.fs, &self.node)
+    }
+}
+
+impl std::ops::Deref for NodeRef<'_> {
+    type Target = Node;
+
+    fn deref(&self) -> &Node {
+        &self.node
+    }
+}
+
+impl std::fmt::Debug for NodeRef<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.node.fmt(f)
+    }
+}
+
+fn root(fs: &GitSnapFs) -> io::Result<Node> {
+    fs.attr_for_inode(ROOT_ID).map(|attr| node_from_attr(&attr))
+}
+
+fn lookup(fs: &GitSnapFs, parent: &Node, name: &[u8]) -> io::Result<Node> {
+    if parent.kind != NodeKind::Directory {
+        return Err(io::Error::from_raw_os_error(libc::ENOTDIR));
+    }
+    fs.lookup_name(parent.inode, name)
+        .map(|entry| node_from_attr(&entry.attr))
+}
+
+fn resolve(fs: &GitSnapFs, path: &[u8], follow_last: bool) -> io::Result<Node> {
+    let root = root(fs)?;
+    let mut stack: Vec<Node> = Vec::new();
+    let mut pending: Vec<Vec<u8>> = components(path).rev().collect();
+    let mut hops = 0;
+    while let Some(component) = pending.pop() {
+        match component.as_slice() {
+            b"." => continue,
+            b".." => {
+                stack.pop();
+                continue;
+            }
+            _ => {}
+        }
+        let parent = stack.last().unwrap_or(&root);
+        let node = lookup(fs, parent, &component)?;
+        if node.kind == NodeKind::Symlink && (follow_last || !pending.is_empty()) {
+            hops += 1;
+            if hops > MAX_SYMLINK_HOPS {
+                return Err(io::Error::from_raw_os_error(libc::ELOOP));
+            }
+            let target = read_link(fs, &node)?;
+            if target.starts_with(b"/") {
+                stack.clear();
+            }
+            pending.extend(components(&target).rev());
+            continue;
+        }
+        stack.push(node);
+    }
+    Ok(stack.pop().unwrap_or(root))
+}
+
+fn read_dir(fs: &GitSnapFs, dir: &Node) -> io::Result<Vec<DirEntry>> {
+    if dir.kind != NodeKind::Directory {
+        return Err(io::Error::from_raw_os_error(libc::ENOTDIR));
+    }
+    fs.list_directory(dir.inode)?
+        .into_iter()
+        .map(|record| {
+            let attr = match record.entry {
+                Some(entry) => entry.attr,
+                None => fs.attr_for_inode(record.ino)?,
+            };
+            Ok(DirEntry {
+                name: record.name,
+                node: node_from_attr(&attr),
+            })
+        })
+        .collect()
+}
+
+fn read_link(fs: &GitSnapFs, link: &Node) -> io::Result<Vec<u8>> {
+    if link.kind != NodeKind::Symlink {
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+    fs.link_target(link.inode)
+}
+
+fn check_file(node: &Node) -> io::Result<()> {
+    match node.kind {
+        NodeKind::File => Ok(()),
+        NodeKind::Directory => Err(io::Error::from_raw_os_error(libc::EISDIR)),
+        NodeKind::Symlink => Err(io::Error::from_raw_os_error(libc::EINVAL)),
+    }
+}
+
+fn components(path: &[u8]) -> impl DoubleEndedIterator<Item = Vec<u8>> + '_ {
+    path.split(|byte| *byte == b'/')
+        .filter(|component| !component.is_empty())
+        .map(<[u8]>::to_vec)
+}
+
+fn node_from_attr(attr: &stat64) -> Node {
+    let kind = match attr.st_mode & S_IFMT {
+        S_IFDIR => NodeKind::Directory,
+        S_IFLNK => NodeKind::Symlink,
+        _ => NodeKind::File,
+    };
+    Node {
+        inode: attr.st_ino,
+        kind,
+        mode: attr.st_mode & !S_IFMT,
+        size: u64::try_from(attr.st_size).unwrap_or_default(),
+        mtime: attr.st_mtime,
+    }
+}
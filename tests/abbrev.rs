@@ -0,0 +1,72 @@
+//! With `--abbrev`, links name commits by their shortest unique prefix,
+//! prefixes resolve under `commits/` to the same inode as full ids, and a
+//! prefix another object comes to share no longer resolves.
+
+use gix::ObjectId;
+
+use gitsnapfs::config::FsConfig;
+use gitsnapfs::fs::GitSnapFs;
+
+mod common;
+
+#[test]
+fn links_use_unique_prefixes() {
+    let repo = common::TestRepo::bare();
+    let commit = repo.commit(repo.readme("hello\n"), &[], "init");
+    repo.branch("main", commit);
+    repo.set_head("main");
+    let hex = commit.to_string();
+
+    let mount = || {
+        repo.mount_with(FsConfig {
+            abbrev: Some(4),
+            ..FsConfig::default()
+        })
+    };
+    let link = |fs: &GitSnapFs, path: &str| {
+        let link = match path.rsplit_once('/') {
+            Some((dir, name)) => fs.resolve_path(dir).unwrap().lookup(name.as_bytes()),
+            None => fs.resolve_path("").unwrap().lookup(path.as_bytes()),
+        };
+        String::from_utf8(link.unwrap().read_link().unwrap()).unwrap()
+    };
+
+    let fs = mount();
+    let short = &hex[..4];
+    assert_eq!(link(&fs, "HEAD"), format!("commits/{short}"));
+    assert_eq!(link(&fs, "branches/main"), format!("../commits/{short}"));
+    assert_eq!(
+        link(&fs, "refs/heads/main"),
+        format!("../../commits/{short}")
+    );
+    let by_prefix = fs.resolve_path(format!("commits/{short}/README")).unwrap();
+    let by_id = fs.resolve_path(format!("commits/{hex}/README")).unwrap();
+    assert_eq!(by_prefix.inode, by_id.inode);
+    assert_eq!(by_prefix.read_at(0, 64).unwrap(), b"hello\n");
+
+    // A blob sharing exactly the first four digits makes them ambiguous.
+    let clash = (0..u32::MAX)
+        .map(|n| n.to_string())
+        .find(|content| {
+            let id = gix::objs::compute_hash(
+                gix::hash::Kind::Sha1,
+                gix::object::Kind::Blob,
+                content.as_bytes(),
+            )
+            .unwrap();
+            let id = id.to_string();
+            id[..4] == hex[..4] && id[4..5] != hex[4..5]
+        })
+        .unwrap();
+    let clash: ObjectId = repo.blob(clash);
+    assert!(clash.to_string().starts_with(short));
+
+    let fs = mount();
+    let short = &hex[..5];
+    assert_eq!(link(&fs, "HEAD"), format!("commits/{short}"));
+    assert!(fs.resolve_path(format!("commits/{}", &hex[..4])).is_err());
+    assert_eq!(
+        fs.resolve_path(format!("commits/{short}")).unwrap().inode,
+        fs.resolve_path(format!("commits/{hex}")).unwrap().inode
+    );
+}
@@ -0,0 +1,43 @@
+//! `at/<time>` links to the commit `HEAD` stood at then; without a reflog
+//! that is found by committer date along the first-parent history.
+
+use std::ffi::CString;
+
+use fuse_backend_rs::api::filesystem::{Context, FileSystem};
+
+use gitsnapfs::fs::INODE_AT;
+
+mod common;
+
+#[test]
+fn links_to_the_commit_current_at_the_time() {
+    let repo = common::TestRepo::bare();
+    let tree = repo.readme("hello\n");
+    let commit = |time: i64, parents: &[_]| {
+        let mut signature = repo.signature();
+        signature.time = gix::date::Time::new(time, 0);
+        repo.commit_by(signature, tree, parents, "commit")
+    };
+    // 2024-06-01T00:00:00Z and a day later.
+    let first = commit(1_717_200_000, &[]);
+    let second = commit(1_717_286_400, &[first]);
+    repo.branch("main", second);
+
+    let fs = repo.mount();
+    let ctx = Context::default();
+    let at = |name: &str| {
+        fs.lookup(&ctx, INODE_AT, &CString::new(name).unwrap())
+            .and_then(|entry| fs.readlink(&ctx, entry.inode))
+            .map(|target| String::from_utf8(target).unwrap())
+            .map_err(|err| err.raw_os_error())
+    };
+
+    assert_eq!(
+        at("2024-06-01T12:00:00Z"),
+        Ok(format!("../commits/{first}"))
+    );
+    assert_eq!(at("2024-06-02"), Ok(format!("../commits/{second}")));
+    assert_eq!(at("1717286399"), Ok(format!("../commits/{first}")));
+    assert_eq!(at("2024-05-31T23:59:59Z"), Err(Some(libc::ENOENT)));
+    assert_eq!(at("yesterday-ish"), Err(Some(libc::ENOENT)));
+}
@@ -0,0 +1,85 @@
+//! With an authorization hook, each caller's requests are decided on the
+//! path that caller walked, even where two paths lead to the same object,
+//! and opens bypass the page cache so every read reaches the hook.
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+
+use fuse_backend_rs::abi::fuse_abi::ROOT_ID;
+use fuse_backend_rs::api::filesystem::{Context, FileSystem, OpenOptions};
+
+use gitsnapfs::authz::Hook;
+use gitsnapfs::config::FsConfig;
+use gitsnapfs::fs::GitSnapFs;
+
+mod common;
+
+/// Refuses uid 1000 anything under `secret`, and allows everything else.
+const POLICY: &str = "#!/bin/sh\ncase \"$1:$3\" in 1000:*/secret*) exit 1 ;; esac\nexit 0\n";
+
+fn caller(uid: u32) -> Context {
+    Context {
+        uid,
+        ..Context::default()
+    }
+}
+
+/// The inode `uid` reaches by looking up each component of `path`.
+fn walk(fs: &GitSnapFs, uid: u32, path: &str) -> io::Result<u64> {
+    path.split('/').try_fold(ROOT_ID, |parent, name| {
+        let name = CString::new(name).unwrap();
+        fs.lookup(&caller(uid), parent, &name)
+            .map(|entry| entry.inode)
+    })
+}
+
+#[test]
+fn requests_are_decided_on_the_callers_own_path() {
+    let repo = common::TestRepo::bare();
+    let blob = repo.blob("shared\n");
+    let public = repo.tree([("x", blob)]);
+    let secret = repo.tree([("y", blob)]);
+    let tree = repo.tree_of_entries(vec![
+        gix::objs::tree::Entry {
+            mode: gix::objs::tree::EntryKind::Tree.into(),
+            filename: "public".into(),
+            oid: public,
+        },
+        gix::objs::tree::Entry {
+            mode: gix::objs::tree::EntryKind::Tree.into(),
+            filename: "secret".into(),
+            oid: secret,
+        },
+    ]);
+    let hooks = tempfile::tempdir().unwrap();
+    let hook = hooks.path().join("policy");
+    std::fs::write(&hook, POLICY).unwrap();
+    std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let fs = repo.mount_with(FsConfig {
+        authz: Some(Hook::Command(hook)),
+        ..FsConfig::default()
+    });
+
+    let file = walk(&fs, 1000, &format!("trees/{tree}/public/x")).unwrap();
+    // Another caller reaches the same blob inode by a path uid 1000 may not.
+    assert_eq!(
+        walk(&fs, 1001, &format!("trees/{tree}/secret/y")).unwrap(),
+        file
+    );
+    assert_eq!(
+        walk(&fs, 1000, &format!("trees/{tree}/secret/y"))
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EACCES)
+    );
+
+    let open = |uid| fs.open(&caller(uid), file, libc::O_RDONLY as u32, 0);
+    for uid in [1000, 1001] {
+        let (handle, options, _) = open(uid).unwrap();
+        assert!(handle.is_some(), "{uid}");
+        assert!(options.contains(OpenOptions::DIRECT_IO), "{uid}");
+    }
+    // A caller that never looked the file up has no path to decide on.
+    assert_eq!(open(1002).unwrap_err().raw_os_error(), Some(libc::EACCES));
+}
@@ -0,0 +1,57 @@
+//! `branches-meta/<branch>/CHANGELOG` lists the commits since the newest
+//! tagged one, newest first, in the line format `--changelog-template`
+//! sets, and follows the branch and its tags when they move.
+
+use gitsnapfs::config::FsConfig;
+use gitsnapfs::fs::GitSnapFs;
+
+mod common;
+
+#[test]
+fn lists_commits_since_the_last_tag() {
+    let repo = common::TestRepo::bare();
+    let readme = repo.readme("hello\n");
+    let root = repo.commit(readme, &[], "root");
+    let one = repo.commit(readme, &[root], "one");
+    let two = repo.commit(readme, &[one], "two");
+    let three = repo.commit(readme, &[two], "three");
+    let topic = repo.commit(readme, &[root], "topic");
+    repo.tag("v1.0", one);
+    repo.branch("main", three);
+    repo.branch("release", one);
+    repo.branch("topic", topic);
+    repo.set_head("main");
+
+    let changelog = |fs: &GitSnapFs, branch: &str| {
+        let path = format!("branches-meta/{branch}/CHANGELOG");
+        String::from_utf8(fs.resolve_path(path).unwrap().read_at(0, 4096).unwrap()).unwrap()
+    };
+    let short = |id: gix::ObjectId| id.to_hex_with_len(7).to_string();
+
+    let fs = repo.mount();
+    assert_eq!(
+        changelog(&fs, "main"),
+        format!("* three ({})\n* two ({})\n", short(three), short(two))
+    );
+    assert_eq!(changelog(&fs, "release"), "");
+    assert_eq!(
+        changelog(&fs, "topic"),
+        format!("* topic ({})\n* root ({})\n", short(topic), short(root))
+    );
+
+    let four = repo.commit(readme, &[three], "four");
+    repo.branch("main", four);
+    assert!(changelog(&fs, "main").starts_with(&format!("* four ({})\n", short(four))));
+    // A tag added below an unchanged tip ends the listing there.
+    repo.tag("v2.0", three);
+    assert_eq!(
+        changelog(&fs, "main"),
+        format!("* four ({})\n", short(four))
+    );
+
+    let fs = repo.mount_with(FsConfig {
+        changelog_template: Some("{subject} by {author}".to_owned()),
+        ..FsConfig::default()
+    });
+    assert_eq!(changelog(&fs, "main"), "four by Test\n");
+}
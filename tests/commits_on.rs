@@ -0,0 +1,74 @@
+//! `/commits-on/<branch>/<n>` links the branch's `n`-th first-parent
+//! ancestor, for up to `--history-depth` commits.
+
+use gitsnapfs::config::FsConfig;
+use gitsnapfs::fs::GitSnapFs;
+
+mod common;
+
+#[test]
+fn branch_history_is_numbered_along_first_parents() {
+    let repo = common::TestRepo::bare();
+    let tree = repo.readme("hello\n");
+    let commit = |parents: &[_]| repo.commit(tree, parents, "commit");
+    // root - a - b - merge
+    //    \            /
+    //     side -------
+    let root = commit(&[]);
+    let a = commit(&[root]);
+    let b = commit(&[a]);
+    let side = commit(&[root]);
+    let merge = commit(&[b, side]);
+    repo.branch("main", merge);
+    repo.branch("side", side);
+
+    let mount = |history_depth: Option<usize>| {
+        repo.mount_with(FsConfig {
+            history_depth,
+            ..FsConfig::default()
+        })
+    };
+    let names = |fs: &GitSnapFs, path: &str| {
+        let mut names: Vec<String> = fs
+            .resolve_path(path)
+            .unwrap()
+            .read_dir()
+            .unwrap()
+            .into_iter()
+            .map(|entry| String::from_utf8(entry.name).unwrap())
+            .collect();
+        names.retain(|name| name != "." && name != "..");
+        names
+    };
+    let link = |fs: &GitSnapFs, n: usize| {
+        let target = fs
+            .resolve_path("commits-on/main")
+            .unwrap()
+            .lookup(n.to_string().as_bytes())
+            .unwrap()
+            .read_link()
+            .unwrap();
+        String::from_utf8(target).unwrap()
+    };
+
+    let fs = mount(None);
+    assert_eq!(names(&fs, "commits-on"), ["main", "side"]);
+    assert_eq!(names(&fs, "commits-on/main"), ["0", "1", "2", "3"]);
+    assert_eq!(names(&fs, "commits-on/side"), ["0", "1"]);
+    for (n, expected) in [merge, b, a, root].into_iter().enumerate() {
+        assert_eq!(link(&fs, n), format!("../../commits/{expected}"));
+    }
+    assert_eq!(
+        fs.resolve_path("commits-on/main/2/README")
+            .unwrap()
+            .read_at(0, 64)
+            .unwrap(),
+        b"hello\n"
+    );
+    assert!(fs.resolve_path("commits-on/main/4").is_err());
+    assert!(fs.resolve_path("commits-on/missing").is_err());
+
+    let fs = mount(Some(2));
+    assert_eq!(names(&fs, "commits-on/main"), ["0", "1"]);
+    assert!(fs.resolve_path("commits-on/main/2").is_err());
+}
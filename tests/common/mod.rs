@@ -0,0 +1,173 @@
+//! A repository built object by object for the integration tests.
+
+// Each test crate compiles this module and uses only part of it.
+#![allow(dead_code)]
+
+use std::cell::Cell;
+use std::path::Path;
+
+use fuse_backend_rs::api::filesystem::{FileSystem, FsOptions};
+use gix::bstr::BString;
+use gix::objs::tree;
+use gix::ObjectId;
+use tempfile::TempDir;
+
+use gitsnapfs::config::FsConfig;
+use gitsnapfs::fs::GitSnapFs;
+use gitsnapfs::repo::Repository;
+
+/// Committer time of the first commit; each later one is a minute later.
+pub const START_TIME: i64 = 1_700_000_000;
+
+/// A repository in a temporary directory that goes away with it.
+pub struct TestRepo {
+    _dir: TempDir,
+    repo: gix::Repository,
+    time: Cell<i64>,
+}
+
+impl TestRepo {
+    /// An empty bare repository.
+    pub fn bare() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = gix::init_bare(dir.path()).unwrap();
+        Self::wrap(dir, repo)
+    }
+
+    /// An empty repository with a work tree; [`Self::path`] is its `.git`.
+    pub fn with_workdir() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = gix::init(dir.path()).unwrap();
+        Self::wrap(dir, repo)
+    }
+
+    fn wrap(dir: TempDir, repo: gix::Repository) -> Self {
+        Self {
+            _dir: dir,
+            repo,
+            time: Cell::new(START_TIME - 60),
+        }
+    }
+
+    /// The Git directory.
+    pub fn path(&self) -> &Path {
+        self.repo.git_dir()
+    }
+
+    pub fn blob(&self, content: impl AsRef<[u8]>) -> ObjectId {
+        self.repo.write_blob(content).unwrap().detach()
+    }
+
+    /// A tree of regular files, from entries given in Git's sort order.
+    pub fn tree<N: Into<BString>>(
+        &self,
+        files: impl IntoIterator<Item = (N, ObjectId)>,
+    ) -> ObjectId {
+        self.tree_of_entries(
+            files
+                .into_iter()
+                .map(|(name, oid)| tree::Entry {
+                    mode: tree::EntryKind::Blob.into(),
+                    filename: name.into(),
+                    oid,
+                })
+                .collect(),
+        )
+    }
+
+    pub fn tree_of_entries(&self, entries: Vec<tree::Entry>) -> ObjectId {
+        self.repo
+            .write_object(&gix::objs::Tree { entries })
+            .unwrap()
+            .detach()
+    }
+
+    /// A tree holding only `README` with `content`.
+    pub fn readme(&self, content: impl AsRef<[u8]>) -> ObjectId {
+        self.tree([("README", self.blob(content))])
+    }
+
+    /// The next signature by `Test <test@example.com>`, a minute after the
+    /// previous one.
+    pub fn signature(&self) -> gix::actor::Signature {
+        self.time.set(self.time.get() + 60);
+        gix::actor::Signature {
+            name: "Test".into(),
+            email: "test@example.com".into(),
+            time: gix::date::Time::new(self.time.get(), 0),
+        }
+    }
+
+    /// A commit of `tree` with the subject line `message`.
+    pub fn commit(&self, tree: ObjectId, parents: &[ObjectId], message: &str) -> ObjectId {
+        self.commit_by(self.signature(), tree, parents, message)
+    }
+
+    /// A commit authored and committed as `signature`.
+    pub fn commit_by(
+        &self,
+        signature: gix::actor::Signature,
+        tree: ObjectId,
+        parents: &[ObjectId],
+        message: &str,
+    ) -> ObjectId {
+        self.repo
+            .write_object(&gix::objs::Commit {
+                tree,
+                parents: parents.iter().copied().collect(),
+                author: signature.clone(),
+                committer: signature,
+                encoding: None,
+                message: format!("{message}\n").into(),
+                extra_headers: Vec::new(),
+            })
+            .unwrap()
+            .detach()
+    }
+
+    /// Point the reference `name` at `target`, written as a loose file so a
+    /// repository with a work tree needs no committer for its reflog.
+    pub fn reference(&self, name: &str, target: ObjectId) {
+        let path = self.path().join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, format!("{target}\n")).unwrap();
+    }
+
+    pub fn branch(&self, name: &str, target: ObjectId) {
+        self.reference(&format!("refs/heads/{name}"), target);
+    }
+
+    pub fn tag(&self, name: &str, target: ObjectId) {
+        self.reference(&format!("refs/tags/{name}"), target);
+    }
+
+    /// Point `HEAD` at the branch `name`.
+    pub fn set_head(&self, name: &str) {
+        std::fs::write(
+            self.path().join("HEAD"),
+            format!("ref: refs/heads/{name}\n"),
+        )
+        .unwrap();
+    }
+
+    /// The commit `name` points at, if it exists.
+    pub fn resolve(&self, name: &str) -> Option<ObjectId> {
+        let mut reference = self.repo.find_reference(name).ok()?;
+        reference.peel_to_id().ok().map(gix::Id::detach)
+    }
+
+    pub fn open(&self) -> Repository {
+        Repository::open(self.path()).unwrap()
+    }
+
+    /// The repository mounted with the default configuration.
+    pub fn mount(&self) -> GitSnapFs {
+        self.mount_with(FsConfig::default())
+    }
+
+    pub fn mount_with(&self, config: FsConfig) -> GitSnapFs {
+        let fs = GitSnapFs::with_config(self.open(), config);
+        fs.init(FsOptions::all()).unwrap();
+        fs
+    }
+}
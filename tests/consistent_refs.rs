@@ -0,0 +1,69 @@
+//! With `--consistent-refs`, branches, tags, `HEAD` and the `refs/` mirror
+//! are served from the snapshot taken at mount until the next reload, so
+//! refs updated one by one are seen all at once.
+
+use fuse_backend_rs::api::filesystem::{FileSystem, FsOptions};
+
+use gitsnapfs::fs::GitSnapFs;
+use gitsnapfs::repo::Repository;
+
+mod common;
+
+#[test]
+fn refs_change_only_at_reload() {
+    let repo = common::TestRepo::bare();
+    let first = repo.commit(repo.readme("first\n"), &[], "first");
+    let second = repo.commit(repo.readme("second\n"), &[first], "second");
+    repo.branch("main", first);
+    repo.tag("v1", first);
+    repo.set_head("main");
+
+    let mount = |consistent: bool| {
+        let snapshot = Repository::open(repo.path()).unwrap();
+        if consistent {
+            snapshot.freeze_refs().unwrap();
+        }
+        let fs = GitSnapFs::new(snapshot);
+        fs.init(FsOptions::all()).unwrap();
+        fs
+    };
+    let read = |fs: &GitSnapFs, path: &str| {
+        fs.resolve_path(path)
+            .map(|file| String::from_utf8(file.read_at(0, 64).unwrap()).unwrap())
+            .map_err(|err| err.raw_os_error())
+    };
+
+    let frozen = mount(true);
+    let live = mount(false);
+    assert_eq!(read(&frozen, "HEAD/README"), Ok("first\n".to_owned()));
+
+    // A push moving main, adding a branch and deleting the tag.
+    repo.branch("main", second);
+    repo.branch("topic", second);
+    std::fs::remove_file(repo.path().join("refs/tags/v1")).unwrap();
+
+    for path in [
+        "HEAD/README",
+        "branches/main/README",
+        "refs/heads/main/README",
+    ] {
+        assert_eq!(read(&frozen, path), Ok("first\n".to_owned()), "{path}");
+        assert_eq!(read(&live, path), Ok("second\n".to_owned()), "{path}");
+    }
+    assert_eq!(read(&frozen, "tags/v1/README"), Ok("first\n".to_owned()));
+    assert_eq!(
+        read(&frozen, "branches/topic/README"),
+        Err(Some(libc::ENOENT))
+    );
+    assert_eq!(read(&live, "tags/v1/README"), Err(Some(libc::ENOENT)));
+
+    frozen.flush_caches().unwrap();
+    for path in [
+        "HEAD/README",
+        "branches/main/README",
+        "branches/topic/README",
+    ] {
+        assert_eq!(read(&frozen, path), Ok("second\n".to_owned()), "{path}");
+    }
+    assert_eq!(read(&frozen, "tags/v1/README"), Err(Some(libc::ENOENT)));
+}
@@ -0,0 +1,78 @@
+//! `/current` shows the commit given at mount time until the
+//! `SWITCH_CURRENT` ioctl on `.gitsnapfs/current` points it elsewhere.
+
+use std::ffi::CString;
+
+use fuse_backend_rs::abi::fuse_abi::ROOT_ID;
+use fuse_backend_rs::api::filesystem::{Context, FileSystem, FsOptions, IoctlData};
+
+use gitsnapfs::fs::{GitSnapFs, SWITCH_CURRENT, SWITCH_CURRENT_LEN};
+
+mod common;
+
+#[test]
+fn ioctl_switches_current_and_asks_for_invalidation() {
+    let repo = common::TestRepo::bare();
+    let first = repo.commit(repo.readme("first\n"), &[], "commit");
+    let second = repo.commit(repo.readme("second!\n"), &[first], "commit");
+
+    let fs = GitSnapFs::new(repo.open()).with_current(first);
+    fs.init(FsOptions::all()).unwrap();
+    let ctx = Context::default();
+    let lookup = |parent: u64, name: &str| fs.lookup(&ctx, parent, &CString::new(name).unwrap());
+    let readme_size = || {
+        let current = lookup(ROOT_ID, "current").unwrap().inode;
+        lookup(current, "README").unwrap().attr.st_size
+    };
+    let control = {
+        let dir = lookup(ROOT_ID, ".gitsnapfs").unwrap().inode;
+        lookup(dir, "current").unwrap().inode
+    };
+    let switch = |ctx: &Context, rev: &str| {
+        let mut arg = [0u8; SWITCH_CURRENT_LEN];
+        arg[..rev.len()].copy_from_slice(rev.as_bytes());
+        let data = IoctlData {
+            result: 0,
+            data: Some(&arg),
+        };
+        fs.ioctl(ctx, control, 0, 0, SWITCH_CURRENT, data, 0)
+            .map(|_| ())
+            .map_err(|err| err.raw_os_error())
+    };
+
+    assert_eq!(readme_size(), 6);
+    assert_eq!(fs.take_current_switch(), None);
+
+    let stranger = Context {
+        uid: 4242,
+        ..Context::default()
+    };
+    assert_eq!(
+        switch(&stranger, &second.to_string()),
+        Err(Some(libc::EPERM))
+    );
+    assert_eq!(switch(&ctx, "no-such-rev"), Err(Some(libc::ENOENT)));
+    assert_eq!(
+        fs.ioctl(
+            &ctx,
+            control,
+            0,
+            0,
+            SWITCH_CURRENT + 1,
+            IoctlData::default(),
+            0
+        )
+        .map(|_| ())
+        .map_err(|err| err.raw_os_error()),
+        Err(Some(libc::ENOTTY))
+    );
+    assert_eq!(fs.take_current_switch(), None);
+
+    assert_eq!(switch(&ctx, &second.to_string()), Ok(()));
+    assert_eq!(
+        fs.take_current_switch(),
+        Some((ROOT_ID, b"current".to_vec()))
+    );
+    assert_eq!(fs.take_current_switch(), None);
+    assert_eq!(readme_size(), 8);
+}
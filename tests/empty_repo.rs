@@ -0,0 +1,46 @@
+//! A repository without references still mounts: the root lists no `HEAD`,
+//! reference directories are empty, and commits are found by id.
+
+use std::ffi::CString;
+
+use fuse_backend_rs::abi::fuse_abi::ROOT_ID;
+use fuse_backend_rs::api::filesystem::{Context, FileSystem};
+
+use gitsnapfs::fs::{INODE_BRANCHES, INODE_COMMITS};
+
+mod common;
+
+#[test]
+fn unborn_head_leaves_the_rest_usable() {
+    let repo = common::TestRepo::bare();
+    let commit = repo.commit(repo.readme("hello\n"), &[], "dangling");
+
+    let fs = repo.mount();
+    let ctx = Context::default();
+    let list = |inode| {
+        let mut names = Vec::new();
+        fs.readdir(&ctx, inode, 0, 4096, 0, &mut |dirent| {
+            names.push(String::from_utf8(dirent.name.to_vec()).unwrap());
+            Ok(1)
+        })
+        .unwrap();
+        names
+    };
+
+    let root = list(ROOT_ID);
+    assert!(root.contains(&"branches".to_owned()), "{root:?}");
+    assert!(!root.contains(&"HEAD".to_owned()), "{root:?}");
+    assert!(list(INODE_BRANCHES).is_empty());
+    let head = fs
+        .lookup(&ctx, ROOT_ID, &CString::new("HEAD").unwrap())
+        .unwrap_err();
+    assert_eq!(head.raw_os_error(), Some(libc::ENOENT));
+    let snapshot = fs
+        .lookup(
+            &ctx,
+            INODE_COMMITS,
+            &CString::new(commit.to_string()).unwrap(),
+        )
+        .unwrap();
+    assert_eq!(list(snapshot.inode), ["README", ".git-meta"]);
+}
@@ -0,0 +1,54 @@
+//! With `--respect-export-ignore`, paths the root `.gitattributes` marks
+//! `export-ignore` are left out of commit snapshots, as `git archive` leaves
+//! them out of archives.
+
+use gitsnapfs::config::FsConfig;
+use gitsnapfs::fs::GitSnapFs;
+
+mod common;
+
+#[test]
+fn export_ignored_paths_are_hidden() {
+    let repo = common::TestRepo::bare();
+    let tree = repo.tree([
+        (".gitattributes", repo.blob("secret.txt export-ignore\n")),
+        ("README", repo.blob("hello\n")),
+        ("secret.txt", repo.blob("hidden\n")),
+    ]);
+    let commit = repo.commit(tree, &[], "init");
+
+    let names = |fs: &GitSnapFs| {
+        let mut names: Vec<String> = fs
+            .resolve_path(format!("commits/{commit}"))
+            .unwrap()
+            .read_dir()
+            .unwrap()
+            .into_iter()
+            .map(|entry| String::from_utf8(entry.name).unwrap())
+            .collect();
+        names.retain(|name| name != "." && name != "..");
+        names.sort();
+        names
+    };
+    let secret = format!("commits/{commit}/secret.txt");
+
+    let fs = repo.mount_with(FsConfig {
+        respect_export_ignore: true,
+        ..FsConfig::default()
+    });
+    assert_eq!(names(&fs), [".git-meta", ".gitattributes", "README"]);
+    assert_eq!(
+        fs.resolve_path(&secret).err().map(|err| err.raw_os_error()),
+        Some(Some(libc::ENOENT))
+    );
+
+    let fs = repo.mount();
+    assert_eq!(
+        names(&fs),
+        [".git-meta", ".gitattributes", "README", "secret.txt"]
+    );
+    assert_eq!(
+        fs.resolve_path(&secret).unwrap().read_at(0, 64).unwrap(),
+        b"hidden\n"
+    );
+}
@@ -0,0 +1,76 @@
+//! `--first-parent` keeps history-derived views to the mainline: commits
+//! merged in from a side branch drop out of changelogs, `referenced-by/`
+//! and `user.gitsnapfs.reachable-from`.
+
+use std::ffi::CString;
+
+use fuse_backend_rs::api::filesystem::{Context, FileSystem, GetxattrReply};
+
+use gitsnapfs::config::FsConfig;
+use gitsnapfs::fs::GitSnapFs;
+
+mod common;
+
+#[test]
+fn merged_side_branches_drop_out() {
+    let repo = common::TestRepo::bare();
+    // root - mainline - merge, with side branching off root; the merge
+    // keeps the mainline's tree, so side's README is only in side.
+    let main_tree = repo.readme("main\n");
+    let root = repo.commit(repo.readme("root\n"), &[], "root");
+    let mainline = repo.commit(main_tree, &[root], "mainline");
+    let side_blob = repo.blob("side\n");
+    let side = repo.commit(repo.readme("side\n"), &[root], "side");
+    let merge = repo.commit(main_tree, &[mainline, side], "merge");
+    repo.branch("main", merge);
+    repo.set_head("main");
+
+    let mount = |first_parent: bool| {
+        repo.mount_with(FsConfig {
+            first_parent,
+            ..FsConfig::default()
+        })
+    };
+    let subjects = |fs: &GitSnapFs| {
+        let changelog = fs
+            .resolve_path("branches-meta/main/CHANGELOG")
+            .unwrap()
+            .read_at(0, 4096)
+            .unwrap();
+        String::from_utf8(changelog)
+            .unwrap()
+            .lines()
+            .map(|line| line.split(' ').nth(1).unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+    let referrers = |fs: &GitSnapFs| {
+        fs.resolve_path(format!("objects/{side_blob}/referenced-by"))
+            .unwrap()
+            .read_dir()
+            .unwrap()
+            .into_iter()
+            .filter(|entry| entry.name != b"." && entry.name != b"..")
+            .count()
+    };
+    let reachable_from = |fs: &GitSnapFs| {
+        let inode = fs.resolve_path(format!("commits/{side}")).unwrap().inode;
+        let name = CString::new("user.gitsnapfs.reachable-from").unwrap();
+        match fs
+            .getxattr(&Context::default(), inode, &name, 4096)
+            .unwrap()
+        {
+            GetxattrReply::Value(value) => String::from_utf8(value).unwrap(),
+            GetxattrReply::Count(_) => unreachable!(),
+        }
+    };
+
+    let fs = mount(false);
+    assert_eq!(subjects(&fs), ["merge", "side", "mainline", "root"]);
+    assert_eq!(referrers(&fs), 1);
+    assert_eq!(reachable_from(&fs), "HEAD\nrefs/heads/main\n");
+
+    let fs = mount(true);
+    assert_eq!(subjects(&fs), ["merge", "mainline", "root"]);
+    assert_eq!(referrers(&fs), 0);
+    assert_eq!(reachable_from(&fs), "");
+}
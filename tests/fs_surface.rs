@@ -0,0 +1,582 @@
+//! Randomized requests against the `FileSystem` implementation.
+//!
+//! Each round generates a small repository with awkward names (arbitrary
+//! bytes, dot names, long names), symlinks, executables and gitlinks, then
+//! drives the request handlers directly with a mix of known and random
+//! inodes, names and offsets, the way a confused or hostile kernel client
+//! might. Requests must never panic, failures must carry an errno the kernel
+//! can pass on, and successful replies must be self-consistent.
+
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use fuse_backend_rs::abi::fuse_abi::CreateIn;
+use fuse_backend_rs::api::filesystem::{
+    Context, DirEntry, Entry, FileSystem, FsOptions, ZeroCopyWriter,
+};
+use fuse_backend_rs::file_traits::FileReadWriteVolatile;
+use gix::objs::tree;
+use gix::ObjectId;
+
+use gitsnapfs::config::{DirSize, FakeDotGit, FsConfig, SortOrder};
+use gitsnapfs::fs::GitSnapFs;
+use gitsnapfs::health::Health;
+use gitsnapfs::journal::ErrorJournal;
+use gitsnapfs::limits::TreeLimits;
+use gitsnapfs::releases::ReleasePattern;
+use gitsnapfs::repo::Repository;
+use gitsnapfs::splice::Writes;
+
+const ROUNDS: u64 = 6;
+const REQUESTS_PER_ROUND: usize = 1500;
+
+/// Errnos a read-only snapshot filesystem may legitimately answer with.
+const EXPECTED_ERRNOS: &[i32] = &[
+    libc::ENOENT,
+    libc::ENOTDIR,
+    libc::EISDIR,
+    libc::EINVAL,
+    libc::EROFS,
+    libc::ENOSYS,
+    libc::EACCES,
+    libc::EIO,
+    libc::ENODATA,
+    libc::ERANGE,
+    libc::ELOOP,
+    libc::EFBIG,
+    libc::EPERM,
+];
+
+#[test]
+fn random_requests_are_answered_sanely() {
+    for seed in 0..ROUNDS {
+        let mut rng = fastrand::Rng::with_seed(seed);
+        let dir = tempfile::tempdir().unwrap();
+        let commits = generate_repo(&mut rng, dir.path());
+        let config = FsConfig {
+            respect_export_ignore: rng.bool(),
+            fake_dotgit: rng.bool().then_some(FakeDotGit::Stub),
+            sort: [SortOrder::Git, SortOrder::Name, SortOrder::Mtime][rng.usize(..3)],
+            cache_budget: rng.bool().then_some(1 << 20),
+            overlay_compat: rng.bool(),
+            branches_as_dirs: rng.bool(),
+            stats: rng.bool(),
+            readahead: rng.bool(),
+            root_readme: rng.bool(),
+            releases: rng.bool().then(ReleasePattern::default),
+            tree_limits: if rng.u8(..4) == 0 {
+                TreeLimits {
+                    max_depth: rng.usize(..3),
+                    max_entries: rng.u64(..20),
+                }
+            } else {
+                TreeLimits::default()
+            },
+            dir_size: [DirSize::Zero, DirSize::Entries, DirSize::TreeBytes][rng.usize(..3)],
+            only_descendants_of: (rng.u8(..4) == 0).then(|| commits[rng.usize(..commits.len())]),
+            changelog_template: rng.bool().then(|| "{short} {subject} {{x}}".to_owned()),
+            history_depth: rng.bool().then(|| rng.usize(1..4)),
+            first_parent: rng.bool(),
+            refs_debug: rng.bool(),
+            verify_reads: rng.bool(),
+            mmap_loose: rng.bool(),
+            max_file_size: rng.bool().then(|| rng.u64(..64)),
+            metadata_only: rng.u8(..4) == 0,
+            no_xattrs: rng.u8(..8) == 0,
+            lfs_sparse: rng.bool(),
+            ignore_mailmap: rng.bool(),
+            abbrev: rng.bool().then(|| rng.usize(4..12)),
+            git_config_keys: if rng.bool() {
+                vec!["core.bare".to_owned(), "remote.origin.url".to_owned()]
+            } else {
+                Vec::new()
+            },
+            ..FsConfig::default()
+        };
+        let mut fs = GitSnapFs::with_config(Repository::open(dir.path()).unwrap(), config);
+        if rng.bool() {
+            fs = fs.with_health(Health::new(b"HEAD".to_vec(), Duration::from_secs(1)));
+        }
+        if rng.bool() {
+            fs = fs.with_error_journal(ErrorJournal::new(rng.usize(1..8)));
+        }
+        if rng.bool() {
+            fs = fs.with_writes(Arc::new(Writes::new(rng.bool())));
+        }
+        if rng.bool() {
+            fs = fs.with_current(commits[rng.usize(..commits.len())]);
+        }
+        fs.init(FsOptions::all()).unwrap();
+        let mut driver = Driver::new(&fs, rng, &commits);
+        for _ in 0..REQUESTS_PER_ROUND {
+            driver.step();
+        }
+    }
+}
+
+struct Driver<'a> {
+    fs: &'a GitSnapFs,
+    rng: fastrand::Rng,
+    ctx: Context,
+    inodes: Vec<u64>,
+    names: Vec<Vec<u8>>,
+}
+
+impl<'a> Driver<'a> {
+    fn new(fs: &'a GitSnapFs, rng: fastrand::Rng, commits: &[ObjectId]) -> Self {
+        let mut names: Vec<Vec<u8>> = [
+            &b"commits"[..],
+            b"trees",
+            b"branches",
+            b"tags",
+            b"latest",
+            b"refs",
+            b"heads",
+            b"pull",
+            b"branches-meta",
+            b"commits-on",
+            b"refs-debug",
+            b"packed-refs",
+            b"worktrees",
+            b"main-worktree",
+            b"CHANGELOG",
+            b"HEAD",
+            b".git-meta",
+            b".gitsnapfs",
+            b"stats",
+            b"health",
+            b"errors",
+            b"info",
+            b"current",
+            b"parents",
+            b"conflicts",
+            b"DU",
+            b"MANIFEST",
+            b"commit.json",
+            b"objects",
+            b"referenced-by",
+            b"SUBMODULE",
+            b"README.md",
+            b"LICENSE",
+            b"1",
+            b".",
+            b"..",
+            b"",
+        ]
+        .iter()
+        .map(|name| name.to_vec())
+        .collect();
+        for commit in commits {
+            let hex = commit.to_string().into_bytes();
+            names.push(hex[..7].to_vec());
+            names.push(hex);
+        }
+        Self {
+            fs,
+            rng,
+            ctx: Context::default(),
+            inodes: (0..=13).collect(),
+            names,
+        }
+    }
+
+    fn inode(&mut self) -> u64 {
+        // Favour recently discovered inodes so requests reach deep into snapshots.
+        match self.rng.u8(..10) {
+            0 => self.rng.u64(..),
+            1..=4 => self.inodes[self.rng.usize(self.inodes.len() / 2..self.inodes.len())],
+            _ => self.inodes[self.rng.usize(..self.inodes.len())],
+        }
+    }
+
+    fn name(&mut self) -> CString {
+        let name = if self.rng.u8(..8) == 0 {
+            (0..self.rng.usize(..300))
+                .map(|_| self.rng.u8(1..))
+                .collect()
+        } else {
+            self.names[self.rng.usize(..self.names.len())].clone()
+        };
+        CString::new(name).unwrap()
+    }
+
+    fn offset(&mut self) -> u64 {
+        match self.rng.u8(..4) {
+            0 => self.rng.u64(..),
+            1 => u64::MAX,
+            _ => self.rng.u64(..64),
+        }
+    }
+
+    fn learn(&mut self, entry: &Entry) {
+        if !self.inodes.contains(&entry.inode) {
+            self.inodes.push(entry.inode);
+        }
+    }
+
+    fn step(&mut self) {
+        let inode = self.inode();
+        match self.rng.u8(..10) {
+            0..=2 => {
+                let name = self.name();
+                if let Some(entry) = answered(self.fs.lookup(&self.ctx, inode, &name)) {
+                    let (attr, _) = self.fs.getattr(&self.ctx, entry.inode, None).unwrap();
+                    assert_eq!(attr.st_ino, entry.inode);
+                    assert_eq!(attr.st_mode, entry.attr.st_mode);
+                    self.learn(&entry);
+                }
+            }
+            3 => {
+                if let Some((attr, _)) = answered(self.fs.getattr(&self.ctx, inode, None)) {
+                    assert_eq!(attr.st_ino, inode);
+                }
+            }
+            4 => {
+                if let Some(target) = answered(self.fs.readlink(&self.ctx, inode)) {
+                    assert!(!target.is_empty());
+                }
+            }
+            5 => self.readdir(inode),
+            6 => self.readdirplus(inode),
+            7 => self.read(inode),
+            8 => {
+                answered(self.fs.opendir(&self.ctx, inode, 0));
+                answered(self.fs.open(&self.ctx, inode, 0, 0));
+                answered(self.fs.release(&self.ctx, inode, 0, 0, false, false, None));
+                answered(self.fs.access(&self.ctx, inode, self.rng.u32(..8)));
+                let size = self.rng.u32(..256);
+                answered(self.fs.listxattr(&self.ctx, inode, size));
+                let name = CString::new("user.gitsnapfs.reachable-from").unwrap();
+                answered(self.fs.getxattr(&self.ctx, inode, &name, size));
+                let mut query = b"user.gitsnapfs.is-ancestor-of.".to_vec();
+                query.extend_from_slice(self.name().as_bytes());
+                let query = CString::new(query).unwrap();
+                answered(self.fs.getxattr(&self.ctx, inode, &query, size));
+                let name = CString::new("user.gitsnapfs.sha256").unwrap();
+                answered(self.fs.getxattr(&self.ctx, inode, &name, size));
+                let name = CString::new("user.gitsnapfs.tree-oid").unwrap();
+                answered(self.fs.getxattr(&self.ctx, inode, &name, size));
+                let name = CString::new("user.gitsnapfs.generation").unwrap();
+                answered(self.fs.getxattr(&self.ctx, inode, &name, size));
+                let name = CString::new("user.gitsnapfs.lfs").unwrap();
+                answered(self.fs.getxattr(&self.ctx, inode, &name, size));
+                let name = CString::new("security.capability").unwrap();
+                let errno = self
+                    .fs
+                    .getxattr(&self.ctx, inode, &name, size)
+                    .err()
+                    .and_then(|err| err.raw_os_error());
+                assert!(matches!(errno, Some(libc::ENODATA | libc::ENOSYS)));
+                self.fs.forget(&self.ctx, inode, self.rng.u64(..3));
+            }
+            _ => self.mutate(inode),
+        }
+    }
+
+    fn readdir(&mut self, inode: u64) {
+        let offset = self.offset();
+        let mut seen = Vec::new();
+        let result = self
+            .fs
+            .readdir(&self.ctx, inode, 0, 4096, offset, &mut |dirent| {
+                seen.push(check_dirent(&dirent, offset));
+                Ok(1)
+            });
+        if answered(result).is_some() {
+            assert!(seen.windows(2).all(|pair| pair[0].offset < pair[1].offset));
+            for listed in seen {
+                let name = CString::new(listed.name.clone()).unwrap();
+                let entry = self.fs.lookup(&self.ctx, inode, &name).unwrap();
+                assert_eq!(
+                    entry.inode,
+                    listed.ino,
+                    "{:?} in {inode}",
+                    gix::bstr::BStr::new(&listed.name)
+                );
+                self.learn(&entry);
+                if !self.names.contains(&listed.name) {
+                    self.names.push(listed.name);
+                }
+            }
+        }
+    }
+
+    fn readdirplus(&mut self, inode: u64) {
+        let offset = self.offset();
+        let mut entries = Vec::new();
+        let result =
+            self.fs
+                .readdirplus(&self.ctx, inode, 0, 4096, offset, &mut |dirent, entry| {
+                    check_dirent(&dirent, offset);
+                    assert_eq!(dirent.ino, entry.inode);
+                    entries.push(entry);
+                    Ok(1)
+                });
+        if answered(result).is_some() {
+            for entry in &entries {
+                self.learn(entry);
+            }
+        }
+    }
+
+    fn read(&mut self, inode: u64) {
+        let size = match self.rng.u8(..3) {
+            0 => u32::MAX,
+            _ => self.rng.u32(..8192),
+        };
+        let offset = self.offset();
+        let mut sink = Sink::default();
+        let result = self
+            .fs
+            .read(&self.ctx, inode, 0, &mut sink, size, offset, None, 0);
+        if let Some(read) = answered(result) {
+            assert_eq!(read, sink.0.len());
+            assert!(read <= size as usize);
+        }
+        // Reading through an open handle must return the same bytes.
+        if let Some((Some(handle), _, _)) = answered(self.fs.open(&self.ctx, inode, 0, 0)) {
+            let mut through_handle = Sink::default();
+            let result = self.fs.read(
+                &self.ctx,
+                inode,
+                handle,
+                &mut through_handle,
+                size,
+                offset,
+                None,
+                0,
+            );
+            if answered(result).is_some() {
+                assert_eq!(
+                    through_handle.0, sink.0,
+                    "read through handle of {inode:#x}"
+                );
+            }
+            answered(
+                self.fs
+                    .release(&self.ctx, inode, 0, handle, false, false, None),
+            );
+        }
+    }
+
+    fn mutate(&mut self, inode: u64) {
+        let name = self.name();
+        let errors = [
+            self.fs.mkdir(&self.ctx, inode, &name, 0o755, 0).map(drop),
+            self.fs.unlink(&self.ctx, inode, &name),
+            self.fs.rmdir(&self.ctx, inode, &name),
+            self.fs.symlink(&self.ctx, &name, inode, &name).map(drop),
+            self.fs
+                .create(&self.ctx, inode, &name, CreateIn::default())
+                .map(drop),
+        ];
+        for result in errors {
+            let err = result.expect_err("mutating request succeeded on a read-only mount");
+            assert_eq!(err.raw_os_error(), Some(libc::EROFS));
+        }
+    }
+}
+
+/// An entry as returned by readdir.
+struct Listed {
+    name: Vec<u8>,
+    ino: u64,
+    offset: u64,
+}
+
+/// Check a listed entry and keep what later checks need.
+fn check_dirent(dirent: &DirEntry, requested: u64) -> Listed {
+    assert!(!dirent.name.is_empty());
+    assert!(!dirent.name.contains(&b'/') && !dirent.name.contains(&0));
+    assert!(dirent.offset > requested);
+    Listed {
+        name: dirent.name.to_vec(),
+        ino: dirent.ino,
+        offset: dirent.offset,
+    }
+}
+
+/// The successful reply, or `None` after checking the error is a sensible errno.
+fn answered<T>(result: io::Result<T>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(err) => {
+            match err.raw_os_error() {
+                Some(errno) => assert!(EXPECTED_ERRNOS.contains(&errno), "unexpected {err}"),
+                // Errors without an errno reach the kernel as EIO.
+                None => assert!(
+                    matches!(
+                        err.kind(),
+                        io::ErrorKind::Unsupported | io::ErrorKind::Other
+                    ),
+                    "unexpected {err:?}"
+                ),
+            }
+            None
+        }
+    }
+}
+
+#[derive(Default)]
+struct Sink(Vec<u8>);
+
+impl io::Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ZeroCopyWriter for Sink {
+    fn write_from(
+        &mut self,
+        _f: &mut dyn FileReadWriteVolatile,
+        _count: usize,
+        _off: u64,
+    ) -> io::Result<usize> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+
+    fn available_bytes(&self) -> usize {
+        usize::MAX
+    }
+}
+
+/// Write a bare repository with a few random commits on random branches and
+/// return the commit ids.
+fn generate_repo(rng: &mut fastrand::Rng, dir: &Path) -> Vec<ObjectId> {
+    let repo = gix::init_bare(dir).unwrap();
+    let mut commits = Vec::new();
+    for index in 0..rng.usize(1..5) {
+        let tree = random_tree(rng, &repo, 0);
+        let parents = (0..rng.usize(..3).min(commits.len()))
+            .map(|_| commits[rng.usize(..commits.len())])
+            .collect::<Vec<_>>();
+        let signature = gix::actor::Signature {
+            name: "Fuzz".into(),
+            email: "fuzz@example.com".into(),
+            time: gix::date::Time::new(1_700_000_000 + i64::try_from(index).unwrap(), 0),
+        };
+        let commit = gix::objs::Commit {
+            tree,
+            parents: parents.into(),
+            author: signature.clone(),
+            committer: signature,
+            encoding: None,
+            message: format!("commit {index}\n").into(),
+            extra_headers: Vec::new(),
+        };
+        commits.push(repo.write_object(&commit).unwrap().detach());
+    }
+    let mut refs = BTreeMap::new();
+    refs.insert("refs/heads/main".to_owned(), *commits.last().unwrap());
+    for commit in &commits {
+        let name = ["feature/x", "bad..name", "v1.0", "a b"][rng.usize(..4)];
+        let kind = if rng.bool() { "heads" } else { "tags" };
+        refs.insert(format!("refs/{kind}/{name}"), *commit);
+    }
+    for (name, id) in refs {
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, format!("{id}\n")).unwrap();
+    }
+    std::fs::write(dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+    commits
+}
+
+fn random_tree(rng: &mut fastrand::Rng, repo: &gix::Repository, depth: usize) -> ObjectId {
+    let mut entries: BTreeMap<Vec<u8>, tree::Entry> = BTreeMap::new();
+    for _ in 0..rng.usize(..6) {
+        let filename = random_name(rng);
+        let (mode, oid) = match rng.u8(..10) {
+            0..=1 if depth < 3 => (
+                tree::EntryKind::Tree.into(),
+                random_tree(rng, repo, depth + 1),
+            ),
+            2 => (tree::EntryKind::Link.into(), blob(repo, &random_name(rng))),
+            3 => (
+                tree::EntryKind::Commit.into(),
+                ObjectId::from_bytes_or_panic(&[rng.u8(..); 20]),
+            ),
+            4 => (
+                tree::EntryKind::BlobExecutable.into(),
+                blob(repo, b"#!/bin/sh\n"),
+            ),
+            5 if depth == 0 => {
+                let mut attributes = random_name(rng);
+                attributes.retain(u8::is_ascii_alphanumeric);
+                attributes.extend_from_slice(b" export-ignore\n");
+                entries.insert(
+                    b".gitattributes".to_vec(),
+                    tree::Entry {
+                        mode: tree::EntryKind::Blob.into(),
+                        filename: ".gitattributes".into(),
+                        oid: blob(repo, &attributes),
+                    },
+                );
+                continue;
+            }
+            _ => {
+                // Now and then a blob large enough to be read ahead.
+                let len = if rng.u8(..4) == 0 {
+                    rng.usize(140_000..200_000)
+                } else {
+                    rng.usize(..5000)
+                };
+                let content = (0..len).map(|_| rng.u8(..)).collect::<Vec<_>>();
+                (tree::EntryKind::Blob.into(), blob(repo, &content))
+            }
+        };
+        entries.insert(
+            filename.clone(),
+            tree::Entry {
+                mode,
+                filename: filename.into(),
+                oid,
+            },
+        );
+    }
+    let mut entries = entries.into_values().collect::<Vec<_>>();
+    entries.sort();
+    repo.write_object(&gix::objs::Tree { entries })
+        .unwrap()
+        .detach()
+}
+
+fn random_name(rng: &mut fastrand::Rng) -> Vec<u8> {
+    loop {
+        let name = match rng.u8(..4) {
+            0 => (0..rng.usize(1..255))
+                .map(|_| rng.u8(1..))
+                .filter(|byte| *byte != b'/')
+                .collect(),
+            1 => [
+                &b".git-meta"[..],
+                b".git",
+                b"-",
+                b"%2F",
+                b"\xff\xfe",
+                b"README.md",
+                b"LICENSE",
+            ][rng.usize(..7)]
+            .to_vec(),
+            _ => (0..rng.usize(1..12))
+                .map(|_| rng.alphanumeric() as u8)
+                .collect(),
+        };
+        if !name.is_empty() && name != b"." && name != b".." {
+            return name;
+        }
+    }
+}
+
+fn blob(repo: &gix::Repository, data: &[u8]) -> ObjectId {
+    repo.write_blob(data).unwrap().detach()
+}
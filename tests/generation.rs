@@ -0,0 +1,52 @@
+//! `user.gitsnapfs.generation` on commit snapshots counts the longest path
+//! to a root commit, as `git commit-graph` does.
+
+use std::ffi::CString;
+
+use fuse_backend_rs::api::filesystem::{Context, FileSystem, GetxattrReply};
+use gix::ObjectId;
+
+mod common;
+
+#[test]
+fn generation_follows_the_longest_parent_chain() {
+    let repo = common::TestRepo::bare();
+    let tree = repo.readme("hello\n");
+    let commit = |parents: &[_]| repo.commit(tree, parents, "commit");
+    // root - a - b - merge
+    //    \            /
+    //     side -------
+    let root = commit(&[]);
+    let a = commit(&[root]);
+    let b = commit(&[a]);
+    let side = commit(&[root]);
+    let merge = commit(&[side, b]);
+    let orphan = commit(&[]);
+
+    let fs = repo.mount();
+    let generation = |commit: ObjectId| {
+        let inode = fs.resolve_path(format!("commits/{commit}")).unwrap().inode;
+        let name = CString::new("user.gitsnapfs.generation").unwrap();
+        match fs.getxattr(&Context::default(), inode, &name, 64).unwrap() {
+            GetxattrReply::Value(value) => String::from_utf8(value).unwrap(),
+            GetxattrReply::Count(_) => unreachable!(),
+        }
+    };
+    assert_eq!(generation(merge), "4");
+    assert_eq!(generation(side), "2");
+    assert_eq!(generation(b), "3");
+    assert_eq!(generation(root), "1");
+    assert_eq!(generation(orphan), "1");
+
+    let readme = fs
+        .resolve_path(format!("commits/{merge}/README"))
+        .unwrap()
+        .inode;
+    let name = CString::new("user.gitsnapfs.generation").unwrap();
+    assert_eq!(
+        fs.getxattr(&Context::default(), readme, &name, 64)
+            .err()
+            .and_then(|err| err.raw_os_error()),
+        Some(libc::ENODATA)
+    );
+}
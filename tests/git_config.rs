@@ -0,0 +1,65 @@
+//! `.gitsnapfs/config/<key>` serves the repository's values of the keys
+//! given with `--expose-config`, and nothing else from its configuration.
+
+use std::io::Write as _;
+
+use gitsnapfs::config::FsConfig;
+use gitsnapfs::fs::GitSnapFs;
+
+mod common;
+
+#[test]
+fn exposes_only_allowed_keys() {
+    let repo = common::TestRepo::bare();
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(repo.path().join("config"))
+        .unwrap()
+        .write_all(
+            b"[remote \"origin\"]\n\
+              \turl = https://example.com/repo.git\n\
+              \tfetch = +refs/heads/*:refs/remotes/origin/*\n\
+              \tfetch = +refs/tags/*:refs/tags/*\n\
+              [user]\n\
+              \tsigningkey = secret\n",
+        )
+        .unwrap();
+
+    let mount = |keys: &[&str]| {
+        repo.mount_with(FsConfig {
+            git_config_keys: keys.iter().map(|&key| key.to_owned()).collect(),
+            ..FsConfig::default()
+        })
+    };
+    let read = |fs: &GitSnapFs, path: &str| {
+        String::from_utf8(fs.resolve_path(path).unwrap().read_at(0, 4096).unwrap()).unwrap()
+    };
+
+    let fs = mount(&["remote.origin.url", "remote.origin.fetch", "core.hooksPath"]);
+    let mut names: Vec<String> = fs
+        .resolve_path(".gitsnapfs/config")
+        .unwrap()
+        .read_dir()
+        .unwrap()
+        .into_iter()
+        .map(|entry| String::from_utf8(entry.name).unwrap())
+        .collect();
+    names.retain(|name| name != "." && name != "..");
+    names.sort();
+    assert_eq!(names, ["remote.origin.fetch", "remote.origin.url"]);
+    assert_eq!(
+        read(&fs, ".gitsnapfs/config/remote.origin.url"),
+        "https://example.com/repo.git\n"
+    );
+    assert_eq!(
+        read(&fs, ".gitsnapfs/config/remote.origin.fetch"),
+        "+refs/heads/*:refs/remotes/origin/*\n+refs/tags/*:refs/tags/*\n"
+    );
+    assert!(fs.resolve_path(".gitsnapfs/config/core.hooksPath").is_err());
+    assert!(fs
+        .resolve_path(".gitsnapfs/config/user.signingkey")
+        .is_err());
+
+    let fs = mount(&[]);
+    assert!(fs.resolve_path(".gitsnapfs").is_err());
+}
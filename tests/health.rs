@@ -0,0 +1,45 @@
+//! With `--health-interval`, `.gitsnapfs/health` reports the background
+//! checks, which pass on a repository whose `HEAD` is unborn.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use fuse_backend_rs::api::filesystem::{FileSystem, FsOptions};
+
+use gitsnapfs::fs::GitSnapFs;
+use gitsnapfs::health::{self, Health};
+
+mod common;
+
+#[test]
+fn an_unborn_head_is_healthy() {
+    let repo = common::TestRepo::bare();
+    repo.commit(repo.readme("hello\n"), &[], "dangling");
+
+    let fs = GitSnapFs::new(repo.open())
+        .with_health(Health::new(b"HEAD".to_vec(), Duration::from_millis(10)));
+    fs.init(FsOptions::all()).unwrap();
+    let fs = Arc::new(fs);
+    health::spawn(&fs).unwrap();
+
+    let status = fs.resolve_path(".gitsnapfs/health").unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let text = loop {
+        let text = String::from_utf8(status.read_at(0, 4096).unwrap()).unwrap();
+        if !text.starts_with("status pending\n") || Instant::now() > deadline {
+            break text;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    assert!(text.starts_with("status ok\n"), "{text}");
+    assert!(!text.contains("error"), "{text}");
+
+    // The background thread does not keep the filesystem alive.
+    let weak = Arc::downgrade(&fs);
+    drop(fs);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while weak.strong_count() > 0 && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(weak.strong_count(), 0);
+}
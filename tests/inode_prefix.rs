@@ -0,0 +1,67 @@
+//! With `--inode-prefix-bits`, objects sharing the kept bits of their ids
+//! get distinct inodes that resolve to the right object, also after a
+//! remount reading the slots back from the state file.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+
+use fuse_backend_rs::abi::fuse_abi::ROOT_ID;
+use fuse_backend_rs::api::filesystem::{Context, FileSystem, FsOptions};
+
+use gitsnapfs::fs::GitSnapFs;
+use gitsnapfs::ref_inodes::RefInodes;
+
+mod common;
+
+const FILES: usize = 1200;
+
+#[test]
+fn colliding_prefixes_resolve_to_their_blobs() {
+    let repo = common::TestRepo::bare();
+    // Each blob's size is its number, so a stat tells which blob was served.
+    let ids: Vec<_> = (0..FILES).map(|n| repo.blob(vec![b'x'; n])).collect();
+    let tree = repo.tree(
+        ids.iter()
+            .enumerate()
+            .map(|(n, &oid)| (format!("f{n:04}"), oid)),
+    );
+    let commit = repo.commit(tree, &[], "init");
+    let prefixes: HashSet<[u8; 2]> = ids
+        .iter()
+        .map(|id| [id.as_bytes()[0], id.as_bytes()[1]])
+        .collect();
+    assert!(prefixes.len() < FILES, "no two blobs share 16 bits");
+
+    let state_dir = tempfile::tempdir().unwrap();
+    let state = state_dir.path().join("state");
+    let mount = || {
+        let inodes = RefInodes::open(&state).unwrap().with_prefix_bits(16);
+        let fs = GitSnapFs::new(repo.open()).with_ref_inodes(inodes);
+        fs.init(FsOptions::all()).unwrap();
+        fs
+    };
+    let ctx = Context::default();
+    let lookup = |fs: &GitSnapFs, parent: u64, name: &str| {
+        fs.lookup(&ctx, parent, &CString::new(name).unwrap())
+            .unwrap()
+    };
+
+    let fs = mount();
+    let commits = lookup(&fs, ROOT_ID, "commits").inode;
+    let root = lookup(&fs, commits, &commit.to_string()).inode;
+    let mut inodes = HashMap::new();
+    for n in 0..FILES {
+        let entry = lookup(&fs, root, &format!("f{n:04}"));
+        assert_eq!(entry.attr.st_size, i64::try_from(n).unwrap());
+        let (attr, _) = fs.getattr(&ctx, entry.inode, None).unwrap();
+        assert_eq!(attr.st_size, i64::try_from(n).unwrap());
+        assert!(inodes.insert(entry.inode, n).is_none(), "inode shared");
+    }
+    drop(fs);
+
+    let fs = mount();
+    for (&inode, &n) in &inodes {
+        let (attr, _) = fs.getattr(&ctx, inode, None).unwrap();
+        assert_eq!(attr.st_size, i64::try_from(n).unwrap());
+    }
+}
@@ -0,0 +1,77 @@
+//! With `--lfs-sparse`, an LFS pointer file shows the size it records,
+//! reads as zeros and names its object in `user.gitsnapfs.lfs`; without
+//! it, the pointer is served as the text it is.
+
+use std::ffi::CString;
+
+use fuse_backend_rs::api::filesystem::{Context, FileSystem, GetxattrReply};
+
+use gitsnapfs::config::FsConfig;
+use gitsnapfs::fs::GitSnapFs;
+
+mod common;
+
+const OID: &str = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+
+#[test]
+fn pointers_become_sparse_placeholders() {
+    let repo = common::TestRepo::bare();
+    let pointer =
+        format!("version https://git-lfs.github.com/spec/v1\noid sha256:{OID}\nsize 3000000\n");
+    let tree = repo.tree([
+        ("model.bin", repo.blob(&pointer)),
+        ("notes.txt", repo.blob("notes\n")),
+    ]);
+    let commit = repo.commit(tree, &[], "init");
+    let mount = |lfs_sparse| {
+        repo.mount_with(FsConfig {
+            lfs_sparse,
+            ..FsConfig::default()
+        })
+    };
+    let xattr = |fs: &GitSnapFs, inode: u64, name: &str| match fs.getxattr(
+        &Context::default(),
+        inode,
+        &CString::new(name).unwrap(),
+        256,
+    ) {
+        Ok(GetxattrReply::Value(value)) => Ok(String::from_utf8(value).unwrap()),
+        Ok(GetxattrReply::Count(_)) => unreachable!(),
+        Err(err) => Err(err.raw_os_error()),
+    };
+
+    let fs = mount(true);
+    let model = fs
+        .resolve_path(format!("commits/{commit}/model.bin"))
+        .unwrap();
+    assert_eq!(model.size, 3_000_000);
+    assert_eq!(model.read_at(0, 16).unwrap(), [0; 16]);
+    assert_eq!(model.read_at(2_999_990, 100).unwrap(), [0; 10]);
+    assert!(model.read_at(3_000_000, 100).unwrap().is_empty());
+    assert_eq!(
+        xattr(&fs, model.inode, "user.gitsnapfs.lfs"),
+        Ok(format!("sha256:{OID}"))
+    );
+    assert_eq!(
+        xattr(&fs, model.inode, "user.gitsnapfs.sha256"),
+        Ok(OID.to_owned())
+    );
+    let notes = fs
+        .resolve_path(format!("commits/{commit}/notes.txt"))
+        .unwrap();
+    assert_eq!(notes.read_at(0, 100).unwrap(), b"notes\n");
+    assert_eq!(
+        xattr(&fs, notes.inode, "user.gitsnapfs.lfs"),
+        Err(Some(libc::ENODATA))
+    );
+
+    let fs = mount(false);
+    let model = fs
+        .resolve_path(format!("commits/{commit}/model.bin"))
+        .unwrap();
+    assert_eq!(model.read_at(0, 4096).unwrap(), pointer.as_bytes());
+    assert_eq!(
+        xattr(&fs, model.inode, "user.gitsnapfs.lfs"),
+        Err(Some(libc::ENODATA))
+    );
+}
@@ -0,0 +1,48 @@
+//! Authors in `.git-meta/commit.json` are mapped through the repository's
+//! `.mailmap` unless the mount runs with `--no-mailmap`.
+
+use gitsnapfs::config::FsConfig;
+
+mod common;
+
+#[test]
+fn commit_json_names_canonical_identities() {
+    let repo = common::TestRepo::bare();
+    let mailmap = repo.blob("Robert Smith <rob@example.com> <bob@old.example.com>\n");
+    let mut signature = repo.signature();
+    signature.name = "Bob".into();
+    signature.email = "bob@old.example.com".into();
+    let commit = repo.commit_by(
+        signature,
+        repo.tree([(".mailmap", mailmap)]),
+        &[],
+        "init\n\nPair.\n\nCo-authored-by: Bob <bob@old.example.com>",
+    );
+    // A bare repository reads `.mailmap` from the tree at HEAD.
+    repo.branch("main", commit);
+    repo.set_head("main");
+
+    let commit_json = |ignore_mailmap| {
+        let fs = repo.mount_with(FsConfig {
+            ignore_mailmap,
+            ..FsConfig::default()
+        });
+        let file = fs
+            .resolve_path(format!("commits/{commit}/.git-meta/commit.json"))
+            .unwrap();
+        let json: serde_json::Value =
+            serde_json::from_slice(&file.read_at(0, 1 << 16).unwrap()).unwrap();
+        json
+    };
+
+    let json = commit_json(false);
+    assert_eq!(json["author"]["name"], "Robert Smith");
+    assert_eq!(json["author"]["email"], "rob@example.com");
+    assert_eq!(json["committer"]["name"], "Robert Smith");
+    assert_eq!(json["co_authors"][0]["email"], "rob@example.com");
+
+    let json = commit_json(true);
+    assert_eq!(json["author"]["name"], "Bob");
+    assert_eq!(json["author"]["email"], "bob@old.example.com");
+    assert_eq!(json["co_authors"][0]["name"], "Bob");
+}
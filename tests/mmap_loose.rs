@@ -0,0 +1,129 @@
+//! With `--mmap-loose`, a loose blob stored uncompressed is served from its
+//! mapped object file, across block boundaries, with the same bytes gix
+//! would decode.
+
+use std::io;
+
+use fuse_backend_rs::api::filesystem::{Context, FileSystem, ZeroCopyWriter};
+use fuse_backend_rs::file_traits::FileReadWriteVolatile;
+use gix::ObjectId;
+
+use gitsnapfs::config::FsConfig;
+use gitsnapfs::loose::MappedBlob;
+
+mod common;
+
+#[test]
+fn stored_loose_blobs_are_read_from_the_mapping() {
+    let repo = common::TestRepo::bare();
+    let content = (0..200_000u32)
+        .map(|index| u8::try_from(index * 7 % 251).unwrap())
+        .collect::<Vec<_>>();
+    let big = repo.blob(&content);
+    let small = repo.blob("compressed as usual\n");
+    let objects = repo.path().join("objects");
+    rewrite_stored(&objects, big, &content);
+    let commit = repo.commit(
+        repo.tree([("big.bin", big), ("small.txt", small)]),
+        &[],
+        "init",
+    );
+
+    let mapped = MappedBlob::open(&objects, big).unwrap();
+    assert_eq!(mapped.size(), content.len() as u64);
+    assert!(MappedBlob::open(&objects, small).is_none());
+    assert!(MappedBlob::open(&objects, commit).is_none());
+
+    for mmap_loose in [true, false] {
+        let fs = repo.mount_with(FsConfig {
+            mmap_loose,
+            ..FsConfig::default()
+        });
+        let file = fs
+            .resolve_path(format!("commits/{commit}/big.bin"))
+            .unwrap();
+        assert_eq!(file.size, content.len() as u64);
+        for (offset, len) in [(0, 10), (65_000, 2_000), (131_000, 69_000), (199_990, 100)] {
+            let end = (offset + len).min(content.len());
+            assert_eq!(
+                file.read_at(offset as u64, len).unwrap(),
+                &content[offset..end],
+                "{offset}+{len}"
+            );
+            let mut sink = Sink::default();
+            let read = fs
+                .read(
+                    &Context::default(),
+                    file.inode,
+                    0,
+                    &mut sink,
+                    u32::try_from(len).unwrap(),
+                    offset as u64,
+                    None,
+                    0,
+                )
+                .unwrap();
+            assert_eq!(read, end - offset);
+            assert_eq!(sink.0, &content[offset..end], "{offset}+{len}");
+        }
+        assert!(file.read_at(content.len() as u64, 10).unwrap().is_empty());
+        let small = fs
+            .resolve_path(format!("commits/{commit}/small.txt"))
+            .unwrap();
+        assert_eq!(small.read_at(0, 100).unwrap(), b"compressed as usual\n");
+    }
+}
+
+/// Replace the loose object `oid` with the stored zlib stream
+/// `git -c core.looseCompression=0` would write.
+fn rewrite_stored(objects: &std::path::Path, oid: ObjectId, content: &[u8]) {
+    let mut payload = format!("blob {}\0", content.len()).into_bytes();
+    payload.extend_from_slice(content);
+    let mut file = vec![0x78, 0x01];
+    let mut blocks = payload.chunks(0xffff).peekable();
+    while let Some(block) = blocks.next() {
+        let len = u16::try_from(block.len()).unwrap();
+        file.push(u8::from(blocks.peek().is_none()));
+        file.extend_from_slice(&len.to_le_bytes());
+        file.extend_from_slice(&(!len).to_le_bytes());
+        file.extend_from_slice(block);
+    }
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in &payload {
+        a = (a + u32::from(byte)) % 65_521;
+        b = (b + a) % 65_521;
+    }
+    file.extend_from_slice(&((b << 16) | a).to_be_bytes());
+    let hex = oid.to_string();
+    let path = objects.join(&hex[..2]).join(&hex[2..]);
+    std::fs::remove_file(&path).unwrap();
+    std::fs::write(&path, file).unwrap();
+}
+
+#[derive(Default)]
+struct Sink(Vec<u8>);
+
+impl io::Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ZeroCopyWriter for Sink {
+    fn write_from(
+        &mut self,
+        _f: &mut dyn FileReadWriteVolatile,
+        _count: usize,
+        _off: u64,
+    ) -> io::Result<usize> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+
+    fn available_bytes(&self) -> usize {
+        usize::MAX
+    }
+}
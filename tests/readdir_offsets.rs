@@ -0,0 +1,55 @@
+//! A listing resumed from a readdir offset neither repeats nor skips entries
+//! when the directory changes between the calls.
+
+use std::collections::BTreeSet;
+
+use fuse_backend_rs::api::filesystem::{Context, FileSystem};
+
+use gitsnapfs::fs::INODE_BRANCHES;
+
+mod common;
+
+#[test]
+fn resumed_listing_survives_branch_changes() {
+    let repo = common::TestRepo::bare();
+    let commit = repo.commit(repo.readme("hello\n"), &[], "init");
+    let heads = repo.path().join("refs/heads");
+    let names: Vec<String> = (0..20).map(|n| format!("b{n:02}")).collect();
+    for name in &names {
+        repo.branch(name, commit);
+    }
+    repo.set_head("b00");
+
+    let fs = repo.mount();
+    let ctx = Context::default();
+    let mut listed = Vec::new();
+    let mut offset = 0;
+    loop {
+        let mut page = Vec::new();
+        fs.readdir(&ctx, INODE_BRANCHES, 0, 4096, offset, &mut |dirent| {
+            if !page.is_empty() {
+                return Ok(0);
+            }
+            page.push((dirent.name.to_vec(), dirent.offset));
+            Ok(1)
+        })
+        .unwrap();
+        let Some((name, next)) = page.pop() else {
+            break;
+        };
+        listed.push(String::from_utf8(name).unwrap());
+        offset = next;
+        if listed.len() == 5 {
+            // Remove entries already listed and add one before the rest.
+            std::fs::remove_file(heads.join(&names[1])).unwrap();
+            std::fs::remove_file(heads.join(&names[2])).unwrap();
+            repo.branch("a-new", commit);
+        }
+    }
+
+    let unique: BTreeSet<_> = listed.iter().collect();
+    assert_eq!(unique.len(), listed.len(), "repeated entries: {listed:?}");
+    for name in &names {
+        assert!(listed.contains(name), "{name} skipped: {listed:?}");
+    }
+}
@@ -0,0 +1,52 @@
+//! Reloading the settings, as SIGUSR2 does, applies the new policies to
+//! later requests, and keeps those only read at mount with their old values.
+
+use std::ffi::CString;
+
+use fuse_backend_rs::api::filesystem::{Context, FileSystem};
+
+use gitsnapfs::config::{FsConfig, RefFilter};
+
+mod common;
+
+#[test]
+fn reloaded_settings_apply_to_later_requests() {
+    let repo = common::TestRepo::bare();
+    let commit = repo.commit(repo.readme("hello\n"), &[], "init");
+    repo.branch("main", commit);
+    repo.branch("secret", commit);
+    repo.set_head("main");
+
+    let fs = repo.mount();
+    let read = |path: &str| {
+        fs.resolve_path(path)
+            .and_then(|file| file.read_at(0, 64))
+            .map_err(|err| err.raw_os_error())
+    };
+    let has_xattrs = || {
+        let inode = fs.resolve_path(format!("commits/{commit}")).unwrap().inode;
+        let name = CString::new("user.gitsnapfs.generation").unwrap();
+        fs.getxattr(&Context::default(), inode, &name, 64).is_ok()
+    };
+    assert_eq!(read("branches/secret/README"), Ok(b"hello\n".to_vec()));
+    assert!(has_xattrs());
+
+    let fixed = fs
+        .reconfigure(FsConfig {
+            ref_filter: RefFilter {
+                branches: vec!["main".to_owned()],
+                tags: Vec::new(),
+            },
+            max_file_size: Some(4),
+            no_xattrs: true,
+            ..FsConfig::default()
+        })
+        .unwrap();
+    assert_eq!(fixed, ["no-xattrs"]);
+    assert_eq!(read("branches/secret/README"), Err(Some(libc::ENOENT)));
+    assert_eq!(read("branches/main/README"), Err(Some(libc::EFBIG)));
+    assert!(has_xattrs());
+
+    assert!(fs.reconfigure(FsConfig::default()).unwrap().is_empty());
+    assert_eq!(read("branches/secret/README"), Ok(b"hello\n".to_vec()));
+}
@@ -0,0 +1,64 @@
+//! `refs/` mirrors every reference by its full name, including code-review
+//! refs outside `refs/heads/` and `refs/tags/`, as links into `commits/`
+//! or `trees/`.
+
+mod common;
+
+#[test]
+fn review_refs_are_mirrored_by_full_name() {
+    let repo = common::TestRepo::bare();
+    let tree = repo.readme("hello\n");
+    let main = repo.commit(tree, &[], "main");
+    let pull = repo.commit(repo.readme("pull\n"), &[main], "pull");
+    let change = repo.commit(repo.readme("change\n"), &[main], "change");
+    repo.branch("main", main);
+    repo.set_head("main");
+    repo.reference("refs/pull/42/head", pull);
+    repo.reference("refs/changes/34/1234/2", change);
+    repo.reference("refs/trees/snapshot", tree);
+    repo.reference("refs/blobs/readme", repo.blob("hello\n"));
+
+    let fs = repo.mount();
+    let names = |path: &str| {
+        let mut names: Vec<String> = fs
+            .resolve_path(path)
+            .unwrap()
+            .read_dir()
+            .unwrap()
+            .into_iter()
+            .map(|entry| String::from_utf8(entry.name).unwrap())
+            .collect();
+        names.retain(|name| name != "." && name != "..");
+        names.sort();
+        names
+    };
+    let link = |path: &str| {
+        let (dir, name) = path.rsplit_once('/').unwrap();
+        let link = fs
+            .resolve_path(dir)
+            .unwrap()
+            .lookup(name.as_bytes())
+            .unwrap();
+        String::from_utf8(link.read_link().unwrap()).unwrap()
+    };
+    let read = |path: &str| {
+        String::from_utf8(fs.resolve_path(path).unwrap().read_at(0, 64).unwrap()).unwrap()
+    };
+
+    assert_eq!(names("refs"), ["changes", "heads", "pull", "trees"]);
+    assert_eq!(names("refs/pull/42"), ["head"]);
+    assert_eq!(
+        link("refs/pull/42/head"),
+        format!("../../../commits/{pull}")
+    );
+    assert_eq!(read("refs/pull/42/head/README"), "pull\n");
+    assert_eq!(
+        link("refs/changes/34/1234/2"),
+        format!("../../../../commits/{change}")
+    );
+    assert_eq!(read("refs/changes/34/1234/2/README"), "change\n");
+    assert_eq!(link("refs/heads/main"), format!("../../commits/{main}"));
+    assert_eq!(link("refs/trees/snapshot"), format!("../../trees/{tree}"));
+    assert!(fs.resolve_path("refs/blobs").is_err());
+    assert!(fs.resolve_path("refs/pull/43").is_err());
+}
@@ -0,0 +1,60 @@
+//! `/refs-debug` shows every reference as stored: where it was read from,
+//! its value, what it peels to and whether the mount serves it.
+
+use gitsnapfs::config::{FsConfig, RefFilter};
+use gitsnapfs::fs::GitSnapFs;
+
+mod common;
+
+#[test]
+fn lists_loose_packed_and_broken_refs() {
+    let repo = common::TestRepo::bare();
+    let commit = repo.commit(repo.readme("hello\n"), &[], "commit");
+    let packed = format!(
+        "# pack-refs with: peeled fully-peeled sorted \n\
+         {commit} refs/heads/main\n\
+         {commit} refs/heads/packed-only\n\
+         {commit} refs/heads/secret\n"
+    );
+    std::fs::write(repo.path().join("packed-refs"), &packed).unwrap();
+    repo.branch("main", commit);
+    std::fs::write(repo.path().join("refs/heads/wip"), "not a ref\n").unwrap();
+    repo.set_head("main");
+
+    let config = FsConfig {
+        refs_debug: true,
+        ref_filter: RefFilter {
+            branches: vec!["main".to_owned(), "packed-only".to_owned()],
+            tags: Vec::new(),
+        },
+        ..FsConfig::default()
+    };
+    let fs = repo.mount_with(config);
+    let read = |path: &str| {
+        String::from_utf8(fs.resolve_path(path).unwrap().read_at(0, 4096).unwrap()).unwrap()
+    };
+    let table = read("refs-debug/refs");
+    let lines: Vec<Vec<&str>> = table
+        .lines()
+        .map(|line| line.split('\t').collect())
+        .collect();
+    let id = commit.to_string();
+    assert_eq!(
+        lines,
+        [
+            vec!["HEAD", "loose", "ref: refs/heads/main", &id, "served"],
+            vec!["refs/heads/main", "loose+packed", &id, &id, "served"],
+            vec!["refs/heads/packed-only", "packed", &id, &id, "served"],
+            vec!["refs/heads/secret", "packed", &id, &id, "filtered"],
+            vec!["refs/heads/wip", "loose", "-", "-", "unparsable"],
+        ]
+    );
+    assert_eq!(read("refs-debug/packed-refs"), packed);
+    assert!(fs.resolve_path("branches/secret").is_err());
+
+    std::fs::remove_file(repo.path().join("packed-refs")).unwrap();
+    assert!(fs.resolve_path("refs-debug/packed-refs").is_err());
+    assert!(GitSnapFs::new(repo.open())
+        .resolve_path("refs-debug")
+        .is_err());
+}
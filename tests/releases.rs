@@ -0,0 +1,120 @@
+//! With `--releases`, tags named like versions appear under `/releases` by
+//! major, minor and patch number, with `latest` links at every level.
+
+use std::collections::HashMap;
+
+use gix::ObjectId;
+
+use gitsnapfs::config::FsConfig;
+use gitsnapfs::fs::GitSnapFs;
+use gitsnapfs::releases::ReleasePattern;
+
+mod common;
+
+use common::TestRepo;
+
+/// A repository with one commit per tag, and the commits by tag.
+fn tagged() -> (TestRepo, HashMap<&'static str, ObjectId>) {
+    let repo = TestRepo::bare();
+    let tree = repo.readme("hello\n");
+    let tags = [
+        "v1.2.0",
+        "v1.10.3",
+        "v2.0.1",
+        "v2.1.0-rc.1",
+        "nightly",
+        "release-3.0.0",
+    ]
+    .into_iter()
+    .map(|tag| {
+        let id = repo.commit(tree, &[], tag);
+        repo.tag(tag, id);
+        (tag, id)
+    })
+    .collect();
+    (repo, tags)
+}
+
+fn mount(repo: &TestRepo, pattern: Option<&str>) -> GitSnapFs {
+    repo.mount_with(FsConfig {
+        releases: pattern.map(|pattern| ReleasePattern::parse(pattern).unwrap()),
+        ..FsConfig::default()
+    })
+}
+
+fn names(fs: &GitSnapFs, path: &str) -> Vec<String> {
+    let mut names: Vec<String> = fs
+        .resolve_path(path)
+        .unwrap()
+        .read_dir()
+        .unwrap()
+        .into_iter()
+        .map(|entry| String::from_utf8(entry.name).unwrap())
+        .collect();
+    names.retain(|name| name != "." && name != "..");
+    names
+}
+
+fn link(fs: &GitSnapFs, path: &str) -> String {
+    let (dir, name) = path.rsplit_once('/').unwrap();
+    let link = fs
+        .resolve_path(dir)
+        .unwrap()
+        .lookup(name.as_bytes())
+        .unwrap();
+    String::from_utf8(link.read_link().unwrap()).unwrap()
+}
+
+#[test]
+fn tags_are_arranged_by_version() {
+    let (repo, tags) = tagged();
+    let fs = mount(&repo, Some("v*"));
+    assert_eq!(names(&fs, "releases"), ["1", "2", "latest"]);
+    assert_eq!(names(&fs, "releases/1"), ["2", "10", "latest"]);
+    assert_eq!(names(&fs, "releases/2/0"), ["1", "latest"]);
+    for (path, tag) in [
+        ("releases/1/2/0", "v1.2.0"),
+        ("releases/1/10/3", "v1.10.3"),
+        ("releases/2/0/1", "v2.0.1"),
+    ] {
+        assert_eq!(
+            link(&fs, path),
+            format!("../../../commits/{}", tags[tag]),
+            "{path}"
+        );
+    }
+    // Pre-releases and names without a version are left out.
+    for missing in ["releases/2/1", "releases/3", "releases/nightly"] {
+        assert!(fs.resolve_path(missing).is_err(), "{missing}");
+    }
+}
+
+#[test]
+fn latest_links_pick_the_highest_version() {
+    let (repo, _) = tagged();
+    let fs = mount(&repo, Some("v*"));
+    assert_eq!(link(&fs, "releases/latest"), "2/0/1");
+    assert_eq!(link(&fs, "releases/1/latest"), "10/3");
+    assert_eq!(link(&fs, "releases/1/10/latest"), "3");
+    assert_eq!(
+        fs.resolve_path("releases/latest/README")
+            .unwrap()
+            .read_at(0, 100)
+            .unwrap(),
+        b"hello\n"
+    );
+}
+
+#[test]
+fn the_pattern_selects_the_tags() {
+    let (repo, tags) = tagged();
+    let fs = mount(&repo, Some("release-*"));
+    assert_eq!(names(&fs, "releases"), ["3", "latest"]);
+    assert_eq!(
+        link(&fs, "releases/3/0/0"),
+        format!("../../../commits/{}", tags["release-3.0.0"])
+    );
+
+    let fs = mount(&repo, None);
+    assert!(fs.resolve_path("releases").is_err());
+}
@@ -0,0 +1,48 @@
+//! A commit named in `refs/replace/` is served as its replacement, unless
+//! the mount runs with `--no-replace-objects`, the repository sets
+//! `core.useReplaceRefs = false` or `GIT_NO_REPLACE_OBJECTS` is set.
+
+use std::io::Write as _;
+
+use fuse_backend_rs::api::filesystem::{FileSystem, FsOptions};
+
+use gitsnapfs::fs::GitSnapFs;
+use gitsnapfs::repo::Repository;
+
+mod common;
+
+#[test]
+fn replaced_commits_serve_the_replacement() {
+    let repo = common::TestRepo::bare();
+    let original = repo.commit(repo.readme("original\n"), &[], "original");
+    let replacement = repo.commit(repo.readme("replacement\n"), &[], "replacement");
+    repo.branch("main", original);
+    repo.set_head("main");
+    repo.reference(&format!("refs/replace/{original}"), replacement);
+
+    let readme = |use_replace_refs: bool| {
+        let fs = GitSnapFs::new(Repository::open_opts(repo.path(), use_replace_refs).unwrap());
+        fs.init(FsOptions::all()).unwrap();
+        let read = |path: String| fs.resolve_path(path).unwrap().read_at(0, 64).unwrap();
+        let by_id = read(format!("commits/{original}/README"));
+        assert_eq!(by_id, read("branches/main/README".to_owned()));
+        String::from_utf8(by_id).unwrap()
+    };
+
+    assert_eq!(readme(true), "replacement\n");
+    assert_eq!(readme(false), "original\n");
+
+    // Each test is its own process, so the variable reaches no other test.
+    std::env::set_var("GIT_NO_REPLACE_OBJECTS", "1");
+    assert_eq!(readme(true), "original\n");
+    std::env::remove_var("GIT_NO_REPLACE_OBJECTS");
+    assert_eq!(readme(true), "replacement\n");
+
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(repo.path().join("config"))
+        .unwrap()
+        .write_all(b"[core]\n\tuseReplaceRefs = false\n")
+        .unwrap();
+    assert_eq!(readme(true), "original\n");
+}
@@ -0,0 +1,38 @@
+//! The path API behaves like a mount without going through FUSE.
+
+use gitsnapfs::fs::GitSnapFs;
+use gitsnapfs::vfs::NodeKind;
+
+mod common;
+
+#[test]
+fn resolves_branches_and_reads_ranges() {
+    let repo = common::TestRepo::bare();
+    let commit = repo.commit(repo.readme("hello snapshot\n"), &[], "init");
+    repo.branch("main", commit);
+    repo.set_head("main");
+
+    let fs = GitSnapFs::new(repo.open());
+    let main = fs.resolve_path("/branches/main").unwrap();
+    assert_eq!(main.kind, NodeKind::Directory);
+    let names: Vec<_> = main
+        .read_dir()
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    assert!(names.contains(&b"README".to_vec()));
+
+    let file = main.lookup(b"README").unwrap();
+    assert_eq!(file.read_at(6, 8).unwrap(), b"snapshot");
+    assert_eq!(file.read_at(100, 8).unwrap(), b"");
+    let via_head = fs.resolve_path("HEAD/./README").unwrap();
+    assert_eq!(via_head.inode, file.inode);
+
+    let missing = fs.resolve_path("/branches/nope").unwrap_err();
+    assert_eq!(missing.raw_os_error(), Some(libc::ENOENT));
+    assert_eq!(
+        main.read_at(0, 1).unwrap_err().raw_os_error(),
+        Some(libc::EISDIR)
+    );
+}
@@ -0,0 +1,68 @@
+//! With `--verify-sample`, blobs read from their start are re-hashed in the
+//! background and a loose object rewritten behind the mount's back is
+//! counted as corrupt in `.gitsnapfs/scrub`.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use fuse_backend_rs::api::filesystem::{FileSystem, FsOptions};
+use gix::ObjectId;
+
+use gitsnapfs::fs::GitSnapFs;
+use gitsnapfs::scrub::{self, Scrub};
+
+mod common;
+
+#[test]
+fn sampled_reads_count_corrupt_blobs() {
+    let repo = common::TestRepo::bare();
+    let good = repo.blob("good\n");
+    let bad = repo.blob("fine\n");
+    let evil = repo.blob("evil\n");
+    let objects = repo.path().join("objects");
+    std::fs::copy(loose_path(&objects, evil), loose_path(&objects, bad)).unwrap();
+    let commit = repo.commit(
+        repo.tree([("bad.txt", bad), ("good.txt", good)]),
+        &[],
+        "init",
+    );
+
+    let fs = GitSnapFs::new(repo.open()).with_scrub(Scrub::new(1.0));
+    fs.init(FsOptions::all()).unwrap();
+    let fs = Arc::new(fs);
+    scrub::spawn(&fs).unwrap();
+    for name in ["good.txt", "bad.txt"] {
+        let file = fs.resolve_path(format!("commits/{commit}/{name}")).unwrap();
+        assert_eq!(file.read_at(0, 100).unwrap().len(), 5, "{name}");
+        // Only reads from the start are sampled.
+        file.read_at(1, 100).unwrap();
+    }
+
+    let status = fs.resolve_path(".gitsnapfs/scrub").unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let text = loop {
+        let text = String::from_utf8(status.read_at(0, 4096).unwrap()).unwrap();
+        if text.contains("verified 1\ncorrupt 1\n") || Instant::now() > deadline {
+            break text;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    assert_eq!(
+        text,
+        format!("sampled 2\nverified 1\ncorrupt 1\nunreadable 0\ndropped 0\nlast_corrupt {bad}\n")
+    );
+
+    // The background thread does not keep the filesystem alive.
+    let weak = Arc::downgrade(&fs);
+    drop(fs);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while weak.strong_count() > 0 && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(weak.strong_count(), 0);
+}
+
+fn loose_path(objects: &std::path::Path, oid: ObjectId) -> std::path::PathBuf {
+    let hex = oid.to_string();
+    objects.join(&hex[..2]).join(&hex[2..])
+}
@@ -0,0 +1,66 @@
+//! `user.gitsnapfs.uuid` on the mount root names the repository at its
+//! `HEAD`: the same across mounts, different for another repository or
+//! once `HEAD` moves.
+
+use std::ffi::CString;
+
+use fuse_backend_rs::abi::fuse_abi::ROOT_ID;
+use fuse_backend_rs::api::filesystem::{Context, FileSystem, GetxattrReply, ListxattrReply};
+
+mod common;
+
+use common::TestRepo;
+
+#[test]
+fn uuid_follows_repository_and_head() {
+    let first = TestRepo::bare();
+    let second = TestRepo::bare();
+    commit(&first, "one\n");
+    commit(&second, "one\n");
+
+    let before = uuid(&first);
+    assert_eq!(before.len(), 36);
+    assert_eq!(before.as_bytes()[14], b'8');
+    assert_eq!(uuid(&first), before);
+    assert_ne!(uuid(&second), before);
+    commit(&first, "two\n");
+    assert_ne!(uuid(&first), before);
+
+    let fs = first.mount();
+    let ListxattrReply::Names(names) = fs.listxattr(&Context::default(), ROOT_ID, 4096).unwrap()
+    else {
+        unreachable!()
+    };
+    assert!(names
+        .split(|&byte| byte == 0)
+        .any(|name| name == b"user.gitsnapfs.uuid"));
+    let head = fs.resolve_path("HEAD").unwrap().inode;
+    let name = CString::new("user.gitsnapfs.uuid").unwrap();
+    assert_eq!(
+        fs.getxattr(&Context::default(), head, &name, 64)
+            .err()
+            .and_then(|err| err.raw_os_error()),
+        Some(libc::ENODATA)
+    );
+}
+
+fn uuid(repo: &TestRepo) -> String {
+    let fs = repo.mount();
+    let name = CString::new("user.gitsnapfs.uuid").unwrap();
+    match fs
+        .getxattr(&Context::default(), ROOT_ID, &name, 64)
+        .unwrap()
+    {
+        GetxattrReply::Value(value) => String::from_utf8(value).unwrap(),
+        GetxattrReply::Count(_) => unreachable!(),
+    }
+}
+
+/// Commit a tree holding `README` with `content` on `main`, on top of what
+/// `main` holds, and point `HEAD` at it.
+fn commit(repo: &TestRepo, content: &str) {
+    let parents: Vec<_> = repo.resolve("refs/heads/main").into_iter().collect();
+    let commit = repo.commit(repo.readme(content), &parents, "commit");
+    repo.branch("main", commit);
+    repo.set_head("main");
+}
@@ -0,0 +1,71 @@
+//! A linked worktree, whose `.git` is a `gitdir:` file, mounts with its own
+//! `HEAD`, and `worktrees/` links to the `HEAD` of every worktree.
+
+use std::ffi::CString;
+use std::fs;
+
+use fuse_backend_rs::abi::fuse_abi::ROOT_ID;
+use fuse_backend_rs::api::filesystem::{Context, DirEntry, FileSystem, FsOptions};
+
+use gitsnapfs::fs::{GitSnapFs, INODE_WORKTREES};
+use gitsnapfs::repo::Repository;
+
+mod common;
+
+#[test]
+fn linked_worktree_mounts_with_its_own_head() {
+    let repo = common::TestRepo::with_workdir();
+    let first = repo.commit(repo.readme("main\n"), &[], "commit");
+    let second = repo.commit(repo.readme("feature\n"), &[first], "commit");
+    repo.branch("main", first);
+    repo.branch("feature", second);
+    repo.set_head("main");
+
+    // What `git worktree add ../feature feature` leaves behind.
+    let dir = tempfile::tempdir().unwrap();
+    let linked = dir.path().join("feature");
+    let admin = repo.path().join("worktrees/feature");
+    fs::create_dir_all(&admin).unwrap();
+    fs::create_dir_all(&linked).unwrap();
+    fs::write(admin.join("HEAD"), "ref: refs/heads/feature\n").unwrap();
+    fs::write(admin.join("commondir"), "../..\n").unwrap();
+    fs::write(
+        admin.join("gitdir"),
+        format!("{}\n", linked.join(".git").display()),
+    )
+    .unwrap();
+    fs::write(
+        linked.join(".git"),
+        format!("gitdir: {}\n", admin.display()),
+    )
+    .unwrap();
+
+    let fs = GitSnapFs::new(Repository::discover(&linked, true).unwrap());
+    fs.init(FsOptions::all()).unwrap();
+    let ctx = Context::default();
+    let link = |parent: u64, name: &str| {
+        fs.lookup(&ctx, parent, &CString::new(name).unwrap())
+            .and_then(|entry| fs.readlink(&ctx, entry.inode))
+            .map(|target| String::from_utf8(target).unwrap())
+            .map_err(|err| err.raw_os_error())
+    };
+
+    assert_eq!(link(ROOT_ID, "HEAD"), Ok(format!("commits/{second}")));
+    assert_eq!(
+        link(INODE_WORKTREES, "main-worktree"),
+        Ok(format!("../commits/{first}"))
+    );
+    assert_eq!(
+        link(INODE_WORKTREES, "feature"),
+        Ok(format!("../commits/{second}"))
+    );
+    assert_eq!(link(INODE_WORKTREES, "missing"), Err(Some(libc::ENOENT)));
+
+    let mut names = Vec::new();
+    fs.readdir(&ctx, INODE_WORKTREES, 0, 4096, 0, &mut |entry: DirEntry| {
+        names.push(String::from_utf8(entry.name.to_vec()).unwrap());
+        Ok(1)
+    })
+    .unwrap();
+    assert_eq!(names, ["main-worktree", "feature"]);
+}
@@ -0,0 +1,75 @@
+//! Only `user.gitsnapfs.*` attributes are served: other names are absent
+//! without touching the repository, and `--no-xattrs` answers `ENOSYS` so
+//! the kernel stops asking.
+
+use std::ffi::CString;
+
+use fuse_backend_rs::abi::fuse_abi::ROOT_ID;
+use fuse_backend_rs::api::filesystem::{Context, FileSystem, FsOptions, GetxattrReply};
+use gix::objs::tree;
+
+use gitsnapfs::config::FsConfig;
+use gitsnapfs::fs::{fuse_options, GitSnapFs};
+
+mod common;
+
+#[test]
+fn foreign_namespaces_are_absent() {
+    let repo = common::TestRepo::bare();
+    let tree = repo.tree_of_entries(vec![tree::Entry {
+        mode: tree::EntryKind::BlobExecutable.into(),
+        filename: "run".into(),
+        oid: repo.blob("#!/bin/sh\n"),
+    }]);
+    repo.branch("main", repo.commit(tree, &[], "commit"));
+
+    let mount = |no_xattrs: bool| {
+        repo.mount_with(FsConfig {
+            no_xattrs,
+            ..FsConfig::default()
+        })
+    };
+    let errno = |fs: &GitSnapFs, inode: u64, name: &str| {
+        let name = CString::new(name).unwrap();
+        fs.getxattr(&Context::default(), inode, &name, 64)
+            .err()
+            .and_then(|err| err.raw_os_error())
+    };
+
+    let fs = mount(false);
+    let run = fs.resolve_path("branches/main/run").unwrap().inode;
+    for name in [
+        "security.capability",
+        "security.selinux",
+        "system.posix_acl_access",
+    ] {
+        assert_eq!(errno(&fs, run, name), Some(libc::ENODATA), "{name}");
+    }
+    // Even an inode the kernel never learnt of gets the quick answer.
+    assert_eq!(
+        errno(&fs, u64::MAX, "security.capability"),
+        Some(libc::ENODATA)
+    );
+    let name = CString::new("user.gitsnapfs.sha256").unwrap();
+    assert!(matches!(
+        fs.getxattr(&Context::default(), run, &name, 64).unwrap(),
+        GetxattrReply::Value(_)
+    ));
+
+    let fs = mount(true);
+    let run = fs.resolve_path("branches/main/run").unwrap().inode;
+    assert_eq!(errno(&fs, run, "security.capability"), Some(libc::ENOSYS));
+    assert_eq!(
+        errno(&fs, ROOT_ID, "user.gitsnapfs.uuid"),
+        Some(libc::ENOSYS)
+    );
+    assert_eq!(
+        fs.listxattr(&Context::default(), run, 64)
+            .err()
+            .and_then(|err| err.raw_os_error()),
+        Some(libc::ENOSYS)
+    );
+
+    let options = fuse_options(&FsConfig::default(), FsOptions::all()).unwrap();
+    assert!(options.contains(FsOptions::HANDLE_KILLPRIV));
+}